@@ -0,0 +1,58 @@
+//! A route computing the CRC-32 of an upload as it arrives, without holding
+//! the body in memory or waiting for all of it:
+//!
+//! ```sh
+//! cargo run --example checksum
+//! curl -T big.iso http://127.0.0.1:7878/checksum
+//! ```
+//!
+//! The sum is the one `zlib.crc32` and `crc32` compute.
+
+use std::error::Error;
+
+use hello::{
+    config::Config,
+    http::Method,
+    response::{Body, Response},
+    router::Router,
+    Server,
+};
+
+/// The CRC-32 of the bytes read so far continued over `bytes`.
+fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(!crc, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    });
+    !crc
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let router = Router::new().streaming(Method::Put, "/checksum", |_, body| {
+        let (mut crc, mut len, mut buf) = (0, 0u64, [0; 16 * 1024]);
+        loop {
+            match body.read(&mut buf)? {
+                0 => break,
+                read => {
+                    crc = crc32(crc, &buf[..read]);
+                    len += read as u64;
+                }
+            }
+        }
+        Ok(Response::new(200)
+            .with_header("Content-Type", "text/plain")
+            .with_body(Body::Bytes(
+                format!("{crc:08x}  {len} bytes\n").into_bytes(),
+            )))
+    });
+    let mut config = Config::default();
+    // The limit still applies as bytes arrive, but nothing is kept.
+    config.limits.max_body_size = 16 << 30;
+    let server = Server::bind(config.addr())?
+        .configure(&config)?
+        .router(router);
+    println!("PUT uploads to http://{}/checksum", config.addr());
+    server.run()?;
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! Put load on a server and print throughput, latency and connect times.
+//!
+//! Without `--addr` it benchmarks a server of its own on port 0, serving the
+//! current directory, so that runs before and after a change compare:
+//!
+//! ```sh
+//! cargo run --release --example loadgen -- --connections 16 --requests 2000
+//! ```
+
+use std::{error::Error, net::SocketAddr, process};
+
+use hello::{
+    testing::{Load, TestClient},
+    Server,
+};
+
+const USAGE: &str = "usage: loadgen [--addr ADDR] [--connections N] [--requests M] \
+                     [--path PATH] [--close] [--workers N]";
+
+struct Options {
+    addr: Option<SocketAddr>,
+    connections: usize,
+    requests: usize,
+    path: String,
+    keep_alive: bool,
+    workers: i32,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        addr: None,
+        connections: 8,
+        requests: 1000,
+        path: "/hello.html".to_string(),
+        keep_alive: true,
+        workers: -1,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        let invalid = |value: String| format!("invalid value for {arg}: {value}");
+        match arg.as_str() {
+            "--addr" => {
+                let addr = value()?;
+                options.addr = Some(addr.parse().map_err(|_| invalid(addr))?);
+            }
+            "--connections" => {
+                let count = value()?;
+                options.connections = count.parse().map_err(|_| invalid(count))?;
+            }
+            "--requests" => {
+                let count = value()?;
+                options.requests = count.parse().map_err(|_| invalid(count))?;
+            }
+            "--workers" => {
+                let count = value()?;
+                options.workers = count.parse().map_err(|_| invalid(count))?;
+            }
+            "--path" => options.path = value()?,
+            "--close" => options.keep_alive = false,
+            _ => return Err(format!("unknown argument {arg}\n{USAGE}")),
+        }
+    }
+    Ok(options)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = parse_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{err}");
+        process::exit(2);
+    });
+    let spawned = match options.addr {
+        Some(_) => None,
+        None => Some(
+            Server::bind("127.0.0.1:0")?
+                .pool_size(options.workers)
+                .spawn()?,
+        ),
+    };
+    let addr = match (&spawned, options.addr) {
+        (Some(server), _) => server.local_addr(),
+        (None, addr) => addr.expect("either an address or a server"),
+    };
+
+    println!(
+        "{} connections x {} requests for {} on {addr}{}",
+        options.connections,
+        options.requests,
+        options.path,
+        if options.keep_alive {
+            ""
+        } else {
+            ", each on a new connection"
+        }
+    );
+    let report = Load::new(options.connections, options.requests)
+        .keep_alive(options.keep_alive)
+        .request(TestClient::get(&options.path))
+        .run(addr);
+    println!("{report}");
+    if let Some(server) = spawned {
+        server.shutdown();
+    }
+    Ok(())
+}
@@ -0,0 +1,14 @@
+//! Parse arbitrary bytes as a request head: it must never panic, and never
+//! claim more bytes than it was given.
+#![no_main]
+
+use hello::http::parse_request_bytes;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((request, consumed)) = parse_request_bytes(data) {
+        assert!(consumed <= data.len());
+        let (again, _) = parse_request_bytes(&data[..consumed]).expect("the head alone parses");
+        assert_eq!(again.target(), request.target());
+    }
+});
@@ -0,0 +1,126 @@
+//! Allow and deny lists of client address ranges, checked when connections are accepted.
+
+use std::{error::Error, fmt, net::IpAddr};
+
+use crate::cidr::Cidr;
+
+/// All errors which can occur while configuring an access list.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AccessError {
+    InvalidCidr(String),
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for AccessError {}
+
+/// Whether a client may connect, and the rule which decided it if any.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Access {
+    Allowed(Option<String>),
+    Denied(Option<String>),
+}
+
+/// Address ranges which may or may not connect.
+///
+/// Denied ranges take precedence. Once any range is allowed, clients outside
+/// all allowed ranges are denied.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+    respond_forbidden: bool,
+}
+
+impl AccessList {
+    /// Create an access list which allows every client.
+    pub fn new() -> AccessList {
+        AccessList::default()
+    }
+
+    /// Allow clients in `cidr`, such as `10.0.0.0/8` or `2001:db8::/32`.
+    pub fn allow(mut self, cidr: &str) -> Result<AccessList, AccessError> {
+        self.allowed.push(parse(cidr)?);
+        Ok(self)
+    }
+
+    /// Deny clients in `cidr`, even if they are in an allowed range.
+    pub fn deny(mut self, cidr: &str) -> Result<AccessList, AccessError> {
+        self.denied.push(parse(cidr)?);
+        Ok(self)
+    }
+
+    /// Send denied clients a minimal `403 Forbidden` before closing instead of
+    /// closing right away.
+    pub fn respond_forbidden(mut self, respond: bool) -> AccessList {
+        self.respond_forbidden = respond;
+        self
+    }
+
+    /// Whether denied clients get a 403 response.
+    pub fn responds_forbidden(&self) -> bool {
+        self.respond_forbidden
+    }
+
+    /// Decide whether the client at `ip` may connect.
+    pub fn check(&self, ip: IpAddr) -> Access {
+        if let Some(rule) = self.denied.iter().find(|cidr| cidr.contains(ip)) {
+            return Access::Denied(Some(format!("deny {rule}")));
+        }
+        if self.allowed.is_empty() {
+            return Access::Allowed(None);
+        }
+        match self.allowed.iter().find(|cidr| cidr.contains(ip)) {
+            Some(rule) => Access::Allowed(Some(format!("allow {rule}"))),
+            None => Access::Denied(None),
+        }
+    }
+}
+
+fn parse(cidr: &str) -> Result<Cidr, AccessError> {
+    cidr.parse()
+        .map_err(|()| AccessError::InvalidCidr(cidr.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() -> Result<(), Box<dyn std::error::Error>> {
+        let open = AccessList::new().deny("203.0.113.0/24")?;
+        assert_eq!(open.check("192.0.2.1".parse()?), Access::Allowed(None));
+        assert_eq!(
+            open.check("::ffff:203.0.113.5".parse()?),
+            Access::Denied(Some("deny 203.0.113.0/24".to_string()))
+        );
+
+        let internal = AccessList::new()
+            .allow("10.0.0.0/8")?
+            .allow("::1")?
+            .deny("10.0.0.66")?;
+        assert_eq!(
+            internal.check("10.1.2.3".parse()?),
+            Access::Allowed(Some("allow 10.0.0.0/8".to_string()))
+        );
+        assert_eq!(internal.check("192.0.2.1".parse()?), Access::Denied(None));
+        assert!(matches!(
+            internal.check("10.0.0.66".parse()?),
+            Access::Denied(Some(_))
+        ));
+        assert!(matches!(
+            internal.check("::1".parse()?),
+            Access::Allowed(Some(_))
+        ));
+
+        assert_eq!(
+            AccessList::new().allow("10.0.0.0/40").unwrap_err(),
+            AccessError::InvalidCidr("10.0.0.0/40".to_string())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,459 @@
+//! Access logs in the Common and Combined Log Formats or as JSON, one line
+//! per response.
+
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{mpsc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    http::Request,
+    httpdate, json,
+    log_file::{LogFile, Rotation},
+};
+
+/// The layout of the access log lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum AccessLogFormat {
+    /// `client - user [time] "request line" status bytes`.
+    #[default]
+    Common,
+    /// The common format followed by the quoted `Referer` and `User-Agent`.
+    Combined,
+    /// A JSON object with the fields of the combined format and more.
+    Json,
+}
+
+/// What one access log line records of an answered request.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub client: Option<IpAddr>,
+    /// The request, none if it could not be parsed.
+    pub request: Option<&'a Request>,
+    pub status: u16,
+    /// The bytes of the body sent, without the head.
+    pub bytes: u64,
+    /// The bytes of the request read, head included.
+    pub received: u64,
+    /// When the request was received.
+    pub time: SystemTime,
+    /// How long answering the request took.
+    pub duration: Duration,
+    pub request_id: Option<&'a str>,
+}
+
+/// Writes a line for every response to a destination, stdout by default.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    destination: Destination,
+}
+
+enum Destination {
+    Writer(Mutex<Box<dyn Write + Send>>),
+    /// A thread owning the file, so that connections never wait on rotation.
+    File {
+        sender: Option<mpsc::Sender<Message>>,
+        thread: Option<JoinHandle<()>>,
+    },
+}
+
+enum Message {
+    Line(String),
+    Reopen,
+}
+
+impl AccessLog {
+    /// Log lines in `format` to stdout.
+    pub fn stdout(format: AccessLogFormat) -> AccessLog {
+        AccessLog::to_writer(format, io::stdout())
+    }
+
+    /// Log lines in `format` to `destination`.
+    pub fn to_writer(
+        format: AccessLogFormat,
+        destination: impl Write + Send + 'static,
+    ) -> AccessLog {
+        AccessLog {
+            format,
+            destination: Destination::Writer(Mutex::new(Box::new(destination))),
+        }
+    }
+
+    /// Log lines in `format` to the file at `path`, rotated as `rotation` says.
+    ///
+    /// The file is opened right away, so that a bad path is an error here, and
+    /// written by a thread of its own, which writes every line logged before
+    /// the access log is dropped.
+    pub fn to_file(
+        format: AccessLogFormat,
+        path: impl Into<PathBuf>,
+        rotation: Rotation,
+    ) -> io::Result<AccessLog> {
+        let mut file = LogFile::open(path, rotation)?;
+        let (sender, receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                while let Ok(message) = receiver.recv() {
+                    // Flush once the queue is empty rather than after every line.
+                    let mut next = Some(message);
+                    while let Some(message) = next {
+                        let result = match message {
+                            Message::Line(line) => file.write_line(line.as_bytes()),
+                            Message::Reopen => file.reopen(),
+                        };
+                        if let Err(e) = result {
+                            log::warn!("Cannot write {}: {e}", file.path().display());
+                        }
+                        next = receiver.try_recv().ok();
+                    }
+                    if let Err(e) = file.flush() {
+                        log::warn!("Cannot write {}: {e}", file.path().display());
+                    }
+                }
+            })?;
+        Ok(AccessLog {
+            format,
+            destination: Destination::File {
+                sender: Some(sender),
+                thread: Some(thread),
+            },
+        })
+    }
+
+    /// Write the line for `entry`, in one write so that lines never interleave.
+    pub fn log(&self, entry: &Entry<'_>) -> io::Result<()> {
+        let mut line = self.format_entry(entry);
+        line.push('\n');
+        match &self.destination {
+            Destination::Writer(writer) => {
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                writer.write_all(line.as_bytes())?;
+                writer.flush()
+            }
+            Destination::File { sender, .. } => send(sender, Message::Line(line)),
+        }
+    }
+
+    /// Open the log file again, for when it was moved away to be rotated by
+    /// another program. Other destinations are left as they are.
+    pub fn reopen(&self) -> io::Result<()> {
+        match &self.destination {
+            Destination::Writer(_) => Ok(()),
+            Destination::File { sender, .. } => send(sender, Message::Reopen),
+        }
+    }
+
+    /// The line for `entry`, without the line break.
+    pub fn format_entry(&self, entry: &Entry<'_>) -> String {
+        if self.format == AccessLogFormat::Json {
+            return format_json(entry);
+        }
+        let client = entry
+            .client
+            .map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        let user = entry
+            .request
+            .and_then(Request::user)
+            .map_or_else(|| "-".to_string(), escape);
+        let request_line = entry.request.map_or_else(
+            || "-".to_string(),
+            |request| {
+                escape(&format!(
+                    "{} {} {}",
+                    request.method(),
+                    request.target(),
+                    request.version()
+                ))
+            },
+        );
+        let bytes = match entry.bytes {
+            0 => "-".to_string(),
+            bytes => bytes.to_string(),
+        };
+        let mut line = format!(
+            "{client} - {user} [{}] \"{request_line}\" {} {bytes}",
+            httpdate::format_log(entry.time),
+            entry.status
+        );
+        if self.format == AccessLogFormat::Combined {
+            let header = |name| {
+                entry
+                    .request
+                    .and_then(|request| request.header(name))
+                    .map_or_else(|| "-".to_string(), escape)
+            };
+            let _ = write!(
+                line,
+                " \"{}\" \"{}\"",
+                header("Referer"),
+                header("User-Agent")
+            );
+        }
+        line
+    }
+}
+
+impl std::fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for AccessLog {
+    fn drop(&mut self) {
+        if let Destination::File { sender, thread } = &mut self.destination {
+            // Hanging up lets the thread write what is queued and stop.
+            sender.take();
+            if let Some(thread) = thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+fn send(sender: &Option<mpsc::Sender<Message>>, message: Message) -> io::Result<()> {
+    sender
+        .as_ref()
+        .and_then(|sender| sender.send(message).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "the access log thread stopped"))
+}
+
+/// `entry` as a JSON object, with `null` for what is unknown.
+fn format_json(entry: &Entry<'_>) -> String {
+    let header = |name| entry.request.and_then(|request| request.header(name));
+    let target = entry.request.map(Request::target);
+    let (path, query) = match target.and_then(|target| target.split_once('?')) {
+        Some((path, query)) => (Some(path), Some(query)),
+        None => (target, None),
+    };
+    json::Object::new()
+        .string("timestamp", &httpdate::format_rfc3339(entry.time))
+        .optional(
+            "client_ip",
+            entry.client.map(|ip| ip.to_string()).as_deref(),
+        )
+        .optional(
+            "method",
+            entry.request.map(|request| request.method().as_str()),
+        )
+        .optional("path", path)
+        .optional("query", query)
+        .number("status", entry.status)
+        .number("bytes_sent", entry.bytes)
+        .number("bytes_received", entry.received)
+        .number(
+            "duration_ms",
+            format_args!("{:.3}", entry.duration.as_secs_f64() * 1000.0),
+        )
+        .optional("user_agent", header("User-Agent"))
+        .optional("referer", header("Referer"))
+        .optional("request_id", entry.request_id)
+        .optional("user", entry.request.and_then(Request::user))
+        .optional("host", header("Host"))
+        .finish()
+}
+
+/// `text` with quotes and backslashes escaped by a backslash, and everything
+/// but printable ASCII as `\xhh` bytes, so a field never breaks its line.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'"' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(char::from(byte));
+            }
+            b' '..=b'~' => escaped.push(char::from(byte)),
+            _ => {
+                let _ = write!(escaped, "\\x{byte:02x}");
+            }
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{Method, Version},
+        test_util::TempDir,
+    };
+    use std::{
+        collections::HashSet,
+        fs,
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    #[test]
+    fn test_common_and_combined_lines() {
+        let time = UNIX_EPOCH + Duration::from_secs(1728568536);
+        let request = Request::new(Method::Get, "/", Version::Http11)
+            .with_header("Referer", "http://a.test/")
+            .with_header("User-Agent", "curl/8.0 \"quoted\"");
+        let entry = Entry {
+            client: "127.0.0.1".parse().ok(),
+            request: Some(&request),
+            status: 200,
+            bytes: 612,
+            received: 120,
+            time,
+            duration: Duration::ZERO,
+            request_id: None,
+        };
+        let common = AccessLog::to_writer(AccessLogFormat::Common, io::sink());
+        assert_eq!(
+            common.format_entry(&entry),
+            "127.0.0.1 - - [10/Oct/2024:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612"
+        );
+        let combined = AccessLog::to_writer(AccessLogFormat::Combined, io::sink());
+        assert_eq!(
+            combined.format_entry(&entry),
+            "127.0.0.1 - - [10/Oct/2024:13:55:36 +0000] \"GET / HTTP/1.1\" 200 612 \
+             \"http://a.test/\" \"curl/8.0 \\\"quoted\\\"\""
+        );
+
+        let anonymous = Request::new(Method::Head, "/a b\u{e9}", Version::Http10);
+        let entry = Entry {
+            client: None,
+            request: Some(&anonymous),
+            status: 304,
+            bytes: 0,
+            received: 30,
+            time,
+            duration: Duration::ZERO,
+            request_id: None,
+        };
+        assert_eq!(
+            combined.format_entry(&entry),
+            "- - - [10/Oct/2024:13:55:36 +0000] \"HEAD /a b\\xc3\\xa9 HTTP/1.0\" 304 - \"-\" \"-\""
+        );
+        let malformed = Entry {
+            request: None,
+            status: 404,
+            bytes: 9,
+            ..entry
+        };
+        assert_eq!(
+            common.format_entry(&malformed),
+            "- - - [10/Oct/2024:13:55:36 +0000] \"-\" 404 9"
+        );
+    }
+
+    #[test]
+    fn test_json_lines_parse_back() {
+        let agent = "Mozilla/5.0 \"quoted\"\nX-Injected: yes\\ caf\u{e9}";
+        let request = Request::new(Method::Post, "/a%20b?x=1&y=\"2\"", Version::Http11)
+            .with_header("User-Agent", agent)
+            .with_header("Host", "a.test");
+        let entry = Entry {
+            client: "2001:db8::1".parse().ok(),
+            request: Some(&request),
+            status: 201,
+            bytes: 42,
+            received: 96,
+            time: UNIX_EPOCH + Duration::from_millis(1728568536123),
+            duration: Duration::from_micros(1500),
+            request_id: Some("abc-1"),
+        };
+        let log = AccessLog::to_writer(AccessLogFormat::Json, io::sink());
+        let line = log.format_entry(&entry);
+        assert!(!line.contains('\n'), "{line}");
+        let parsed = json::parse(&line).expect("valid JSON");
+        let field = |name| parsed.get(name).cloned();
+        let string = |text: &str| Some(json::Value::String(text.to_string()));
+        assert_eq!(field("timestamp"), string("2024-10-10T13:55:36.123Z"));
+        assert_eq!(field("client_ip"), string("2001:db8::1"));
+        assert_eq!(field("method"), string("POST"));
+        assert_eq!(field("path"), string("/a%20b"));
+        assert_eq!(field("query"), string("x=1&y=\"2\""));
+        assert_eq!(field("status"), Some(json::Value::Number(201.0)));
+        assert_eq!(field("bytes_sent"), Some(json::Value::Number(42.0)));
+        assert_eq!(field("bytes_received"), Some(json::Value::Number(96.0)));
+        assert_eq!(field("duration_ms"), Some(json::Value::Number(1.5)));
+        assert_eq!(field("user_agent"), string(agent));
+        assert_eq!(field("referer"), Some(json::Value::Null));
+        assert_eq!(field("request_id"), string("abc-1"));
+        assert_eq!(field("host"), string("a.test"));
+
+        let malformed = Entry {
+            client: None,
+            request: None,
+            request_id: None,
+            ..entry
+        };
+        let parsed = json::parse(&log.format_entry(&malformed)).expect("valid JSON");
+        assert_eq!(parsed.get("method"), Some(&json::Value::Null));
+        assert_eq!(parsed.get("path"), Some(&json::Value::Null));
+    }
+
+    #[test]
+    fn test_file_rotation_loses_no_lines() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.path().join("access.log");
+        let rotation = Rotation::new().max_size(1024).keep(1000);
+        let log = Arc::new(AccessLog::to_file(
+            AccessLogFormat::Common,
+            &path,
+            rotation,
+        )?);
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let log = Arc::clone(&log);
+                thread::spawn(move || {
+                    for line in 0..100 {
+                        let target = format!("/{thread}/{line}");
+                        let request = Request::new(Method::Get, &target, Version::Http11);
+                        let entry = Entry {
+                            client: None,
+                            request: Some(&request),
+                            status: 200,
+                            bytes: 1,
+                            received: 1,
+                            time: UNIX_EPOCH,
+                            duration: Duration::ZERO,
+                            request_id: None,
+                        };
+                        log.log(&entry).expect("the line is queued");
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().expect("no panic");
+        }
+        log.reopen()?;
+        drop(Arc::into_inner(log));
+
+        let rotated = LogFile::open(&path, rotation)?.rotated()?;
+        assert!(rotated.len() > 5, "{rotated:?}");
+        let mut seen = HashSet::new();
+        for file in rotated.iter().chain([&path]) {
+            let contents = fs::read_to_string(file)?;
+            assert!(contents.len() <= 1024);
+            for line in contents.lines() {
+                let target = line
+                    .strip_prefix("- - - [01/Jan/1970:00:00:00 +0000] \"GET ")
+                    .and_then(|rest| rest.strip_suffix(" HTTP/1.1\" 200 1"))
+                    .unwrap_or_else(|| panic!("a whole line, not {line:?}"));
+                assert!(seen.insert(target.to_string()), "{target} twice");
+            }
+        }
+        assert_eq!(seen.len(), 400);
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+//! Listening sockets passed in by systemd socket activation.
+
+use std::{
+    env, io,
+    net::TcpListener,
+    ops::Range,
+    os::fd::{FromRawFd, RawFd},
+};
+
+use socket2::{Socket, Type};
+
+/// The first file descriptor passed by the service manager.
+pub const LISTEN_FDS_START: RawFd = 3;
+
+/// The file descriptors passed to process `pid`, given the values of
+/// `LISTEN_PID` and `LISTEN_FDS`. None when either is unset, the sockets are
+/// meant for another process or there are none.
+pub fn listen_fds(
+    pid: u32,
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+) -> io::Result<Option<Range<RawFd>>> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+    let listen_pid: u32 = listen_pid
+        .parse()
+        .map_err(|_| invalid(format!("LISTEN_PID is not a process id: {listen_pid}")))?;
+    if listen_pid != pid {
+        return Ok(None);
+    }
+    let end = listen_fds
+        .parse::<RawFd>()
+        .ok()
+        .filter(|count| *count >= 0)
+        .and_then(|count| LISTEN_FDS_START.checked_add(count))
+        .ok_or_else(|| {
+            invalid(format!(
+                "LISTEN_FDS is not a number of sockets: {listen_fds}"
+            ))
+        })?;
+    Ok(Some(LISTEN_FDS_START..end).filter(|fds| !fds.is_empty()))
+}
+
+/// The TCP listeners systemd passed to this process, none when it passed
+/// nothing and the addresses should be bound as usual.
+///
+/// The `LISTEN_*` variables are removed, so that the sockets are only taken
+/// over once and not by child processes.
+pub fn inherited() -> io::Result<Option<Vec<TcpListener>>> {
+    let listen_pid = env::var("LISTEN_PID").ok();
+    let listen_fds_var = env::var("LISTEN_FDS").ok();
+    let fds = listen_fds(
+        std::process::id(),
+        listen_pid.as_deref(),
+        listen_fds_var.as_deref(),
+    )?;
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+    let Some(fds) = fds else {
+        return Ok(None);
+    };
+    fds.map(adopt).collect::<io::Result<_>>().map(Some)
+}
+
+/// Take over `fd`, which must be a TCP socket listening for connections.
+fn adopt(fd: RawFd) -> io::Result<TcpListener> {
+    // SAFETY: `LISTEN_PID` names this process, so the service manager passed
+    // `fd` to it, and the variables are removed before anything else owns it.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    socket.set_cloexec(true)?;
+    let is_tcp = socket.r#type()? == Type::STREAM && socket.local_addr()?.as_socket().is_some();
+    if !is_tcp {
+        return Err(invalid(format!(
+            "the inherited socket {fd} is not a TCP listener"
+        )));
+    }
+    Ok(socket.into())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(listen_fds(42, None, None)?, None);
+        assert_eq!(listen_fds(42, Some("42"), None)?, None);
+        assert_eq!(listen_fds(42, Some("42"), Some("2"))?, Some(3..5));
+        assert_eq!(listen_fds(42, Some("42"), Some("0"))?, None);
+        assert_eq!(
+            listen_fds(42, Some("7"), Some("2"))?,
+            None,
+            "sockets for another process are left alone"
+        );
+
+        assert!(listen_fds(42, Some("me"), Some("1")).is_err());
+        assert!(listen_fds(42, Some("42"), Some("-1")).is_err());
+        assert!(listen_fds(42, Some("42"), Some("many")).is_err());
+        Ok(())
+    }
+}
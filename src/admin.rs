@@ -0,0 +1,149 @@
+//! Endpoints for orchestration scripts to shut the server down or drain it
+//! over HTTP, and for turning the traffic dump on and off, answered only to
+//! requests with an admin bearer token.
+
+use crate::{
+    http::{self, Method, Request},
+    json::Object,
+    response::{Body, Response},
+};
+
+/// What an admin endpoint asks the server to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminAction {
+    /// Drain and exit, as on `SIGTERM`.
+    Shutdown,
+    /// Turn new connections away while finishing the open ones, but keep
+    /// running.
+    Drain,
+    /// Dump the raw bytes of the connections accepted from now on.
+    StartDump,
+    /// Stop dumping new connections.
+    StopDump,
+}
+
+impl AdminAction {
+    /// The name of the action in the answers.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AdminAction::Shutdown => "shutdown",
+            AdminAction::Drain => "drain",
+            AdminAction::StartDump => "start_dump",
+            AdminAction::StopDump => "stop_dump",
+        }
+    }
+}
+
+/// The paths of the admin endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminEndpoints {
+    shutdown: String,
+    drain: String,
+    start_dump: String,
+    stop_dump: String,
+}
+
+impl AdminEndpoints {
+    /// `POST /admin/shutdown`, `POST /admin/drain`, `POST /admin/dump/start`
+    /// and `POST /admin/dump/stop`.
+    pub fn new() -> AdminEndpoints {
+        AdminEndpoints {
+            shutdown: "/admin/shutdown".to_string(),
+            drain: "/admin/drain".to_string(),
+            start_dump: "/admin/dump/start".to_string(),
+            stop_dump: "/admin/dump/stop".to_string(),
+        }
+    }
+
+    /// The action `request` asks for, or the `405` it gets for asking with
+    /// another method than `POST`; none if it is not for an endpoint.
+    pub fn action(&self, request: &Request) -> Option<Result<AdminAction, Response>> {
+        let action = match &*http::merge_slashes(request.path()) {
+            target if target == self.shutdown => AdminAction::Shutdown,
+            target if target == self.drain => AdminAction::Drain,
+            target if target == self.start_dump => AdminAction::StartDump,
+            target if target == self.stop_dump => AdminAction::StopDump,
+            _ => return None,
+        };
+        if request.method() != &Method::Post {
+            return Some(Err(
+                Response::builtin_error(405).with_header("Allow", "POST")
+            ));
+        }
+        Some(Ok(action))
+    }
+}
+
+impl Default for AdminEndpoints {
+    fn default() -> AdminEndpoints {
+        AdminEndpoints::new()
+    }
+}
+
+/// `202 Accepted`, describing the `action` taken with `connections` other
+/// connections still open.
+pub fn accepted(action: AdminAction, connections: usize) -> Response {
+    let body = Object::new()
+        .string("action", action.as_str())
+        .string("status", "draining")
+        .number("connections", connections)
+        .finish();
+    Response::new(202)
+        .with_header("Content-Type", "application/json")
+        .with_header("Cache-Control", "no-store")
+        .with_body(Body::Bytes(body.into_bytes()))
+}
+
+/// `200 OK` for the dump `action`, saying whether connections are now dumped.
+pub fn dumping(action: AdminAction, enabled: bool) -> Response {
+    let body = Object::new()
+        .string("action", action.as_str())
+        .string("status", if enabled { "dumping" } else { "stopped" })
+        .finish();
+    Response::new(200)
+        .with_header("Content-Type", "application/json")
+        .with_header("Cache-Control", "no-store")
+        .with_body(Body::Bytes(body.into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Version, json};
+
+    #[test]
+    fn test_action() {
+        let endpoints = AdminEndpoints::new();
+        let action = |method, target| {
+            endpoints
+                .action(&Request::new(method, target, Version::Http11))
+                .map(|action| action.map_err(|response| response.status()))
+        };
+        assert_eq!(
+            action(Method::Post, "/admin/shutdown"),
+            Some(Ok(AdminAction::Shutdown))
+        );
+        assert_eq!(
+            action(Method::Post, "/admin/drain"),
+            Some(Ok(AdminAction::Drain))
+        );
+        assert_eq!(
+            action(Method::Post, "/admin/dump/stop"),
+            Some(Ok(AdminAction::StopDump))
+        );
+        assert_eq!(action(Method::Get, "/admin/shutdown"), Some(Err(405)));
+        assert_eq!(action(Method::Post, "/admin/shutdown/now"), None);
+
+        let response = accepted(AdminAction::Drain, 3);
+        assert_eq!(response.status(), 202);
+        let Body::Bytes(body) = response.body() else {
+            panic!("the answer is in memory");
+        };
+        let body = json::parse(std::str::from_utf8(body).unwrap()).expect("valid JSON");
+        assert_eq!(
+            body.get("action").and_then(json::Value::as_str),
+            Some("drain")
+        );
+        assert_eq!(body.get("connections"), Some(&json::Value::Number(3.0)));
+    }
+}
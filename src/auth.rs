@@ -0,0 +1,415 @@
+//! HTTP Basic authentication of the requests under a path prefix, against
+//! an htpasswd file of bcrypt or SHA-crypt hashes, and bearer tokens for the
+//! admin endpoints.
+
+use std::{collections::HashMap, fs, io, path::Path, str, time::Instant};
+
+use crate::{
+    base64,
+    http::{self, Request},
+    ratelimit::RateLimiter,
+    response::Response,
+};
+
+/// The hash formats accepted: bcrypt, SHA-512 crypt and SHA-256 crypt.
+const SCHEMES: [&str; 5] = ["$2y$", "$2b$", "$2a$", "$6$", "$5$"];
+
+/// Users and the hashes of their passwords.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Credentials {
+    users: HashMap<String, String>,
+}
+
+impl Credentials {
+    /// Read `name:hash` lines like those `htpasswd -B` writes, skipping
+    /// blank lines and `#` comments.
+    pub fn parse(text: &str) -> Result<Credentials, String> {
+        let mut users = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, hash)) = line.split_once(':') else {
+                return Err(format!("line {} is not name:hash", number + 1));
+            };
+            if !SCHEMES.iter().any(|scheme| hash.starts_with(scheme)) {
+                return Err(format!(
+                    "line {}: the hash of {name} is neither bcrypt nor SHA-crypt",
+                    number + 1
+                ));
+            }
+            users.insert(name.to_string(), hash.to_string());
+        }
+        Ok(Credentials { users })
+    }
+
+    /// Read the credentials in the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Credentials> {
+        let text = fs::read_to_string(path)?;
+        Credentials::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Whether `password` is the one of `user`. The hashes are compared in
+    /// constant time.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(hash) => pwhash::unix::verify(password, hash),
+            None => {
+                // Hash anyway, so that the time taken does not tell who exists.
+                if let Some(hash) = self.users.values().next() {
+                    pwhash::unix::verify(password, hash);
+                }
+                false
+            }
+        }
+    }
+}
+
+/// Asks for credentials on the requests for a prefix and below.
+///
+/// Clones share the count of failures.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    prefix: String,
+    realm: String,
+    credentials: Credentials,
+    failures: RateLimiter,
+}
+
+impl BasicAuth {
+    /// Ask for one of `credentials` below `prefix`, `/` for the whole site,
+    /// naming `realm` to the client. Each client may fail 5 times in a row,
+    /// and once every 10 seconds after that.
+    pub fn new(prefix: &str, realm: &str, credentials: Credentials) -> BasicAuth {
+        BasicAuth {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            realm: realm.to_string(),
+            credentials,
+            failures: RateLimiter::new(0.1, 5).allow(&[]),
+        }
+    }
+
+    /// Throttle the failures of each client with `failures` instead.
+    pub fn throttle(mut self, failures: RateLimiter) -> BasicAuth {
+        self.failures = failures;
+        self
+    }
+
+    /// Whether the path of `request` is the prefix or below it.
+    pub fn matches(&self, request: &Request) -> bool {
+        below(&self.prefix, request)
+    }
+
+    /// The user `request` authenticates as at `now`, or the response it
+    /// gets instead: `401` without credentials or with wrong ones, `400`
+    /// for a malformed `Authorization` header and `429` once its client
+    /// failed too often.
+    pub fn authenticate(&self, request: &Request, now: Instant) -> Result<String, Response> {
+        let Some(header) = request.header("Authorization") else {
+            return Err(self.challenge());
+        };
+        let Some((user, password)) = basic_credentials(header) else {
+            return Err(Response::builtin_error(400));
+        };
+        let client = request.client_ip();
+        if let Some(Err(wait)) = client.map(|ip| self.failures.peek(ip, now)) {
+            let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+            return Err(
+                Response::builtin_error(429).with_header("Retry-After", retry_after.to_string())
+            );
+        }
+        if self.credentials.verify(&user, &password) {
+            return Ok(user);
+        }
+        log::info!("Wrong credentials for {user} below {}/", self.prefix);
+        if let Some(ip) = client {
+            let _ = self.failures.check(ip, now);
+        }
+        Err(self.challenge())
+    }
+
+    /// `401 Unauthorized`, asking for credentials for the realm.
+    fn challenge(&self) -> Response {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        Response::builtin_error(401).with_header(
+            "WWW-Authenticate",
+            format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+        )
+    }
+}
+
+/// Named bearer tokens, so that a new one can be accepted beside the old one
+/// while scripts move over.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tokens {
+    tokens: Vec<(String, String)>,
+}
+
+impl Tokens {
+    /// Read `name:token` lines, skipping blank lines and `#` comments.
+    pub fn parse(text: &str) -> Result<Tokens, String> {
+        let mut tokens: Vec<(String, String)> = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((name, token)) = line.split_once(':') else {
+                return Err(format!("line {} is not name:token", number + 1));
+            };
+            if token.is_empty() || token.contains(char::is_whitespace) {
+                return Err(format!(
+                    "line {}: the token of {name} is empty or has spaces",
+                    number + 1
+                ));
+            }
+            if tokens.iter().any(|(named, _)| named == name) {
+                return Err(format!("line {}: {name} is named twice", number + 1));
+            }
+            tokens.push((name.to_string(), token.to_string()));
+        }
+        Ok(Tokens { tokens })
+    }
+
+    /// Read the tokens in the file at `path`.
+    pub fn load(path: &Path) -> io::Result<Tokens> {
+        let text = fs::read_to_string(path)?;
+        Tokens::parse(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// The name of `token`, if it is one of them. Every token is compared,
+    /// each in constant time.
+    pub fn name_of(&self, token: &str) -> Option<&str> {
+        self.tokens.iter().fold(None, |found, (name, known)| {
+            let same = constant_time_eq(known.as_bytes(), token.as_bytes());
+            if same {
+                Some(name.as_str())
+            } else {
+                found
+            }
+        })
+    }
+}
+
+/// Asks for a bearer token on the requests for the admin paths and below.
+#[derive(Debug, Clone, Default)]
+pub struct BearerAuth {
+    paths: Vec<String>,
+    tokens: Tokens,
+}
+
+impl BearerAuth {
+    /// Ask for one of `tokens`, on no path until [`protect`](BearerAuth::protect)
+    /// names some.
+    pub fn new(tokens: Tokens) -> BearerAuth {
+        BearerAuth {
+            paths: Vec::new(),
+            tokens,
+        }
+    }
+
+    /// Ask for a token on `path` and below as well.
+    pub fn protect(mut self, path: &str) -> BearerAuth {
+        self.paths.push(path.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Whether the path of `request` is one of the admin paths or below one.
+    pub fn matches(&self, request: &Request) -> bool {
+        self.paths.iter().any(|path| below(path, request))
+    }
+
+    /// The name of the token `request` carries, or the `401` it gets
+    /// without one of the tokens. Each use of a token is logged by name.
+    pub fn authenticate(&self, request: &Request) -> Result<String, Response> {
+        let token = request.header("Authorization").and_then(|header| {
+            let (scheme, token) = header.trim().split_once(' ')?;
+            scheme.eq_ignore_ascii_case("Bearer").then(|| token.trim())
+        });
+        let Some(token) = token else {
+            return Err(bearer_challenge(None));
+        };
+        match self.tokens.name_of(token) {
+            Some(name) => {
+                log::info!(
+                    "The token {name} authorized {} {}",
+                    request.method(),
+                    request.target()
+                );
+                Ok(name.to_string())
+            }
+            None => {
+                log::info!("Wrong token for {} {}", request.method(), request.target());
+                Err(bearer_challenge(Some("invalid_token")))
+            }
+        }
+    }
+}
+
+/// `401 Unauthorized`, asking for a bearer token, with the `error` of the
+/// one sent if any.
+fn bearer_challenge(error: Option<&str>) -> Response {
+    let challenge = match error {
+        Some(error) => format!("Bearer realm=\"admin\", error=\"{error}\""),
+        None => "Bearer realm=\"admin\"".to_string(),
+    };
+    Response::builtin_error(401).with_header("WWW-Authenticate", challenge)
+}
+
+/// Whether `a` and `b` are equal, taking as long whichever byte differs;
+/// only their lengths can tell in how long it takes.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Whether the path of `request` is `prefix` or below it, with its slashes
+/// merged as for the file it may name.
+fn below(prefix: &str, request: &Request) -> bool {
+    http::merge_slashes(http::origin_form(request.path()))
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The user and password of a `Basic` `Authorization` header.
+fn basic_credentials(header: &str) -> Option<(String, String)> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = base64::decode(token.trim())?;
+    let (user, password) = str::from_utf8(&decoded).ok()?.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+
+    const HTPASSWD: &str = "# name:hash\n\
+        alice:$2y$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe\n\
+        \n\
+        bob:$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1\n";
+
+    #[test]
+    fn test_credentials() {
+        let credentials = Credentials::parse(HTPASSWD).unwrap();
+        assert!(credentials.verify("alice", "password"));
+        assert!(credentials.verify("bob", "secret"));
+        assert!(!credentials.verify("alice", "secret"));
+        assert!(!credentials.verify("carol", "password"));
+
+        assert!(Credentials::parse("carol").is_err());
+        assert!(
+            Credentials::parse("carol:$1$salt$hash").is_err(),
+            "MD5 crypt is refused"
+        );
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let credentials = Credentials::parse(HTPASSWD).unwrap();
+        let auth =
+            BasicAuth::new("/admin/", "Admin", credentials).throttle(RateLimiter::new(1.0, 1));
+        let request = |target: &str, authorization: Option<&str>| {
+            let request = Request::new(Method::Get, target, Version::Http11)
+                .with_client_ip("192.0.2.1".parse().ok());
+            match authorization {
+                Some(value) => request.with_header("Authorization", value),
+                None => request,
+            }
+        };
+        assert!(auth.matches(&request("/admin", None)));
+        assert!(auth.matches(&request("/admin/users?page=2", None)));
+        assert!(auth.matches(&request("http://a.test/admin/users", None)));
+        assert!(!auth.matches(&request("/administrator", None)));
+        assert!(BasicAuth::new("/", "Site", Credentials::default()).matches(&request("/a", None)));
+
+        let now = Instant::now();
+        let status = |authorization| match auth.authenticate(&request("/admin", authorization), now)
+        {
+            Ok(user) => Ok(user),
+            Err(response) => Err(response.status()),
+        };
+        let Err(challenge) = auth.authenticate(&request("/admin", None), now) else {
+            panic!("no credentials were sent");
+        };
+        assert_eq!(
+            challenge.headers().get("WWW-Authenticate"),
+            Some("Basic realm=\"Admin\", charset=\"UTF-8\"")
+        );
+        assert_eq!(status(Some("Bearer abc")), Err(400));
+        assert_eq!(status(Some("Basic bm9jb2xvbg==")), Err(400), "no colon");
+        assert_eq!(
+            status(Some("Basic YWxpY2U6cGFzc3dvcmQ=")),
+            Ok("alice".to_string())
+        );
+        assert_eq!(
+            status(Some("basic Ym9iOnNlY3JldA==")),
+            Ok("bob".to_string())
+        );
+        assert_eq!(status(Some("Basic Ym9iOndyb25n")), Err(401));
+        assert_eq!(
+            status(Some("Basic YWxpY2U6cGFzc3dvcmQ=")),
+            Err(429),
+            "throttled"
+        );
+    }
+
+    #[test]
+    fn test_bearer_tokens() {
+        let tokens = Tokens::parse("# rotating\nold:s3cret\nnew:n3w-s3cret\n").unwrap();
+        assert_eq!(tokens.name_of("s3cret"), Some("old"));
+        assert_eq!(tokens.name_of("n3w-s3cret"), Some("new"));
+        assert_eq!(tokens.name_of("s3cre"), None);
+        assert_eq!(tokens.name_of(""), None);
+        for invalid in ["old", "old:", "old:s3 cret", "old:a\nold:b"] {
+            assert!(Tokens::parse(invalid).is_err(), "{invalid}");
+        }
+
+        let auth = BearerAuth::new(tokens)
+            .protect("/admin/")
+            .protect("/status");
+        let request = |target: &str, authorization: Option<&str>| {
+            let request = Request::new(Method::Get, target, Version::Http11);
+            match authorization {
+                Some(value) => request.with_header("Authorization", value),
+                None => request,
+            }
+        };
+        assert!(auth.matches(&request("/admin/shutdown", None)));
+        assert!(auth.matches(&request("/status?full", None)));
+        assert!(!auth.matches(&request("/statuses", None)));
+        assert!(!auth.matches(&request("/", None)));
+
+        let challenge = |authorization| match auth.authenticate(&request("/status", authorization))
+        {
+            Ok(name) => Ok(name),
+            Err(response) => Err((
+                response.status(),
+                response
+                    .headers()
+                    .get("WWW-Authenticate")
+                    .map(str::to_string),
+            )),
+        };
+        assert_eq!(
+            challenge(None),
+            Err((401, Some("Bearer realm=\"admin\"".to_string())))
+        );
+        assert_eq!(
+            challenge(Some("Basic czNjcmV0")),
+            Err((401, Some("Bearer realm=\"admin\"".to_string())))
+        );
+        assert_eq!(
+            challenge(Some("Bearer wrong")),
+            Err((
+                401,
+                Some("Bearer realm=\"admin\", error=\"invalid_token\"".to_string())
+            ))
+        );
+        assert_eq!(challenge(Some("Bearer s3cret")), Ok("old".to_string()));
+        assert_eq!(challenge(Some("bearer n3w-s3cret")), Ok("new".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+//! The standard base64 alphabet with padding, as `Authorization` headers
+//! and WebSocket handshakes carry it.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// `bytes` encoded, padded.
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| {
+            bits | u32::from(byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The bytes `text` encodes, none if it is not padded base64.
+pub(crate) fn decode(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let padding = text.iter().rev().take_while(|&&byte| byte == b'=').count();
+    if padding > 2 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut bits, mut count) = (0u32, 0);
+    for &byte in &text[..text.len() - padding] {
+        let value = ALPHABET.iter().position(|&symbol| symbol == byte)?;
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    // The bits left over from the last symbol must be zero.
+    (bits & ((1 << count) - 1) == 0).then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("").as_deref(), Some(&b""[..]));
+        assert_eq!(decode("YQ==").as_deref(), Some(&b"a"[..]));
+        assert_eq!(decode("YWI=").as_deref(), Some(&b"ab"[..]));
+        assert_eq!(
+            decode("YWxpY2U6c2VjcmV0").as_deref(),
+            Some(&b"alice:secret"[..])
+        );
+        assert_eq!(decode("+/8=").as_deref(), Some(&[0xfb, 0xff][..]));
+        for invalid in ["YQ", "YQ=", "Y===", "YR==", "YW!=", "YQ==YQ=="] {
+            assert_eq!(decode(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        for (bytes, text) in [
+            (&b""[..], ""),
+            (b"a", "YQ=="),
+            (b"ab", "YWI="),
+            (b"abc", "YWJj"),
+            (&[0xfb, 0xff], "+/8="),
+        ] {
+            assert_eq!(encode(bytes), text);
+            assert_eq!(decode(text).as_deref(), Some(bytes));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! A pool of byte buffers reused across connections, and a reader buffering into them.
+
+use std::{
+    io::{self, BufRead, Read, Write},
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Recycles buffers so that connections do not allocate their own.
+///
+/// Clones share their buffers.
+#[derive(Debug, Clone)]
+pub struct BufferPool {
+    max_pooled: usize,
+    max_capacity: usize,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Counters describing how well a [`BufferPool`] is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Checkouts served by a pooled buffer.
+    pub hits: u64,
+    /// Checkouts which had to allocate.
+    pub misses: u64,
+    /// The capacity of the buffers waiting in the pool, in bytes.
+    pub retained_bytes: usize,
+}
+
+impl Default for BufferPool {
+    fn default() -> BufferPool {
+        BufferPool::new()
+    }
+}
+
+impl BufferPool {
+    /// Keep up to 64 buffers of at most 64 KiB each.
+    pub fn new() -> BufferPool {
+        BufferPool {
+            max_pooled: 64,
+            max_capacity: 64 * 1024,
+            shared: Arc::default(),
+        }
+    }
+
+    /// Keep at most `count` buffers waiting in the pool.
+    pub fn max_pooled(mut self, count: usize) -> BufferPool {
+        self.max_pooled = count;
+        self
+    }
+
+    /// Drop returned buffers which grew beyond `bytes` instead of keeping them.
+    pub fn max_capacity(mut self, bytes: usize) -> BufferPool {
+        self.max_capacity = bytes;
+        self
+    }
+
+    /// An empty buffer with room for at least `capacity` bytes, which returns to
+    /// the pool when dropped.
+    pub fn get(&self, capacity: usize) -> PooledBuffer {
+        let pooled = self.buffers().pop();
+        let mut buffer = match pooled {
+            Some(buffer) => {
+                self.shared.hits.fetch_add(1, Ordering::Relaxed);
+                buffer
+            }
+            None => {
+                self.shared.misses.fetch_add(1, Ordering::Relaxed);
+                Vec::new()
+            }
+        };
+        buffer.reserve(capacity);
+        PooledBuffer {
+            buffer,
+            pool: self.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            hits: self.shared.hits.load(Ordering::Relaxed),
+            misses: self.shared.misses.load(Ordering::Relaxed),
+            retained_bytes: self.buffers().iter().map(Vec::capacity).sum(),
+        }
+    }
+
+    fn buffers(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.shared
+            .buffers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > self.max_capacity {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers();
+        if buffers.len() < self.max_pooled {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// A buffer checked out of a [`BufferPool`].
+#[derive(Debug)]
+pub struct PooledBuffer {
+    buffer: Vec<u8>,
+    pool: BufferPool,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.buffer));
+    }
+}
+
+/// Buffers reads from `inner` like [`io::BufReader`], in a pooled buffer.
+#[derive(Debug)]
+pub struct PooledReader<R> {
+    inner: R,
+    buffer: PooledBuffer,
+    pos: usize,
+    filled: usize,
+}
+
+impl<R: Read> PooledReader<R> {
+    /// Read from `inner` into `buffer`, which is zeroed to its capacity.
+    pub fn new(inner: R, mut buffer: PooledBuffer) -> PooledReader<R> {
+        let capacity = buffer.capacity();
+        buffer.resize(capacity, 0);
+        PooledReader {
+            inner,
+            buffer,
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The bytes read ahead which have not been consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer[self.pos..self.filled]
+    }
+}
+
+impl<R: Read> Read for PooledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.filled && buf.len() >= self.buffer.len() {
+            return self.inner.read(buf);
+        }
+        let read = self.fill_buf()?.read(buf)?;
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+/// Writes go straight to `inner`, so that one value can read and write a
+/// connection.
+impl<R: Read + Write> Write for PooledReader<R> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> BufRead for PooledReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(&mut self.buffer)?;
+            self.pos = 0;
+        }
+        Ok(&self.buffer[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.filled);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Cursor, thread};
+
+    #[test]
+    fn test_buffers_are_reused_within_limits() {
+        let pool = BufferPool::new().max_pooled(1).max_capacity(1024);
+        drop(pool.get(512));
+        drop(pool.get(512));
+        let kept = pool.stats();
+        assert_eq!((kept.hits, kept.misses), (1, 1));
+        assert!(kept.retained_bytes >= 512);
+
+        drop((pool.get(512), pool.get(512)));
+        assert!(
+            pool.stats().retained_bytes <= 1024,
+            "only one buffer is kept"
+        );
+
+        drop(pool.get(4096));
+        assert_eq!(pool.stats().retained_bytes, 0, "large buffers are dropped");
+    }
+
+    #[test]
+    fn test_recycled_buffers_hold_no_data() {
+        let pool = BufferPool::new();
+        let workers: Vec<_> = (0..8u8)
+            .map(|id| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        let input = Cursor::new(vec![id; 64]);
+                        let mut reader = PooledReader::new(input, pool.get(256));
+                        assert!(reader.buffer().is_empty());
+                        let mut contents = Vec::new();
+                        reader.read_to_end(&mut contents).unwrap();
+                        assert_eq!(contents, [id; 64]);
+
+                        let written = pool.get(256);
+                        assert!(written.is_empty());
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(pool.stats().hits > 0);
+    }
+}
@@ -0,0 +1,72 @@
+//! `Cache-Control` values chosen by path pattern.
+
+use std::path::Path;
+
+use crate::glob::glob_match;
+
+/// Maps path patterns to the `Cache-Control` value sent with matching files.
+///
+/// Patterns starting with `/` are matched against the request path, where `*`
+/// matches within one path segment and `**` matches across segments. Other
+/// patterns, such as `*.html`, are matched against the served file's name.
+/// When several patterns match, the one with the most literal characters wins.
+#[derive(Debug, Clone, Default)]
+pub struct CachePolicy {
+    rules: Vec<(String, String)>,
+}
+
+impl CachePolicy {
+    /// Create a policy without rules, which sends no `Cache-Control` at all.
+    pub fn new() -> CachePolicy {
+        CachePolicy { rules: Vec::new() }
+    }
+
+    /// Send `value` for files matching `pattern`.
+    pub fn rule(mut self, pattern: &str, value: &str) -> CachePolicy {
+        self.rules.push((pattern.to_string(), value.to_string()));
+        self
+    }
+
+    /// The value for a request for `request_path` which is served from `file`.
+    pub fn lookup(&self, request_path: &str, file: &Path) -> Option<&str> {
+        let file_name = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let mut best: Option<(usize, &str)> = None;
+        for (pattern, value) in &self.rules {
+            let matched = if pattern.starts_with('/') {
+                glob_match(pattern.as_bytes(), request_path.as_bytes())
+            } else {
+                glob_match(pattern.as_bytes(), file_name.as_bytes())
+            };
+            let specificity = pattern.bytes().filter(|b| *b != b'*').count();
+            if matched && best.is_none_or(|(best, _)| specificity > best) {
+                best = Some((specificity, value));
+            }
+        }
+        best.map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_pattern_wins() {
+        let policy = CachePolicy::new()
+            .rule("/static/**", "public, max-age=60")
+            .rule("/static/*.css", "public, max-age=31536000, immutable")
+            .rule("*.html", "no-cache");
+
+        let lookup = |path: &str| policy.lookup(path, Path::new(path.trim_start_matches('/')));
+        assert_eq!(
+            lookup("/static/app.css"),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(lookup("/static/logo.png"), Some("public, max-age=60"));
+        assert_eq!(lookup("/index.html"), Some("no-cache"));
+        assert_eq!(lookup("/robots.txt"), None);
+    }
+}
@@ -0,0 +1,421 @@
+//! Running the executables in a directory as CGI scripts for the requests
+//! under a path prefix.
+
+use std::{
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    config,
+    http::{self, Method, Request},
+    response::{Body, Response, StreamBody},
+    spool::RequestBody,
+    vhost,
+};
+
+/// The largest header block accepted from a script, in bytes.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// How often a running script is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The headers of a script's output which the server sets itself.
+const SET_BY_SERVER: [&str; 4] = [
+    "Status",
+    "Content-Length",
+    "Connection",
+    "Transfer-Encoding",
+];
+
+/// Runs the scripts in a directory for the requests under a prefix, with the
+/// CGI/1.1 environment.
+///
+/// Clones share the count of running scripts.
+#[derive(Debug, Clone)]
+pub struct Cgi {
+    prefix: String,
+    dir: PathBuf,
+    timeout: Duration,
+    max_processes: usize,
+    running: Arc<AtomicUsize>,
+}
+
+impl Cgi {
+    /// Run the scripts in `dir` for the requests under `prefix`: with the
+    /// prefix `/cgi-bin`, `/cgi-bin/hello/extra` runs `dir/hello` with the
+    /// path info `/extra`.
+    pub fn new(prefix: &str, dir: impl Into<PathBuf>) -> Cgi {
+        Cgi {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            dir: dir.into(),
+            timeout: Duration::from_secs(30),
+            max_processes: 16,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Kill scripts still running after `timeout`, 30 seconds by default;
+    /// those which did not print their headers yet are answered with 504.
+    pub fn timeout(mut self, timeout: Duration) -> Cgi {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run at most `max` scripts at once, answering 503 beyond, 16 by default.
+    pub fn max_processes(mut self, max: usize) -> Cgi {
+        self.max_processes = max;
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The scripts running now.
+    pub fn running(&self) -> usize {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Whether the path of `request` is the prefix or below it.
+    pub fn matches(&self, request: &Request) -> bool {
+        request
+            .path()
+            .strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// Whether the path of `request` names a script which can be run.
+    pub fn has_script(&self, request: &Request) -> bool {
+        self.script(request.path()).is_some()
+    }
+
+    /// Run the script `request` names, piping `body` to its standard input
+    /// on a thread of its own, and answer with what it prints.
+    ///
+    /// The body is read ahead, as scripts take its length first and may
+    /// print before they read it.
+    ///
+    /// The output starts with a header block, where `Status` sets the status
+    /// and `Location` alone redirects; the rest is streamed as the body until
+    /// the script exits. Scripts which are missing or not executable are
+    /// answered with 404, and those which print no valid headers with 502.
+    pub fn run(&self, request: &Request, body: &RequestBody) -> Response {
+        let Some((name, info)) = self.script(request.path()) else {
+            return Response::builtin_error(404);
+        };
+        let Some(slot) = Slot::take(&self.running, self.max_processes) else {
+            warn!(
+                "Turned away {} with {} scripts running",
+                request.target(),
+                self.max_processes
+            );
+            return Response::builtin_error(503);
+        };
+
+        // Relative paths would be taken from the directory the script runs in.
+        let file = self.dir.join(name);
+        let file = std::path::absolute(&file).unwrap_or(file);
+        let mut command = Command::new(&file);
+        // A group of its own, so that killing it reaches what it started.
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        let spawned = command
+            .env_clear()
+            .envs(self.environment(request, name, info, body.len()))
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(err) => {
+                warn!("Could not run {}: {err}", file.display());
+                return Response::builtin_error(500);
+            }
+        };
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            unreachable!("both are piped");
+        };
+        let timed_out = Arc::new(AtomicBool::new(false));
+        if let Err(err) = self.watch(child, name, Arc::clone(&timed_out), slot) {
+            warn!("Killed {name}, as no thread could be spawned to watch it: {err}");
+            return Response::builtin_error(503);
+        }
+
+        // While the output is read here, so that neither pipe fills up with
+        // the script waiting on the other.
+        let (input, script) = (body.clone(), name.to_string());
+        let fed = thread::Builder::new()
+            .name("cgi-stdin".to_string())
+            .spawn(move || {
+                // Scripts need not read their input, which only fails the copy.
+                let copied = input
+                    .reader()
+                    .and_then(|mut input| io::copy(&mut input, &mut stdin));
+                if let Err(err) = copied {
+                    debug!("{script} did not take the whole body: {err}");
+                }
+            });
+        if let Err(err) = fed {
+            warn!("Ran {name} without its input, as no thread could be spawned: {err}");
+        }
+
+        let mut output = BufReader::new(stdout);
+        let headers = match http::read_header_block(&mut output, MAX_HEAD_SIZE) {
+            Ok(headers) if !headers.is_empty() => headers,
+            result => {
+                if timed_out.load(Ordering::SeqCst) {
+                    return Response::builtin_error(504);
+                }
+                let reason = result
+                    .err()
+                    .map_or("none".to_string(), |err| err.to_string());
+                warn!("{name} printed no valid headers: {reason}");
+                return Response::builtin_error(502);
+            }
+        };
+        let status = match headers.get("Status") {
+            Some(status) => status.split(' ').next().and_then(|code| code.parse().ok()),
+            None if headers.contains("Location") => Some(302),
+            None => Some(200),
+        };
+        let Some(status @ 100..=999) = status else {
+            warn!("{name} printed an invalid status");
+            return Response::builtin_error(502);
+        };
+
+        let mut response = Response::new(status);
+        for (name, value) in headers.iter() {
+            if !SET_BY_SERVER
+                .iter()
+                .any(|set| set.eq_ignore_ascii_case(name))
+            {
+                response.headers_mut().append(name, value);
+            }
+        }
+        if request.method() != &Method::Head && !matches!(status, 204 | 304) {
+            let length = headers
+                .get("Content-Length")
+                .and_then(|length| length.parse().ok());
+            response = response.with_body(Body::Stream(StreamBody::new(output, length)));
+        }
+        response
+    }
+
+    /// The name of the script `path` runs and the path info after it, if the
+    /// script is an executable file. Dotfiles are never run.
+    fn script<'a>(&self, path: &'a str) -> Option<(&'a str, &'a str)> {
+        let rest = path.strip_prefix(self.prefix.as_str())?.strip_prefix('/')?;
+        let (name, info) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        if name.is_empty() || name.starts_with('.') {
+            return None;
+        }
+        is_executable(&self.dir.join(name)).then_some((name, info))
+    }
+
+    /// The CGI/1.1 variables for running `name` with the path `info` for
+    /// `request`, whose body is `length` bytes long.
+    fn environment(
+        &self,
+        request: &Request,
+        name: &str,
+        info: &str,
+        length: u64,
+    ) -> Vec<(String, String)> {
+        let query = request.query();
+        let mut variables: Vec<(String, String)> = [
+            ("GATEWAY_INTERFACE", "CGI/1.1"),
+            ("SERVER_SOFTWARE", config::SERVER),
+            ("SERVER_PROTOCOL", request.version().as_str()),
+            ("REQUEST_METHOD", request.method().as_str()),
+            ("PATH_INFO", info),
+            ("QUERY_STRING", query),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        variables.push(("SCRIPT_NAME".to_string(), format!("{}/{name}", self.prefix)));
+        if let Some(host) = vhost::host(request) {
+            variables.push(("SERVER_NAME".to_string(), host));
+        }
+        if let Some(ip) = request.client_ip() {
+            variables.push(("REMOTE_ADDR".to_string(), ip.to_string()));
+        }
+        if length > 0 {
+            variables.push(("CONTENT_LENGTH".to_string(), length.to_string()));
+        }
+        if let Some(path) = std::env::var_os("PATH") {
+            variables.push(("PATH".to_string(), path.to_string_lossy().into_owned()));
+        }
+        for (field, value) in request.headers().iter() {
+            let variable = match field.to_ascii_uppercase().replace('-', "_") {
+                content if content == "CONTENT_TYPE" => content,
+                // The length is the one read, and HTTP_PROXY must never be set
+                // from a header, as programs take it for their proxy.
+                length if length == "CONTENT_LENGTH" => continue,
+                proxy if proxy == "PROXY" => continue,
+                other => format!("HTTP_{other}"),
+            };
+            match variables.iter_mut().find(|(name, _)| *name == variable) {
+                Some((_, joined)) => {
+                    joined.push_str(", ");
+                    joined.push_str(value);
+                }
+                None => variables.push((variable, value.to_string())),
+            }
+        }
+        variables
+    }
+
+    /// Wait for `child` on a thread of its own, killing it once it runs out
+    /// of time, and free its `slot` when it exited. Without a thread, it is
+    /// killed straight away.
+    fn watch(
+        &self,
+        mut child: Child,
+        name: &str,
+        timed_out: Arc<AtomicBool>,
+        slot: Slot,
+    ) -> io::Result<()> {
+        let deadline = Instant::now() + self.timeout;
+        let name = name.to_string();
+        // The child is handed over once the thread exists, so that it is
+        // still here to be killed if it does not.
+        let (sender, receiver) = mpsc::channel::<Child>();
+        let watching = thread::Builder::new()
+            .name("cgi-watch".to_string())
+            .spawn(move || {
+                let _slot = slot;
+                let Ok(mut child) = receiver.recv() else {
+                    return;
+                };
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(status)) if status.success() => return,
+                        Ok(Some(status)) => return debug!("{name} exited with {status}"),
+                        Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+                        Ok(None) | Err(_) => break,
+                    }
+                }
+                timed_out.store(true, Ordering::SeqCst);
+                warn!("Killing {name}, which ran out of time");
+                kill(&mut child);
+                let _ = child.wait();
+            });
+        match watching {
+            Ok(_) => {
+                let _ = sender.send(child);
+                Ok(())
+            }
+            Err(err) => {
+                kill(&mut child);
+                let _ = child.wait();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A place among the scripts allowed to run at once, given back on drop.
+#[derive(Debug)]
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(running: &Arc<AtomicUsize>, max: usize) -> Option<Slot> {
+        running
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .ok()?;
+        Some(Slot(Arc::clone(running)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Kill `child` and the processes it started, which could otherwise keep its
+/// output open.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: sending a signal touches no memory of this process.
+        unsafe { libc::kill(-group, libc::SIGKILL) };
+    }
+    let _ = child.kill();
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Version, test_util::TempDir};
+
+    #[test]
+    fn test_scripts_and_their_environment() {
+        let dir = TempDir::new();
+        dir.write("plain.txt", "not a script");
+        let cgi = Cgi::new("/cgi-bin/", dir.path());
+        let get = |target| Request::new(Method::Get, target, Version::Http11);
+        assert!(cgi.matches(&get("/cgi-bin/run?x=1")));
+        assert!(!cgi.matches(&get("/cgi-binary")));
+        if cfg!(unix) {
+            assert_eq!(cgi.script("/cgi-bin/plain.txt"), None);
+        }
+        assert_eq!(cgi.script("/cgi-bin/missing/x"), None);
+        assert_eq!(cgi.script("/cgi-bin/../cgi-bin/x"), None);
+
+        let request = get("/cgi-bin/run/a/b?x=1&y=2")
+            .with_header("Host", "a.test:8080")
+            .with_header("Content-Type", "text/plain")
+            .with_header("Content-Length", "3")
+            .with_header("Proxy", "http://evil.test")
+            .with_header("Accept", "text/plain")
+            .with_header("accept", "text/html")
+            .with_client_ip("192.0.2.1".parse().ok());
+        let variables = cgi.environment(&request, "run", "/a/b", 3);
+        let variable = |name: &str| {
+            variables
+                .iter()
+                .find(|(variable, _)| variable == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(variable("REQUEST_METHOD"), Some("GET"));
+        assert_eq!(variable("SCRIPT_NAME"), Some("/cgi-bin/run"));
+        assert_eq!(variable("PATH_INFO"), Some("/a/b"));
+        assert_eq!(variable("QUERY_STRING"), Some("x=1&y=2"));
+        assert_eq!(variable("SERVER_NAME"), Some("a.test"));
+        assert_eq!(variable("REMOTE_ADDR"), Some("192.0.2.1"));
+        assert_eq!(variable("CONTENT_LENGTH"), Some("3"));
+        assert_eq!(variable("CONTENT_TYPE"), Some("text/plain"));
+        assert_eq!(variable("HTTP_ACCEPT"), Some("text/plain, text/html"));
+        assert_eq!(variable("HTTP_HOST"), Some("a.test:8080"));
+        assert_eq!(variable("HTTP_PROXY"), None);
+        assert_eq!(variable("HTTP_CONTENT_LENGTH"), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! Faults injected into responses on purpose, to see how clients cope with
+//! a server which misbehaves.
+//!
+//! Each request whose path matches is picked with a probability, and then
+//! gets one of the configured faults, chosen at random: added latency, the
+//! connection closed after the head, a `500` or `503` instead of the answer,
+//! or a body cut short. Every fault is logged with the request id, and a seed
+//! makes the choices repeat from run to run.
+
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io::{self, Write},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use log::info;
+
+use crate::{glob::path_matches, http::Request, response::Response};
+
+/// The kinds of fault which can be injected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FaultKind {
+    /// Answering only after the configured latency.
+    Latency,
+    /// Closing the connection once the head is sent.
+    Close,
+    /// `500 Internal Server Error` instead of the answer.
+    #[cfg_attr(feature = "config", serde(rename = "500"))]
+    InternalError,
+    /// `503 Service Unavailable` instead of the answer.
+    #[cfg_attr(feature = "config", serde(rename = "503"))]
+    Unavailable,
+    /// Closing the connection halfway through the body.
+    Truncate,
+}
+
+impl FaultKind {
+    /// Every kind, in order.
+    pub const ALL: [FaultKind; 5] = [
+        FaultKind::Latency,
+        FaultKind::Close,
+        FaultKind::InternalError,
+        FaultKind::Unavailable,
+        FaultKind::Truncate,
+    ];
+}
+
+/// The fault injected into one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Latency(Duration),
+    Close,
+    Status(u16),
+    Truncate,
+}
+
+impl Fault {
+    /// The response sent instead of the answer, for the faults which have one.
+    pub fn response(self) -> Option<Response> {
+        match self {
+            Fault::Status(status) => {
+                Some(Response::builtin_error(status).with_header("Connection", "close"))
+            }
+            _ => None,
+        }
+    }
+
+    /// How many bytes of `response` to write before closing the connection,
+    /// for the faults which cut it short: its head for [`Fault::Close`], and
+    /// half its body as well for [`Fault::Truncate`], or none of a body whose
+    /// length is not known.
+    pub fn cutoff(self, response: &Response) -> Option<u64> {
+        let head = response.head_len();
+        match self {
+            Fault::Close => Some(head),
+            Fault::Truncate => Some(head + response.content_length().unwrap_or(0) / 2),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::Latency(delay) => write!(f, "{delay:?} of latency"),
+            Fault::Close => f.write_str("a close after the head"),
+            Fault::Status(status) => write!(f, "a {status}"),
+            Fault::Truncate => f.write_str("a truncated body"),
+        }
+    }
+}
+
+/// Which requests get faults, which ones, and the random choices between them.
+#[derive(Debug)]
+pub struct Chaos {
+    probability: f64,
+    paths: Vec<String>,
+    faults: Vec<FaultKind>,
+    latency: Duration,
+    jitter: Duration,
+    rng: Mutex<u64>,
+}
+
+impl Chaos {
+    /// Inject any fault into each request with `probability`, from 0 to 1,
+    /// with a second of latency and a random seed.
+    pub fn new(probability: f64) -> Chaos {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Chaos {
+            probability,
+            paths: Vec::new(),
+            faults: FaultKind::ALL.to_vec(),
+            latency: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+            rng: Mutex::new(hasher.finish()),
+        }
+    }
+
+    /// Only inject faults into requests for paths matching one of `patterns`,
+    /// like `/api/**`, instead of all of them.
+    pub fn paths(mut self, patterns: &[&str]) -> Chaos {
+        self.paths = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        self
+    }
+
+    /// Pick the fault injected from `faults` only, each as likely.
+    pub fn faults(mut self, faults: &[FaultKind]) -> Chaos {
+        self.faults = faults.to_vec();
+        self
+    }
+
+    /// Add `latency` and up to `jitter` more, at random, for latency faults.
+    pub fn latency(mut self, latency: Duration, jitter: Duration) -> Chaos {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    /// Make the random choices from `seed`, so that a run can be repeated.
+    pub fn seed(mut self, seed: u64) -> Chaos {
+        self.rng = Mutex::new(seed);
+        self
+    }
+
+    /// The fault to inject into `request`, if any, which is logged.
+    pub fn decide(&self, request: &Request) -> Option<Fault> {
+        let path = request.path();
+        let matches =
+            self.paths.is_empty() || self.paths.iter().any(|pattern| path_matches(pattern, path));
+        if !matches || self.faults.is_empty() {
+            return None;
+        }
+        let mut rng = self.rng.lock().unwrap_or_else(PoisonError::into_inner);
+        if unit(next(&mut rng)) >= self.probability {
+            return None;
+        }
+        let kind = self.faults[(next(&mut rng) % self.faults.len() as u64) as usize];
+        let fault = match kind {
+            FaultKind::Latency => {
+                Fault::Latency(self.latency + self.jitter.mul_f64(unit(next(&mut rng))))
+            }
+            FaultKind::Close => Fault::Close,
+            FaultKind::InternalError => Fault::Status(500),
+            FaultKind::Unavailable => Fault::Status(503),
+            FaultKind::Truncate => Fault::Truncate,
+        };
+        info!(
+            "Chaos: injecting {fault} into {} {} ({}).",
+            request.method(),
+            request.target(),
+            request.id()
+        );
+        Some(fault)
+    }
+}
+
+/// The next number of the xorshift64* generator with `state`.
+fn next(state: &mut u64) -> u64 {
+    // Zero would stay zero.
+    if *state == 0 {
+        *state = 0x9e37_79b9_7f4a_7c15;
+    }
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// `random` as a number from 0 up to 1.
+fn unit(random: u64) -> f64 {
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// A writer taking `remaining` bytes, then failing as a closed connection.
+#[derive(Debug)]
+pub struct Cutoff<W> {
+    inner: W,
+    remaining: u64,
+}
+
+impl<W: Write> Cutoff<W> {
+    pub fn new(inner: W, remaining: u64) -> Cutoff<W> {
+        Cutoff { inner, remaining }
+    }
+}
+
+impl<W: Write> Write for Cutoff<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "cut off by chaos",
+            ));
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let written = self.inner.write(&buf[..len])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+
+    fn request(path: &str) -> Request {
+        Request::new(Method::Get, path, Version::Http11).with_id("test-id")
+    }
+
+    #[test]
+    fn test_seeded_decisions_repeat() {
+        let decisions = |seed| {
+            let chaos = Chaos::new(0.5)
+                .latency(Duration::from_millis(10), Duration::from_millis(5))
+                .seed(seed);
+            (0..64)
+                .map(|_| chaos.decide(&request("/")))
+                .collect::<Vec<_>>()
+        };
+        let first = decisions(7);
+        assert_eq!(first, decisions(7));
+        assert_ne!(first, decisions(8));
+        let injected = first.iter().flatten().count();
+        assert!((16..48).contains(&injected), "about half: {injected}");
+        for fault in first.iter().flatten() {
+            if let Fault::Latency(delay) = fault {
+                assert!((Duration::from_millis(10)..=Duration::from_millis(15)).contains(delay));
+            }
+        }
+        let kinds = FaultKind::ALL.map(|kind| {
+            let chaos = Chaos::new(1.0)
+                .faults(&[kind])
+                .latency(Duration::ZERO, Duration::ZERO);
+            chaos.decide(&request("/"))
+        });
+        assert_eq!(
+            kinds,
+            [
+                Some(Fault::Latency(Duration::ZERO)),
+                Some(Fault::Close),
+                Some(Fault::Status(500)),
+                Some(Fault::Status(503)),
+                Some(Fault::Truncate),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_only_matching_paths() {
+        let chaos = Chaos::new(1.0).paths(&["/api/**"]).seed(1);
+        assert!(chaos.decide(&request("/api/users/1")).is_some());
+        assert!(chaos.decide(&request("/index.html")).is_none());
+        assert!(Chaos::new(0.0).decide(&request("/api")).is_none());
+    }
+
+    #[test]
+    fn test_cutoff() -> Result<(), Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        let mut cut = Cutoff::new(&mut output, 4);
+        assert_eq!(cut.write(b"abcdef")?, 4);
+        let err = cut.write_all(b"gh").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+        assert_eq!(output, b"abcd");
+        Ok(())
+    }
+}
@@ -0,0 +1,531 @@
+//! Content-coding negotiation and on-the-fly compression of responses.
+
+use std::io::{self, Write};
+
+use crate::{
+    glob,
+    http::Request,
+    response::{Body, ChunkedBody, Response},
+};
+
+/// The content codings the server can send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// The token used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn token(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// Whether responses can be compressed with this coding on the fly, which
+    /// depends on the cargo features the crate was built with.
+    pub fn is_compiled_in(&self) -> bool {
+        match self {
+            Encoding::Gzip => cfg!(feature = "gzip"),
+            Encoding::Brotli => cfg!(feature = "brotli"),
+        }
+    }
+
+    /// The extension of precompressed sidecar files in this coding.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// Whether the `Accept-Encoding` header `header` allows `encoding`.
+///
+/// An explicit entry for the coding takes precedence over `*`, and a quality of
+/// zero marks a coding as unacceptable.
+pub fn accepts(header: Option<&str>, encoding: Encoding) -> bool {
+    let header = match header {
+        Some(header) => header,
+        None => return false,
+    };
+    let mut wildcard = None;
+    for element in header.split(',') {
+        let mut params = element.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        let acceptable = params
+            .filter_map(|param| param.trim().split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .is_none_or(|(_, quality)| {
+                quality
+                    .trim()
+                    .parse::<f32>()
+                    .is_ok_and(|quality| quality > 0.0)
+            });
+        if coding.eq_ignore_ascii_case(encoding.token()) {
+            return acceptable;
+        }
+        if coding == "*" {
+            wildcard = Some(acceptable);
+        }
+    }
+    wildcard.unwrap_or(false)
+}
+
+/// When and how responses are compressed on the fly.
+///
+/// Only successful responses of a compressible content type whose size lies
+/// between the minimum and maximum are compressed, and only if they aren't
+/// encoded already and their path isn't excluded.
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    min_size: u64,
+    max_size: u64,
+    types: Vec<String>,
+    preference: Vec<Encoding>,
+    level: Option<u32>,
+    excluded: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig::new()
+    }
+}
+
+impl CompressionConfig {
+    /// Compress text, scripts, JSON and SVG between 1 KiB and 32 MiB.
+    pub fn new() -> CompressionConfig {
+        CompressionConfig {
+            min_size: 1024,
+            max_size: 32 * 1024 * 1024,
+            types: [
+                "text/html",
+                "text/css",
+                "text/plain",
+                "text/javascript",
+                "application/javascript",
+                "application/json",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            preference: vec![Encoding::Brotli, Encoding::Gzip],
+            level: None,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Leave bodies smaller than `bytes` uncompressed.
+    pub fn min_size(mut self, bytes: u64) -> CompressionConfig {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Leave whole bodies larger than `bytes` uncompressed, since they are
+    /// compressed in memory before being sent; streamed ones are compressed
+    /// as they are sent, whatever their size.
+    pub fn max_size(mut self, bytes: u64) -> CompressionConfig {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Compress with `level`, from 0 to 9 for gzip and up to 11 for brotli.
+    ///
+    /// By default gzip uses level 6 and brotli level 5.
+    pub fn level(mut self, level: u32) -> CompressionConfig {
+        self.level = Some(level);
+        self
+    }
+
+    /// Never compress responses to paths matching `pattern`.
+    ///
+    /// Patterns starting with `/` are matched against the whole path, others
+    /// against its last segment, as in [`CachePolicy`](crate::cache::CachePolicy).
+    pub fn exclude(mut self, pattern: &str) -> CompressionConfig {
+        self.excluded.push(pattern.to_string());
+        self
+    }
+
+    /// Compress only these content types, compared without their parameters.
+    pub fn types(mut self, types: &[&str]) -> CompressionConfig {
+        self.types = types.iter().map(|t| t.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// When the client accepts several codings, use the first of `order`, which
+    /// is brotli before gzip by default.
+    pub fn prefer(mut self, order: &[Encoding]) -> CompressionConfig {
+        self.preference = order.to_vec();
+        self
+    }
+
+    /// Compress `response` if it is eligible and the client accepts a coding
+    /// which was compiled in.
+    pub fn apply(&self, request: &Request, mut response: Response) -> io::Result<Response> {
+        let compiled_in = self.preference.iter().any(Encoding::is_compiled_in);
+        if !compiled_in || !self.is_negotiated(request, &response) {
+            return Ok(response);
+        }
+        // Also when the response is sent as it is, and for the `304`s which
+        // stand in for a compressed one.
+        response.headers_mut().add_vary("Accept-Encoding");
+        if !self.is_eligible(request, &response) {
+            return Ok(response);
+        }
+
+        let Some(encoding) = self.negotiate(request) else {
+            return Ok(response);
+        };
+
+        let level = self.level;
+        let body = match std::mem::replace(response.body_mut(), Body::Empty) {
+            // Streamed bodies go out in chunks of what the encoder emits,
+            // their length being unknown until the end.
+            body @ Body::Stream(_) => Body::Chunked(ChunkedBody::writer(move |chunks| {
+                encode(encoding, level, chunks, |mut encoder| {
+                    body.write_to(&mut encoder)
+                })
+                .map(drop)
+            })),
+            Body::Chunked(chunked) => Body::Chunked(chunked.through(move |chunks, write| {
+                encode(encoding, level, chunks, |encoder| {
+                    write(&mut Flushing(encoder))
+                })
+                .map(drop)
+            })),
+            body => Body::Bytes(encode(encoding, level, Vec::new(), |mut encoder| {
+                body.write_to(&mut encoder)
+            })?),
+        };
+        let headers = response.headers_mut();
+        headers.append("Content-Encoding", encoding.token());
+        // The compressed bytes are not those a strong validator vouches for.
+        if let Some(etag) = headers.get("ETag").filter(|etag| etag.starts_with('"')) {
+            let weak = format!("W/{etag}");
+            headers.insert("ETag", weak);
+        }
+        Ok(response.with_body(body))
+    }
+
+    /// The coding an eligible response to `request` is compressed with, the
+    /// first compiled in which the client accepts.
+    pub fn negotiate(&self, request: &Request) -> Option<Encoding> {
+        let accept_encoding = request.header("Accept-Encoding");
+        self.preference
+            .iter()
+            .copied()
+            .find(|e| e.is_compiled_in() && accepts(accept_encoding, *e))
+    }
+
+    /// Whether `response` to `request` is one whose coding depends on the
+    /// client's `Accept-Encoding`, going by its type rather than its size.
+    fn is_negotiated(&self, request: &Request, response: &Response) -> bool {
+        let headers = response.headers();
+        let content_type = headers
+            .get("Content-Type")
+            .and_then(|value| value.split(';').next())
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase();
+        let no_transform = headers
+            .get_all("Cache-Control")
+            .flat_map(|value| value.split(','))
+            .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"));
+
+        matches!(response.status(), 200 | 304)
+            && !headers.contains("Content-Encoding")
+            && !no_transform
+            && self.types.contains(&content_type)
+            && !self
+                .excluded
+                .iter()
+                .any(|pattern| glob::path_matches(pattern, request.path()))
+    }
+
+    /// Whether `response` would be compressed if the client accepted it: a
+    /// negotiated one with a body of a size worth compressing.
+    fn is_eligible(&self, request: &Request, response: &Response) -> bool {
+        let body = response.body();
+        let size = match body {
+            Body::Stream(_) | Body::Chunked(_) => {
+                !body.has_known_length() || body.len() >= self.min_size
+            }
+            _ => (self.min_size..=self.max_size).contains(&body.len()),
+        };
+        self.is_negotiated(request, response) && response.status() == 200 && size
+    }
+}
+
+/// Passes every write on and flushes it, so that each chunk a handler writes
+/// goes out compressed before the next one is produced.
+struct Flushing<W>(W);
+
+impl<W: Write> Write for Flushing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.0.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Compress what `write` writes with `encoding`, streaming it through the
+/// encoder on to `writer`, which is given back once the coding is finished.
+#[cfg_attr(
+    not(any(feature = "gzip", feature = "brotli")),
+    allow(unused_variables)
+)]
+fn encode<W, F>(encoding: Encoding, level: Option<u32>, writer: W, write: F) -> io::Result<W>
+where
+    W: Write,
+    F: FnOnce(&mut dyn Write) -> io::Result<()>,
+{
+    match encoding {
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip => {
+            let level = flate2::Compression::new(level.unwrap_or(6).min(9));
+            let mut encoder = flate2::write::GzEncoder::new(writer, level);
+            write(&mut encoder)?;
+            encoder.finish()
+        }
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli => {
+            let quality = level.unwrap_or(5).min(11);
+            let mut encoder = brotli::CompressorWriter::new(writer, 4096, quality, 22);
+            write(&mut encoder)?;
+            Ok(encoder.into_inner())
+        }
+        #[allow(unreachable_patterns)]
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} support was not compiled in", encoding.token()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::http::{Method, Version};
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts(Some("gzip, deflate, br"), Encoding::Brotli));
+        assert!(accepts(Some("GZIP;q=0.5"), Encoding::Gzip));
+        assert!(!accepts(Some("gzip;q=0"), Encoding::Gzip));
+        assert!(!accepts(Some("gzip"), Encoding::Brotli));
+        assert!(accepts(Some("*"), Encoding::Brotli));
+        assert!(!accepts(Some("*, br;q=0"), Encoding::Brotli));
+        assert!(!accepts(None, Encoding::Gzip));
+    }
+
+    fn html_request(path: &str) -> Request {
+        Request::new(Method::Get, path, Version::Http11).with_header("Accept-Encoding", "gzip, br")
+    }
+
+    fn html_response(len: usize) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(Body::Bytes(vec![b'a'; len]))
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let config = CompressionConfig::new().min_size(100).max_size(1000);
+        assert!(!config.is_eligible(&html_request("/"), &html_response(99)));
+        assert!(config.is_eligible(&html_request("/"), &html_response(100)));
+        assert!(config.is_eligible(&html_request("/"), &html_response(1000)));
+        assert!(!config.is_eligible(&html_request("/"), &html_response(1001)));
+    }
+
+    #[test]
+    fn test_excluded_paths() {
+        let config = CompressionConfig::new()
+            .exclude("/events")
+            .exclude("*.html");
+        assert!(config.is_eligible(&html_request("/page"), &html_response(2048)));
+        assert!(!config.is_eligible(&html_request("/events"), &html_response(2048)));
+        assert!(!config.is_eligible(&html_request("/docs/index.html"), &html_response(2048)));
+
+        let response = config
+            .apply(&html_request("/events"), html_response(2048))
+            .unwrap();
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.headers().get("Vary"), None);
+    }
+
+    #[cfg(feature = "gzip")]
+    mod gzip {
+        use super::*;
+
+        use std::io::Read;
+
+        use crate::response::StreamBody;
+
+        fn request() -> Request {
+            Request::new(Method::Get, "/", Version::Http11).with_header("Accept-Encoding", "gzip")
+        }
+
+        #[test]
+        fn test_html_is_compressed() -> Result<(), Box<dyn std::error::Error>> {
+            let html = "<p>Hi from Rust</p>\n".repeat(200);
+            let response = Response::new(200)
+                .with_header("Content-Type", "text/html")
+                .with_header("ETag", "\"1\"")
+                .with_body(Body::Bytes(html.clone().into_bytes()));
+
+            let response = CompressionConfig::new().apply(&request(), response)?;
+            assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+            assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+            assert_eq!(response.headers().get("ETag"), Some("W/\"1\""), "weakened");
+            assert!(response.body().len() < html.len() as u64 / 10);
+
+            let compressed = match response.body() {
+                Body::Bytes(bytes) => bytes.clone(),
+                other => panic!("expected a buffered body, got {other:?}"),
+            };
+            let mut decompressed = String::new();
+            flate2::read::GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed)?;
+            assert_eq!(decompressed, html);
+            Ok(())
+        }
+
+        fn decompress(compressed: &[u8]) -> io::Result<String> {
+            let mut decompressed = String::new();
+            flate2::read::GzDecoder::new(compressed).read_to_string(&mut decompressed)?;
+            Ok(decompressed)
+        }
+
+        #[test]
+        fn test_streams_are_compressed_as_they_are_sent() -> Result<(), Box<dyn std::error::Error>>
+        {
+            let html = "<p>Hi from Rust</p>\n".repeat(200);
+            let len = Some(html.len() as u64);
+            let stream = StreamBody::new(io::Cursor::new(html.clone().into_bytes()), len);
+            let lines: Vec<Vec<u8>> = html.split_inclusive('\n').map(Vec::from).collect();
+            let config = CompressionConfig::new().max_size(100);
+            for body in [Body::Stream(stream), Body::Chunked(ChunkedBody::new(lines))] {
+                let response = Response::new(200)
+                    .with_header("Content-Type", "text/html")
+                    .with_body(body);
+                let mut response = config.apply(&request(), response)?;
+                assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+                assert!(response.body().is_chunked(), "{:?}", response.body());
+
+                let Body::Chunked(chunked) = response.body_mut() else {
+                    unreachable!();
+                };
+                chunked.unframed();
+                let mut compressed = Vec::new();
+                response.body().write_to(&mut compressed)?;
+                assert!(compressed.len() < html.len() / 2);
+                assert_eq!(decompress(&compressed)?, html);
+            }
+            Ok(())
+        }
+
+        #[test]
+        fn test_chunks_keep_their_trailers() -> Result<(), Box<dyn std::error::Error>> {
+            let html = "<p>Hi from Rust</p>\n".repeat(200);
+            let body = ChunkedBody::new(vec![html.clone().into_bytes()])
+                .trailer("X-Status", || "done".to_string())?;
+            let response = Response::new(200)
+                .with_header("Content-Type", "text/html")
+                .with_body(Body::Chunked(body));
+            let response = CompressionConfig::new().apply(&request(), response)?;
+
+            let mut output = Vec::new();
+            response.write_to(&mut output)?;
+            let output = String::from_utf8_lossy(&output);
+            assert!(output.contains("Trailer: X-Status\r\n"), "{output}");
+            assert!(
+                output.ends_with("\r\n0\r\nX-Status: done\r\n\r\n"),
+                "{output}"
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn test_small_responses_still_vary() -> Result<(), Box<dyn std::error::Error>> {
+            let config = CompressionConfig::new().min_size(100);
+            let small = config.apply(&request(), html_response(99))?;
+            assert_eq!(small.headers().get("Content-Encoding"), None);
+            assert_eq!(small.headers().get("Vary"), Some("Accept-Encoding"));
+            let unmodified = Response::new(304).with_header("Content-Type", "text/html");
+            let unmodified = config.apply(&request(), unmodified)?;
+            assert_eq!(unmodified.headers().get("Vary"), Some("Accept-Encoding"));
+            Ok(())
+        }
+
+        #[test]
+        fn test_png_is_left_alone() -> Result<(), Box<dyn std::error::Error>> {
+            let response = Response::new(200)
+                .with_header("Content-Type", "image/png")
+                .with_body(Body::Bytes(vec![0; 4096]));
+
+            let response = CompressionConfig::new().apply(&request(), response)?;
+            assert_eq!(response.headers().get("Content-Encoding"), None);
+            assert_eq!(response.body().len(), 4096);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "brotli")]
+    mod brotli {
+        use super::*;
+
+        use std::io::Read;
+
+        fn html_response(html: &str) -> Response {
+            Response::new(200)
+                .with_header("Content-Type", "text/html")
+                .with_body(Body::Bytes(html.as_bytes().to_vec()))
+        }
+
+        #[test]
+        fn test_brotli_round_trip() -> Result<(), Box<dyn std::error::Error>> {
+            let html = "<li>an item in a long list</li>\n".repeat(100);
+            let request = Request::new(Method::Get, "/", Version::Http11)
+                .with_header("Accept-Encoding", "gzip, deflate, br");
+
+            let response = CompressionConfig::new().apply(&request, html_response(&html))?;
+            assert_eq!(response.headers().get("Content-Encoding"), Some("br"));
+
+            let compressed = match response.body() {
+                Body::Bytes(bytes) => bytes.clone(),
+                other => panic!("expected a buffered body, got {other:?}"),
+            };
+            let mut decompressed = String::new();
+            ::brotli::Decompressor::new(&compressed[..], 4096).read_to_string(&mut decompressed)?;
+            assert_eq!(decompressed, html);
+
+            let small = CompressionConfig::new().apply(&request, html_response("<p>tiny</p>"))?;
+            assert_eq!(
+                small.headers().get("Content-Encoding"),
+                None,
+                "the threshold applies to brotli too"
+            );
+            Ok(())
+        }
+
+        #[cfg(feature = "gzip")]
+        #[test]
+        fn test_preference_order() -> Result<(), Box<dyn std::error::Error>> {
+            let html = "<p>Hi from Rust</p>\n".repeat(100);
+            let request = Request::new(Method::Get, "/", Version::Http11)
+                .with_header("Accept-Encoding", "br, gzip");
+
+            let config = CompressionConfig::new().prefer(&[Encoding::Gzip, Encoding::Brotli]);
+            let response = config.apply(&request, html_response(&html))?;
+            assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,1769 @@
+//! Typed server configuration, which can be loaded from a TOML file with the
+//! `config` feature.
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use log::LevelFilter;
+
+use crate::{
+    access::AccessList,
+    access_log::AccessLogFormat,
+    auth::{Credentials, Tokens},
+    chaos::FaultKind,
+    error_pages::ErrorPages,
+    files,
+    compression::{self, Encoding},
+    files::FaviconFallback,
+    forwarded::{ForwardedHeaders, TrustedProxies},
+    http,
+    log_file::Rotation,
+    logging::LogFormat,
+    vhost::UnknownHost,
+};
+
+/// All errors which can occur while loading or validating a configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read.
+    Io(PathBuf, std::io::Error),
+    /// The file is not valid TOML or does not match the expected types.
+    Parse(String),
+    /// The file contains keys which are not settings.
+    UnknownKeys(Vec<String>),
+    /// A setting has a value the server cannot use.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {err}", path.display()),
+            ConfigError::Parse(err) => f.write_str(err),
+            ConfigError::UnknownKeys(keys) => write!(f, "unknown keys {}", keys.join(", ")),
+            ConfigError::Invalid(err) => f.write_str(err),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Everything the server can be configured with, grouped into the sections
+/// of the configuration file.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct Config {
+    pub listener: ListenerConfig,
+    pub pool: PoolConfig,
+    #[cfg_attr(feature = "config", serde(rename = "static"))]
+    pub static_files: StaticConfig,
+    pub routing: RoutingConfig,
+    /// The templates replacing the built-in error pages, by status like
+    /// `404` or class like `5xx`, in an `[error_pages]` table; none by default.
+    pub error_pages: BTreeMap<String, PathBuf>,
+    /// The `Cache-Control` values of static files, by pattern like `*.html`
+    /// or `/static/**` as in [`CachePolicy`](crate::cache::CachePolicy), in a
+    /// `[cache]` table; none by default.
+    pub cache: BTreeMap<String, String>,
+    pub limits: LimitsConfig,
+    pub logging: LoggingConfig,
+    pub daemon: DaemonConfig,
+    pub tls: TlsConfig,
+    pub redirect: RedirectConfig,
+    pub security_headers: SecurityHeadersConfig,
+    pub headers: HeadersConfig,
+    pub cors: CorsConfig,
+    pub proxy: ProxyConfig,
+    pub vhosts: VhostsConfig,
+    /// The path prefixes forwarded to other servers, each in a
+    /// `[[reverse_proxy]]` table; none by default.
+    pub reverse_proxy: Vec<ReverseProxyConfig>,
+    pub cgi: CgiConfig,
+    /// The path prefixes which ask for credentials, each in an `[[auth]]`
+    /// table; none by default.
+    pub auth: Vec<AuthConfig>,
+    pub access_log: AccessLogConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub status: StatusConfig,
+    pub admin: AdminConfig,
+    pub debug: DebugConfig,
+    pub chaos: ChaosConfig,
+    pub dump: DumpConfig,
+    pub well_known: WellKnownConfig,
+    pub rate_limit: RateLimitConfig,
+    pub access: AccessConfig,
+    pub compression: CompressionConfig,
+}
+
+/// Where the server listens.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct ListenerConfig {
+    /// 127.0.0.1 by default.
+    pub addr: IpAddr,
+    /// 7878 by default; 0 lets the OS pick one.
+    pub port: u16,
+    /// More addresses to listen on, each with its own port; none by default.
+    pub extra_addrs: Vec<SocketAddr>,
+    /// Whether `::` also accepts IPv4 clients, true by default; otherwise
+    /// IPv4 gets its own listener on 0.0.0.0.
+    pub dual_stack: bool,
+    /// Whether to bind with `SO_REUSEPORT`, on unix, so that a new instance
+    /// can take over the port; false by default.
+    pub reuse_port: bool,
+    /// A Unix domain socket to listen on as well, on unix; none by default.
+    pub unix_socket: Option<PathBuf>,
+    /// The permissions of the socket file, 0o660 by default.
+    pub unix_socket_mode: u32,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> ListenerConfig {
+        ListenerConfig {
+            addr: IpAddr::from([127, 0, 0, 1]),
+            port: 7878,
+            extra_addrs: Vec::new(),
+            dual_stack: true,
+            reuse_port: false,
+            unix_socket: None,
+            unix_socket_mode: 0o660,
+        }
+    }
+}
+
+/// The worker threads answering connections.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct PoolConfig {
+    /// The number of threads, -1 by default for one per core.
+    pub threads: i32,
+}
+
+impl Default for PoolConfig {
+    fn default() -> PoolConfig {
+        PoolConfig { threads: -1 }
+    }
+}
+
+/// How static files are served.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct StaticConfig {
+    /// The document root, the working directory by default.
+    pub root: PathBuf,
+    /// The files served for requests of a directory, the root's included,
+    /// `index.html` and then `hello.html` by default.
+    pub index: Vec<String>,
+    /// List directories without an index file, off by default.
+    pub autoindex: bool,
+    /// Serve names starting with a dot, off by default.
+    pub dotfiles: bool,
+    /// Render `.md` files as HTML pages, off by default.
+    pub markdown: bool,
+    /// The template rendered pages are put in, with `{{title}}` and
+    /// `{{{content}}}`, a plain page by default.
+    pub markdown_shell: Option<PathBuf>,
+    /// Pass HTML in Markdown files through instead of escaping it, off by
+    /// default.
+    pub markdown_raw_html: bool,
+    /// Isolate the pages from other origins with the COOP, COEP and CORP
+    /// headers on every file, as `SharedArrayBuffer` needs, off by default.
+    pub cross_origin_isolation: bool,
+    /// The codings of the sidecars like `app.js.br` sent instead of a file,
+    /// in order of preference, `br` and then `gzip` by default; none when
+    /// empty.
+    pub precompressed: Vec<String>,
+    /// The charset text files are labelled with, `utf-8` by default.
+    pub charset: String,
+    /// The content types labelled with it, where `text/*` stands for every
+    /// subtype, text, JSON, JavaScript and SVG by default.
+    pub charset_types: Vec<String>,
+    /// What to answer for a missing `/favicon.ico`, `icon` for the built-in
+    /// one by default, `no_content` for 204 or `not_found` for 404.
+    pub favicon: FaviconFallback,
+    /// The ranges one request may ask for before getting the whole file
+    /// instead, 16 by default.
+    pub max_ranges: usize,
+}
+
+impl Default for StaticConfig {
+    fn default() -> StaticConfig {
+        StaticConfig {
+            root: PathBuf::from("."),
+            index: files::INDEX.iter().map(|name| name.to_string()).collect(),
+            autoindex: false,
+            dotfiles: false,
+            markdown: false,
+            markdown_shell: None,
+            markdown_raw_html: false,
+            cross_origin_isolation: false,
+            precompressed: vec!["br".to_string(), "gzip".to_string()],
+            charset: "utf-8".to_string(),
+            charset_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            favicon: FaviconFallback::Icon,
+            max_ranges: 16,
+        }
+    }
+}
+
+/// How request paths are matched against the routes.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct RoutingConfig {
+    /// Collapse runs of slashes before matching, on by default; files are
+    /// looked up without them either way.
+    pub merge_slashes: bool,
+}
+
+impl Default for RoutingConfig {
+    fn default() -> RoutingConfig {
+        RoutingConfig {
+            merge_slashes: true,
+        }
+    }
+}
+
+impl StaticConfig {
+    /// The codings of the sidecars, or the first which is not one the
+    /// server knows.
+    pub fn sidecar_encodings(&self) -> Result<Vec<Encoding>, String> {
+        encodings("static.precompressed", &self.precompressed)
+    }
+}
+
+/// The codings named by `tokens`, or the first token, as the value of `key`,
+/// which is not `gzip` or `br`.
+fn encodings(key: &str, tokens: &[String]) -> Result<Vec<Encoding>, String> {
+    tokens
+        .iter()
+        .map(|token| match token.to_ascii_lowercase().as_str() {
+            "gzip" => Ok(Encoding::Gzip),
+            "br" => Ok(Encoding::Brotli),
+            _ => Err(format!("{key} {token:?}")),
+        })
+        .collect()
+}
+
+/// Limits on what clients may send and how long they may take; durations are
+/// given in seconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct LimitsConfig {
+    /// The largest request body in bytes, 1 MiB by default.
+    pub max_body_size: u64,
+    /// The size in bytes past which a request body is spooled to a temporary
+    /// file instead of kept in memory, 1 MiB by default.
+    pub spool_threshold: u64,
+    /// Where spooled bodies go, the system's temporary directory by default.
+    pub spool_dir: Option<PathBuf>,
+    /// The largest request head in bytes, 64 KiB by default.
+    pub max_header_size: usize,
+    /// How long sending the whole request head may take, 10 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub header_timeout: Duration,
+    /// How long sending each part of a request body may take, 30 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub body_timeout: Duration,
+    /// How long accepting each part of a response may take, 30 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub write_timeout: Duration,
+    /// How long a kept-alive connection may wait for another request, 15 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub idle_timeout: Duration,
+    /// How long a handler may take to respond before the client gets 503
+    /// instead; none by default. CGI scripts and proxies have timeouts of
+    /// their own.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "optional_seconds"))]
+    pub handler_timeout: Option<Duration>,
+    /// The number of requests answered on one connection, 100 by default.
+    pub max_requests: usize,
+    /// The number of connections served at once, 256 by default. Up to as many
+    /// again are answered with 503 before the server stops accepting.
+    pub max_connections: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> LimitsConfig {
+        LimitsConfig {
+            max_body_size: 1024 * 1024,
+            spool_threshold: 1024 * 1024,
+            spool_dir: None,
+            max_header_size: 64 * 1024,
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            idle_timeout: Duration::from_secs(15),
+            handler_timeout: None,
+            max_requests: 100,
+            max_connections: 256,
+        }
+    }
+}
+
+/// What the server logs.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct LoggingConfig {
+    /// The most verbose level logged, `info` by default.
+    pub level: LevelFilter,
+    /// `text` by default, or `json` for records, events and access log lines
+    /// as JSON objects; read at startup only.
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> LoggingConfig {
+        LoggingConfig {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+        }
+    }
+}
+
+/// How the process runs; read at startup only.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct DaemonConfig {
+    /// Detach from the terminal into the background, on unix; off by default.
+    pub detach: bool,
+    /// A file to write the process id to while running; none by default.
+    pub pid_file: Option<PathBuf>,
+    /// The file a detached server appends its output to, /dev/null by default.
+    pub log_file: Option<PathBuf>,
+    /// The user to switch to once the listeners are bound, on unix; none by default.
+    pub user: Option<String>,
+    /// The group to switch to, the user's own by default.
+    pub group: Option<String>,
+}
+
+/// Where the server listens for HTTPS, with the `tls` feature; read at
+/// startup only, but for the HSTS policy.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct TlsConfig {
+    /// The addresses to answer HTTPS on, besides the plain ones; none by default.
+    pub addrs: Vec<SocketAddr>,
+    /// The PEM file with the certificate chain, leaf first.
+    pub cert: Option<PathBuf>,
+    /// The PEM file with the certificate's private key.
+    pub key: Option<PathBuf>,
+    /// The `Strict-Transport-Security` policy, which a reload can change.
+    pub hsts: HstsConfig,
+}
+
+/// The `Strict-Transport-Security` header sent with every response over TLS.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct HstsConfig {
+    /// Send the header, off by default.
+    pub enabled: bool,
+    /// How long browsers keep to HTTPS, in seconds; a year by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub max_age: Duration,
+    /// Cover the subdomains as well, off by default.
+    pub include_subdomains: bool,
+    /// Ask to be preloaded into browsers, off by default.
+    pub preload: bool,
+}
+
+impl Default for HstsConfig {
+    fn default() -> HstsConfig {
+        HstsConfig {
+            enabled: false,
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+}
+
+/// Plain HTTP listeners which send every request over to HTTPS.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct RedirectConfig {
+    /// The addresses to redirect on, read at startup only; none by default.
+    pub addrs: Vec<SocketAddr>,
+    /// The host to redirect requests without a `Host` header to; without
+    /// one they are answered with 400.
+    pub canonical_host: Option<String>,
+    /// The port HTTPS is served on, 443 by default.
+    pub https_port: u16,
+}
+
+impl Default for RedirectConfig {
+    fn default() -> RedirectConfig {
+        RedirectConfig {
+            addrs: Vec::new(),
+            canonical_host: None,
+            https_port: 443,
+        }
+    }
+}
+
+/// The security headers added to every response; an empty value leaves the
+/// header out.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct SecurityHeadersConfig {
+    /// Add the headers, off by default.
+    pub enabled: bool,
+    /// `X-Content-Type-Options`, `nosniff` by default.
+    pub content_type_options: String,
+    /// `X-Frame-Options`, `DENY` by default.
+    pub frame_options: String,
+    /// `Referrer-Policy`, `strict-origin-when-cross-origin` by default.
+    pub referrer_policy: String,
+    /// `Content-Security-Policy`, left out by default.
+    pub content_security_policy: String,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> SecurityHeadersConfig {
+        SecurityHeadersConfig {
+            enabled: false,
+            content_type_options: "nosniff".to_string(),
+            frame_options: "DENY".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            content_security_policy: String::new(),
+        }
+    }
+}
+
+/// The headers the server adds to its responses.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct HeadersConfig {
+    /// The `Server` header, `hello_rust_webserver/` and the version by
+    /// default; empty leaves it out.
+    pub server: String,
+    /// The headers added by path, each in a `[[headers.rules]]` table; none
+    /// by default.
+    pub rules: Vec<HeaderRuleConfig>,
+}
+
+impl Default for HeadersConfig {
+    fn default() -> HeadersConfig {
+        HeadersConfig {
+            server: SERVER.to_string(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// Headers added to the responses for the paths matching a pattern. Of the
+/// rules setting a header for a path, the most specific pattern wins.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct HeaderRuleConfig {
+    /// A glob like `/downloads/*`, where `*` stops at `/` and `**` does not.
+    pub path: String,
+    /// The names and values of the headers.
+    pub headers: BTreeMap<String, String>,
+    /// Replace the headers responses already have, off by default.
+    pub force: bool,
+}
+
+/// Which other origins' pages may call the server from a browser.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct CorsConfig {
+    /// Answer cross-origin requests, off by default.
+    pub enabled: bool,
+    /// The origins allowed, like `https://app.example.com`, or `*` for
+    /// every origin; none by default.
+    pub origins: Vec<String>,
+    /// `GET`, `HEAD` and `POST` by default.
+    pub methods: Vec<String>,
+    /// The request headers allowed beyond the simple ones, none by default.
+    pub headers: Vec<String>,
+    /// Allow cookies and credentials from the listed origins, off by default.
+    pub credentials: bool,
+    /// How long browsers may cache the answers to preflights, in seconds;
+    /// none by default, which leaves it to them.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "optional_seconds"))]
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> CorsConfig {
+        CorsConfig {
+            enabled: false,
+            origins: Vec::new(),
+            methods: vec!["GET".to_string(), "HEAD".to_string(), "POST".to_string()],
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+/// What the server believes of a reverse proxy in front of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct ProxyConfig {
+    /// Identify requests by their `X-Request-Id` header when they carry one,
+    /// off by default.
+    pub trust_request_id: bool,
+    /// The address ranges of the proxies whose `X-Forwarded-For` or
+    /// `Forwarded` header names the client, none by default.
+    pub trusted_proxies: Vec<String>,
+}
+
+/// HTTP Basic authentication of the requests below a path prefix.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct AuthConfig {
+    /// The prefix, `/` by default for the whole site.
+    pub prefix: String,
+    /// The realm named to clients, `Restricted` by default.
+    pub realm: String,
+    /// The htpasswd file of bcrypt or SHA-crypt hashes the users are checked
+    /// against; reread on reload.
+    pub htpasswd: PathBuf,
+}
+
+impl Default for AuthConfig {
+    fn default() -> AuthConfig {
+        AuthConfig {
+            prefix: "/".to_string(),
+            realm: "Restricted".to_string(),
+            htpasswd: PathBuf::new(),
+        }
+    }
+}
+
+/// Sites served for the hosts requests name, besides the `[static]` one.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct VhostsConfig {
+    /// What requests for none of the hosts get: `default` serves them from
+    /// `[static]`, which it is by default, `misdirected` answers 421 and
+    /// `not_found` 404.
+    pub unknown_host: UnknownHost,
+    /// The hosts, each in a `[[vhosts.hosts]]` table.
+    pub hosts: Vec<VirtualHostConfig>,
+}
+
+/// One site; the settings left out are those of `[static]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct VirtualHostConfig {
+    /// The host names, `*.` followed by a domain matching its subdomains.
+    pub names: Vec<String>,
+    /// The document root.
+    pub root: PathBuf,
+    pub index: Option<Vec<String>>,
+    pub autoindex: Option<bool>,
+    pub dotfiles: Option<bool>,
+    pub cross_origin_isolation: Option<bool>,
+}
+
+/// A path prefix whose requests are forwarded to an upstream server; durations
+/// are given in seconds.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct ReverseProxyConfig {
+    /// The prefix, matching itself and the paths below it.
+    pub prefix: String,
+    /// The addresses of the upstream servers, which take turns.
+    pub upstreams: Vec<SocketAddr>,
+    /// Remove the prefix from the paths sent upstream, off by default.
+    pub strip_prefix: bool,
+    /// How long connecting may take before answering 504, 5 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub connect_timeout: Duration,
+    /// How long the upstream may take with each part of a message, 30 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub timeout: Duration,
+    /// How many other upstreams a request tries when connecting fails, 2 by default.
+    pub max_retries: usize,
+    /// The connect failures in a row which take an upstream out of rotation,
+    /// 3 by default.
+    pub failure_threshold: u32,
+    /// How long an upstream stays out of rotation, 10 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub cooldown: Duration,
+    /// Which headers tell the upstreams about the client: `x_forwarded`
+    /// by default, `forwarded` or `both`.
+    pub forwarded_headers: ForwardedHeaders,
+}
+
+impl Default for ReverseProxyConfig {
+    fn default() -> ReverseProxyConfig {
+        ReverseProxyConfig {
+            prefix: "/".to_string(),
+            upstreams: Vec::new(),
+            strip_prefix: false,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            max_retries: 2,
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(10),
+            forwarded_headers: ForwardedHeaders::XForwarded,
+        }
+    }
+}
+
+/// The CGI scripts run for the requests under a prefix.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct CgiConfig {
+    /// The directory of the scripts; none by default, which runs no scripts.
+    pub dir: Option<PathBuf>,
+    /// The prefix, `/cgi-bin` by default.
+    pub prefix: String,
+    /// How long a script may run before it is killed, 30 seconds by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub timeout: Duration,
+    /// How many scripts may run at once, 16 by default.
+    pub max_processes: usize,
+}
+
+impl Default for CgiConfig {
+    fn default() -> CgiConfig {
+        CgiConfig {
+            dir: None,
+            prefix: "/cgi-bin".to_string(),
+            timeout: Duration::from_secs(30),
+            max_processes: 16,
+        }
+    }
+}
+
+/// The line written for every response; read at startup only.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct AccessLogConfig {
+    /// Write the lines, off by default.
+    pub enabled: bool,
+    /// `common` by default, or `combined` to add the referer and user agent.
+    pub format: AccessLogFormat,
+    /// The file to write the lines to, stdout if none.
+    pub path: Option<PathBuf>,
+    /// Rotate the file before it grows beyond this many bytes; 0, the
+    /// default, never does.
+    pub max_size: u64,
+    /// Rotate the file when the day changes, in UTC.
+    pub daily: bool,
+    /// The number of rotated files kept, 7 by default.
+    pub keep: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> AccessLogConfig {
+        AccessLogConfig {
+            enabled: false,
+            format: AccessLogFormat::Common,
+            path: None,
+            max_size: 0,
+            daily: false,
+            keep: 7,
+        }
+    }
+}
+
+impl AccessLogConfig {
+    /// When the file at [`path`](AccessLogConfig::path) is rotated.
+    pub fn rotation(&self) -> Rotation {
+        let rotation = Rotation::new().daily(self.daily).keep(self.keep);
+        match self.max_size {
+            0 => rotation,
+            bytes => rotation.max_size(bytes),
+        }
+    }
+}
+
+/// The Prometheus metrics endpoint; read at startup only.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct MetricsConfig {
+    /// Answer the metrics, off by default.
+    pub enabled: bool,
+    /// `/metrics` by default.
+    pub path: String,
+    /// A listener of their own to answer the metrics on, instead of on all.
+    pub addr: Option<SocketAddr>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> MetricsConfig {
+        MetricsConfig {
+            enabled: false,
+            path: "/metrics".to_string(),
+            addr: None,
+        }
+    }
+}
+
+/// The probes load balancers send.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct HealthConfig {
+    /// Answer the probes, on by default.
+    pub enabled: bool,
+    /// The liveness probe, `/healthz` by default; empty to leave it out.
+    pub path: String,
+    /// The readiness probe, `/readyz` by default; empty to leave it out.
+    pub ready_path: String,
+    /// Write the probes to the logs like other requests, off by default.
+    pub access_log: bool,
+}
+
+impl Default for HealthConfig {
+    fn default() -> HealthConfig {
+        HealthConfig {
+            enabled: true,
+            path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            access_log: false,
+        }
+    }
+}
+
+/// The JSON status page.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct StatusConfig {
+    /// Answer the page, off by default.
+    pub enabled: bool,
+    /// `/status` by default.
+    pub path: String,
+    /// The address ranges of the clients shown the page, the loopback ones
+    /// by default; an empty list shows it to everyone.
+    pub allow: Vec<String>,
+}
+
+impl Default for StatusConfig {
+    fn default() -> StatusConfig {
+        StatusConfig {
+            enabled: false,
+            path: "/status".to_string(),
+            allow: vec!["127.0.0.0/8".to_string(), "::1".to_string()],
+        }
+    }
+}
+
+/// The bearer tokens asked for on the admin endpoints.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct AdminConfig {
+    /// The file of `name:token` lines, none by default to leave the admin
+    /// paths open; reread on reload.
+    pub tokens: Option<PathBuf>,
+    /// The paths asking for a token, with those below them; `/admin`,
+    /// `/metrics` and `/status` by default.
+    pub paths: Vec<String>,
+    /// Answer `POST /admin/shutdown`, `POST /admin/drain` and the dump
+    /// switches under `/admin/dump/`, off by default.
+    pub endpoints: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> AdminConfig {
+        AdminConfig {
+            tokens: None,
+            paths: vec![
+                "/admin".to_string(),
+                "/metrics".to_string(),
+                "/status".to_string(),
+            ],
+            endpoints: false,
+        }
+    }
+}
+
+/// Behaviour meant for debugging only.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct DebugConfig {
+    /// Echo `TRACE` requests back, with credentials redacted, instead of
+    /// refusing them with 405; off by default.
+    pub trace: bool,
+    /// Watch the document root and reload the HTML pages open in a browser
+    /// once a file under it changes, letting no response be cached; off by
+    /// default.
+    pub dev: bool,
+}
+
+/// Faults injected into responses on purpose, for testing clients.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct ChaosConfig {
+    /// Inject faults, off by default; the binary refuses to outside dev
+    /// builds unless run with `--i-know-what-im-doing`.
+    pub enabled: bool,
+    /// The chance of a fault for each request, 0.1 by default.
+    pub probability: f64,
+    /// Glob patterns of the paths which get faults, like `/api/**`; all of
+    /// them when empty, the default.
+    pub paths: Vec<String>,
+    /// The faults picked from, each as likely: `latency`, `close`, `500`,
+    /// `503` and `truncate`, all of them by default.
+    pub faults: Vec<FaultKind>,
+    /// The latency added, one second by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub latency: Duration,
+    /// Up to how much more latency is added at random, none by default.
+    #[cfg_attr(feature = "config", serde(deserialize_with = "seconds"))]
+    pub jitter: Duration,
+    /// The seed of the random choices, so that a run can be repeated;
+    /// random by default.
+    pub seed: Option<u64>,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> ChaosConfig {
+        ChaosConfig {
+            enabled: false,
+            probability: 0.1,
+            paths: Vec::new(),
+            faults: FaultKind::ALL.to_vec(),
+            latency: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+            seed: None,
+        }
+    }
+}
+
+/// Raw copies of the bytes crossing connections, for debugging clients.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct DumpConfig {
+    /// Dump from the start, off by default; the admin endpoints start and
+    /// stop it while running as long as there is a `dir`.
+    pub enabled: bool,
+    /// The directory the files of each connection go into, none by default.
+    pub dir: Option<PathBuf>,
+    /// The address ranges of the clients dumped; all of them when empty,
+    /// the default.
+    pub clients: Vec<String>,
+    /// The path prefixes whose requests get their connection dumped; all of
+    /// them when empty, the default.
+    pub paths: Vec<String>,
+    /// The bytes dumped of each connection at most, 1 MiB by default.
+    pub max_connection_bytes: u64,
+    /// The bytes dumped in all at most, 64 MiB by default.
+    pub max_total_bytes: u64,
+}
+
+impl Default for DumpConfig {
+    fn default() -> DumpConfig {
+        DumpConfig {
+            enabled: false,
+            dir: None,
+            clients: Vec::new(),
+            paths: Vec::new(),
+            max_connection_bytes: 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// What `/.well-known/` answers, ahead of the routes and the document root.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct WellKnownConfig {
+    /// The directory served at `/.well-known/`, which hidden files are not
+    /// refused for; none by default.
+    pub dir: Option<PathBuf>,
+    /// Documents answered from the configuration, by their path under
+    /// `/.well-known/` like `security.txt`, ahead of the directory; none by
+    /// default.
+    pub documents: BTreeMap<String, WellKnownDocument>,
+}
+
+/// A document under `/.well-known/` given in the configuration.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct WellKnownDocument {
+    pub content: String,
+    /// `text/plain; charset=utf-8` by default.
+    pub content_type: String,
+}
+
+impl Default for WellKnownDocument {
+    fn default() -> WellKnownDocument {
+        WellKnownDocument {
+            content: String::new(),
+            content_type: "text/plain; charset=utf-8".to_string(),
+        }
+    }
+}
+
+/// The default `Server` header.
+pub const SERVER: &str = concat!("hello_rust_webserver/", env!("CARGO_PKG_VERSION"));
+
+/// Token bucket limits on the requests of each client address; read at
+/// startup only.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct RateLimitConfig {
+    /// Answer clients past their limit with 429, off by default.
+    pub enabled: bool,
+    /// The requests per second each client may send, 10 by default.
+    pub rate: f64,
+    /// The requests a client may send at once, 20 by default.
+    pub burst: u32,
+    /// The addresses never limited, the loopback ones by default.
+    pub allow: Vec<IpAddr>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> RateLimitConfig {
+        RateLimitConfig {
+            enabled: false,
+            rate: 10.0,
+            burst: 20,
+            allow: vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+        }
+    }
+}
+
+/// Which client addresses may connect; read at startup only. Denied ranges
+/// win, and once a range is allowed the clients outside all of them are
+/// denied.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct AccessConfig {
+    /// The address ranges allowed, like `10.0.0.0/8`; all of them when
+    /// empty, the default.
+    pub allow: Vec<String>,
+    /// The address ranges denied, none by default.
+    pub deny: Vec<String>,
+    /// Answer denied clients with 403 before closing instead of closing
+    /// right away, off by default.
+    pub respond_forbidden: bool,
+}
+
+impl AccessConfig {
+    /// The access list of these ranges, or the first range which is not one.
+    pub fn access_list(&self) -> Result<AccessList, String> {
+        let list = AccessList::new().respond_forbidden(self.respond_forbidden);
+        let list = self.allow.iter().try_fold(list, |list, cidr| {
+            list.allow(cidr)
+                .map_err(|_| format!("access.allow {cidr:?}"))
+        })?;
+        self.deny.iter().try_fold(list, |list, cidr| {
+            list.deny(cidr).map_err(|_| format!("access.deny {cidr:?}"))
+        })
+    }
+}
+
+/// Which responses are compressed on the fly; read at startup only.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "config", derive(serde::Deserialize), serde(default))]
+pub struct CompressionConfig {
+    /// Compress responses the client accepts a coding for, on by default.
+    pub enabled: bool,
+    /// The smallest body compressed, 1 KiB by default.
+    pub min_size: u64,
+    /// The largest whole body compressed, 32 MiB by default; streamed ones
+    /// are compressed as they are sent, whatever their size.
+    pub max_size: u64,
+    /// The content types compressed, text, scripts, JSON and SVG by default.
+    pub types: Vec<String>,
+    /// The codings in order of preference, `br` and then `gzip` by default.
+    pub prefer: Vec<String>,
+    /// The compression level, 6 for gzip and 5 for brotli by default.
+    pub level: Option<u32>,
+    /// The paths never compressed, none by default.
+    pub exclude: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            enabled: true,
+            min_size: 1024,
+            max_size: 32 * 1024 * 1024,
+            types: [
+                "text/html",
+                "text/css",
+                "text/plain",
+                "text/javascript",
+                "application/javascript",
+                "application/json",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+            prefer: vec!["br".to_string(), "gzip".to_string()],
+            level: None,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// The compression of these settings, or the first coding which is not
+    /// one the server knows.
+    pub fn compression(&self) -> Result<compression::CompressionConfig, String> {
+        let order = encodings("compression.prefer", &self.prefer)?;
+        let types: Vec<&str> = match self.enabled {
+            true => self.types.iter().map(String::as_str).collect(),
+            false => Vec::new(),
+        };
+        let config = compression::CompressionConfig::new()
+            .min_size(self.min_size)
+            .max_size(self.max_size)
+            .types(&types)
+            .prefer(&order);
+        let config = match self.level {
+            Some(level) => config.level(level),
+            None => config,
+        };
+        Ok(self
+            .exclude
+            .iter()
+            .fold(config, |config, pattern| config.exclude(pattern)))
+    }
+}
+
+impl Config {
+    /// The format of the access log lines: JSON when logging as JSON, else
+    /// the one chosen for the access log.
+    pub fn access_log_format(&self) -> AccessLogFormat {
+        match self.logging.format {
+            LogFormat::Json => AccessLogFormat::Json,
+            LogFormat::Text => self.access_log.format,
+        }
+    }
+
+    /// The address the listener binds.
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.listener.addr, self.listener.port)
+    }
+
+    /// Every address to listen on: [`addr`](Config::addr) followed by the
+    /// extra ones, with 0.0.0.0 ahead of `::` when the stacks are separate.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        let addr = self.addr();
+        let separate_v4 = (!self.listener.dual_stack && addr.ip() == Ipv6Addr::UNSPECIFIED)
+            .then(|| SocketAddr::from((Ipv4Addr::UNSPECIFIED, addr.port())));
+        separate_v4
+            .into_iter()
+            .chain(std::iter::once(addr))
+            .chain(self.listener.extra_addrs.iter().copied())
+            .collect()
+    }
+
+    /// Check the settings which their types alone do not restrict enough.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+        if self.pool.threads == 0 || self.pool.threads < -1 {
+            return invalid(format!(
+                "pool.threads must be positive or -1, not {}",
+                self.pool.threads
+            ));
+        }
+        if !self.static_files.root.is_dir() {
+            return invalid(format!(
+                "static.root {} is not a directory",
+                self.static_files.root.display()
+            ));
+        }
+        if let Some(name) = self
+            .static_files
+            .index
+            .iter()
+            .find(|name| name.is_empty() || name.contains('/'))
+        {
+            return invalid(format!("static.index {name:?} is not a file name"));
+        }
+        for host in &self.vhosts.hosts {
+            let Some(first) = host.names.first() else {
+                return invalid("vhosts.hosts needs names for every host".to_string());
+            };
+            if let Some(name) = host.names.iter().find(|name| {
+                let domain = name.strip_prefix("*.").unwrap_or(name);
+                domain.is_empty() || domain.contains(['*', ':', '/'])
+            }) {
+                return invalid(format!("vhosts.hosts name {name:?} is not a host name"));
+            }
+            if !host.root.is_dir() {
+                return invalid(format!(
+                    "vhosts.hosts root {} of {first} is not a directory",
+                    host.root.display()
+                ));
+            }
+        }
+        if let Some(err) = self
+            .error_pages
+            .keys()
+            .find_map(|codes| ErrorPages::new().page(codes, "").err())
+        {
+            return invalid(format!("error_pages {err}"));
+        }
+        if let Some(cidr) = self
+            .proxy
+            .trusted_proxies
+            .iter()
+            .find(|cidr| TrustedProxies::new().trust(cidr).is_err())
+        {
+            return invalid(format!(
+                "proxy.trusted_proxies {cidr:?} is not an address range"
+            ));
+        }
+        for mount in &self.reverse_proxy {
+            if !mount.prefix.starts_with('/') {
+                return invalid(format!(
+                    "reverse_proxy.prefix {:?} does not start with /",
+                    mount.prefix
+                ));
+            }
+            if mount.upstreams.is_empty() {
+                return invalid(format!("reverse_proxy {} needs upstreams", mount.prefix));
+            }
+        }
+        for auth in &self.auth {
+            if !auth.prefix.starts_with('/') {
+                return invalid(format!(
+                    "auth.prefix {:?} does not start with /",
+                    auth.prefix
+                ));
+            }
+            if let Err(err) = Credentials::load(&auth.htpasswd) {
+                return invalid(format!(
+                    "auth.htpasswd {} of {}: {err}",
+                    auth.htpasswd.display(),
+                    auth.prefix
+                ));
+            }
+        }
+        let cors = &self.cors;
+        if cors.credentials && cors.origins.iter().any(|origin| origin == "*") {
+            return invalid("cors.credentials needs cors.origins to list them".to_string());
+        }
+        if let Some(path) = &self.admin.tokens {
+            if let Err(err) = Tokens::load(path) {
+                return invalid(format!("admin.tokens {}: {err}", path.display()));
+            }
+        }
+        if let Some(path) = self.admin.paths.iter().find(|path| !path.starts_with('/')) {
+            return invalid(format!("admin.paths {path:?} does not start with /"));
+        }
+        let protected = self.admin.paths.iter().any(|path| {
+            let path = path.trim_end_matches('/');
+            "/admin/shutdown"
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        if self.admin.endpoints && (self.admin.tokens.is_none() || !protected) {
+            return invalid("admin.endpoints needs admin.tokens on /admin".to_string());
+        }
+        if !self.metrics.path.starts_with('/') {
+            return invalid(format!(
+                "metrics.path {:?} does not start with /",
+                self.metrics.path
+            ));
+        }
+        if !self.status.path.starts_with('/') {
+            return invalid(format!(
+                "status.path {:?} does not start with /",
+                self.status.path
+            ));
+        }
+        if let Some(cidr) = self
+            .status
+            .allow
+            .iter()
+            .find(|cidr| AccessList::new().allow(cidr).is_err())
+        {
+            return invalid(format!("status.allow {cidr:?} is not an address range"));
+        }
+        if self.dump.enabled && self.dump.dir.is_none() {
+            return invalid("dump.enabled needs a dump.dir".to_string());
+        }
+        if let Some(cidr) = self
+            .dump
+            .clients
+            .iter()
+            .find(|cidr| AccessList::new().allow(cidr).is_err())
+        {
+            return invalid(format!("dump.clients {cidr:?} is not an address range"));
+        }
+        if let Some(path) = self.dump.paths.iter().find(|path| !path.starts_with('/')) {
+            return invalid(format!("dump.paths {path:?} does not start with /"));
+        }
+        for rule in &self.headers.rules {
+            if rule.path.is_empty() {
+                return invalid("headers.rules needs a path for every rule".to_string());
+            }
+            for (name, value) in &rule.headers {
+                let framing = ["Connection", "Content-Length", "Transfer-Encoding"]
+                    .iter()
+                    .any(|framing| framing.eq_ignore_ascii_case(name));
+                if !http::is_field_name(name) || framing {
+                    return invalid(format!("headers.rules header {name:?} cannot be set"));
+                }
+                if !http::is_field_value(value) {
+                    return invalid(format!(
+                        "headers.rules value {value:?} of {name} is invalid"
+                    ));
+                }
+            }
+        }
+        if let Some(dir) = self.well_known.dir.as_ref().filter(|dir| !dir.is_dir()) {
+            return invalid(format!(
+                "well_known.dir {} is not a directory",
+                dir.display()
+            ));
+        }
+        for (name, document) in &self.well_known.documents {
+            let segments = name.split('/');
+            if segments
+                .clone()
+                .any(|segment| matches!(segment, "" | "." | ".."))
+            {
+                return invalid(format!(
+                    "well_known.documents {name:?} is not a relative path"
+                ));
+            }
+            if document.content_type.is_empty() || document.content_type.contains(char::is_control)
+            {
+                return invalid(format!(
+                    "well_known.documents {name:?} has no valid content_type"
+                ));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.chaos.probability) {
+            return invalid(format!(
+                "chaos.probability {} is not between 0 and 1",
+                self.chaos.probability
+            ));
+        }
+        for (key, path) in [
+            ("health.path", &self.health.path),
+            ("health.ready_path", &self.health.ready_path),
+        ] {
+            if !path.is_empty() && !path.starts_with('/') {
+                return invalid(format!("{key} {path:?} does not start with /"));
+            }
+        }
+        if let Some(dir) = &self.cgi.dir {
+            if !self.cgi.prefix.starts_with('/') {
+                return invalid(format!(
+                    "cgi.prefix {:?} does not start with /",
+                    self.cgi.prefix
+                ));
+            }
+            if !dir.is_dir() {
+                return invalid(format!("cgi.dir {} is not a directory", dir.display()));
+            }
+        }
+        if let Err(coding) = self.static_files.sidecar_encodings() {
+            return invalid(format!("{coding} is not gzip or br"));
+        }
+        if !http::is_field_name(&self.static_files.charset) {
+            return invalid(format!(
+                "static.charset {:?} is not a charset name",
+                self.static_files.charset
+            ));
+        }
+        for (pattern, value) in &self.cache {
+            if pattern.is_empty() || value.is_empty() || !http::is_field_value(value) {
+                return invalid(format!("cache {pattern:?} has no valid value"));
+            }
+        }
+        if self.listener.unix_socket.is_some() && !cfg!(unix) {
+            return invalid("listener.unix_socket needs a unix platform".to_string());
+        }
+        let switches = self.daemon.user.is_some() || self.daemon.group.is_some();
+        if (self.daemon.detach || switches) && !cfg!(unix) {
+            return invalid("daemon.detach, user and group need a unix platform".to_string());
+        }
+        if !self.tls.addrs.is_empty() && (self.tls.cert.is_none() || self.tls.key.is_none()) {
+            return invalid("tls.addrs needs tls.cert and tls.key".to_string());
+        }
+        let hsts = &self.tls.hsts;
+        if hsts.preload
+            && !(hsts.include_subdomains && hsts.max_age >= HstsConfig::default().max_age)
+        {
+            return invalid(
+                "tls.hsts.preload needs include_subdomains and a max_age of a year or more"
+                    .to_string(),
+            );
+        }
+        if self.listener.unix_socket_mode > 0o777 {
+            return invalid(format!(
+                "listener.unix_socket_mode {:o} is not a permission mode",
+                self.listener.unix_socket_mode
+            ));
+        }
+        if self.limits.max_connections == 0 || self.limits.max_requests == 0 {
+            return invalid(
+                "limits.max_connections and limits.max_requests must be positive".to_string(),
+            );
+        }
+        if let Err(range) = self.access.access_list() {
+            return invalid(format!("{range} is not an address range"));
+        }
+        let rate = self.rate_limit.rate;
+        if !rate.is_finite() || rate <= 0.0 {
+            return invalid(format!("rate_limit.rate {rate} is not positive"));
+        }
+        if let Err(coding) = self.compression.compression() {
+            return invalid(format!("{coding} is not gzip or br"));
+        }
+        if let Some(level) = self.compression.level.filter(|level| *level > 11) {
+            return invalid(format!("compression.level {level} is above 11"));
+        }
+        if self.compression.min_size > self.compression.max_size {
+            return invalid(format!(
+                "compression.min_size {} is above max_size",
+                self.compression.min_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parse the TOML document `text`, returning the configuration and the
+    /// keys which are not settings, sorted.
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<(Config, Vec<String>), ConfigError> {
+        let deserializer =
+            toml::Deserializer::parse(text).map_err(|err| ConfigError::Parse(err.to_string()))?;
+        let mut unknown = Vec::new();
+        let config =
+            serde_ignored::deserialize(deserializer, |path| unknown.push(path.to_string()))
+                .map_err(|err| ConfigError::Parse(err.to_string()))?;
+        unknown.sort();
+        Ok((config, unknown))
+    }
+
+    /// Load the TOML file at `path` like [`from_toml`](Config::from_toml),
+    /// taking the relative paths in it from the file's directory, so that
+    /// the server finds the same files wherever it was started.
+    #[cfg(feature = "config")]
+    pub fn load(path: impl Into<PathBuf>) -> Result<(Config, Vec<String>), ConfigError> {
+        let path = path.into();
+        let text =
+            std::fs::read_to_string(&path).map_err(|err| ConfigError::Io(path.clone(), err))?;
+        let (mut config, unknown) = Config::from_toml(&text).map_err(|err| match err {
+            ConfigError::Parse(err) => ConfigError::Parse(format!("{}: {err}", path.display())),
+            err => err,
+        })?;
+        config.relative_to(path.parent().unwrap_or(std::path::Path::new("")));
+        Ok((config, unknown))
+    }
+
+    /// Take every relative path of the settings from `dir`.
+    #[cfg(feature = "config")]
+    fn relative_to(&mut self, dir: &std::path::Path) {
+        let rebase = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = dir.join(&*path);
+            }
+        };
+        // Left at its default, the root stays the working directory.
+        if self.static_files.root != StaticConfig::default().root {
+            rebase(&mut self.static_files.root);
+        }
+        if let Some(shell) = &mut self.static_files.markdown_shell {
+            rebase(shell);
+        }
+        self.vhosts
+            .hosts
+            .iter_mut()
+            .for_each(|host| rebase(&mut host.root));
+        self.error_pages.values_mut().for_each(rebase);
+        if let Some(dir) = &mut self.dump.dir {
+            rebase(dir);
+        }
+        self.auth
+            .iter_mut()
+            .for_each(|auth| rebase(&mut auth.htpasswd));
+        let optional = [
+            &mut self.listener.unix_socket,
+            &mut self.daemon.pid_file,
+            &mut self.daemon.log_file,
+            &mut self.tls.cert,
+            &mut self.tls.key,
+            &mut self.cgi.dir,
+            &mut self.access_log.path,
+            &mut self.admin.tokens,
+            &mut self.limits.spool_dir,
+            &mut self.well_known.dir,
+        ];
+        optional.into_iter().flatten().for_each(rebase);
+    }
+}
+
+/// Deserialize a duration given in seconds, possibly fractional.
+#[cfg(feature = "config")]
+fn seconds<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let seconds = <f64 as serde::Deserialize>::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| serde::de::Error::custom(format!("invalid duration {seconds}")))
+}
+
+/// Deserialize a duration given in seconds like [`seconds`], when it is given.
+#[cfg(feature = "config")]
+fn optional_seconds<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    seconds(deserializer).map(Some)
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_every_section() -> Result<(), Box<dyn std::error::Error>> {
+        let (config, unknown) = Config::load("tests/fixtures/server.toml")?;
+        assert!(unknown.is_empty());
+        assert_eq!(
+            config,
+            Config {
+                listener: ListenerConfig {
+                    addr: IpAddr::from([0, 0, 0, 0]),
+                    port: 8080,
+                    extra_addrs: vec!["[::1]:8081".parse()?],
+                    dual_stack: false,
+                    reuse_port: true,
+                    unix_socket: Some(PathBuf::from("/run/hello.sock")),
+                    unix_socket_mode: 0o600,
+                },
+                pool: PoolConfig { threads: 4 },
+                static_files: StaticConfig {
+                    // Relative paths are taken from the file's directory.
+                    root: PathBuf::from("tests/fixtures/public"),
+                    index: vec!["index.html".to_string(), "index.htm".to_string()],
+                    autoindex: true,
+                    dotfiles: true,
+                    markdown: true,
+                    markdown_shell: Some(PathBuf::from("tests/fixtures/shell.html")),
+                    markdown_raw_html: true,
+                    cross_origin_isolation: false,
+                    precompressed: vec!["gzip".to_string()],
+                    charset: "iso-8859-1".to_string(),
+                    charset_types: vec!["text/html".to_string()],
+                    favicon: FaviconFallback::NoContent,
+                    max_ranges: 4,
+                },
+                routing: RoutingConfig {
+                    merge_slashes: false,
+                },
+                error_pages: BTreeMap::from([
+                    ("404".to_string(), "tests/fixtures/errors/404.html".into()),
+                    ("5xx".to_string(), "/srv/errors/5xx.html".into()),
+                ]),
+                cache: BTreeMap::from([
+                    (
+                        "/static/**".to_string(),
+                        "public, max-age=31536000, immutable".to_string(),
+                    ),
+                    ("*.html".to_string(), "no-cache".to_string()),
+                ]),
+                limits: LimitsConfig {
+                    max_body_size: 2048,
+                    spool_threshold: 1024,
+                    spool_dir: Some("tests/fixtures/spool".into()),
+                    max_header_size: 4096,
+                    header_timeout: Duration::from_secs(5),
+                    body_timeout: Duration::from_millis(20500),
+                    write_timeout: Duration::from_secs(10),
+                    idle_timeout: Duration::from_secs(2),
+                    handler_timeout: Some(Duration::from_millis(7500)),
+                    max_requests: 50,
+                    max_connections: 64,
+                },
+                logging: LoggingConfig {
+                    level: LevelFilter::Debug,
+                    format: LogFormat::Json,
+                },
+                daemon: DaemonConfig {
+                    detach: true,
+                    pid_file: Some(PathBuf::from("/run/hello.pid")),
+                    log_file: Some(PathBuf::from("/var/log/hello.log")),
+                    user: Some("www-data".to_string()),
+                    group: Some("www-data".to_string()),
+                },
+                tls: TlsConfig {
+                    addrs: vec!["0.0.0.0:8443".parse()?],
+                    cert: Some(PathBuf::from("/etc/hello/cert.pem")),
+                    key: Some(PathBuf::from("/etc/hello/key.pem")),
+                    hsts: HstsConfig {
+                        enabled: true,
+                        max_age: Duration::from_secs(63072000),
+                        include_subdomains: true,
+                        preload: true,
+                    },
+                },
+                redirect: RedirectConfig {
+                    addrs: vec!["0.0.0.0:80".parse()?],
+                    canonical_host: Some("example.com".to_string()),
+                    https_port: 8443,
+                },
+                security_headers: SecurityHeadersConfig {
+                    enabled: true,
+                    content_type_options: String::new(),
+                    frame_options: "SAMEORIGIN".to_string(),
+                    referrer_policy: "no-referrer".to_string(),
+                    content_security_policy: "default-src 'self'".to_string(),
+                },
+                headers: HeadersConfig {
+                    server: "hello".to_string(),
+                    rules: vec![HeaderRuleConfig {
+                        path: "/downloads/*".to_string(),
+                        headers: BTreeMap::from([(
+                            "Content-Disposition".to_string(),
+                            "attachment".to_string(),
+                        )]),
+                        force: true,
+                    }],
+                },
+                cors: CorsConfig {
+                    enabled: true,
+                    origins: vec!["https://app.example.com".to_string()],
+                    methods: vec!["GET".to_string(), "PUT".to_string()],
+                    headers: vec!["Content-Type".to_string()],
+                    credentials: true,
+                    max_age: Some(Duration::from_secs(600)),
+                },
+                proxy: ProxyConfig {
+                    trust_request_id: true,
+                    trusted_proxies: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+                },
+                vhosts: VhostsConfig {
+                    unknown_host: UnknownHost::Misdirected,
+                    hosts: vec![VirtualHostConfig {
+                        names: vec!["example.com".to_string(), "*.example.com".to_string()],
+                        root: PathBuf::from("tests/fixtures"),
+                        index: Some(vec!["home.html".to_string()]),
+                        autoindex: Some(false),
+                        dotfiles: Some(false),
+                        cross_origin_isolation: Some(true),
+                    }],
+                },
+                reverse_proxy: vec![ReverseProxyConfig {
+                    prefix: "/api".to_string(),
+                    upstreams: vec!["127.0.0.1:9000".parse()?, "127.0.0.1:9001".parse()?],
+                    strip_prefix: true,
+                    connect_timeout: Duration::from_secs(2),
+                    timeout: Duration::from_secs(60),
+                    max_retries: 1,
+                    failure_threshold: 5,
+                    cooldown: Duration::from_secs(30),
+                    forwarded_headers: ForwardedHeaders::Both,
+                }],
+                cgi: CgiConfig {
+                    dir: Some(PathBuf::from("tests/fixtures/cgi-bin")),
+                    prefix: "/scripts".to_string(),
+                    timeout: Duration::from_secs(5),
+                    max_processes: 4,
+                },
+                auth: vec![AuthConfig {
+                    prefix: "/admin".to_string(),
+                    realm: "Admin".to_string(),
+                    htpasswd: PathBuf::from("tests/fixtures/htpasswd"),
+                }],
+                access_log: AccessLogConfig {
+                    enabled: true,
+                    format: AccessLogFormat::Combined,
+                    path: Some(PathBuf::from("/var/log/hello/access.log")),
+                    max_size: 10485760,
+                    daily: true,
+                    keep: 14,
+                },
+                metrics: MetricsConfig {
+                    enabled: true,
+                    path: "/internal/metrics".to_string(),
+                    addr: Some(SocketAddr::from(([127, 0, 0, 1], 9100))),
+                },
+                health: HealthConfig {
+                    enabled: true,
+                    path: "/livez".to_string(),
+                    ready_path: String::new(),
+                    access_log: true,
+                },
+                status: StatusConfig {
+                    enabled: true,
+                    path: "/server-status".to_string(),
+                    allow: vec!["10.0.0.0/8".to_string()],
+                },
+                admin: AdminConfig {
+                    tokens: Some(PathBuf::from("tests/fixtures/tokens")),
+                    paths: vec!["/admin".to_string(), "/server-status".to_string()],
+                    endpoints: true,
+                },
+                debug: DebugConfig {
+                    trace: true,
+                    dev: true,
+                },
+                chaos: ChaosConfig {
+                    enabled: true,
+                    probability: 0.25,
+                    paths: vec!["/api/**".to_string()],
+                    faults: vec![FaultKind::Latency, FaultKind::Unavailable],
+                    latency: Duration::from_millis(200),
+                    jitter: Duration::from_millis(100),
+                    seed: Some(42),
+                },
+                dump: DumpConfig {
+                    enabled: true,
+                    dir: Some(PathBuf::from("tests/fixtures/dumps")),
+                    clients: vec!["127.0.0.1".to_string()],
+                    paths: vec!["/api".to_string()],
+                    max_connection_bytes: 4096,
+                    max_total_bytes: 65536,
+                },
+                well_known: WellKnownConfig {
+                    dir: Some(PathBuf::from("tests/fixtures/well-known")),
+                    documents: BTreeMap::from([(
+                        "security.txt".to_string(),
+                        WellKnownDocument {
+                            content: "Contact: mailto:security@example.com\n".to_string(),
+                            content_type: "text/plain; charset=utf-8".to_string(),
+                        },
+                    )]),
+                },
+                rate_limit: RateLimitConfig {
+                    enabled: true,
+                    rate: 2.5,
+                    burst: 5,
+                    allow: vec![IpAddr::from([10, 0, 0, 1])],
+                },
+                access: AccessConfig {
+                    allow: vec!["10.0.0.0/8".to_string()],
+                    deny: vec!["10.0.0.66".to_string()],
+                    respond_forbidden: true,
+                },
+                compression: CompressionConfig {
+                    enabled: true,
+                    min_size: 256,
+                    max_size: 1048576,
+                    types: vec!["text/html".to_string(), "text/css".to_string()],
+                    prefer: vec!["gzip".to_string()],
+                    level: Some(9),
+                    exclude: vec!["/events".to_string()],
+                },
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_omitted_settings_have_defaults() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(Config::from_toml("")?, (Config::default(), Vec::new()));
+
+        let (config, unknown) = Config::from_toml(
+            "[static]\nautoindex = true\nautoindx = false\n\n[extra]\nkey = 1\n",
+        )?;
+        assert!(config.static_files.autoindex);
+        assert_eq!(config.static_files.index, ["index.html", "hello.html"]);
+        assert_eq!(config.limits, LimitsConfig::default());
+        assert_eq!(unknown, ["extra", "static.autoindx"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_malformed_file_points_at_the_line() {
+        let err = Config::from_toml("[listener]\naddr = \"0.0.0.0\"\nport = \"http\"\n")
+            .expect_err("the port is not a number");
+        let message = err.to_string();
+        assert!(message.contains("line 3"), "{message}");
+        assert!(message.contains("port = \"http\""), "{message}");
+
+        let err =
+            Config::from_toml("[pool\nthreads = 2\n").expect_err("the header is unterminated");
+        assert!(err.to_string().contains("line 1"), "{err}");
+
+        let err = Config::load("does/not/exist.toml").expect_err("the file is missing");
+        assert!(err.to_string().contains("does/not/exist.toml"));
+    }
+
+    #[test]
+    fn test_addrs_with_separate_stacks() {
+        let mut config = Config::default();
+        config.listener.addr = IpAddr::from([0u16; 8]);
+        assert_eq!(config.addrs(), ["[::]:7878".parse().unwrap()]);
+
+        config.listener.dual_stack = false;
+        config.listener.extra_addrs = vec!["127.0.0.1:8080".parse().unwrap()];
+        let addrs: Vec<String> = config.addrs().iter().map(|addr| addr.to_string()).collect();
+        assert_eq!(addrs, ["0.0.0.0:7878", "[::]:7878", "127.0.0.1:8080"]);
+    }
+
+    #[test]
+    fn test_validate() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(Config::default().validate().is_ok());
+        for text in [
+            "pool.threads = 0",
+            "static.root = \"does/not/exist\"",
+            "static.index = [\"../index.html\"]",
+            "error_pages.200 = \"200.html\"",
+            "chaos.probability = 1.5",
+            "dump.enabled = true",
+            "well_known.dir = \"does/not/exist\"",
+            "[[headers.rules]]\nheaders = { X-Robots-Tag = \"noindex\" }",
+            "[[headers.rules]]\npath = \"/api/*\"\nheaders = { \"X Robots\" = \"noindex\" }",
+            "[[headers.rules]]\npath = \"/api/*\"\nheaders = { X-Robots-Tag = \"a\\r\\nb\" }",
+            "[[headers.rules]]\npath = \"/*\"\nheaders = { Content-Length = \"0\" }",
+            "well_known.documents.\"../secret\" = { content = \"x\" }",
+            "well_known.documents.\"a.txt\" = { content = \"x\", content_type = \"\" }",
+            "dump.dir = \"dumps\"\ndump.clients = [\"localhost\"]",
+            "dump.dir = \"dumps\"\ndump.paths = [\"api\"]",
+            "static.precompressed = [\"zstd\"]",
+            "static.charset = \"\"",
+            "static.charset = \"utf-8; q=1\"",
+            "cache.\"*.html\" = \"\"",
+            "cache.\"*.html\" = \"no-cache\\r\\nX-Injected: 1\"",
+            "limits.max_connections = 0",
+            "tls.addrs = [\"127.0.0.1:8443\"]",
+            "tls.hsts.preload = true",
+            "[[vhosts.hosts]]\nroot = \".\"",
+            "[[vhosts.hosts]]\nnames = [\"*\"]\nroot = \".\"",
+            "[[vhosts.hosts]]\nnames = [\"a.test\"]\nroot = \"does/not/exist\"",
+            "[[reverse_proxy]]\nprefix = \"/api\"",
+            "proxy.trusted_proxies = [\"10.0.0.0/40\"]",
+            "cgi.dir = \"does/not/exist\"",
+            "cgi.dir = \".\"\ncgi.prefix = \"cgi-bin\"",
+            "metrics.path = \"metrics\"",
+            "health.ready_path = \"readyz\"",
+            "status.path = \"status\"",
+            "status.allow = [\"localhost\"]",
+            "[[auth]]\nhtpasswd = \"does/not/exist\"",
+            "[[auth]]\nprefix = \"admin\"\nhtpasswd = \"tests/fixtures/htpasswd\"",
+            "admin.tokens = \"does/not/exist\"",
+            "admin.tokens = \"tests/fixtures/server.toml\"",
+            "admin.paths = [\"admin\"]",
+            "admin.endpoints = true",
+            "admin.tokens = \"tests/fixtures/tokens\"\nadmin.paths = [\"/status\"]\nadmin.endpoints = true",
+            "cors.origins = [\"*\"]\ncors.credentials = true",
+            "[[reverse_proxy]]\nprefix = \"api\"\nupstreams = [\"127.0.0.1:9000\"]",
+            "rate_limit.rate = 0",
+            "rate_limit.rate = -1",
+            "access.allow = [\"10.0.0.0/40\"]",
+            "access.deny = [\"localhost\"]",
+            "compression.prefer = [\"deflate\"]",
+            "compression.level = 12",
+            "compression.min_size = 2048\ncompression.max_size = 1024",
+        ] {
+            let (config, _) = Config::from_toml(text)?;
+            assert!(
+                matches!(config.validate(), Err(ConfigError::Invalid(_))),
+                "{text}"
+            );
+        }
+        Ok(())
+    }
+}
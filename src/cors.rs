@@ -0,0 +1,267 @@
+//! Cross-origin resource sharing: which other origins' pages may call the
+//! server from a browser, and with what.
+
+use std::time::Duration;
+
+use crate::{
+    http::{Headers, Method, Request},
+    response::Response,
+};
+
+/// The origins, methods and headers cross-origin requests may use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cors {
+    /// The origins allowed, or none for every origin.
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// Allow no origin `GET`, `HEAD` and `POST` requests with no headers
+    /// beyond the simple ones, without credentials.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Some(Vec::new()),
+            methods: ["GET", "HEAD", "POST"].map(str::to_string).to_vec(),
+            headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// Allow `origin`, like `https://app.example.com`, as well; `*` for
+    /// every origin.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        match (origin, &mut self.origins) {
+            ("*", _) => self.origins = None,
+            (_, Some(origins)) => origins.push(origin.trim_end_matches('/').to_string()),
+            (_, None) => {}
+        }
+        self
+    }
+
+    /// Allow the `methods` instead.
+    pub fn methods(mut self, methods: &[&str]) -> Cors {
+        self.methods = methods.iter().map(|method| method.to_string()).collect();
+        self
+    }
+
+    /// Allow requests with the `headers` as well.
+    pub fn headers(mut self, headers: &[&str]) -> Cors {
+        self.headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    /// Allow requests with cookies or credentials, which browsers only send
+    /// to listed origins: with every origin allowed, they are still refused.
+    pub fn credentials(mut self, credentials: bool) -> Cors {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Let browsers cache the answers to preflights for `max_age`, or for as
+    /// long as they like without it.
+    pub fn max_age(mut self, max_age: Option<Duration>) -> Cors {
+        self.max_age = max_age;
+        self
+    }
+
+    /// The answer to `request` if it is a preflight: `204 No Content`,
+    /// allowing what it asks for only if all of it is allowed.
+    pub fn preflight(&self, request: &Request) -> Option<Response> {
+        if request.method() != &Method::Options {
+            return None;
+        }
+        let origin = request.header("Origin")?;
+        let method = request.header("Access-Control-Request-Method")?;
+        let mut response = Response::new(204);
+        let requested = request.header("Access-Control-Request-Headers");
+        let headers_allowed = requested
+            .into_iter()
+            .flat_map(|names| names.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                self.headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            });
+        let method_allowed = self.methods.iter().any(|allowed| allowed == method.trim());
+        if method_allowed && headers_allowed && self.allow(origin, response.headers_mut()) {
+            let headers = response.headers_mut();
+            headers.insert("Access-Control-Allow-Methods", self.methods.join(", "));
+            if let Some(requested) = requested {
+                headers.insert("Access-Control-Allow-Headers", requested.trim());
+            }
+            if let Some(max_age) = self.max_age {
+                headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+            }
+        } else {
+            log::debug!("Refused the preflight of {method} from {origin}");
+        }
+        if self.origins.is_some() {
+            response.headers_mut().add_vary("Origin");
+        }
+        Some(response)
+    }
+
+    /// Let the page of the origin of `request` read the response with
+    /// `headers`, if the origin is allowed and the response does not say
+    /// otherwise. Requests from no other origin are left alone, but when the
+    /// answer depends on the origin their responses still vary by it.
+    pub fn apply(&self, request: &Request, headers: &mut Headers) {
+        if headers.contains("Access-Control-Allow-Origin") {
+            return;
+        }
+        if self.origins.is_some() {
+            headers.add_vary("Origin");
+        }
+        if let Some(origin) = request.header("Origin") {
+            self.allow(origin, headers);
+        }
+    }
+
+    /// Add the headers allowing `origin` to `headers`, returning whether it
+    /// is allowed.
+    fn allow(&self, origin: &str, headers: &mut Headers) -> bool {
+        match &self.origins {
+            None => {
+                headers.insert("Access-Control-Allow-Origin", "*");
+                true
+            }
+            Some(origins)
+                if origins
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(origin)) =>
+            {
+                headers.insert("Access-Control-Allow-Origin", origin);
+                if self.credentials {
+                    headers.insert("Access-Control-Allow-Credentials", "true");
+                }
+                true
+            }
+            Some(_) => false,
+        }
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Version;
+
+    fn preflight(origin: &str, method: &str, headers: Option<&str>) -> Request {
+        let request = Request::new(Method::Options, "/api/items", Version::Http11)
+            .with_header("Origin", origin)
+            .with_header("Access-Control-Request-Method", method);
+        match headers {
+            Some(headers) => request.with_header("Access-Control-Request-Headers", headers),
+            None => request,
+        }
+    }
+
+    #[test]
+    fn test_preflight() {
+        let cors = Cors::new()
+            .allow_origin("https://app.test")
+            .methods(&["GET", "PUT"])
+            .headers(&["Content-Type", "X-Token"])
+            .max_age(Some(Duration::from_secs(600)));
+
+        let allowed = cors
+            .preflight(&preflight(
+                "https://app.test",
+                "PUT",
+                Some("content-type, x-token"),
+            ))
+            .expect("a preflight");
+        assert_eq!(allowed.status(), 204);
+        let headers = allowed.headers();
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.test")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Headers"),
+            Some("content-type, x-token")
+        );
+        assert_eq!(headers.get("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(headers.get("Access-Control-Allow-Credentials"), None);
+        assert_eq!(headers.get("Vary"), Some("Origin"));
+
+        for refused in [
+            preflight("https://evil.test", "PUT", None),
+            preflight("https://app.test", "DELETE", None),
+            preflight("https://app.test", "GET", Some("X-Other")),
+        ] {
+            let response = cors.preflight(&refused).expect("a preflight");
+            assert_eq!(response.status(), 204);
+            assert_eq!(response.headers().get("Access-Control-Allow-Origin"), None);
+            assert_eq!(response.headers().get("Access-Control-Allow-Methods"), None);
+        }
+
+        let options = Request::new(Method::Options, "/api/items", Version::Http11);
+        assert!(cors.preflight(&options).is_none(), "not a CORS request");
+        let get = Request::new(Method::Get, "/api/items", Version::Http11)
+            .with_header("Origin", "https://app.test")
+            .with_header("Access-Control-Request-Method", "GET");
+        assert!(cors.preflight(&get).is_none());
+    }
+
+    #[test]
+    fn test_apply() {
+        let response_headers = |cors: &Cors, origin: Option<&str>| {
+            let request = Request::new(Method::Get, "/api/items", Version::Http11);
+            let request = match origin {
+                Some(origin) => request.with_header("Origin", origin),
+                None => request,
+            };
+            let mut headers = Headers::new();
+            cors.apply(&request, &mut headers);
+            headers
+        };
+
+        let any = Cors::new().allow_origin("*").credentials(true);
+        let headers = response_headers(&any, Some("https://app.test"));
+        assert_eq!(headers.get("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(headers.get("Access-Control-Allow-Credentials"), None);
+        assert_eq!(headers.get("Vary"), None);
+        assert!(
+            response_headers(&any, None).is_empty(),
+            "not a CORS request"
+        );
+
+        let listed = Cors::new()
+            .allow_origin("https://app.test/")
+            .credentials(true);
+        let headers = response_headers(&listed, Some("https://app.test"));
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.test")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(headers.get("Vary"), Some("Origin"));
+        let headers = response_headers(&listed, Some("https://evil.test"));
+        assert_eq!(headers.get("Access-Control-Allow-Origin"), None);
+        assert_eq!(headers.get("Vary"), Some("Origin"));
+        let headers = response_headers(&listed, None);
+        assert_eq!(headers.get("Vary"), Some("Origin"), "as the others vary");
+        assert_eq!(headers.len(), 1);
+    }
+}
@@ -0,0 +1,181 @@
+//! Running in the background: pid files and detaching from the terminal.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{error, warn};
+
+/// A file holding the process id of a running server, removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write `pid` to a new file at `path`. An existing file naming a process
+    /// which still runs is an error, one whose process is gone is replaced.
+    pub fn create(path: impl Into<PathBuf>, pid: u32) -> io::Result<PidFile> {
+        let path = path.into();
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let held = fs::read_to_string(&path)?;
+                let held: u32 = held.trim().parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} does not hold a process id", path.display()),
+                    )
+                })?;
+                if is_running(held) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} names the running process {held}", path.display()),
+                    ));
+                }
+                warn!("Replacing {}, its process {held} is gone.", path.display());
+                fs::remove_file(&path)?;
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)?
+            }
+            opened => opened?,
+        };
+        writeln!(file, "{pid}")?;
+        Ok(PidFile { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Could not remove {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Whether process `pid` exists, assumed when it cannot be checked.
+fn is_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        let Ok(pid) = libc::pid_t::try_from(pid) else {
+            return false;
+        };
+        // SAFETY: signal 0 only checks whether the process can be signalled.
+        let signalled = unsafe { libc::kill(pid, 0) };
+        signalled == 0 || io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+/// What the launching process prints when the daemon reports it started.
+#[cfg(unix)]
+const READY: &str = "ready";
+
+/// The daemon's line back to the process which launched it.
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct Detached {
+    launcher: std::os::unix::net::UnixStream,
+}
+
+#[cfg(unix)]
+impl Detached {
+    /// Let the launching process exit successfully.
+    pub fn ready(mut self) -> io::Result<()> {
+        self.launcher.write_all(READY.as_bytes())
+    }
+
+    /// Let the launching process print `message` and exit with an error.
+    pub fn fail(mut self, message: &str) {
+        let _ = self.launcher.write_all(message.as_bytes());
+    }
+}
+
+/// Fork into a new session in the background, with stdin reading /dev/null
+/// and stdout and stderr appending to `output`, or /dev/null without one.
+///
+/// The launching process waits until the daemon reports through the returned
+/// [`Detached`], then exits; only the daemon returns. Call it before starting
+/// any threads, which would not survive the fork.
+#[cfg(unix)]
+pub fn detach(output: Option<&Path>) -> io::Result<Detached> {
+    use std::{io::Read, os::fd::AsRawFd, os::unix::net::UnixStream};
+
+    let output = match output {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let input = File::open("/dev/null")?;
+    let (mut launcher, daemon) = UnixStream::pair()?;
+
+    // SAFETY: the process has a single thread, so the child's copy of the
+    // memory is consistent.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(launcher);
+            // SAFETY: these calls only change process attributes and file
+            // descriptors owned by this function.
+            unsafe {
+                if libc::setsid() == -1
+                    || libc::dup2(input.as_raw_fd(), libc::STDIN_FILENO) == -1
+                    || libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO) == -1
+                    || libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO) == -1
+                {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(Detached { launcher: daemon })
+        }
+        _ => {
+            drop(daemon);
+            let mut report = String::new();
+            let _ = launcher.read_to_string(&mut report);
+            if report == READY {
+                std::process::exit(0);
+            }
+            if report.is_empty() {
+                report = "the server exited before it started".to_string();
+            }
+            error!("{report}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_refuses_running_processes() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("hello-{}.pid", std::process::id()));
+        let pid_file = PidFile::create(&path, std::process::id())?;
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            format!("{}\n", std::process::id())
+        );
+        let err = PidFile::create(&path, 1).expect_err("this process still runs");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        drop(pid_file);
+        assert!(!path.exists(), "the file is removed when dropped");
+
+        let mut exited = std::process::Command::new("true").spawn()?;
+        exited.wait()?;
+        fs::write(&path, format!("{}\n", exited.id()))?;
+        let pid_file = PidFile::create(&path, 7)?;
+        assert_eq!(fs::read_to_string(pid_file.path())?, "7\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Deadlines for handlers, so that one which takes too long does not keep
+//! its client waiting.
+//!
+//! A handler with a deadline runs on a thread of its own while the worker
+//! watches the clock. Once the deadline passed without a response, the worker
+//! answers for it and moves on; what the handler returns afterwards is
+//! discarded and counted. So that handlers which hang do not pile up threads,
+//! requests are answered with 503 at once while too many are still running
+//! past their deadline.
+
+use std::{
+    any::Any,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::{http::Request, logging, response::Response, router::Handler};
+
+/// The handler threads left running past their deadline by default.
+pub const MAX_OVERDUE: usize = 64;
+
+/// Runs handlers against deadlines, counting those they missed; clones
+/// share the counts.
+#[derive(Debug, Clone)]
+pub struct Deadlines {
+    counts: Arc<Counts>,
+    max_overdue: usize,
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    exceeded: AtomicU64,
+    discarded: AtomicU64,
+    /// The threads of handlers still running past their deadline.
+    overdue: AtomicUsize,
+}
+
+impl Default for Deadlines {
+    fn default() -> Deadlines {
+        Deadlines::new()
+    }
+}
+
+/// Where a handler running on its own thread leaves its result.
+enum Slot {
+    Running,
+    Done(Result<io::Result<Response>, Box<dyn Any + Send>>),
+    /// The worker stopped waiting, so the result goes nowhere.
+    Abandoned,
+}
+
+type Shared = (Mutex<Slot>, Condvar);
+
+impl Deadlines {
+    pub fn new() -> Deadlines {
+        Deadlines {
+            counts: Arc::default(),
+            max_overdue: MAX_OVERDUE,
+        }
+    }
+
+    /// Answer requests without running their handler while `max` handlers
+    /// are still running past their deadline, [`MAX_OVERDUE`] by default.
+    pub fn max_overdue(mut self, max: usize) -> Deadlines {
+        self.max_overdue = max;
+        self
+    }
+
+    /// The response of `handler` to `request`, or none if it did not come
+    /// within `timeout` or too many handlers are overdue. `route` names the
+    /// handler in the log.
+    ///
+    /// A panic of the handler carries on on the calling thread.
+    pub fn run(
+        &self,
+        handler: &Handler,
+        request: &Request,
+        route: &str,
+        timeout: Duration,
+    ) -> Option<io::Result<Response>> {
+        let started = Instant::now();
+        let overdue = self.counts.overdue.load(Ordering::Relaxed);
+        if overdue >= self.max_overdue {
+            self.counts.exceeded.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Answering 503 for {route} at once, as {overdue} handlers are still \
+                 running past their deadline."
+            );
+            return None;
+        }
+        let shared: Arc<Shared> = Arc::new((Mutex::new(Slot::Running), Condvar::new()));
+        let spawned = {
+            let (handler, request, shared) =
+                (Arc::clone(handler), request.clone(), Arc::clone(&shared));
+            let (counts, route) = (Arc::clone(&self.counts), route.to_string());
+            thread::Builder::new()
+                .name("handler".to_string())
+                .spawn(move || {
+                    let _scope = logging::request_scope(request.id());
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| handler(&request)));
+                    let (slot, done) = &*shared;
+                    let mut slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+                    if let Slot::Abandoned = *slot {
+                        counts.overdue.fetch_sub(1, Ordering::Relaxed);
+                        counts.discarded.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "Discarded what {route} returned after {:?}, past its deadline.",
+                            started.elapsed()
+                        );
+                    } else {
+                        *slot = Slot::Done(result);
+                        done.notify_one();
+                    }
+                })
+        };
+        if let Err(err) = spawned {
+            warn!("Running {route} without a deadline, as no thread could be spawned: {err}");
+            return Some(handler(request));
+        }
+
+        let (slot, done) = &*shared;
+        let slot = slot.lock().unwrap_or_else(PoisonError::into_inner);
+        let (mut slot, _) = done
+            .wait_timeout_while(slot, timeout, |slot| matches!(slot, Slot::Running))
+            .unwrap_or_else(PoisonError::into_inner);
+        match std::mem::replace(&mut *slot, Slot::Abandoned) {
+            Slot::Done(Ok(result)) => Some(result),
+            Slot::Done(Err(payload)) => {
+                drop(slot);
+                panic::resume_unwind(payload)
+            }
+            Slot::Running | Slot::Abandoned => {
+                // While the slot is locked, ahead of the thread seeing it abandoned.
+                self.counts.overdue.fetch_add(1, Ordering::Relaxed);
+                self.counts.exceeded.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "{route} did not answer within {timeout:?}, answering 503 after {:?}.",
+                    started.elapsed()
+                );
+                None
+            }
+        }
+    }
+
+    /// The handlers which missed their deadline.
+    pub fn exceeded(&self) -> u64 {
+        self.counts.exceeded.load(Ordering::Relaxed)
+    }
+
+    /// The responses which came after their deadline and were thrown away.
+    pub fn discarded(&self) -> u64 {
+        self.counts.discarded.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        http::{Method, Version},
+        response::Body,
+    };
+
+    #[test]
+    fn test_late_responses_are_discarded() -> Result<(), Box<dyn std::error::Error>> {
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let (finished, finishing) = std::sync::mpsc::channel();
+        let finished = Mutex::new(finished);
+        let slow: Handler = Arc::new(move |_| {
+            let _ = released.lock().unwrap().recv();
+            let _ = finished.lock().unwrap().send(());
+            Ok(Response::new(200))
+        });
+        let quick: Handler =
+            Arc::new(|_| Ok(Response::new(200).with_body(Body::Bytes(b"quick".to_vec()))));
+        let request = Request::new(Method::Get, "/", Version::Http11);
+        let deadlines = Deadlines::new();
+
+        let answered = deadlines.run(&quick, &request, "GET /quick", Duration::from_secs(10));
+        assert_eq!(answered.transpose()?.map(|r| r.status()), Some(200));
+        let late = deadlines.run(&slow, &request, "GET /slow", Duration::from_millis(20));
+        assert!(late.is_none());
+        assert_eq!((deadlines.exceeded(), deadlines.discarded()), (1, 0));
+
+        release.send(())?;
+        finishing.recv()?;
+        // The count follows the send, once the slot is seen abandoned.
+        while deadlines.discarded() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!((deadlines.exceeded(), deadlines.discarded()), (1, 1));
+
+        let panicking: Handler = Arc::new(|_| panic!("on purpose"));
+        let caught = panic::catch_unwind(AssertUnwindSafe(|| {
+            deadlines.run(&panicking, &request, "GET /panic", Duration::from_secs(10))
+        }));
+        assert!(caught.is_err(), "the panic reaches the caller");
+        Ok(())
+    }
+
+    #[test]
+    fn test_overdue_handlers_are_capped() -> Result<(), Box<dyn std::error::Error>> {
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        let slow: Handler = Arc::new(move |_| {
+            let _ = released.lock().unwrap().recv();
+            Ok(Response::new(200))
+        });
+        let quick: Handler = Arc::new(|_| Ok(Response::new(200)));
+        let request = Request::new(Method::Get, "/", Version::Http11);
+        let deadlines = Deadlines::new().max_overdue(1);
+
+        let late = deadlines.run(&slow, &request, "GET /slow", Duration::from_millis(20));
+        assert!(late.is_none());
+        let started = Instant::now();
+        let refused = deadlines.run(&quick, &request, "GET /quick", Duration::from_secs(10));
+        assert!(refused.is_none(), "answered at once while one is overdue");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(deadlines.exceeded(), 2);
+
+        release.send(())?;
+        while deadlines.discarded() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let answered = deadlines.run(&quick, &request, "GET /quick", Duration::from_secs(10));
+        assert_eq!(answered.transpose()?.map(|r| r.status()), Some(200));
+        Ok(())
+    }
+}
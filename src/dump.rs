@@ -0,0 +1,417 @@
+//! Raw dumps of the bytes crossing connections, for seeing exactly what a
+//! misbehaving client sent and what it got back.
+//!
+//! Each dumped connection gets two files in the dump directory, named after
+//! when it was accepted and its number: `20261014T081500Z-7.request` with the
+//! bytes read and `20261014T081500Z-7.response` with those written. They are
+//! captured beneath the parser, so malformed requests show as they came.
+//!
+//! A connection is dumped when its client is in one of the configured ranges,
+//! if any, and one of its requests is for a path under one of the configured
+//! prefixes, if any; until a request decides it, what crossed is held in
+//! memory. The files of a connection stop at a limit, and those of all of
+//! them at another, each ending with [`TRUNCATED`] once cut short.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+
+use crate::{access::AccessError, cidr::Cidr, httpdate, net::Timeouts};
+
+/// What a dump file ends with when a limit cut it short.
+pub const TRUNCATED: &[u8] = b"\n[truncated: the dump limit was reached]\n";
+
+/// Where and which connections are dumped; the switch and the bytes dumped
+/// so far are shared by all of them.
+#[derive(Debug)]
+pub struct TrafficDump {
+    dir: PathBuf,
+    clients: Vec<Cidr>,
+    prefixes: Vec<String>,
+    max_connection_bytes: u64,
+    max_total_bytes: u64,
+    enabled: AtomicBool,
+    total: Arc<AtomicU64>,
+}
+
+impl TrafficDump {
+    /// Dump every connection into `dir`, up to 1 MiB each and 64 MiB in all.
+    pub fn new(dir: impl Into<PathBuf>) -> TrafficDump {
+        TrafficDump {
+            dir: dir.into(),
+            clients: Vec::new(),
+            prefixes: Vec::new(),
+            max_connection_bytes: 1024 * 1024,
+            max_total_bytes: 64 * 1024 * 1024,
+            enabled: AtomicBool::new(true),
+            total: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Only dump the connections of clients in `cidr`, and those of the other
+    /// ranges added.
+    pub fn client(mut self, cidr: &str) -> Result<TrafficDump, AccessError> {
+        let range = cidr
+            .parse()
+            .map_err(|()| AccessError::InvalidCidr(cidr.to_string()))?;
+        self.clients.push(range);
+        Ok(self)
+    }
+
+    /// Only dump the connections with a request for `prefix` or a path below
+    /// it, or under the other prefixes added.
+    pub fn path_prefix(mut self, prefix: &str) -> TrafficDump {
+        self.prefixes.push(prefix.to_string());
+        self
+    }
+
+    /// Dump up to `max` bytes of each connection, both files together.
+    pub fn max_connection_bytes(mut self, max: u64) -> TrafficDump {
+        self.max_connection_bytes = max;
+        self
+    }
+
+    /// Dump up to `max` bytes in all, counted from when dumping last started.
+    pub fn max_total_bytes(mut self, max: u64) -> TrafficDump {
+        self.max_total_bytes = max;
+        self
+    }
+
+    /// Start dumping right away or not, on by default.
+    pub fn enabled(mut self, enabled: bool) -> TrafficDump {
+        self.enabled = AtomicBool::new(enabled);
+        self
+    }
+
+    /// Start or stop dumping the connections accepted from now on.
+    pub fn set_enabled(&self, enabled: bool) {
+        if enabled && !self.enabled.swap(true, Ordering::SeqCst) {
+            self.total.store(0, Ordering::Relaxed);
+            info!("Dumping traffic into {}.", self.dir.display());
+        } else if !enabled && self.enabled.swap(false, Ordering::SeqCst) {
+            info!("Stopped dumping traffic.");
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// The dump of the connection numbered `number` from `client`, if it is
+    /// dumped at all.
+    pub fn start(&self, client: Option<IpAddr>, number: u64) -> Option<ConnectionDump> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let matches = |ip: IpAddr| self.clients.iter().any(|range| range.contains(ip));
+        if !self.clients.is_empty() && !client.is_some_and(matches) {
+            return None;
+        }
+        let name = format!("{}-{number}", httpdate::format_basic(SystemTime::now()));
+        let mut dump = ConnectionDump {
+            dir: self.dir.clone(),
+            name,
+            prefixes: self.prefixes.clone(),
+            sinks: Sinks::Held([Vec::new(), Vec::new()]),
+            recorded: 0,
+            max_bytes: self.max_connection_bytes,
+            total: Arc::clone(&self.total),
+            max_total_bytes: self.max_total_bytes,
+            truncated: [false; 2],
+        };
+        if self.prefixes.is_empty() {
+            dump.open();
+        }
+        Some(dump)
+    }
+}
+
+/// The bytes read from a connection, or those written to it.
+#[derive(Debug, Clone, Copy)]
+enum Direction {
+    Request = 0,
+    Response = 1,
+}
+
+/// Where the bytes of a connection go.
+#[derive(Debug)]
+enum Sinks {
+    /// Held until a request decides whether the connection is dumped.
+    Held([Vec<u8>; 2]),
+    Files([File; 2]),
+    /// Not dumped after all, or the files could not be written.
+    Off,
+}
+
+/// The dump of one connection.
+#[derive(Debug)]
+pub struct ConnectionDump {
+    dir: PathBuf,
+    name: String,
+    /// The prefixes deciding for the held bytes.
+    prefixes: Vec<String>,
+    sinks: Sinks,
+    recorded: u64,
+    max_bytes: u64,
+    total: Arc<AtomicU64>,
+    max_total_bytes: u64,
+    truncated: [bool; 2],
+}
+
+impl ConnectionDump {
+    /// The path of the file the bytes read go into.
+    pub fn request_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.request", self.name))
+    }
+
+    /// The path of the file the bytes written go into.
+    pub fn response_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.response", self.name))
+    }
+
+    /// Note a request for `path`, which decides whether a connection held
+    /// for one under the prefixes is dumped.
+    pub fn request(&mut self, path: &str) {
+        let under = |prefix: &String| {
+            let prefix = prefix.strip_suffix('/').unwrap_or(prefix);
+            path.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        };
+        if matches!(self.sinks, Sinks::Held(_)) && self.prefixes.iter().any(under) {
+            self.open();
+        }
+    }
+
+    /// Write what was held to the files, and what follows from now on.
+    fn open(&mut self) {
+        let Sinks::Held(held) = std::mem::replace(&mut self.sinks, Sinks::Off) else {
+            return;
+        };
+        let opened = fs::create_dir_all(&self.dir).and_then(|()| {
+            let mut files = [
+                File::create(self.request_path())?,
+                File::create(self.response_path())?,
+            ];
+            for (file, bytes) in files.iter_mut().zip(&held) {
+                file.write_all(bytes)?;
+            }
+            Ok(files)
+        });
+        match opened {
+            Ok(files) => self.sinks = Sinks::Files(files),
+            Err(err) => self.fail(&err),
+        }
+    }
+
+    /// Add `bytes` to the dump, as far as the limits allow.
+    fn record(&mut self, direction: Direction, bytes: &[u8]) {
+        let side = direction as usize;
+        if matches!(self.sinks, Sinks::Off) || self.truncated[side] || bytes.is_empty() {
+            return;
+        }
+        let room = self.max_bytes.saturating_sub(self.recorded);
+        let wanted = (bytes.len() as u64).min(room);
+        let taken = self
+            .total
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                Some(total + wanted.min(self.max_total_bytes.saturating_sub(total)))
+            })
+            .map_or(0, |before| {
+                wanted.min(self.max_total_bytes.saturating_sub(before))
+            });
+        self.recorded += taken;
+        let kept = &bytes[..taken as usize];
+        let cut = kept.len() < bytes.len();
+        self.truncated[side] = cut;
+        let written = match &mut self.sinks {
+            Sinks::Held(held) => {
+                held[side].extend_from_slice(kept);
+                if cut {
+                    held[side].extend_from_slice(TRUNCATED);
+                }
+                Ok(())
+            }
+            Sinks::Files(files) => files[side].write_all(kept).and_then(|()| {
+                if cut {
+                    files[side].write_all(TRUNCATED)
+                } else {
+                    Ok(())
+                }
+            }),
+            Sinks::Off => Ok(()),
+        };
+        if let Err(err) = written {
+            self.fail(&err);
+        }
+    }
+
+    fn fail(&mut self, err: &io::Error) {
+        warn!(
+            "Stopped dumping {} into {}: {err}",
+            self.name,
+            self.dir.display()
+        );
+        self.sinks = Sinks::Off;
+    }
+}
+
+impl Drop for ConnectionDump {
+    /// Bytes held for a connection which was never dumped count no more.
+    fn drop(&mut self) {
+        if let Sinks::Held(_) = self.sinks {
+            self.total.fetch_sub(self.recorded, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A stream copying what is read from and written to it into its dump, if
+/// it has one.
+#[derive(Debug)]
+pub struct Dumped<T> {
+    inner: T,
+    dump: Option<ConnectionDump>,
+}
+
+impl<T> Dumped<T> {
+    pub fn new(inner: T, dump: Option<ConnectionDump>) -> Dumped<T> {
+        Dumped { inner, dump }
+    }
+
+    pub fn dump_mut(&mut self) -> Option<&mut ConnectionDump> {
+        self.dump.as_mut()
+    }
+}
+
+impl<R: Read> Read for Dumped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(dump) = &mut self.dump {
+            dump.record(Direction::Request, &buf[..read]);
+        }
+        Ok(read)
+    }
+}
+
+impl<W: Write> Write for Dumped<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(dump) = &mut self.dump {
+            dump.record(Direction::Response, &buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: Timeouts> Timeouts for Dumped<T> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::path::Path;
+
+    /// The files in `dir`, sorted, for the tests.
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_limits_truncate_with_a_marker() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let dumps = TrafficDump::new(dir.path())
+            .max_connection_bytes(10)
+            .max_total_bytes(16);
+        let dump = dumps.start(None, 1);
+        let (request, response) = {
+            let dump = dump.as_ref().expect("every connection is dumped");
+            (dump.request_path(), dump.response_path())
+        };
+        let mut stream = Dumped::new(&b"GET / HTTP/1.1\r\n"[..], dump);
+        let mut read = [0; 64];
+        assert_eq!(stream.read(&mut read)?, 16, "the client reads it all");
+        let mut sent = Dumped::new(Vec::new(), stream.dump.take());
+        sent.write_all(b"HTTP/1.1 200 OK\r\n")?;
+        drop(sent);
+        assert_eq!(
+            fs::read(&request)?,
+            [&b"GET / HTTP"[..], TRUNCATED].concat()
+        );
+        assert_eq!(fs::read(&response)?, TRUNCATED);
+
+        // Six bytes of the total are left for the next connections.
+        let mut next = Dumped::new(Vec::new(), dumps.start(None, 2));
+        next.write_all(b"HTTP/1.1 200 OK\r\n")?;
+        let path = next.dump.as_ref().map(ConnectionDump::response_path);
+        assert_eq!(
+            fs::read(path.unwrap())?,
+            [&b"HTTP/1"[..], TRUNCATED].concat()
+        );
+        dumps.set_enabled(false);
+        assert!(dumps.start(None, 3).is_none());
+        dumps.set_enabled(true);
+        assert_eq!(dumps.total.load(Ordering::Relaxed), 0, "counted afresh");
+        Ok(())
+    }
+
+    #[test]
+    fn test_filters() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let local: IpAddr = "127.0.0.1".parse()?;
+        let dumps = TrafficDump::new(dir.path())
+            .client("127.0.0.0/8")?
+            .path_prefix("/api/");
+        assert!(dumps.start(Some("192.0.2.1".parse()?), 1).is_none());
+        assert!(dumps.start(None, 2).is_none(), "no client to match");
+
+        let mut other = Dumped::new(
+            &b"GET /index.html HTTP/1.1\r\n\r\n"[..],
+            dumps.start(Some(local), 3),
+        );
+        io::copy(&mut other, &mut io::sink())?;
+        other.dump_mut().unwrap().request("/index.html");
+        drop(other);
+        assert!(listing(dir.path()).is_empty(), "held, then dropped");
+        assert_eq!(dumps.total.load(Ordering::Relaxed), 0);
+
+        let sent = b"GET /api HTTP/1.1\r\n\r\n";
+        let mut api = Dumped::new(&sent[..], dumps.start(Some(local), 4));
+        io::copy(&mut api, &mut io::sink())?;
+        api.dump_mut().unwrap().request("/api");
+        let dump = api.dump_mut().unwrap();
+        assert_eq!(fs::read(dump.request_path())?, sent);
+        let names = listing(dir.path());
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with("-4.request"), "{names:?}");
+        assert!(names[1].ends_with("-4.response"), "{names:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+//! Assets compiled into the binary with the `embedded-assets` feature.
+
+/// The embedded assets by their path relative to the document root.
+#[cfg(feature = "embedded-assets")]
+static ASSETS: &[(&str, &[u8])] = &[
+    ("hello.html", include_bytes!("../hello.html")),
+    ("404.html", include_bytes!("../404.html")),
+];
+
+#[cfg(not(feature = "embedded-assets"))]
+static ASSETS: &[(&str, &[u8])] = &[];
+
+/// The embedded asset at `path`, relative to the document root.
+pub(crate) fn get(path: &str) -> Option<(&'static str, &'static [u8])> {
+    ASSETS.iter().copied().find(|(name, _)| *name == path)
+}
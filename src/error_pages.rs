@@ -0,0 +1,195 @@
+//! Templates for the error pages the server answers with itself, in place of
+//! its built-in ones.
+//!
+//! A template is picked by the exact status of the error, or else by its
+//! class, such as `5xx`. It may use `{{status}}`, `{{reason}}`, `{{path}}` and
+//! `{{request_id}}`, all of them escaped for HTML.
+
+use std::{
+    error::Error,
+    fmt,
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::{
+    response::{reason_phrase, Body, Response},
+    template::{Missing, Template},
+};
+
+/// How often a template which cannot be rendered is warned about; the
+/// built-in page is served in between.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The statuses a template is used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codes {
+    Status(u16),
+    /// Every status starting with this digit.
+    Class(u16),
+}
+
+/// The templates for error pages, by status or class of status.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    pages: Vec<(Codes, PathBuf)>,
+}
+
+/// The statuses of an error page could not be understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidCodes(pub String);
+
+impl fmt::Display for InvalidCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is neither an error status nor a class like 5xx",
+            self.0
+        )
+    }
+}
+
+impl Error for InvalidCodes {}
+
+impl ErrorPages {
+    /// No templates, so that every error gets its built-in page.
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    /// Render the file at `template` for `codes`: an error status such as
+    /// `404`, or a class such as `5xx`. Statuses win over classes.
+    pub fn page(
+        mut self,
+        codes: &str,
+        template: impl Into<PathBuf>,
+    ) -> Result<ErrorPages, InvalidCodes> {
+        let invalid = || InvalidCodes(codes.to_string());
+        let parsed = match codes.to_ascii_lowercase().strip_suffix("xx") {
+            Some(class) if class.len() == 1 => class.parse().ok().map(Codes::Class),
+            None if codes.len() == 3 => codes.parse().ok().map(Codes::Status),
+            _ => None,
+        };
+        let codes = parsed.ok_or_else(invalid)?;
+        let first = match codes {
+            Codes::Status(status) => status / 100,
+            Codes::Class(class) => class,
+        };
+        if !(4..=5).contains(&first) {
+            return Err(invalid());
+        }
+        self.pages.push((codes, template.into()));
+        Ok(self)
+    }
+
+    /// Whether a template is configured for `status`.
+    pub fn covers(&self, status: u16) -> bool {
+        self.template(status).is_some()
+    }
+
+    fn template(&self, status: u16) -> Option<&PathBuf> {
+        let exact = self
+            .pages
+            .iter()
+            .find(|(codes, _)| *codes == Codes::Status(status));
+        exact
+            .or_else(|| {
+                self.pages
+                    .iter()
+                    .find(|(codes, _)| *codes == Codes::Class(status / 100))
+            })
+            .map(|(_, template)| template)
+    }
+
+    /// `response` with its built-in page replaced by the template for its
+    /// status, rendered for a request for `path` with `request_id`.
+    ///
+    /// Responses which are not built-in error pages, and those whose template
+    /// cannot be read or rendered, are left as they are.
+    pub fn apply(&self, response: Response, path: &str, request_id: &str) -> Response {
+        let status = response.status();
+        let Some(template) = self
+            .template(status)
+            .filter(|_| response.is_builtin_error())
+        else {
+            return response;
+        };
+        let status_code = status.to_string();
+        let values = [
+            ("status", status_code.as_str()),
+            ("reason", reason_phrase(status)),
+            ("path", path),
+            ("request_id", request_id),
+        ];
+        let page = Template::load(template)
+            .map(|template| template.missing(Missing::Keep))
+            .and_then(|template| template.render(&values));
+        match page {
+            Ok(page) => {
+                let mut response = response.with_body(Body::Bytes(page.into_bytes()));
+                let headers = response.headers_mut();
+                headers.insert("Content-Type", "text/html; charset=utf-8");
+                response
+            }
+            Err(err) => {
+                static WARNED: Mutex<Option<Instant>> = Mutex::new(None);
+                let mut warned = WARNED.lock().unwrap_or_else(PoisonError::into_inner);
+                if !warned.is_some_and(|at| at.elapsed() < WARNING_INTERVAL) {
+                    *warned = Some(Instant::now());
+                    warn!("Serving the built-in {status} page, as its template failed: {err}");
+                }
+                response
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn text(response: &Response) -> Result<String, Box<dyn std::error::Error>> {
+        let mut body = Vec::new();
+        response.body().write_to(&mut body)?;
+        Ok(String::from_utf8(body)?)
+    }
+
+    #[test]
+    fn test_templates_by_status_and_class() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let missing = dir.write("404.tmpl", "<p>{{status}} {{reason}}: {{path}}</p>");
+        let server = dir.write("5xx.tmpl", "<p>{{status}} {{reason}} {{request_id}}</p>");
+        let pages = ErrorPages::new()
+            .page("5XX", &server)?
+            .page("404", &missing)?
+            .page("503", dir.path().join("gone.tmpl"))?;
+        assert!(pages.covers(404) && pages.covers(500) && !pages.covers(403));
+
+        let page = pages.apply(Response::builtin_error(404), "/<script>", "abc");
+        assert_eq!(text(&page)?, "<p>404 NOT FOUND: /&lt;script&gt;</p>");
+        assert_eq!(
+            page.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        let page = pages.apply(Response::builtin_error(502), "/", "abc");
+        assert!(text(&page)?.ends_with(" abc</p>"));
+        assert!(!page.is_builtin_error());
+
+        let gone = pages.apply(Response::builtin_error(503), "/", "abc");
+        assert!(gone.is_builtin_error(), "a missing template keeps the page");
+        let handled = Response::new(404).with_body(Body::Bytes(b"mine".to_vec()));
+        assert_eq!(text(&pages.apply(handled, "/", "abc"))?, "mine");
+
+        for codes in ["200", "4x", "6xx", "40", "4044", "abc"] {
+            assert_eq!(
+                ErrorPages::new().page(codes, &server).err(),
+                Some(InvalidCodes(codes.to_string()))
+            );
+        }
+        Ok(())
+    }
+}
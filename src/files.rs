@@ -0,0 +1,1185 @@
+//! Responses backed by files on disk or assets embedded in the binary.
+
+use std::{
+    collections::hash_map::RandomState,
+    fs::{self, File, Metadata},
+    hash::{BuildHasher, Hasher},
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    cache::CachePolicy,
+    compression::{self, Encoding},
+    embedded,
+    http::{parse_query, Method, Request},
+    httpdate,
+    markdown::Markdown,
+    mime::{self, CharsetConfig},
+    range::{self, RangeRequest},
+    response::{Body, FileBody, MultipartBody, Response, Source},
+    security, spans,
+    template::escape_html,
+};
+
+/// Where [`StaticFiles`] looks for assets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetSource {
+    /// Only files under the document root.
+    Disk,
+    /// Only the assets compiled in with the `embedded-assets` feature.
+    Embedded,
+    /// Files under the document root, falling back to the embedded assets.
+    EmbeddedWithOverrides,
+}
+
+/// The files served for requests of a directory by default: `index.html`,
+/// or else `hello.html`, the page this server greeted with from the start.
+pub const INDEX: [&str; 2] = ["index.html", "hello.html"];
+
+/// What to answer for `/favicon.ico` when there is no such asset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum FaviconFallback {
+    /// A small icon compiled into the binary.
+    #[default]
+    Icon,
+    /// An empty `204 No Content` response.
+    NoContent,
+    /// The usual not found page.
+    NotFound,
+}
+
+/// The built-in icon sent by [`FaviconFallback::Icon`].
+static FAVICON: &[u8] = include_bytes!("favicon.ico");
+
+/// How long clients may cache the built-in favicon responses.
+const FAVICON_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// An asset found by [`StaticFiles::lookup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Asset {
+    File(PathBuf),
+    Embedded(&'static str, &'static [u8]),
+    /// A directory without an index file, listed when autoindex is enabled.
+    Directory(PathBuf),
+}
+
+impl std::fmt::Display for Asset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Asset::File(path) => write!(f, "{}", path.display()),
+            Asset::Embedded(name, _) => write!(f, "the embedded {name}"),
+            Asset::Directory(path) => write!(f, "the listing of {}", path.display()),
+        }
+    }
+}
+
+/// Serves files from disk or embedded assets according to its configuration.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    source: AssetSource,
+    max_ranges: usize,
+    cache_policy: CachePolicy,
+    precompressed: Vec<Encoding>,
+    charsets: CharsetConfig,
+    favicon: FaviconFallback,
+    index: Vec<String>,
+    autoindex: bool,
+    dotfiles: bool,
+    markdown: Option<Markdown>,
+    cross_origin_isolation: bool,
+}
+
+impl Default for StaticFiles {
+    fn default() -> StaticFiles {
+        StaticFiles::new()
+    }
+}
+
+impl StaticFiles {
+    /// Create a file server for the current directory with the default configuration.
+    ///
+    /// When built with the `embedded-assets` feature the embedded assets are
+    /// served for the files which are not on disk.
+    pub fn new() -> StaticFiles {
+        let source = if cfg!(feature = "embedded-assets") {
+            AssetSource::EmbeddedWithOverrides
+        } else {
+            AssetSource::Disk
+        };
+        StaticFiles {
+            root: PathBuf::from("."),
+            source,
+            max_ranges: 16,
+            cache_policy: CachePolicy::new(),
+            precompressed: vec![Encoding::Brotli, Encoding::Gzip],
+            charsets: CharsetConfig::new(),
+            favicon: FaviconFallback::Icon,
+            index: INDEX.iter().map(|name| name.to_string()).collect(),
+            autoindex: false,
+            dotfiles: false,
+            markdown: None,
+            cross_origin_isolation: false,
+        }
+    }
+
+    /// Serve files from the directory `root`.
+    pub fn root(mut self, root: impl Into<PathBuf>) -> StaticFiles {
+        self.root = root.into();
+        self
+    }
+
+    /// Choose whether assets come from disk, from the binary, or both.
+    pub fn source(mut self, source: AssetSource) -> StaticFiles {
+        self.source = source;
+        self
+    }
+
+    /// Answer requests for more than `max` ranges with the full file, 16 by default.
+    pub fn max_ranges(mut self, max: usize) -> StaticFiles {
+        self.max_ranges = max;
+        self
+    }
+
+    /// Choose the `Cache-Control` header of successful responses with `policy`.
+    pub fn cache_policy(mut self, policy: CachePolicy) -> StaticFiles {
+        self.cache_policy = policy;
+        self
+    }
+
+    /// Look for precompressed sidecars such as `app.js.br` in the given order of
+    /// preference, brotli before gzip by default. An empty list disables them.
+    pub fn precompressed(mut self, order: &[Encoding]) -> StaticFiles {
+        self.precompressed = order.to_vec();
+        self
+    }
+
+    /// Label text content types with the charset of `charsets`, UTF-8 by default.
+    pub fn charsets(mut self, charsets: CharsetConfig) -> StaticFiles {
+        self.charsets = charsets;
+        self
+    }
+
+    /// Choose what to answer for a missing `/favicon.ico`, the built-in icon by default.
+    pub fn favicon(mut self, fallback: FaviconFallback) -> StaticFiles {
+        self.favicon = fallback;
+        self
+    }
+
+    /// Serve the first of `names` which exists for requests of a directory,
+    /// the document root's included, [`INDEX`] by default.
+    pub fn index(mut self, names: &[&str]) -> StaticFiles {
+        self.index = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// List the contents of directories without an index file, off by default.
+    pub fn autoindex(mut self, enabled: bool) -> StaticFiles {
+        self.autoindex = enabled;
+        self
+    }
+
+    /// Serve files and directories whose names start with a dot, off by default.
+    pub fn dotfiles(mut self, allowed: bool) -> StaticFiles {
+        self.dotfiles = allowed;
+        self
+    }
+
+    /// Render `.md` files as HTML pages with `markdown`, off by default.
+    /// Clients still get the source with `?raw=1` or by accepting only
+    /// `text/markdown`.
+    pub fn markdown(mut self, markdown: Option<Markdown>) -> StaticFiles {
+        self.markdown = markdown;
+        self
+    }
+
+    /// Send the [`CROSS_ORIGIN_ISOLATION`](security::CROSS_ORIGIN_ISOLATION)
+    /// headers with every asset, off by default, for pages using
+    /// `SharedArrayBuffer`.
+    pub fn cross_origin_isolation(mut self, enabled: bool) -> StaticFiles {
+        self.cross_origin_isolation = enabled;
+        self
+    }
+
+    /// Whether the document root can be listed, or is not needed as the
+    /// embedded assets are served.
+    pub fn is_readable(&self) -> bool {
+        self.source != AssetSource::Disk || fs::read_dir(&self.root).is_ok()
+    }
+
+    /// The file under the root which `target` refers to, or the index file of
+    /// the directory it refers to.
+    ///
+    /// Targets climbing out of the root or naming hidden files resolve to nothing.
+    pub fn resolve(&self, target: &str) -> Option<PathBuf> {
+        let path = self.root.join(relative_path(target, self.dotfiles)?);
+        if path.is_dir() {
+            return self
+                .index
+                .iter()
+                .map(|name| path.join(name))
+                .find(|index| index.is_file());
+        }
+        path.is_file().then_some(path)
+    }
+
+    /// The asset which `target` refers to, from the configured sources.
+    pub fn lookup(&self, target: &str) -> Option<Asset> {
+        let embedded = || {
+            let name = relative_path(target, self.dotfiles)?;
+            let index = self.index.iter().map(|index| match name.as_str() {
+                "" => index.clone(),
+                dir => format!("{dir}/{index}"),
+            });
+            std::iter::once(name.clone())
+                .chain(index)
+                .find_map(|name| embedded::get(&name))
+                .map(|(name, bytes)| Asset::Embedded(name, bytes))
+        };
+        let disk = || {
+            self.resolve(target).map(Asset::File).or_else(|| {
+                let path = self.root.join(relative_path(target, self.dotfiles)?);
+                (self.autoindex && path.is_dir()).then_some(Asset::Directory(path))
+            })
+        };
+        match self.source {
+            AssetSource::Disk => disk(),
+            AssetSource::Embedded => embedded(),
+            AssetSource::EmbeddedWithOverrides => disk().or_else(embedded),
+        }
+    }
+
+    /// Whether `target` has no asset because the server may not look at the
+    /// file, or a directory on the way to it.
+    pub fn is_denied(&self, target: &str) -> bool {
+        let Some(path) = relative_path(target, self.dotfiles) else {
+            return false;
+        };
+        self.source != AssetSource::Embedded
+            && fs::metadata(self.root.join(path))
+                .is_err_and(|err| err.kind() == io::ErrorKind::PermissionDenied)
+    }
+
+    /// The response for a `target` which has no asset, if it is built in.
+    pub fn fallback(&self, target: &str) -> Option<Response> {
+        if target != "/favicon.ico" {
+            return None;
+        }
+        let response = match self.favicon {
+            FaviconFallback::Icon => Some(
+                Response::new(200)
+                    .with_header("Content-Type", "image/x-icon")
+                    .with_header("Cache-Control", FAVICON_CACHE_CONTROL)
+                    .with_body(Body::File(FileBody::new(FAVICON, 0, FAVICON.len() as u64))),
+            ),
+            FaviconFallback::NoContent => {
+                Some(Response::new(204).with_header("Cache-Control", FAVICON_CACHE_CONTROL))
+            }
+            FaviconFallback::NotFound => None,
+        };
+        response.map(|response| self.isolate(response))
+    }
+
+    /// Respond with `asset`, as [`serve`](StaticFiles::serve) does for files.
+    pub fn serve_asset(&self, request: &Request, asset: &Asset) -> io::Result<Response> {
+        let markdown = self.markdown.as_ref().filter(|_| is_markdown(asset));
+        let mut response = match (markdown, asset) {
+            (Some(markdown), Asset::File(path)) if !wants_source(request) => {
+                let metadata = fs::metadata(path)?;
+                let etag = file_etag(&metadata);
+                self.render(
+                    request,
+                    markdown,
+                    path,
+                    etag,
+                    last_modified(&metadata),
+                    || fs::read(path),
+                )?
+            }
+            (Some(markdown), Asset::Embedded(name, bytes)) if !wants_source(request) => {
+                let etag = content_etag(bytes);
+                self.render(request, markdown, Path::new(name), etag, None, || {
+                    Ok(bytes.to_vec())
+                })?
+            }
+            _ => self.serve_source(request, asset)?,
+        };
+        if markdown.is_some() {
+            response.headers_mut().add_vary("Accept");
+        }
+        Ok(self.isolate(response))
+    }
+
+    fn isolate(&self, mut response: Response) -> Response {
+        if self.cross_origin_isolation {
+            security::isolate(response.headers_mut());
+        }
+        response
+    }
+
+    fn serve_source(&self, request: &Request, asset: &Asset) -> io::Result<Response> {
+        match asset {
+            Asset::File(path) => self.serve(request, path),
+            Asset::Directory(path) => self.listing(request.path(), path),
+            Asset::Embedded(name, bytes) => {
+                let representation = Representation {
+                    path: Path::new(name),
+                    total: bytes.len() as u64,
+                    etag: content_etag(bytes),
+                    modified: None,
+                    encoding: None,
+                    varies: false,
+                };
+                self.respond(request, representation, || Ok(Source::Static(bytes)))
+            }
+        }
+    }
+
+    /// Respond with the file at `path`, honoring the request's `Range` header
+    /// and its `If-None-Match` and `If-Modified-Since` validators.
+    ///
+    /// A precompressed sidecar is sent instead when the client accepts its coding.
+    pub fn serve(&self, request: &Request, path: impl AsRef<Path>) -> io::Result<Response> {
+        let path = path.as_ref();
+        let (served, encoding, varies) = self.select_variant(request, path);
+        let metadata = fs::metadata(&served)?;
+        let representation = Representation {
+            path,
+            total: metadata.len(),
+            etag: file_etag(&metadata),
+            modified: last_modified(&metadata),
+            encoding,
+            varies,
+        };
+        self.respond(request, representation, || {
+            Ok(Source::File(File::open(&served)?))
+        })
+    }
+
+    /// Respond with `status` and the whole asset at `target` as the body, for
+    /// pages such as `/404.html`.
+    pub fn page(&self, status: u16, target: &str) -> io::Result<Response> {
+        let (path, source) = match self.lookup(target) {
+            Some(Asset::File(path)) => {
+                let file = File::open(&path)?;
+                (path, Source::File(file))
+            }
+            Some(Asset::Embedded(name, bytes)) => (PathBuf::from(name), Source::Static(bytes)),
+            Some(Asset::Directory(_)) | None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no asset for {target}"),
+                ))
+            }
+        };
+        let total = source.size()?;
+        Ok(Response::new(status)
+            .with_header("Content-Type", self.content_type(&path))
+            .with_body(Body::File(FileBody::new(source, 0, total))))
+    }
+
+    /// An HTML page linking to the entries of the directory at `path`, which
+    /// `target` refers to.
+    fn listing(&self, target: &str, path: &Path) -> io::Result<Response> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') && !self.dotfiles {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names.sort();
+
+        let base = target.strip_suffix('/').unwrap_or(target);
+        let title = format!("Index of {}/", escape_html(base));
+        let mut page = format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <title>{title}</title>\n  </head>\n  <body>\n    <h1>{title}</h1>\n    <ul>\n"
+        );
+        for name in names {
+            let name = escape_html(&name);
+            page.push_str(&format!(
+                "      <li><a href=\"{}/{name}\">{name}</a></li>\n",
+                escape_html(base)
+            ));
+        }
+        page.push_str("    </ul>\n  </body>\n</html>\n");
+        Ok(Response::new(200)
+            .with_header("Content-Type", self.charsets.apply("text/html"))
+            .with_body(Body::Bytes(page.into_bytes())))
+    }
+
+    /// The response for `representation`, whose contents `open` gives access to
+    /// unless the client's copy turns out to be current.
+    fn respond(
+        &self,
+        request: &Request,
+        representation: Representation,
+        open: impl FnOnce() -> io::Result<Source>,
+    ) -> io::Result<Response> {
+        let Representation {
+            path,
+            total,
+            etag,
+            modified,
+            encoding,
+            varies,
+        } = representation;
+
+        let mut response = if not_modified(request, &etag, modified) {
+            self.not_modified(path, self.content_type(path))
+        } else {
+            let ranged = range_applies(request, &etag, modified);
+            let response = self.body(request, ranged, open()?, total, &self.content_type(path));
+            match encoding {
+                Some(encoding) if response.status() != 416 => {
+                    response.with_header("Content-Encoding", encoding.token())
+                }
+                _ => response,
+            }
+        };
+
+        if response.status() != 416 {
+            self.add_validators(request, path, etag, modified, &mut response);
+        }
+        if varies {
+            response.headers_mut().add_vary("Accept-Encoding");
+        }
+        Ok(response)
+    }
+
+    /// The HTML page for the Markdown file at `path`, whose source `read`
+    /// gives unless the client's copy turns out to be current.
+    fn render(
+        &self,
+        request: &Request,
+        markdown: &Markdown,
+        path: &Path,
+        etag: String,
+        modified: Option<SystemTime>,
+        read: impl FnOnce() -> io::Result<Vec<u8>>,
+    ) -> io::Result<Response> {
+        // The page is another representation than the source.
+        let etag = format!("{}-html\"", etag.strip_suffix('"').unwrap_or(&etag));
+        let content_type = self.charsets.apply("text/html");
+        let mut response = if not_modified(request, &etag, modified) {
+            self.not_modified(path, content_type)
+        } else {
+            let source = read()?;
+            let fallback_title = path.file_stem().unwrap_or_default().to_string_lossy();
+            let page = markdown.page(&String::from_utf8_lossy(&source), &fallback_title);
+            Response::new(200)
+                .with_header("Content-Type", content_type)
+                .with_body(Body::Bytes(page.into_bytes()))
+        };
+        self.add_validators(request, path, etag, modified, &mut response);
+        Ok(response)
+    }
+
+    fn not_modified(&self, path: &Path, content_type: String) -> Response {
+        spans::debug_event!("The client's copy of {} is fresh", path.display());
+        // The type tells how the `200` would have varied, which the
+        // `304` has to as well.
+        Response::new(304).with_header("Content-Type", content_type)
+    }
+
+    /// Send the validators of the asset at `path` with `response`, and the
+    /// caching the policy has for it.
+    fn add_validators(
+        &self,
+        request: &Request,
+        path: &Path,
+        etag: String,
+        modified: Option<SystemTime>,
+        response: &mut Response,
+    ) {
+        let headers = response.headers_mut();
+        headers.append("ETag", etag);
+        if let Some(modified) = modified {
+            headers.append("Last-Modified", httpdate::format(modified));
+        }
+        if let Some(value) = self.cache_policy.lookup(request.path(), path) {
+            if !headers.contains("Cache-Control") {
+                headers.append("Cache-Control", value);
+            }
+        }
+    }
+
+    /// The content type of `path`, labelled with a charset where appropriate.
+    fn content_type(&self, path: &Path) -> String {
+        self.charsets.apply(mime::from_path(path))
+    }
+
+    /// The file to send for `path`, its content coding, and whether a sidecar
+    /// exists so that the response varies by `Accept-Encoding`.
+    fn select_variant(&self, request: &Request, path: &Path) -> (PathBuf, Option<Encoding>, bool) {
+        let accept_encoding = request.header("Accept-Encoding");
+        let mut varies = false;
+        for &encoding in &self.precompressed {
+            let mut sidecar = path.as_os_str().to_owned();
+            sidecar.push(".");
+            sidecar.push(encoding.extension());
+            let sidecar = PathBuf::from(sidecar);
+            if sidecar.is_file() {
+                varies = true;
+                if compression::accepts(accept_encoding, encoding) {
+                    return (sidecar, Some(encoding), true);
+                }
+            }
+        }
+        (path.to_path_buf(), None, varies)
+    }
+
+    /// The full or partial contents of `source`, as selected by the `Range`
+    /// header if it is `ranged`.
+    fn body(
+        &self,
+        request: &Request,
+        ranged: bool,
+        source: Source,
+        total: u64,
+        content_type: &str,
+    ) -> Response {
+        let range = match request.header("Range").filter(|_| ranged) {
+            Some(header) => range::parse_range(header, total, self.max_ranges),
+            None => RangeRequest::Full,
+        };
+
+        match range {
+            RangeRequest::Full => Response::new(200)
+                .with_header("Content-Type", content_type)
+                .with_header("Accept-Ranges", "bytes")
+                .with_body(Body::File(FileBody::new(source, 0, total))),
+            RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+                let range = ranges[0];
+                Response::new(206)
+                    .with_header("Content-Type", content_type)
+                    .with_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{total}", range.start, range.end),
+                    )
+                    .with_body(Body::File(FileBody::new(
+                        source,
+                        range.start,
+                        range.length(),
+                    )))
+            }
+            RangeRequest::Partial(ranges) => {
+                let boundary = boundary();
+                Response::new(206)
+                    .with_header(
+                        "Content-Type",
+                        format!("multipart/byteranges; boundary={boundary}"),
+                    )
+                    .with_body(Body::Multipart(MultipartBody::new(
+                        source,
+                        &ranges,
+                        total,
+                        content_type,
+                        &boundary,
+                    )))
+            }
+            RangeRequest::Unsatisfiable => {
+                Response::new(416).with_header("Content-Range", format!("bytes */{total}"))
+            }
+        }
+    }
+}
+
+/// What is known about an asset before deciding how to respond with it.
+struct Representation<'a> {
+    /// The path which the content type and cache policy are chosen by.
+    path: &'a Path,
+    total: u64,
+    etag: String,
+    modified: Option<SystemTime>,
+    encoding: Option<Encoding>,
+    varies: bool,
+}
+
+/// The path of `target` relative to the root, unless it tries to climb out of
+/// the root or names hidden files while `dotfiles` are not allowed.
+fn relative_path(target: &str, dotfiles: bool) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in target.strip_prefix('/')?.split('/') {
+        let hidden = segment.starts_with('.') && !dotfiles;
+        if hidden || segment == "." || segment == ".." || segment.contains(['\\', '\0']) {
+            return None;
+        }
+        if !segment.is_empty() {
+            segments.push(segment);
+        }
+    }
+    Some(segments.join("/"))
+}
+
+/// Whether `asset` is a Markdown file.
+fn is_markdown(asset: &Asset) -> bool {
+    let path = match asset {
+        Asset::File(path) => path.as_path(),
+        Asset::Embedded(name, _) => Path::new(name),
+        Asset::Directory(_) => return false,
+    };
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("md"))
+}
+
+/// Whether the client asked for the source of a Markdown file rather than
+/// its page, with `?raw=1` or an `Accept` header of `text/markdown`.
+fn wants_source(request: &Request) -> bool {
+    let raw = parse_query(request.query())
+        .iter()
+        .any(|(name, value)| name == "raw" && value == "1");
+    let accepts_markdown = request.header("Accept").is_some_and(|accept| {
+        accept.split(',').any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            media_type.eq_ignore_ascii_case("text/markdown")
+                && !params.any(|param| {
+                    param.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0)
+                })
+        })
+    });
+    raw || accepts_markdown
+}
+
+/// Whether the request's validators show that the client's copy is current.
+///
+/// `If-Modified-Since` is only consulted when no `If-None-Match` was sent.
+fn not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return false;
+    }
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        return etag_matches(if_none_match, etag);
+    }
+    match (
+        request
+            .header("If-Modified-Since")
+            .and_then(httpdate::parse),
+        modified,
+    ) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
+
+/// Whether the `Range` of `request` is honored: always without `If-Range`,
+/// else only if its validator shows the client's part is of the current
+/// version, by strong comparison for an ETag.
+fn range_applies(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.header("If-Range").map(str::trim) else {
+        return true;
+    };
+    if if_range.starts_with('"') {
+        return !etag.starts_with("W/") && if_range == etag;
+    }
+    match (httpdate::parse(if_range), modified) {
+        (Some(date), Some(modified)) => date >= modified,
+        _ => false,
+    }
+}
+
+/// The file's modification time, truncated to whole seconds.
+fn last_modified(metadata: &Metadata) -> Option<SystemTime> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs()))
+}
+
+/// A validator derived from the file's size and modification time, down to
+/// the nanosecond, which changes with every write.
+fn file_etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos());
+    format!("\"{:x}-{modified:x}\"", metadata.len())
+}
+
+/// Whether the `If-None-Match` value `header` matches `etag` by weak comparison.
+fn etag_matches(header: &str, etag: &str) -> bool {
+    let header = header.trim();
+    header == "*"
+        || header
+            .split(',')
+            .any(|candidate| opaque_tag(candidate.trim()) == opaque_tag(etag))
+}
+
+fn opaque_tag(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+/// A validator derived from the contents of an embedded asset.
+fn content_etag(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("\"{:x}-{hash:x}\"", bytes.len())
+}
+
+/// A random multipart boundary, which is vanishingly unlikely to occur in a file.
+fn boundary() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    format!("hello-{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{http::Version, test_util::TempDir};
+
+    fn get(path: &str) -> Request {
+        Request::new(Method::Get, path, Version::Http11)
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = "W/\"5-1a\"";
+        assert!(etag_matches(etag, etag));
+        assert!(etag_matches("\"5-1a\"", etag), "comparison should be weak");
+        assert!(etag_matches("\"0-0\", W/\"5-1a\"", etag));
+        assert!(etag_matches(" * ", etag));
+        assert!(!etag_matches("W/\"5-1b\"", etag));
+    }
+
+    #[test]
+    fn test_if_none_match_not_modified() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("page.html", "first version");
+        let files = StaticFiles::new();
+
+        let first = files.serve(&get("/page.html"), &path)?;
+        assert_eq!(first.status(), 200);
+        let etag = first
+            .headers()
+            .get("ETag")
+            .expect("an etag should be sent")
+            .to_string();
+        assert!(etag.starts_with('"'), "strong, for If-Range");
+
+        let request = get("/page.html").with_header("If-None-Match", &etag);
+        let second = files.serve(&request, &path)?;
+        assert_eq!(second.status(), 304);
+        assert_eq!(second.headers().get("ETag"), Some(etag.as_str()));
+        assert!(second.body().is_empty());
+        assert_eq!(second.content_length(), None);
+
+        fs::write(&path, "second, longer version")?;
+        let third = files.serve(&request, &path)?;
+        assert_eq!(third.status(), 200);
+        assert_ne!(third.headers().get("ETag"), Some(etag.as_str()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_modified_since() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("page.html", "contents");
+        let files = StaticFiles::new();
+
+        let first = files.serve(&get("/page.html"), &path)?;
+        let modified = first
+            .headers()
+            .get("Last-Modified")
+            .expect("a date should be sent")
+            .to_string();
+        assert!(httpdate::parse(&modified).is_some());
+
+        let current = files.serve(
+            &get("/page.html").with_header("If-Modified-Since", &modified),
+            &path,
+        )?;
+        assert_eq!(current.status(), 304);
+        assert_eq!(
+            current.headers().get("Last-Modified"),
+            Some(modified.as_str())
+        );
+
+        let stale = files.serve(
+            &get("/page.html").with_header("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT"),
+            &path,
+        )?;
+        assert_eq!(stale.status(), 200);
+
+        let etag_wins = get("/page.html")
+            .with_header("If-None-Match", "W/\"other\"")
+            .with_header("If-Modified-Since", &modified);
+        assert_eq!(
+            files.serve(&etag_wins, &path)?.status(),
+            200,
+            "If-None-Match takes precedence"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_range() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("video.bin", "0123456789");
+        let files = StaticFiles::new();
+        let first = files.serve(&get("/video.bin"), &path)?;
+        let header = |name| first.headers().get(name).unwrap_or_default().to_string();
+        let (etag, modified) = (header("ETag"), header("Last-Modified"));
+        let resume = |if_range: &str| -> io::Result<u16> {
+            let request = get("/video.bin")
+                .with_header("Range", "bytes=4-")
+                .with_header("If-Range", if_range);
+            Ok(files.serve(&request, &path)?.status())
+        };
+
+        assert_eq!(resume(&etag)?, 206);
+        assert_eq!(resume(&modified)?, 206);
+        assert_eq!(resume("Thu, 01 Jan 2099 00:00:00 GMT")?, 206, "not earlier");
+        assert_eq!(resume("Thu, 01 Jan 1970 00:00:00 GMT")?, 200);
+        assert_eq!(resume(&format!("W/{etag}"))?, 200, "weak never matches");
+        assert_eq!(resume("yesterday")?, 200, "invalid");
+
+        fs::write(&path, "0123456789, and then some")?;
+        let full = files.serve(
+            &get("/video.bin")
+                .with_header("Range", "bytes=4-")
+                .with_header("If-Range", &etag),
+            &path,
+        )?;
+        assert_eq!(full.status(), 200, "the file changed");
+        assert_eq!(full.body().len(), 25);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_policy_applies_to_not_modified() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("app.css", "body {}");
+        let files = StaticFiles::new()
+            .cache_policy(CachePolicy::new().rule("*.css", "public, max-age=600"));
+
+        let first = files.serve(&get("/app.css"), &path)?;
+        assert_eq!(
+            first.headers().get("Cache-Control"),
+            Some("public, max-age=600")
+        );
+
+        let etag = first.headers().get("ETag").unwrap();
+        let second = files.serve(&get("/app.css").with_header("If-None-Match", etag), &path)?;
+        assert_eq!(second.status(), 304);
+        assert_eq!(
+            second.headers().get("Cache-Control"),
+            Some("public, max-age=600")
+        );
+
+        let uncached = StaticFiles::new().serve(&get("/app.css"), &path)?;
+        assert!(!uncached.headers().contains("Cache-Control"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_stays_inside_root() {
+        let dir = TempDir::new();
+        let path = dir.write("static/app.js", "app");
+        dir.write(".secret", "hidden");
+        let files = StaticFiles::new().root(dir.path());
+
+        assert_eq!(files.resolve("/static/app.js"), Some(path));
+        assert_eq!(files.resolve("/static"), None, "directories are not files");
+        assert_eq!(files.resolve("/static/../../etc/passwd"), None);
+        assert_eq!(files.resolve("/.secret"), None);
+        assert_eq!(files.resolve("static/app.js"), None);
+    }
+
+    #[test]
+    fn test_index_and_dotfiles() {
+        let dir = TempDir::new();
+        let index = dir.write("docs/index.htm", "docs");
+        let hidden = dir.write(".well-known/security.txt", "contact");
+        let files = StaticFiles::new()
+            .root(dir.path())
+            .source(AssetSource::Disk);
+
+        assert_eq!(files.resolve("/docs/"), None, "index.html is the default");
+        let files = files.index(&["index.html", "index.htm"]);
+        assert_eq!(files.resolve("/docs/"), Some(index.clone()));
+        assert_eq!(files.resolve("/docs"), Some(index));
+
+        assert_eq!(files.resolve("/.well-known/security.txt"), None);
+        let files = files.dotfiles(true);
+        assert_eq!(files.resolve("/.well-known/security.txt"), Some(hidden));
+        assert_eq!(files.resolve("/docs/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn test_autoindex() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        dir.write("files/b <b>.txt", "b");
+        dir.write("files/a.txt", "a");
+        dir.write("files/nested/c.txt", "c");
+        dir.write("files/.hidden", "hidden");
+        let files = StaticFiles::new()
+            .root(dir.path())
+            .source(AssetSource::Disk);
+        assert_eq!(files.lookup("/files/"), None, "autoindex is off by default");
+
+        let files = files.autoindex(true);
+        let asset = files.lookup("/files/").expect("the directory is listed");
+        let response = files.serve_asset(&get("/files/"), &asset)?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        let mut page = Vec::new();
+        response.body().write_to(&mut page)?;
+        let page = String::from_utf8(page)?;
+        let links: Vec<_> = page.lines().filter(|line| line.contains("<li>")).collect();
+        assert_eq!(
+            links,
+            [
+                "      <li><a href=\"/files/a.txt\">a.txt</a></li>",
+                "      <li><a href=\"/files/b &lt;b&gt;.txt\">b &lt;b&gt;.txt</a></li>",
+                "      <li><a href=\"/files/nested/\">nested/</a></li>",
+            ]
+        );
+        assert!(files.page(404, "/files/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_precompressed_sidecars() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("app.js", "plain");
+        dir.write("app.js.gz", "gzipped");
+        dir.write("app.js.br", "brotli!!");
+        let files = StaticFiles::new();
+
+        let cases = [
+            (None, None, "plain"),
+            (Some("identity"), None, "plain"),
+            (Some("gzip"), Some("gzip"), "gzipped"),
+            (Some("br"), Some("br"), "brotli!!"),
+            (Some("gzip, br"), Some("br"), "brotli!!"),
+            (Some("gzip, br;q=0"), Some("gzip"), "gzipped"),
+        ];
+        for (accept_encoding, encoding, contents) in cases {
+            let mut request = get("/app.js");
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.with_header("Accept-Encoding", accept_encoding);
+            }
+            let response = files.serve(&request, &path)?;
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                response.headers().get("Content-Encoding"),
+                encoding,
+                "for {accept_encoding:?}"
+            );
+            assert_eq!(
+                response.headers().get("Content-Type"),
+                Some("text/javascript; charset=utf-8")
+            );
+            assert_eq!(response.headers().get("Vary"), Some("Accept-Encoding"));
+            assert_eq!(response.content_length(), Some(contents.len() as u64));
+        }
+
+        let gzip_first = StaticFiles::new().precompressed(&[Encoding::Gzip, Encoding::Brotli]);
+        let response = gzip_first.serve(
+            &get("/app.js").with_header("Accept-Encoding", "br, gzip"),
+            &path,
+        )?;
+        assert_eq!(response.headers().get("Content-Encoding"), Some("gzip"));
+
+        let plain = files.serve(&get("/app.js"), &path)?;
+        let brotli = files.serve(&get("/app.js").with_header("Accept-Encoding", "br"), &path)?;
+        assert_ne!(plain.headers().get("ETag"), brotli.headers().get("ETag"));
+
+        let lonely = dir.write("other.js", "plain");
+        let response = files.serve(
+            &get("/other.js").with_header("Accept-Encoding", "gzip"),
+            &lonely,
+        )?;
+        assert_eq!(response.headers().get("Content-Encoding"), None);
+        assert_eq!(response.headers().get("Vary"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_types_have_no_charset() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("logo.png", "\u{89}PNG");
+
+        let response = StaticFiles::new().serve(&get("/logo.png"), &path)?;
+        assert_eq!(response.headers().get("Content-Type"), Some("image/png"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_favicon_fallback() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let files = StaticFiles::new()
+            .root(dir.path())
+            .source(AssetSource::Disk);
+
+        let icon = files.fallback("/favicon.ico").expect("an icon is built in");
+        assert_eq!(icon.status(), 200);
+        assert_eq!(icon.headers().get("Content-Type"), Some("image/x-icon"));
+        assert_eq!(icon.content_length(), Some(FAVICON.len() as u64));
+
+        let empty = files.clone().favicon(FaviconFallback::NoContent);
+        assert_eq!(
+            empty.fallback("/favicon.ico").map(|r| r.status()),
+            Some(204)
+        );
+        assert!(files
+            .clone()
+            .favicon(FaviconFallback::NotFound)
+            .fallback("/favicon.ico")
+            .is_none());
+        assert!(files.fallback("/other.ico").is_none());
+
+        let path = dir.write("favicon.ico", "custom");
+        assert_eq!(files.lookup("/favicon.ico"), Some(Asset::File(path)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lookup_by_source() {
+        let dir = TempDir::new();
+        let path = dir.write("hello.html", "from disk");
+
+        let disk = StaticFiles::new()
+            .root(dir.path())
+            .source(AssetSource::Disk);
+        assert_eq!(disk.lookup("/hello.html"), Some(Asset::File(path.clone())));
+        assert_eq!(disk.lookup("/missing.html"), None);
+
+        let overrides = disk.clone().source(AssetSource::EmbeddedWithOverrides);
+        assert_eq!(overrides.lookup("/hello.html"), Some(Asset::File(path)));
+    }
+
+    #[test]
+    fn test_markdown_pages() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let path = dir.write("notes.md", "# Notes\n\n<script>alert(1)</script>\n");
+        let asset = Asset::File(path);
+        let files = StaticFiles::new()
+            .root(dir.path())
+            .source(AssetSource::Disk)
+            .markdown(Some(Markdown::new()));
+
+        let page = files.serve_asset(&get("/notes.md"), &asset)?;
+        assert_eq!(page.status(), 200);
+        assert_eq!(
+            page.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(page.headers().get("Vary"), Some("Accept"));
+        let Body::Bytes(body) = page.body() else {
+            panic!("the page is rendered");
+        };
+        let body = String::from_utf8(body.clone())?;
+        assert!(body.contains("<title>Notes</title>"), "{body}");
+        assert!(
+            body.contains("<h1>Notes</h1>\n<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>"),
+            "{body}"
+        );
+
+        let etag = page.headers().get("ETag").expect("the page has an etag");
+        assert!(page.headers().contains("Last-Modified"));
+        let fresh = get("/notes.md").with_header("If-None-Match", etag);
+        let not_modified = files.serve_asset(&fresh, &asset)?;
+        assert_eq!(not_modified.status(), 304);
+        assert_eq!(
+            not_modified.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        for raw in [
+            get("/notes.md?raw=1"),
+            get("/notes.md").with_header("Accept", "text/markdown"),
+        ] {
+            let source = files.serve_asset(&raw, &asset)?;
+            assert_eq!(source.status(), 200);
+            assert_eq!(
+                source.headers().get("Content-Type"),
+                Some("text/markdown; charset=utf-8")
+            );
+            assert_ne!(
+                source.headers().get("ETag"),
+                Some(etag),
+                "another representation"
+            );
+            assert_eq!(source.headers().get("Vary"), Some("Accept"));
+        }
+        let refused = get("/notes.md").with_header("Accept", "text/markdown;q=0, text/html");
+        assert_eq!(
+            files.serve_asset(&refused, &asset)?.headers().get("ETag"),
+            Some(etag)
+        );
+
+        let plain = files
+            .markdown(None)
+            .serve_asset(&get("/notes.md"), &asset)?;
+        assert_eq!(plain.headers().get("Vary"), None, "served as it is");
+        Ok(())
+    }
+
+    #[cfg(feature = "embedded-assets")]
+    #[test]
+    fn test_embedded_assets_without_disk() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let files = StaticFiles::new().root(dir.path());
+
+        let asset = files.lookup("/hello.html").expect("hello.html is embedded");
+        let response = files.serve_asset(&get("/hello.html"), &asset)?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            response.content_length(),
+            Some(include_bytes!("../hello.html").len() as u64)
+        );
+
+        let etag = response
+            .headers()
+            .get("ETag")
+            .expect("an etag should be sent");
+        let request = get("/hello.html").with_header("If-None-Match", etag);
+        assert_eq!(files.serve_asset(&request, &asset)?.status(), 304);
+
+        let not_found = files.page(404, "/404.html")?;
+        assert_eq!(
+            not_found.content_length(),
+            Some(include_bytes!("../404.html").len() as u64)
+        );
+
+        dir.write("hello.html", "patched");
+        dir.write("notes.txt", "only on disk");
+        let asset = files.lookup("/hello.html").unwrap();
+        assert!(
+            matches!(asset, Asset::File(_)),
+            "disk overrides embedded assets"
+        );
+        assert!(matches!(files.lookup("/notes.txt"), Some(Asset::File(_))));
+
+        let embedded = files.source(AssetSource::Embedded);
+        assert!(matches!(
+            embedded.lookup("/hello.html"),
+            Some(Asset::Embedded(..))
+        ));
+        assert_eq!(embedded.lookup("/notes.txt"), None);
+        Ok(())
+    }
+}
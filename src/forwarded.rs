@@ -0,0 +1,236 @@
+//! The headers proxies pass the client on in: believing those of the proxies
+//! in front of the server, and sending them when forwarding requests.
+
+use std::net::IpAddr;
+
+use crate::{access::AccessError, cidr::Cidr, http::Headers};
+
+/// Which headers tell upstreams about the client of a forwarded request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ForwardedHeaders {
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`.
+    #[default]
+    XForwarded,
+    /// The RFC 7239 `Forwarded` header.
+    Forwarded,
+    Both,
+}
+
+/// The proxies in front of the server, whose forwarded headers are believed.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<Cidr>,
+}
+
+impl TrustedProxies {
+    /// Trust no proxy, so that every client is the peer it connects from.
+    pub fn new() -> TrustedProxies {
+        TrustedProxies::default()
+    }
+
+    /// Believe the forwarded headers of peers in `cidr`, such as `10.0.0.0/8`.
+    pub fn trust(mut self, cidr: &str) -> Result<TrustedProxies, AccessError> {
+        let range = cidr
+            .parse()
+            .map_err(|()| AccessError::InvalidCidr(cidr.to_string()))?;
+        self.ranges.push(range);
+        Ok(self)
+    }
+
+    fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    /// The client behind `peer` which sent a request with `headers`.
+    ///
+    /// Only trusted peers are believed, as anyone else could forge the headers.
+    /// Their `X-Forwarded-For`, or else the `for` parameters of `Forwarded`,
+    /// are walked back from the last hop to the first address which is not a
+    /// trusted proxy. Walking stops at an address which does not parse.
+    pub fn client_ip(&self, headers: &Headers, peer: Option<IpAddr>) -> Option<IpAddr> {
+        let mut client = peer?;
+        if !self.is_trusted(client) {
+            return Some(client);
+        }
+        for hop in forwarded_for(headers).into_iter().rev() {
+            let Some(hop) = hop else { break };
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+}
+
+/// The addresses `headers` say the request was forwarded for, first hop first,
+/// none for those which are not addresses.
+fn forwarded_for(headers: &Headers) -> Vec<Option<IpAddr>> {
+    if headers.contains("X-Forwarded-For") {
+        return headers
+            .get_all("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .map(|hop| hop.trim().parse().ok())
+            .collect();
+    }
+    headers
+        .get_all("Forwarded")
+        .flat_map(|value| value.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, node)| node_ip(node.trim()))
+        })
+        .collect()
+}
+
+/// The address of a `Forwarded` node such as `192.0.2.1:80` or
+/// `"[2001:db8::1]:80"`, without its port.
+fn node_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+    match node.strip_prefix('[') {
+        Some(rest) => rest.split(']').next()?.parse().ok(),
+        None => node.split(':').next()?.parse().ok(),
+    }
+}
+
+/// The headers telling an upstream that the request with `headers` came from
+/// `peer`, over TLS if `secure`.
+///
+/// They replace those of the same names in the request: the chains of
+/// `X-Forwarded-For` and `Forwarded` are extended with this hop, the others
+/// describe the request as the server got it.
+pub(crate) fn headers(
+    headers: &Headers,
+    peer: Option<IpAddr>,
+    secure: bool,
+    which: ForwardedHeaders,
+) -> Vec<(&'static str, String)> {
+    let proto = if secure { "https" } else { "http" };
+    let host = headers.get("Host");
+    let mut added = Vec::new();
+    if which != ForwardedHeaders::Forwarded {
+        let chain: Vec<String> = headers
+            .get_all("X-Forwarded-For")
+            .map(str::to_string)
+            .chain(peer.map(|peer| peer.to_string()))
+            .collect();
+        if !chain.is_empty() {
+            added.push(("X-Forwarded-For", chain.join(", ")));
+        }
+        added.push(("X-Forwarded-Proto", proto.to_string()));
+        if let Some(host) = host {
+            added.push(("X-Forwarded-Host", host.to_string()));
+        }
+    }
+    if which != ForwardedHeaders::XForwarded {
+        let node = match peer {
+            Some(IpAddr::V6(ip)) => format!("\"[{ip}]\""),
+            Some(ip) => ip.to_string(),
+            None => "unknown".to_string(),
+        };
+        let mut element = format!("for={node};proto={proto}");
+        if let Some(host) = host {
+            element.push_str(&format!(";host=\"{}\"", host.replace(['"', '\\'], "")));
+        }
+        let chain: Vec<&str> = headers
+            .get_all("Forwarded")
+            .chain([element.as_str()])
+            .collect();
+        added.push(("Forwarded", chain.join(", ")));
+    }
+    added
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(fields: &[(&str, &str)]) -> Headers {
+        let mut headers = Headers::new();
+        for (name, value) in fields {
+            headers.append(name, *value);
+        }
+        headers
+    }
+
+    #[test]
+    fn test_only_trusted_peers_name_the_client() -> Result<(), Box<dyn std::error::Error>> {
+        let trusted = TrustedProxies::new().trust("10.0.0.0/8")?.trust("::1")?;
+        let proxy = "10.0.0.1".parse().ok();
+        let stranger = "203.0.113.9".parse().ok();
+        let ip = |ip: &str| ip.parse::<IpAddr>().ok();
+
+        let spoofed = with(&[("X-Forwarded-For", "192.0.2.1")]);
+        assert_eq!(trusted.client_ip(&spoofed, stranger), stranger);
+        assert_eq!(TrustedProxies::new().client_ip(&spoofed, proxy), proxy);
+        assert_eq!(trusted.client_ip(&spoofed, proxy), ip("192.0.2.1"));
+
+        let chain = with(&[
+            ("X-Forwarded-For", "198.51.100.7, 192.0.2.1"),
+            ("X-Forwarded-For", "10.0.0.2"),
+        ]);
+        assert_eq!(
+            trusted.client_ip(&chain, proxy),
+            ip("192.0.2.1"),
+            "the client may forge the start of the chain"
+        );
+        let garbled = with(&[("X-Forwarded-For", "192.0.2.1, nonsense, 10.0.0.2")]);
+        assert_eq!(trusted.client_ip(&garbled, proxy), ip("10.0.0.2"));
+
+        let forwarded = with(&[("Forwarded", "for=192.0.2.1, for=\"[::1]:8080\";proto=https")]);
+        assert_eq!(trusted.client_ip(&forwarded, proxy), ip("192.0.2.1"));
+        assert_eq!(trusted.client_ip(&forwarded, None), None);
+
+        assert_eq!(
+            TrustedProxies::new().trust("10.0.0.0/33").unwrap_err(),
+            AccessError::InvalidCidr("10.0.0.0/33".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_extend_the_chains() {
+        let peer = "192.0.2.1".parse().ok();
+        let fresh = with(&[("Host", "a.test")]);
+        assert_eq!(
+            headers(&fresh, peer, false, ForwardedHeaders::XForwarded),
+            [
+                ("X-Forwarded-For", "192.0.2.1".to_string()),
+                ("X-Forwarded-Proto", "http".to_string()),
+                ("X-Forwarded-Host", "a.test".to_string()),
+            ]
+        );
+
+        let forwarded = with(&[
+            ("X-Forwarded-For", "198.51.100.7"),
+            ("Forwarded", "for=198.51.100.7"),
+        ]);
+        let v6 = "2001:db8::1".parse().ok();
+        assert_eq!(
+            headers(&forwarded, v6, true, ForwardedHeaders::Both),
+            [
+                ("X-Forwarded-For", "198.51.100.7, 2001:db8::1".to_string()),
+                ("X-Forwarded-Proto", "https".to_string()),
+                (
+                    "Forwarded",
+                    "for=198.51.100.7, for=\"[2001:db8::1]\";proto=https".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            headers(&fresh, None, false, ForwardedHeaders::Forwarded),
+            [(
+                "Forwarded",
+                "for=unknown;proto=http;host=\"a.test\"".to_string()
+            )]
+        );
+    }
+}
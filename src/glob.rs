@@ -0,0 +1,98 @@
+//! Glob-style patterns over request paths.
+
+/// Match `text` against `pattern`, where `*` stops at `/` but `**` does not.
+///
+/// The pattern is matched a piece at a time against every prefix of the text
+/// at once, so that matching takes time in proportion to the product of their
+/// lengths rather than backtracking, as the text can be any request path.
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    // Whether the pattern so far matches the first `i` bytes, for each `i`.
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    let mut next = vec![false; text.len() + 1];
+    let mut pattern = pattern;
+    while !pattern.is_empty() {
+        match pattern {
+            [b'*', b'*', rest @ ..] => {
+                let mut reached = false;
+                for (end, next) in next.iter_mut().enumerate() {
+                    reached |= matched[end];
+                    *next = reached;
+                }
+                pattern = rest;
+            }
+            [b'*', rest @ ..] => {
+                let mut reached = false;
+                for (end, next) in next.iter_mut().enumerate() {
+                    reached |= matched[end];
+                    *next = reached;
+                    if text.get(end) == Some(&b'/') {
+                        reached = false;
+                    }
+                }
+                pattern = rest;
+            }
+            [first, rest @ ..] => {
+                next[0] = false;
+                for (end, next) in next.iter_mut().enumerate().skip(1) {
+                    *next = matched[end - 1] && text[end - 1] == *first;
+                }
+                pattern = rest;
+            }
+            [] => unreachable!(),
+        }
+        std::mem::swap(&mut matched, &mut next);
+    }
+    matched[text.len()]
+}
+
+/// Match `pattern` against `request_path`, or against its last segment when
+/// the pattern doesn't start with `/`.
+pub(crate) fn path_matches(pattern: &str, request_path: &str) -> bool {
+    if pattern.starts_with('/') {
+        glob_match(pattern.as_bytes(), request_path.as_bytes())
+    } else {
+        let name = request_path.rsplit('/').next().unwrap_or("");
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"/static/*.css", b"/static/app.1f2e.css"));
+        assert!(!glob_match(b"/static/*.css", b"/static/sub/app.css"));
+        assert!(glob_match(b"/static/**", b"/static/sub/app.css"));
+        assert!(glob_match(b"*.html", b"hello.html"));
+        assert!(!glob_match(b"*.html", b"hello.htm"));
+    }
+
+    #[test]
+    fn test_many_stars_match_in_bounded_time() {
+        let text = format!("/{}", "a".repeat(8 * 1024));
+        let started = std::time::Instant::now();
+        assert!(!glob_match(
+            b"/**a**a**a**a**a**a**a**a**b",
+            text.as_bytes()
+        ));
+        assert!(!glob_match(b"/*a*a*a*a*a*a*a*a*b", text.as_bytes()));
+        assert!(glob_match(b"/**a*a**a", text.as_bytes()));
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+
+        assert!(glob_match(b"", b""));
+        assert!(!glob_match(b"", b"a"));
+        assert!(glob_match(b"/**/*.css", b"/a/b/c.css"));
+        assert!(!glob_match(b"/*/*.css", b"/a/b/c.css"));
+        assert!(glob_match(b"/a***", b"/a/b"));
+    }
+
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("*.zip", "/downloads/archive.zip"));
+        assert!(path_matches("/events", "/events"));
+        assert!(!path_matches("/events", "/events/1"));
+    }
+}
@@ -0,0 +1,148 @@
+//! Headers added to responses by path, declared in the configuration instead
+//! of in handlers, like the `_headers` files of static site hosts.
+//!
+//! Where several rules set the same header for a path, the most specific
+//! pattern wins: the one with the most characters which are not wildcards,
+//! then the one with the fewest `*`s, then the one declared first. A header
+//! the response already has is kept unless the winning rule is forced.
+
+use std::collections::HashSet;
+
+use crate::{glob::path_matches, http::Headers};
+
+/// One pattern and the headers its paths get.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderRule {
+    pattern: String,
+    headers: Vec<(String, String)>,
+    force: bool,
+}
+
+impl HeaderRule {
+    /// A rule for the paths matching `pattern`, like `/downloads/*` or
+    /// `/api/**`, adding nothing yet.
+    pub fn new(pattern: &str) -> HeaderRule {
+        HeaderRule {
+            pattern: pattern.to_string(),
+            headers: Vec::new(),
+            force: false,
+        }
+    }
+
+    /// Add `name: value` to the responses.
+    pub fn header(mut self, name: &str, value: &str) -> HeaderRule {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Replace the headers the response already has as well.
+    pub fn force(mut self, force: bool) -> HeaderRule {
+        self.force = force;
+        self
+    }
+
+    /// How specific the pattern is, the most specific the largest.
+    fn specificity(&self) -> (usize, usize) {
+        let stars = self.pattern.bytes().filter(|byte| *byte == b'*').count();
+        (self.pattern.len() - stars, usize::MAX - stars)
+    }
+}
+
+/// The rules for every path, in the order they were declared.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+}
+
+impl HeaderRules {
+    pub fn new() -> HeaderRules {
+        HeaderRules::default()
+    }
+
+    /// Apply `rule` as well.
+    pub fn rule(mut self, rule: HeaderRule) -> HeaderRules {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Add the headers of the rules matching `path` to `headers`.
+    pub fn apply(&self, path: &str, headers: &mut Headers) {
+        let mut matching: Vec<&HeaderRule> = self
+            .rules
+            .iter()
+            .filter(|rule| path_matches(&rule.pattern, path))
+            .collect();
+        // Stable, so that of equally specific rules the first one wins.
+        matching.sort_by_key(|rule| std::cmp::Reverse(rule.specificity()));
+        let mut decided = HashSet::new();
+        for rule in matching {
+            for (name, value) in &rule.headers {
+                if !decided.insert(name.to_ascii_lowercase()) {
+                    continue;
+                }
+                if rule.force || !headers.contains(name) {
+                    headers.insert(name, value.as_str());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_rule_wins() {
+        let rules = HeaderRules::new()
+            .rule(
+                HeaderRule::new("/**")
+                    .header("X-Robots-Tag", "all")
+                    .header("Cache-Control", "no-cache"),
+            )
+            .rule(HeaderRule::new("/api/**").header("X-Robots-Tag", "noindex"))
+            .rule(HeaderRule::new("/api/*").header("X-Robots-Tag", "none"))
+            .rule(HeaderRule::new("/api/*").header("X-Robots-Tag", "later"));
+
+        let mut headers = Headers::new();
+        rules.apply("/api/users", &mut headers);
+        assert_eq!(headers.get("X-Robots-Tag"), Some("none"));
+        assert_eq!(headers.get("Cache-Control"), Some("no-cache"));
+
+        let mut headers = Headers::new();
+        rules.apply("/api/users/1", &mut headers);
+        assert_eq!(headers.get("X-Robots-Tag"), Some("noindex"));
+
+        let mut headers = Headers::new();
+        rules.apply("/index.html", &mut headers);
+        assert_eq!(headers.get("X-Robots-Tag"), Some("all"));
+    }
+
+    #[test]
+    fn test_set_headers_stay_unless_forced() {
+        let rules = HeaderRules::new()
+            .rule(HeaderRule::new("/fonts/*").header("Access-Control-Allow-Origin", "*"))
+            .rule(
+                HeaderRule::new("/downloads/*")
+                    .header("Content-Disposition", "attachment")
+                    .force(true),
+            );
+
+        let mut headers = Headers::new();
+        headers.insert("Access-Control-Allow-Origin", "https://a.test");
+        rules.apply("/fonts/a.woff2", &mut headers);
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://a.test")
+        );
+
+        let mut headers = Headers::new();
+        headers.insert("Content-Disposition", "inline");
+        rules.apply("/downloads/a.zip", &mut headers);
+        assert_eq!(headers.get("Content-Disposition"), Some("attachment"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Probes a load balancer sends to learn whether the server is alive and
+//! ready for traffic, answered without touching the document root unless
+//! readiness asks for it.
+
+use crate::{
+    http::{Method, Request},
+    response::{Body, Response},
+};
+
+/// Which probe a request asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// Whether the server runs and is not stopping.
+    Liveness,
+    /// Whether it can also take jobs and read its document root.
+    Readiness,
+}
+
+/// The paths the probes are answered on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthChecks {
+    liveness: Option<String>,
+    readiness: Option<String>,
+    logged: bool,
+}
+
+impl HealthChecks {
+    /// Answer liveness on `/healthz` and readiness on `/readyz`, leaving
+    /// both out of the access log.
+    pub fn new() -> HealthChecks {
+        HealthChecks {
+            liveness: Some("/healthz".to_string()),
+            readiness: Some("/readyz".to_string()),
+            logged: false,
+        }
+    }
+
+    /// Answer the liveness probe on `path`, or not at all.
+    pub fn liveness(mut self, path: Option<&str>) -> HealthChecks {
+        self.liveness = path.map(str::to_string);
+        self
+    }
+
+    /// Answer the readiness probe on `path`, or not at all.
+    pub fn readiness(mut self, path: Option<&str>) -> HealthChecks {
+        self.readiness = path.map(str::to_string);
+        self
+    }
+
+    /// Log the probes like any other request, off by default.
+    pub fn log_requests(mut self, logged: bool) -> HealthChecks {
+        self.logged = logged;
+        self
+    }
+
+    pub fn logs_requests(&self) -> bool {
+        self.logged
+    }
+
+    /// The probe `request` asks for, if it is a `GET` of one.
+    pub fn probe(&self, request: &Request) -> Option<Probe> {
+        if request.method() != &Method::Get {
+            return None;
+        }
+        let target = Some(request.path());
+        if self.liveness.as_deref() == target {
+            Some(Probe::Liveness)
+        } else if self.readiness.as_deref() == target {
+            Some(Probe::Readiness)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for HealthChecks {
+    fn default() -> HealthChecks {
+        HealthChecks::new()
+    }
+}
+
+/// The answer to a probe: `200 OK` if the server is `healthy`, else
+/// `503 Service Unavailable`.
+pub fn respond(healthy: bool) -> Response {
+    let (status, text) = if healthy {
+        (200, "ok\n")
+    } else {
+        (503, "unavailable\n")
+    };
+    Response::new(status)
+        .with_header("Content-Type", "text/plain; charset=utf-8")
+        .with_header("Cache-Control", "no-store")
+        .with_body(Body::Bytes(text.as_bytes().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Version;
+
+    #[test]
+    fn test_probes_by_method_and_path() {
+        let checks = HealthChecks::new().readiness(Some("/ready"));
+        let probe = |method, target| checks.probe(&Request::new(method, target, Version::Http11));
+        assert_eq!(probe(Method::Get, "/healthz"), Some(Probe::Liveness));
+        assert_eq!(probe(Method::Get, "/ready"), Some(Probe::Readiness));
+        assert_eq!(probe(Method::Head, "/ready"), None);
+        assert_eq!(probe(Method::Get, "/readyz"), None);
+        assert_eq!(probe(Method::Post, "/healthz"), None);
+        assert_eq!(probe(Method::Get, "/healthz/"), None);
+
+        let checks = checks.liveness(None);
+        assert_eq!(
+            checks.probe(&Request::new(Method::Get, "/healthz", Version::Http11)),
+            None
+        );
+    }
+}
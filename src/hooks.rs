@@ -0,0 +1,138 @@
+//! Callbacks observing every request and the response it got, for accounting
+//! which does not belong in the server.
+//!
+//! Hooks run on the worker answering the request, in the order they were
+//! registered. A hook which panics is logged and skipped; the request is
+//! answered all the same.
+
+use std::{
+    any::Any,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    time::Duration,
+};
+
+use log::error;
+
+use crate::http::Request;
+
+type RequestHook = Box<dyn Fn(&Request) + Send + Sync>;
+type ResponseHook = Box<dyn Fn(&Request, &ResponseMeta) + Send + Sync>;
+
+/// What response hooks learn about the response a request got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseMeta {
+    pub status: u16,
+    /// The bytes sent, the head included.
+    pub bytes: u64,
+    /// How long reading, answering and sending took.
+    pub duration: Duration,
+}
+
+/// The hooks called for each request, before it is answered and once the
+/// response went out.
+#[derive(Default)]
+pub struct Hooks {
+    on_request: Vec<RequestHook>,
+    on_response: Vec<ResponseHook>,
+}
+
+impl Hooks {
+    pub fn new() -> Hooks {
+        Hooks::default()
+    }
+
+    /// Call `hook` with each request once it was read.
+    pub fn on_request(&mut self, hook: impl Fn(&Request) + Send + Sync + 'static) {
+        self.on_request.push(Box::new(hook));
+    }
+
+    /// Call `hook` with each request and its response once it was sent.
+    pub fn on_response(&mut self, hook: impl Fn(&Request, &ResponseMeta) + Send + Sync + 'static) {
+        self.on_response.push(Box::new(hook));
+    }
+
+    /// Whether no hook is registered.
+    pub fn is_empty(&self) -> bool {
+        self.on_request.is_empty() && self.on_response.is_empty()
+    }
+
+    /// Run the request hooks for `request`.
+    pub fn request(&self, request: &Request) {
+        for hook in &self.on_request {
+            guarded("request", request, || hook(request));
+        }
+    }
+
+    /// Run the response hooks for `request`, answered as `meta` says.
+    pub fn response(&self, request: &Request, meta: &ResponseMeta) {
+        for hook in &self.on_response {
+            guarded("response", request, || hook(request, meta));
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_request", &self.on_request.len())
+            .field("on_response", &self.on_response.len())
+            .finish()
+    }
+}
+
+/// Run `hook`, logging it if it panics.
+fn guarded(kind: &str, request: &Request, hook: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(hook)) {
+        error!(
+            "A {kind} hook panicked on {} {}: {}",
+            request.method(),
+            request.target(),
+            panic_message(payload.as_ref())
+        );
+    }
+}
+
+/// The message `panic!` was given, if it was a string.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_in_order_past_panics() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut hooks = Hooks::new();
+        for name in ["first", "panicking", "last"] {
+            let calls = Arc::clone(&calls);
+            hooks.on_request(move |request| {
+                assert_ne!(name, "panicking", "on purpose");
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("{name} {}", request.path()));
+            });
+        }
+        let seen = Arc::clone(&calls);
+        hooks.on_response(move |_, meta| seen.lock().unwrap().push(meta.status.to_string()));
+
+        let request = Request::new(Method::Get, "/a", Version::Http11);
+        hooks.request(&request);
+        let meta = ResponseMeta {
+            status: 204,
+            bytes: 10,
+            duration: Duration::ZERO,
+        };
+        hooks.response(&request, &meta);
+        assert_eq!(*calls.lock().unwrap(), ["first /a", "last /a", "204"]);
+    }
+}
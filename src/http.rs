@@ -0,0 +1,1520 @@
+//! Parsing of HTTP/1.x requests and the types shared with responses.
+
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read},
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::{
+    net::ConnectionInfo,
+    spool::{BodyContents, RequestBody},
+};
+
+/// The longest request line or header line that will be accepted.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// The maximum number of header fields in a single request.
+const MAX_HEADERS: usize = 100;
+
+/// All errors which can occur while reading a request from a stream.
+#[derive(Debug)]
+pub enum ParseError {
+    MalformedRequestLine,
+    MalformedStatusLine,
+    MalformedHeader,
+    LineTooLong,
+    TooManyHeaders,
+    HeadTooLarge,
+    /// The input ended before the head did.
+    Incomplete,
+    Io(io::Error),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::Io(err)
+    }
+}
+
+/// The request method.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Options,
+    Trace,
+    Connect,
+    Patch,
+    Other(String),
+}
+
+impl Method {
+    fn parse(token: &str) -> Method {
+        match token {
+            "GET" => Method::Get,
+            "HEAD" => Method::Head,
+            "POST" => Method::Post,
+            "PUT" => Method::Put,
+            "DELETE" => Method::Delete,
+            "OPTIONS" => Method::Options,
+            "TRACE" => Method::Trace,
+            "CONNECT" => Method::Connect,
+            "PATCH" => Method::Patch,
+            other => Method::Other(other.to_string()),
+        }
+    }
+
+    /// The method as it appears on the wire.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Connect => "CONNECT",
+            Method::Patch => "PATCH",
+            Method::Other(token) => token,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The protocol version of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    fn parse(token: &str) -> Option<Version> {
+        match token {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
+        }
+    }
+
+    /// The version as it appears on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Header fields in the order they were added, looked up case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Create an empty set of headers.
+    pub fn new() -> Headers {
+        Headers { fields: Vec::new() }
+    }
+
+    /// The value of the first field called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The values of all fields called `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether any field called `name` lists `token` in its comma separated
+    /// value, compared case-insensitively.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Record that the response was chosen by the request's `name` header,
+    /// for caches to tell apart the responses to requests differing in it.
+    pub fn add_vary(&mut self, name: &str) {
+        self.append("Vary", name);
+        self.combine_vary();
+    }
+
+    /// Merge the `Vary` fields into one which lists each header once, or is
+    /// `*` if one of them is.
+    pub fn combine_vary(&mut self) {
+        let mut names: Vec<String> = Vec::new();
+        for name in self.get_all("Vary").flat_map(|value| value.split(',')) {
+            let name = name.trim();
+            if !name.is_empty() && !names.iter().any(|seen| seen.eq_ignore_ascii_case(name)) {
+                names.push(name.to_string());
+            }
+        }
+        if names.iter().any(|name| name == "*") {
+            self.insert("Vary", "*");
+        } else if !names.is_empty() {
+            self.insert("Vary", names.join(", "));
+        }
+    }
+
+    /// Whether a field called `name` is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Set `name` to `value`, replacing any existing fields of that name.
+    pub fn insert(&mut self, name: &str, value: impl Into<String>) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Add a field called `name`, keeping any existing fields of that name.
+    pub fn append(&mut self, name: &str, value: impl Into<String>) {
+        self.fields.push((name.to_string(), value.into()));
+    }
+
+    /// Remove all fields called `name`, returning the first value.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.fields.retain(|(field, value)| {
+            if field.eq_ignore_ascii_case(name) {
+                removed.get_or_insert_with(|| value.clone());
+                false
+            } else {
+                true
+            }
+        });
+        removed
+    }
+
+    /// Iterate over all fields as `(name, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+/// The form of a request target, which decides how it is looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetForm {
+    /// A path with an optional query, like `/index.html?lang=en`.
+    Origin,
+    /// A whole URL, as sent to proxies.
+    Absolute,
+    /// A host and port, as `CONNECT` sends.
+    Authority,
+    /// `*`, with which `OPTIONS` asks about the server rather than a resource.
+    Asterisk,
+}
+
+impl TargetForm {
+    fn of(target: &str) -> TargetForm {
+        if target == "*" {
+            TargetForm::Asterisk
+        } else if target.starts_with('/') {
+            TargetForm::Origin
+        } else if target.contains("://") {
+            TargetForm::Absolute
+        } else {
+            TargetForm::Authority
+        }
+    }
+}
+
+/// A parsed request head, with its body once that was read.
+#[derive(Debug, Clone)]
+pub struct Request {
+    method: Method,
+    target: String,
+    form: TargetForm,
+    /// The path in its normalized form, once it was normalized.
+    path: Option<String>,
+    version: Version,
+    headers: Headers,
+    id: String,
+    connection: ConnectionInfo,
+    client_ip: Option<IpAddr>,
+    user: Option<String>,
+    body: RequestBody,
+}
+
+impl Request {
+    /// Create a request without reading it from a stream.
+    pub fn new(method: Method, target: &str, version: Version) -> Request {
+        Request {
+            method,
+            target: target.to_string(),
+            form: TargetForm::of(target),
+            path: None,
+            version,
+            headers: Headers::new(),
+            id: String::new(),
+            connection: ConnectionInfo::default(),
+            client_ip: None,
+            user: None,
+            body: RequestBody::Empty,
+        }
+    }
+
+    /// Add a header field, for building requests by hand.
+    pub fn with_header(mut self, name: &str, value: &str) -> Request {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Identify the request as `id` in logs and to the client.
+    pub fn with_id(mut self, id: impl Into<String>) -> Request {
+        self.id = id.into();
+        self
+    }
+
+    /// Take the request to have come over `connection`.
+    pub fn with_connection(mut self, connection: ConnectionInfo) -> Request {
+        self.connection = connection;
+        self
+    }
+
+    /// Take `ip` for the address of the client, if it is known.
+    pub fn with_client_ip(mut self, ip: Option<IpAddr>) -> Request {
+        self.client_ip = ip;
+        self
+    }
+
+    /// Take `user` for whom the client authenticated as.
+    pub fn with_user(mut self, user: impl Into<String>) -> Request {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Take `body` for the body of the request.
+    pub fn with_body(mut self, body: RequestBody) -> Request {
+        self.body = body;
+        self
+    }
+
+    /// Read the next request head from `reader`.
+    ///
+    /// Returns `Ok(None)` when the stream ends before a request starts. A stream
+    /// which ends inside the header section is treated as the end of the headers.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Option<Request>, ParseError> {
+        Request::read_limited(reader, usize::MAX)
+    }
+
+    /// Read the next request head like [`read_from`](Request::read_from), but
+    /// fail once it grows beyond `max_size` bytes.
+    ///
+    /// The head is parsed by [`parse_request_bytes`] once it is buffered,
+    /// which each read only scans the new bytes for.
+    pub fn read_limited<R: BufRead>(
+        reader: &mut R,
+        max_size: usize,
+    ) -> Result<Option<Request>, ParseError> {
+        let mut head = Vec::new();
+        let mut scan = HeadScan::default();
+        loop {
+            let available = reader.fill_buf()?;
+            let at_end = available.is_empty();
+            if at_end && head.is_empty() {
+                return Ok(None);
+            }
+            // One byte beyond the limit tells a head which is too large.
+            let wanted = max_size.saturating_add(1).saturating_sub(head.len());
+            let taken = available.len().min(wanted);
+            head.extend_from_slice(&available[..taken]);
+            let before = head.len() - taken;
+            if !at_end && !scan.is_complete(&head) {
+                if head.len() > max_size {
+                    return Err(ParseError::HeadTooLarge);
+                }
+                reader.consume(taken);
+                continue;
+            }
+            match parse_head(&head, at_end) {
+                Ok((request, consumed)) if consumed <= max_size => {
+                    reader.consume(consumed.saturating_sub(before));
+                    return Ok(Some(request));
+                }
+                Ok(_) => return Err(ParseError::HeadTooLarge),
+                Err(ParseError::Incomplete) if head.len() > max_size => {
+                    return Err(ParseError::HeadTooLarge)
+                }
+                Err(ParseError::Incomplete) => reader.consume(taken),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    /// The request target exactly as it was sent.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The path of the target, which routes and files are looked up by:
+    /// without the query, and without a fragment clients should not send.
+    /// Decoded and without dot segments once [normalized](Request::normalize).
+    pub fn path(&self) -> &str {
+        if let Some(path) = &self.path {
+            return path;
+        }
+        let end = self.target.find(['?', '#']).unwrap_or(self.target.len());
+        &self.target[..end]
+    }
+
+    /// Take the path as [`normalize_path`] makes it from now on, that of
+    /// an absolute target included; false, leaving it as it was, if it cannot
+    /// be normalized. Other targets are left alone.
+    pub fn normalize(&mut self, merge_slashes: bool) -> bool {
+        let absolute = self.form == TargetForm::Absolute;
+        if !(self.form == TargetForm::Origin || absolute) || self.path.is_some() {
+            return true;
+        }
+        self.path = normalize_path(origin_form(self.path()), merge_slashes);
+        self.path.is_some()
+    }
+
+    /// The query string of the target, without the `?` and a fragment, empty
+    /// if there is none.
+    pub fn query(&self) -> &str {
+        let target = self.target.split('#').next().unwrap_or("");
+        target.split_once('?').map_or("", |(_, query)| query)
+    }
+
+    /// Whether the target is a path, or another form which must not be
+    /// looked up as one.
+    pub fn target_form(&self) -> TargetForm {
+        self.form
+    }
+
+    /// The identifier the server gave the request, empty for requests built
+    /// by hand.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The address of the client: the peer, or whom the trusted proxies in
+    /// front forwarded the request for. None for requests built by hand or
+    /// received over Unix sockets.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    /// The address the request came from, which may be a proxy's; none for
+    /// requests built by hand or received over Unix sockets.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.connection.peer_addr()
+    }
+
+    /// The address of the server's end of the connection, none for requests
+    /// built by hand or received over Unix sockets.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.connection.local_addr()
+    }
+
+    /// The name the client authenticated as, if the path asks for it.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    /// The body, empty until the server read it for the handler.
+    pub fn body(&self) -> &RequestBody {
+        &self.body
+    }
+
+    /// Read the body from its start, whether it is in memory or was spooled
+    /// to a file.
+    pub fn body_reader(&self) -> io::Result<BodyContents<'_>> {
+        self.body.reader()
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The value of the first header called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Whether the client is willing to send further requests on the connection.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+
+    /// Whether the body is framed so that its end can be told: by one
+    /// length however often it is given, or by chunks without a length.
+    pub fn has_valid_framing(&self) -> bool {
+        framing(&self.headers, Framing::Length(0)) != Framing::Invalid
+    }
+}
+
+/// The names and values of a query string or form body, in order, with `+`
+/// read as a space and percent escapes decoded, invalid UTF-8 replaced.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_form(name), decode_form(value))
+        })
+        .collect()
+}
+
+/// `text` from a query string, with `+` as a space and `%XX` decoded; other
+/// `%`s are kept as they are.
+fn decode_form(text: &str) -> String {
+    let mut decoded = Vec::with_capacity(text.len());
+    let mut bytes = text.as_bytes().iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'+' => decoded.push(b' '),
+            b'%' => match bytes.as_slice() {
+                [high, low, ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                    decoded.push(hex_value(*high) << 4 | hex_value(*low));
+                    bytes.nth(1);
+                }
+                _ => decoded.push(b'%'),
+            },
+            byte => decoded.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `path` with its `%XX` escapes decoded and its `.` and `..` segments
+/// removed, and with runs of slashes collapsed if `merge_slashes`; none if
+/// it has broken escapes, decodes to something other than UTF-8 without
+/// NULs, or climbs above the root.
+pub fn normalize_path(path: &str, merge_slashes: bool) -> Option<String> {
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.as_bytes().iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'%' => match bytes.as_slice() {
+                [high, low, ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                    decoded.push(hex_value(*high) << 4 | hex_value(*low));
+                    bytes.nth(1);
+                }
+                _ => return None,
+            },
+            byte => decoded.push(byte),
+        }
+    }
+    let decoded = String::from_utf8(decoded)
+        .ok()
+        .filter(|path| !path.contains('\0'))?;
+
+    // Remove the dot segments as RFC 3986 does, but refuse to climb above
+    // the root where it would stay there.
+    let mut segments = Vec::new();
+    let mut rest = decoded.strip_prefix('/')?.split('/').peekable();
+    while let Some(segment) = rest.next() {
+        let last = rest.peek().is_none();
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            "" if merge_slashes && !last => continue,
+            segment => {
+                segments.push(segment);
+                continue;
+            }
+        }
+        // A path ending in a dot segment names a directory.
+        if last {
+            segments.push("");
+        }
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// `path` with `%XX` escapes for the bytes which may not appear in a path
+/// as they are, the inverse of the decoding [`normalize_path`] does.
+pub fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for &byte in path.as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => encoded.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'!' | b'$' | b'&' | b'\'' | b'(' | b')' => {
+                encoded.push(byte as char)
+            }
+            b'*' | b'+' | b',' | b';' | b'=' | b':' | b'@' | b'/' => encoded.push(byte as char),
+            byte => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn hex_value(digit: u8) -> u8 {
+    (digit as char).to_digit(16).unwrap_or(0) as u8
+}
+
+/// Parse the request head at the start of `input`, returning the request and
+/// the bytes its head took up, the blank line ending it included.
+///
+/// Fails with [`ParseError::Incomplete`] when `input` ends before the head
+/// does. Whatever the input, it does not panic, reads no further than the
+/// line and header limits, and allocates no more than they allow.
+pub fn parse_request_bytes(input: &[u8]) -> Result<(Request, usize), ParseError> {
+    parse_head(input, false)
+}
+
+/// Parse a request head like [`parse_request_bytes`]; when `at_end`, nothing
+/// follows `input`, so that a head it cuts short ends with it.
+fn parse_head(input: &[u8], at_end: bool) -> Result<(Request, usize), ParseError> {
+    let mut lines = Lines {
+        rest: input,
+        consumed: 0,
+        at_end,
+    };
+    let request_line = lines.next_line()?.ok_or(ParseError::Incomplete)?;
+    let mut request = parse_request_line(&request_line)?;
+    while let Some(line) = lines.next_line()? {
+        if line.is_empty() {
+            break;
+        }
+        parse_field(&line, &mut request.headers)?;
+    }
+    Ok((request, lines.consumed))
+}
+
+/// The request a request line asks for, without its headers.
+fn parse_request_line(line: &str) -> Result<Request, ParseError> {
+    let mut parts = line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None)
+            if !method.is_empty() && !target.is_empty() =>
+        {
+            (method, target, version)
+        }
+        _ => return Err(ParseError::MalformedRequestLine),
+    };
+    let version = Version::parse(version).ok_or(ParseError::MalformedRequestLine)?;
+    let method = Method::parse(method);
+    if target == "*" && method != Method::Options {
+        return Err(ParseError::MalformedRequestLine);
+    }
+    Ok(Request::new(method, target, version))
+}
+
+/// How far a head being buffered was scanned for the blank line ending it.
+#[derive(Debug, Default)]
+struct HeadScan {
+    scanned: usize,
+    /// Where the line being scanned started.
+    line_start: usize,
+}
+
+impl HeadScan {
+    /// Whether `head`, which grew from what was scanned before, ends with a
+    /// blank line, or has a line too long for [`parse_head`] to take; only
+    /// the bytes added since are looked at.
+    fn is_complete(&mut self, head: &[u8]) -> bool {
+        for (at, &byte) in head.iter().enumerate().skip(self.scanned) {
+            if byte != b'\n' {
+                continue;
+            }
+            let line = &head[self.line_start..at];
+            self.line_start = at + 1;
+            if line.is_empty() || line == b"\r" {
+                self.scanned = at + 1;
+                return true;
+            }
+        }
+        self.scanned = head.len();
+        // A line may take up its limit and the line ending: CR and LF.
+        head.len() - self.line_start >= MAX_LINE_LENGTH + 2
+    }
+}
+
+/// The lines of a buffered head, ended by LF or CRLF.
+struct Lines<'a> {
+    rest: &'a [u8],
+    /// The bytes of the lines returned so far, line endings included.
+    consumed: usize,
+    /// Whether nothing follows `rest`, so that its last line ends with it.
+    at_end: bool,
+}
+
+impl Lines<'_> {
+    /// The next line without its line ending, none once `rest` ran out at
+    /// the end of the input.
+    fn next_line(&mut self) -> Result<Option<String>, ParseError> {
+        // A line may take up its limit and the line ending: CR and LF.
+        let longest = MAX_LINE_LENGTH + 2;
+        let newline = self
+            .rest
+            .iter()
+            .take(longest)
+            .position(|&byte| byte == b'\n');
+        let (line, len) = match newline {
+            Some(end) => {
+                let line = self.rest.get(..end).unwrap_or_default();
+                (line.strip_suffix(b"\r").unwrap_or(line), end + 1)
+            }
+            None if self.rest.len() >= longest => return Err(ParseError::LineTooLong),
+            None if !self.at_end => return Err(ParseError::Incomplete),
+            None if self.rest.len() > MAX_LINE_LENGTH => return Err(ParseError::LineTooLong),
+            None if self.rest.is_empty() => return Ok(None),
+            None => (self.rest, self.rest.len()),
+        };
+        self.rest = self.rest.get(len..).unwrap_or_default();
+        self.consumed += len;
+        String::from_utf8(line.to_vec())
+            .map(Some)
+            .or(Err(ParseError::MalformedHeader))
+    }
+}
+
+/// `target` without the scheme and authority of the absolute form.
+pub(crate) fn origin_form(target: &str) -> &str {
+    let Some(rest) = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"))
+    else {
+        return target;
+    };
+    match rest.find(['/', '?']) {
+        Some(at) if rest[at..].starts_with('/') => &rest[at..],
+        _ => "/",
+    }
+}
+
+/// `path` with its runs of slashes collapsed into one, as files are looked up
+/// whether or not routing keeps them, for what guards paths to match it.
+pub(crate) fn merge_slashes(path: &str) -> Cow<'_, str> {
+    if !path.contains("//") {
+        return Cow::Borrowed(path);
+    }
+    let mut merged = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !merged.ends_with('/') {
+            merged.push(c);
+        }
+    }
+    Cow::Owned(merged)
+}
+
+/// Read the status line and headers of a response from `reader`, failing once
+/// they grow beyond `max_size` bytes.
+///
+/// Returns `Ok(None)` when the stream ends before the status line.
+pub fn read_response_head<R: BufRead>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<(u16, Headers)>, ParseError> {
+    let mut budget = max_size;
+    let status_line = match read_line(reader, &mut budget)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let mut parts = status_line.splitn(3, ' ');
+    let status = match (parts.next(), parts.next()) {
+        (Some("HTTP/1.1" | "HTTP/1.0"), Some(code)) if code.len() == 3 => code.parse().ok(),
+        _ => None,
+    };
+    let status = status
+        .filter(|status| (100..=999).contains(status))
+        .ok_or(ParseError::MalformedStatusLine)?;
+    let mut headers = Headers::new();
+    read_fields(reader, &mut budget, &mut headers)?;
+    Ok(Some((status, headers)))
+}
+
+/// Read a header block without a start line, like the one CGI scripts print,
+/// failing once it grows beyond `max_size` bytes.
+pub fn read_header_block<R: BufRead>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Headers, ParseError> {
+    let mut budget = max_size;
+    let mut headers = Headers::new();
+    read_fields(reader, &mut budget, &mut headers)?;
+    Ok(headers)
+}
+
+/// Read header fields into `headers` up to and including the blank line
+/// ending them, or the end of the stream.
+fn read_fields<R: BufRead>(
+    reader: &mut R,
+    budget: &mut usize,
+    headers: &mut Headers,
+) -> Result<(), ParseError> {
+    while let Some(line) = read_line(reader, budget)? {
+        if line.is_empty() {
+            break;
+        }
+        parse_field(&line, headers)?;
+    }
+    Ok(())
+}
+
+/// Add the header field on `line` to `headers`.
+fn parse_field(line: &str, headers: &mut Headers) -> Result<(), ParseError> {
+    if headers.len() == MAX_HEADERS {
+        return Err(ParseError::TooManyHeaders);
+    }
+    let (name, value) = line.split_once(':').ok_or(ParseError::MalformedHeader)?;
+    if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+        return Err(ParseError::MalformedHeader);
+    }
+    headers.append(name, value.trim());
+    Ok(())
+}
+
+/// How the end of a message body is found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// This many bytes are left.
+    Length(u64),
+    /// Chunked, with this many bytes left of the current chunk, none before
+    /// the first chunk size was read.
+    Chunked(Option<u64>),
+    /// Everything up to the end of the stream.
+    UntilClose,
+    /// The body ended.
+    Done,
+    /// The framing is not understood, so nothing can be read.
+    Invalid,
+}
+
+/// Reads one message body from `R` and no further, removing chunked
+/// transfer coding, so that the next message can be read after it.
+#[derive(Debug)]
+pub struct BodyReader<R> {
+    inner: R,
+    framing: Framing,
+    trailers: Headers,
+}
+
+impl<R: BufRead> BodyReader<R> {
+    /// The body of a request with `headers`, empty without a `Content-Length`
+    /// or chunked `Transfer-Encoding`, and unreadable with any other.
+    pub fn request(inner: R, headers: &Headers) -> BodyReader<R> {
+        BodyReader {
+            inner,
+            framing: framing(headers, Framing::Length(0)),
+            trailers: Headers::new(),
+        }
+    }
+
+    /// The body of a response with `headers`, which without a length runs
+    /// until the connection closes.
+    pub fn response(inner: R, headers: &Headers) -> BodyReader<R> {
+        BodyReader {
+            inner,
+            framing: framing(headers, Framing::UntilClose),
+            trailers: Headers::new(),
+        }
+    }
+
+    /// The number of bytes left, if the body announced its length.
+    pub fn remaining(&self) -> Option<u64> {
+        match self.framing {
+            Framing::Length(remaining) => Some(remaining),
+            Framing::Done => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Whether the body is chunked.
+    pub fn is_chunked(&self) -> bool {
+        matches!(self.framing, Framing::Chunked(_))
+    }
+
+    /// Whether the framing allows reading the body.
+    pub fn is_valid(&self) -> bool {
+        self.framing != Framing::Invalid
+    }
+
+    /// The fields of the trailer section after the last chunk, which are
+    /// there once the body was read to its end.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// Whether the whole body was read, so that the stream is at the next message.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.framing, Framing::Done | Framing::Length(0))
+    }
+
+    /// Read the size line of the next chunk, and the trailer section after the last.
+    fn next_chunk(&mut self) -> io::Result<u64> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut budget = MAX_LINE_LENGTH;
+        let line = read_line(&mut self.inner, &mut budget)
+            .map_err(|_| invalid("malformed chunk size"))?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        // Hex digits alone, where from_str_radix would also take a sign.
+        let size = Some(size)
+            .filter(|size| size.bytes().all(|byte| byte.is_ascii_hexdigit()))
+            .and_then(|size| u64::from_str_radix(size, 16).ok())
+            .ok_or_else(|| invalid("malformed chunk size"))?;
+        if size == 0 {
+            read_fields(&mut self.inner, &mut budget, &mut self.trailers)
+                .map_err(|_| invalid("malformed trailer section"))?;
+        }
+        Ok(size)
+    }
+}
+
+/// The framing `headers` announce, `unframed` without any.
+fn framing(headers: &Headers, unframed: Framing) -> Framing {
+    let length = content_length(headers);
+    if let Some(codings) = headers.get("Transfer-Encoding") {
+        // A proxy in front may have gone by the length instead, and taken
+        // the rest of the body for another request.
+        if length.is_some() && unframed != Framing::UntilClose {
+            return Framing::Invalid;
+        }
+        let last = codings.rsplit(',').next().unwrap_or("").trim();
+        return match last.eq_ignore_ascii_case("chunked") {
+            true => Framing::Chunked(None),
+            false if unframed == Framing::UntilClose => Framing::UntilClose,
+            false => Framing::Invalid,
+        };
+    }
+    match length {
+        None => unframed,
+        Some(Ok(length)) => Framing::Length(length),
+        Some(Err(_)) => Framing::Invalid,
+    }
+}
+
+/// The length the `Content-Length` fields of `headers` give, none without
+/// any; malformed unless each value is digits alone and all are the same.
+pub(crate) fn content_length(headers: &Headers) -> Option<Result<u64, ParseError>> {
+    let mut length = None;
+    for value in headers
+        .get_all("Content-Length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        let parsed = Some(value)
+            .filter(|value| value.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|value| value.parse::<u64>().ok());
+        match (parsed, length) {
+            (Some(parsed), None) => length = Some(parsed),
+            (Some(parsed), Some(known)) if parsed == known => {}
+            _ => return Some(Err(ParseError::MalformedHeader)),
+        }
+    }
+    length.map(Ok)
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let limit = match self.framing {
+                Framing::Done | Framing::Invalid | Framing::Length(0) => return Ok(0),
+                Framing::Length(remaining) => remaining,
+                Framing::Chunked(None) => {
+                    let size = self.next_chunk()?;
+                    self.framing = match size {
+                        0 => Framing::Done,
+                        size => Framing::Chunked(Some(size)),
+                    };
+                    continue;
+                }
+                Framing::Chunked(Some(0)) => {
+                    let mut budget = 2;
+                    match read_line(&mut self.inner, &mut budget) {
+                        Ok(Some(line)) if line.is_empty() => {}
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "chunk without its line ending",
+                            ))
+                        }
+                    }
+                    self.framing = Framing::Chunked(None);
+                    continue;
+                }
+                Framing::Chunked(Some(remaining)) => remaining,
+                Framing::UntilClose => u64::MAX,
+            };
+            let len = buf.len().min(usize::try_from(limit).unwrap_or(usize::MAX));
+            let read = self.inner.read(&mut buf[..len])?;
+            if read == 0 && !buf.is_empty() {
+                if self.framing == Framing::UntilClose {
+                    self.framing = Framing::Done;
+                    return Ok(0);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let left = limit - read as u64;
+            self.framing = match self.framing {
+                Framing::Length(_) => Framing::Length(left),
+                Framing::Chunked(_) => Framing::Chunked(Some(left)),
+                framing => framing,
+            };
+            return Ok(read);
+        }
+    }
+}
+
+/// A body which fails to read once more than a limit of bytes came in.
+#[derive(Debug)]
+pub struct LimitedBody<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> LimitedBody<R> {
+    /// Read `inner`, up to `max_size` bytes.
+    pub fn new(inner: R, max_size: u64) -> LimitedBody<R> {
+        LimitedBody {
+            inner,
+            remaining: max_size,
+            exceeded: false,
+        }
+    }
+
+    /// Whether the body was longer than the limit.
+    pub fn exceeded(&self) -> bool {
+        self.exceeded
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: Read> Read for LimitedBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.exceeded {
+            return Err(too_large());
+        }
+        // One byte past the limit tells whether there is more.
+        let len = buf
+            .len()
+            .min(usize::try_from(self.remaining.saturating_add(1)).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..len])?;
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(too_large());
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "request body too large")
+}
+
+/// Whether `name` can be the name of a header field: a token.
+pub fn is_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !b"\"(),/:;<=>?@[\\]{}".contains(&byte))
+}
+
+/// Whether `value` can be sent as the value of a header field, without line
+/// breaks or other control characters but tabs.
+pub fn is_field_value(value: &str) -> bool {
+    value
+        .bytes()
+        .all(|byte| byte == b'\t' || !byte.is_ascii_control())
+}
+
+/// Read one CRLF (or LF) terminated line of at most `budget` bytes, returning
+/// `None` at the end of the stream, and deduct its length from `budget`.
+fn read_line<R: BufRead>(reader: &mut R, budget: &mut usize) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    let limit = (MAX_LINE_LENGTH + 2).min(budget.saturating_add(1));
+    let read = Read::take(&mut *reader, limit as u64).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if read > *budget {
+        return Err(ParseError::HeadTooLarge);
+    }
+    *budget -= read;
+    if line.ends_with(b"\n") {
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+    } else if line.len() > MAX_LINE_LENGTH {
+        return Err(ParseError::LineTooLong);
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .or(Err(ParseError::MalformedHeader))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_read_request_with_headers() -> Result<(), Box<dyn std::error::Error>> {
+        let mut input =
+            Cursor::new(b"GET /a HTTP/1.1\r\nHost: x\r\nRange:  bytes=0-1 \r\n\r\n".to_vec());
+        let request = Request::read_from(&mut input)?.expect("a request should be present");
+
+        assert_eq!(request.method(), &Method::Get);
+        assert_eq!(request.target(), "/a");
+        assert_eq!(request.version(), Version::Http11);
+        assert_eq!(request.header("host"), Some("x"));
+        assert_eq!(request.header("RANGE"), Some("bytes=0-1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_request_malformed() {
+        let mut line = Cursor::new(b"INVALID".to_vec());
+        assert!(matches!(
+            Request::read_from(&mut line),
+            Err(ParseError::MalformedRequestLine)
+        ));
+
+        let mut header = Cursor::new(b"GET / HTTP/1.1\r\nno colon\r\n\r\n".to_vec());
+        assert!(matches!(
+            Request::read_from(&mut header),
+            Err(ParseError::MalformedHeader)
+        ));
+
+        let mut empty = Cursor::new(Vec::new());
+        assert!(matches!(Request::read_from(&mut empty), Ok(None)));
+    }
+
+    #[test]
+    fn test_parse_query() {
+        let request = Request::new(
+            Method::Get,
+            "/greet?name=J%C3%B6+Doe&x&=v&&a=1=2",
+            Version::Http11,
+        );
+        let pairs = parse_query(request.query());
+        let pairs: Vec<_> = pairs
+            .iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [("name", "Jö Doe"), ("x", ""), ("", "v"), ("a", "1=2")]
+        );
+        let decoded = parse_query("p=%3Cb%3E%20%26%2b%2F&bad=100%+%zz%4");
+        assert_eq!(decoded[0].1, "<b> &+/");
+        assert_eq!(decoded[1].1, "100% %zz%4", "broken escapes stay");
+        assert_eq!(parse_query("x=%FF")[0].1, "\u{FFFD}");
+        assert_eq!(Request::new(Method::Get, "/", Version::Http11).query(), "");
+
+        let fragment = Request::new(Method::Get, "/a/b?c=%2F#d?e", Version::Http11);
+        assert_eq!((fragment.path(), fragment.query()), ("/a/b", "c=%2F"));
+        assert_eq!(fragment.target(), "/a/b?c=%2F#d?e", "kept for the logs");
+        let bare = Request::new(Method::Get, "/a#b", Version::Http11);
+        assert_eq!((bare.path(), bare.query()), ("/a", ""));
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        for (path, merged, kept) in [
+            ("/", Some("/"), Some("/")),
+            ("/a/b", Some("/a/b"), Some("/a/b")),
+            ("/a/./b", Some("/a/b"), Some("/a/b")),
+            ("/a//b", Some("/a/b"), Some("/a//b")),
+            ("//", Some("/"), Some("//")),
+            ("/a/b/../c", Some("/a/c"), Some("/a/c")),
+            ("/a/b/..", Some("/a/"), Some("/a/")),
+            ("/a/..", Some("/"), Some("/")),
+            ("/a/b/.", Some("/a/b/"), Some("/a/b/")),
+            ("/a/%2e%2E/b%20c", Some("/b c"), Some("/b c")),
+            ("/a%2Fb/", Some("/a/b/"), Some("/a/b/")),
+            ("/..", None, None),
+            ("/a/../../b", None, None),
+            ("/%2e%2e/etc", None, None),
+            ("/a%zz", None, None),
+            ("/a%00", None, None),
+            ("/%FF", None, None),
+            ("a/b", None, None),
+        ] {
+            assert_eq!(normalize_path(path, true).as_deref(), merged, "{path}");
+            assert_eq!(normalize_path(path, false).as_deref(), kept, "{path}");
+        }
+    }
+
+    #[test]
+    fn test_normalize_keeps_the_target() {
+        let mut request = Request::new(Method::Get, "/a/./b/../c%20d?q=/../x", Version::Http11);
+        assert!(request.normalize(true));
+        assert_eq!((request.path(), request.query()), ("/a/c d", "q=/../x"));
+        assert_eq!(request.target(), "/a/./b/../c%20d?q=/../x");
+
+        let mut climbing = Request::new(Method::Get, "/../secret", Version::Http11);
+        assert!(!climbing.normalize(true));
+        let mut absolute = Request::new(Method::Get, "http://x/a/../b", Version::Http11);
+        assert!(absolute.normalize(true));
+        assert_eq!(absolute.path(), "/b");
+        let mut asterisk = Request::new(Method::Options, "*", Version::Http11);
+        assert!(asterisk.normalize(true));
+        assert_eq!(asterisk.path(), "*");
+    }
+
+    #[test]
+    fn test_parse_request_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let input = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nGET /b HTTP/1.1\n\n";
+        let (first, consumed) = parse_request_bytes(input)?;
+        assert_eq!((first.target(), first.header("host")), ("/a", Some("x")));
+        let (second, rest) = parse_request_bytes(&input[consumed..])?;
+        assert_eq!(second.target(), "/b");
+        assert_eq!(consumed + rest, input.len());
+
+        for cut in [0, 5, 17, 26] {
+            assert!(
+                matches!(
+                    parse_request_bytes(&input[..cut]),
+                    Err(ParseError::Incomplete)
+                ),
+                "{cut}"
+            );
+        }
+        let long = format!("GET /{} HTTP/1.1", "a".repeat(MAX_LINE_LENGTH));
+        assert!(matches!(
+            parse_request_bytes(long.as_bytes()),
+            Err(ParseError::LineTooLong)
+        ));
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(matches!(
+            parse_request_bytes(many.as_bytes()),
+            Err(ParseError::TooManyHeaders)
+        ));
+        Ok(())
+    }
+
+    /// A small xorshift generator, so that the mutations are the same on
+    /// every run.
+    struct Mutations(u64);
+
+    impl Mutations {
+        fn next(&mut self, below: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % below.max(1) as u64) as usize
+        }
+
+        /// `input` with a few bytes flipped, inserted, removed or cut off.
+        fn mutate(&mut self, input: &[u8]) -> Vec<u8> {
+            let mut mutated = input.to_vec();
+            for _ in 0..=self.next(4) {
+                let at = self.next(mutated.len() + 1);
+                let byte = [b'\r', b'\n', b' ', b':', 0, 0xff, b'a'][self.next(7)];
+                match self.next(4) {
+                    0 if at < mutated.len() => mutated[at] = byte,
+                    1 => mutated.insert(at, byte),
+                    2 if at < mutated.len() => drop(mutated.remove(at)),
+                    _ => mutated.truncate(at),
+                }
+            }
+            mutated
+        }
+    }
+
+    /// Parse `input` both ways, checking that they agree and that the bytes
+    /// consumed hold the whole head.
+    fn check_parse(input: &[u8]) {
+        let parsed = parse_request_bytes(input);
+        let streamed = Request::read_from(&mut Cursor::new(input.to_vec()));
+        match parsed {
+            Ok((request, consumed)) => {
+                assert!(consumed <= input.len(), "{input:?}");
+                let (alone, again) =
+                    parse_request_bytes(&input[..consumed]).expect("the head alone parses");
+                assert_eq!((alone.target(), again), (request.target(), consumed));
+                let streamed = streamed.expect("streaming agrees").expect("a request");
+                assert_eq!(streamed.headers(), request.headers(), "{input:?}");
+            }
+            Err(ParseError::Incomplete) => {}
+            Err(_) => assert!(streamed.is_err(), "streaming fails too: {input:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_survives_mutations() {
+        let valid: [&[u8]; 4] = [
+            b"GET /hello.html HTTP/1.1\r\nHost: localhost\r\nRange: bytes=0-9\r\n\r\n",
+            b"POST /form HTTP/1.0\nContent-Length: 5\n\nhello",
+            b"OPTIONS * HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n",
+            b"GET http://example.com/a?b HTTP/1.1\r\nConnection: Upgrade\r\n\r\n",
+        ];
+        let mut mutations = Mutations(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5000 {
+            let input = valid[mutations.next(valid.len())];
+            check_parse(&mutations.mutate(input));
+        }
+    }
+
+    #[test]
+    fn test_parse_fuzz_corpus() -> Result<(), Box<dyn std::error::Error>> {
+        let corpus = concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/corpus/parse_request");
+        let mut cases = 0;
+        for entry in std::fs::read_dir(corpus)? {
+            check_parse(&std::fs::read(entry?.path())?);
+            cases += 1;
+        }
+        assert!(cases > 20, "the corpus is found");
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_forms() -> Result<(), Box<dyn std::error::Error>> {
+        let parse = |line: &str| Request::read_from(&mut Cursor::new(format!("{line}\r\n\r\n")));
+        let options = parse("OPTIONS * HTTP/1.1")?.expect("a request");
+        assert_eq!(options.target(), "*");
+        assert_eq!(options.target_form(), TargetForm::Asterisk);
+        assert!(matches!(
+            parse("GET * HTTP/1.1"),
+            Err(ParseError::MalformedRequestLine)
+        ));
+        for (line, form) in [
+            ("OPTIONS /* HTTP/1.1", TargetForm::Origin),
+            ("GET http://example.com/ HTTP/1.1", TargetForm::Absolute),
+            ("CONNECT example.com:443 HTTP/1.1", TargetForm::Authority),
+        ] {
+            assert_eq!(
+                parse(line)?.expect("a request").target_form(),
+                form,
+                "{line}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_request_limited() -> Result<(), Box<dyn std::error::Error>> {
+        let head = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        let request = Request::read_limited(&mut Cursor::new(head.to_vec()), head.len())?;
+        assert!(request.is_some());
+
+        for max_size in [head.len() - 1, 10] {
+            assert!(matches!(
+                Request::read_limited(&mut Cursor::new(head.to_vec()), max_size),
+                Err(ParseError::HeadTooLarge)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_request_a_byte_at_a_time() -> Result<(), Box<dyn std::error::Error>> {
+        let input = b"GET / HTTP/1.1\nHost: example.com\r\nX-A: 1\r\n\r\nbody".to_vec();
+        let mut reader = BufReader::with_capacity(1, Cursor::new(input));
+        let request = Request::read_limited(&mut reader, 1024)?.expect("a request");
+        assert_eq!(request.header("X-A"), Some("1"));
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest)?;
+        assert_eq!(rest, "body");
+
+        let long = format!("GET /{} HTTP/1.1\r\n", "a".repeat(MAX_LINE_LENGTH));
+        let mut reader = BufReader::with_capacity(1, Cursor::new(long.into_bytes()));
+        assert!(matches!(
+            Request::read_limited(&mut reader, usize::MAX),
+            Err(ParseError::LineTooLong)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_response_head() -> Result<(), Box<dyn std::error::Error>> {
+        let mut input = Cursor::new(b"HTTP/1.1 404 Not Found\r\nX-A: 1\r\n\r\nbody".to_vec());
+        let (status, headers) = read_response_head(&mut input, 1024)?.expect("a head");
+        assert_eq!(status, 404);
+        assert_eq!(headers.get("x-a"), Some("1"));
+        assert_eq!(input.position(), 34);
+
+        for head in [
+            "HTTP/2 200 OK\r\n\r\n",
+            "HTTP/1.1 20 OK\r\n\r\n",
+            "garbage\r\n\r\n",
+        ] {
+            assert!(matches!(
+                read_response_head(&mut Cursor::new(head.as_bytes().to_vec()), 1024),
+                Err(ParseError::MalformedStatusLine)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_body_reader_stops_at_the_end_of_the_body() -> Result<(), Box<dyn std::error::Error>> {
+        let mut headers = Headers::new();
+        headers.insert("Transfer-Encoding", "gzip, chunked");
+        let mut input =
+            Cursor::new(b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nX-T: 1\r\n\r\nGET".to_vec());
+        let mut body = BodyReader::request(&mut input, &headers);
+        assert!(body.is_chunked());
+        let mut read = String::new();
+        body.read_to_string(&mut read)?;
+        assert_eq!(read, "Wikipedia");
+        assert!(body.is_finished());
+        assert_eq!(body.trailers().get("x-t"), Some("1"));
+        assert_eq!(input.position(), 38, "the next request is left unread");
+
+        let mut headers = Headers::new();
+        headers.insert("Content-Length", "3");
+        let mut body = BodyReader::request(Cursor::new(b"abcdef".to_vec()), &headers);
+        assert_eq!(body.remaining(), Some(3));
+        let mut read = String::new();
+        body.read_to_string(&mut read)?;
+        assert_eq!((read.as_str(), body.is_finished()), ("abc", true));
+
+        let mut truncated = BodyReader::request(Cursor::new(b"ab".to_vec()), &headers);
+        assert!(truncated.read_to_end(&mut Vec::new()).is_err());
+
+        headers.insert("Transfer-Encoding", "gzip");
+        assert!(!BodyReader::request(Cursor::new(Vec::new()), &headers).is_valid());
+        let mut body = BodyReader::response(Cursor::new(b"to the end".to_vec()), &headers);
+        let mut read = String::new();
+        body.read_to_string(&mut read)?;
+        assert_eq!((read.as_str(), body.is_finished()), ("to the end", true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_framing_is_strict() {
+        let framed = |fields: &[(&str, &str)]| {
+            let request = Request::new(Method::Post, "/", Version::Http11);
+            let request = fields.iter().fold(request, |request, (name, value)| {
+                request.with_header(name, value)
+            });
+            request.has_valid_framing()
+        };
+        assert!(framed(&[]));
+        assert!(framed(&[("Content-Length", "5")]));
+        assert!(framed(&[
+            ("Content-Length", "5"),
+            ("Content-Length", "5, 5")
+        ]));
+        assert!(framed(&[("Transfer-Encoding", "chunked")]));
+        for fields in [
+            &[("Content-Length", "+5")][..],
+            &[("Content-Length", "")],
+            &[("Content-Length", "0x5")],
+            &[("Content-Length", "5"), ("Content-Length", "6")],
+            &[("Content-Length", "5, 6")],
+            &[("Content-Length", "5"), ("Transfer-Encoding", "chunked")],
+        ] {
+            assert!(!framed(fields), "{fields:?}");
+        }
+
+        let mut headers = Headers::new();
+        headers.insert("Transfer-Encoding", "chunked");
+        for chunks in ["+4\r\nWiki\r\n0\r\n\r\n", "\r\n"] {
+            let mut body = BodyReader::request(Cursor::new(chunks.as_bytes().to_vec()), &headers);
+            assert!(body.read_to_end(&mut Vec::new()).is_err(), "{chunks:?}");
+        }
+    }
+
+    #[test]
+    fn test_headers_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Vary", "Origin");
+        headers.append("vary", "Accept");
+        assert_eq!(
+            headers.get_all("VARY").collect::<Vec<_>>(),
+            ["Origin", "Accept"]
+        );
+
+        headers.insert("VARY", "*");
+        assert_eq!(headers.get("vary"), Some("*"));
+        assert_eq!(headers.len(), 1);
+
+        let mut headers = Headers::new();
+        headers.append("Vary", "Accept, origin");
+        headers.add_vary("Accept-Encoding");
+        headers.add_vary("Origin");
+        assert_eq!(
+            headers.get_all("Vary").collect::<Vec<_>>(),
+            ["Accept, origin, Accept-Encoding"]
+        );
+        headers.append("Vary", "*");
+        headers.combine_vary();
+        assert_eq!(headers.get_all("Vary").collect::<Vec<_>>(), ["*"]);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let get = |version| Request::new(Method::Get, "/", version);
+        assert!(get(Version::Http11).keep_alive());
+        assert!(!get(Version::Http11)
+            .with_header("Connection", "Close")
+            .keep_alive());
+        assert!(!get(Version::Http10).keep_alive());
+        assert!(get(Version::Http10)
+            .with_header("Connection", "keep-alive")
+            .keep_alive());
+        assert!(!get(Version::Http11)
+            .with_header("Connection", "Upgrade, CLOSE")
+            .keep_alive());
+        assert!(get(Version::Http10)
+            .with_header("connection", "Keep-Alive, Upgrade")
+            .keep_alive());
+    }
+}
@@ -0,0 +1,312 @@
+//! Formatting and parsing of the date formats used in HTTP headers.
+
+use std::{
+    sync::{PoisonError, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const LONG_WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Format `time` as an IMF-fixdate, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Times before 1970 are formatted as the epoch.
+pub fn format(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let weekday = WEEKDAYS[((days + 4) % 7) as usize];
+    format!(
+        "{weekday}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        MONTHS[month as usize - 1],
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Format `time` like access logs do, e.g. `06/Nov/1994:08:49:37 +0000`.
+pub fn format_log(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Format `time` in the basic ISO 8601 format, e.g. `19941106T084937Z`, which
+/// sorts by time and fits in file names.
+pub fn format_basic(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Format `time` as an RFC 3339 timestamp in UTC with milliseconds, e.g.
+/// `1994-11-06T08:49:37.000Z`.
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// A formatted date which is only formatted again once the second changed,
+/// so that busy workers share the string instead of each formatting the time.
+#[derive(Debug)]
+pub struct DateCache {
+    /// The second since the epoch which `formatted` shows.
+    cached: RwLock<(u64, String)>,
+}
+
+impl DateCache {
+    pub const fn new() -> DateCache {
+        DateCache {
+            cached: RwLock::new((u64::MAX, String::new())),
+        }
+    }
+
+    /// `time` as an IMF-fixdate, the cached one if it is from the same second.
+    pub fn format(&self, time: SystemTime) -> String {
+        let second = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        {
+            let cached = self.cached.read().unwrap_or_else(PoisonError::into_inner);
+            if cached.0 == second {
+                return cached.1.clone();
+            }
+        }
+        let formatted = format(time);
+        *self.cached.write().unwrap_or_else(PoisonError::into_inner) = (second, formatted.clone());
+        formatted
+    }
+}
+
+impl Default for DateCache {
+    fn default() -> DateCache {
+        DateCache::new()
+    }
+}
+
+/// The current time as an IMF-fixdate, for the `Date` header.
+pub fn now() -> String {
+    static NOW: DateCache = DateCache::new();
+    NOW.format(SystemTime::now())
+}
+
+/// Parse an IMF-fixdate, or one of the obsolete RFC 850 and asctime formats.
+pub fn parse(date: &str) -> Option<SystemTime> {
+    let date = date.trim();
+    let (year, month, day, time) = if let Some((weekday, rest)) = date.split_once(", ") {
+        if WEEKDAYS.contains(&weekday) {
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            let mut fields = rest.split(' ');
+            let day = fields.next()?;
+            let month = fields.next()?;
+            let year = fields.next()?;
+            let time = fields.next()?;
+            if fields.next()? != "GMT" || fields.next().is_some() || year.len() != 4 {
+                return None;
+            }
+            (parse_number(year)?, month, parse_number(day)?, time)
+        } else if LONG_WEEKDAYS.contains(&weekday) {
+            // Sunday, 06-Nov-94 08:49:37 GMT
+            let (date, rest) = rest.split_once(' ')?;
+            let (time, zone) = rest.split_once(' ')?;
+            let mut fields = date.split('-');
+            let day = fields.next()?;
+            let month = fields.next()?;
+            let year = fields.next()?;
+            if zone != "GMT" || fields.next().is_some() || year.len() != 2 {
+                return None;
+            }
+            let year = parse_number(year)?;
+            let year = if year < 70 { 2000 + year } else { 1900 + year };
+            (year, month, parse_number(day)?, time)
+        } else {
+            return None;
+        }
+    } else {
+        // Sun Nov  6 08:49:37 1994
+        let mut fields = date.split(' ').filter(|field| !field.is_empty());
+        if !WEEKDAYS.contains(&fields.next()?) {
+            return None;
+        }
+        let month = fields.next()?;
+        let day = fields.next()?;
+        let time = fields.next()?;
+        let year = fields.next()?;
+        if fields.next().is_some() || year.len() != 4 {
+            return None;
+        }
+        (parse_number(year)?, month, parse_number(day)?, time)
+    };
+
+    let month = MONTHS.iter().position(|name| *name == month)? as u64 + 1;
+    let mut clock = time.split(':');
+    let hour = parse_number(clock.next()?)?;
+    let minute = parse_number(clock.next()?)?;
+    let second = parse_number(clock.next()?)?;
+    if clock.next().is_some()
+        || year < 1970
+        || day == 0
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let days = days_from_civil(year as i64, month, day) as u64;
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Parse a field of one or more ASCII digits.
+fn parse_number(field: &str) -> Option<u64> {
+    if field.is_empty() || field.len() > 4 || !field.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    field.parse().ok()
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The civil `(year, month, day)` of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u64;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u64;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The number of days from 1970-01-01 to the civil date `year-month-day`.
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_format_known_dates() {
+        assert_eq!(format(at(0)), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(format(at(784111777)), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(at(951782400)), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(format_log(at(784111777)), "06/Nov/1994:08:49:37 +0000");
+        assert_eq!(format_basic(at(784111777)), "19941106T084937Z");
+        assert_eq!(
+            format_rfc3339(at(784111777) + Duration::from_millis(42)),
+            "1994-11-06T08:49:37.042Z"
+        );
+    }
+
+    #[test]
+    fn test_date_cache_changes_with_the_second() {
+        let cache = DateCache::new();
+        let start = at(1700000000);
+        assert_eq!(cache.format(start), "Tue, 14 Nov 2023 22:13:20 GMT");
+        *cache.cached.write().unwrap() = (1700000000, "cached".to_string());
+        assert_eq!(cache.format(start + Duration::from_millis(999)), "cached");
+        assert_eq!(
+            cache.format(start + Duration::from_secs(1)),
+            "Tue, 14 Nov 2023 22:13:21 GMT"
+        );
+        assert_eq!(format(at(1709164800)), "Thu, 29 Feb 2024 00:00:00 GMT");
+    }
+
+    #[test]
+    fn test_parse_all_formats() {
+        let expected = Some(at(784111777));
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), expected);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        for date in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Funday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37",
+        ] {
+            assert_eq!(parse(date), None, "{date:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for secs in [0, 68169600, 951825600, 1700000000, 4102444799] {
+            assert_eq!(parse(&format(at(secs))), Some(at(secs)));
+        }
+    }
+}
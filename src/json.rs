@@ -0,0 +1,310 @@
+//! Just enough JSON to write log records and the status page: objects of
+//! strings, numbers and other objects.
+
+use std::fmt::{Display, Write};
+
+/// A JSON object written one member at a time.
+#[derive(Debug)]
+pub(crate) struct Object {
+    text: String,
+}
+
+impl Object {
+    pub(crate) fn new() -> Object {
+        Object {
+            text: String::from("{"),
+        }
+    }
+
+    fn key(&mut self, key: &str) {
+        if self.text.len() > 1 {
+            self.text.push(',');
+        }
+        push_string(&mut self.text, key);
+        self.text.push(':');
+    }
+
+    pub(crate) fn string(mut self, key: &str, value: &str) -> Object {
+        self.key(key);
+        push_string(&mut self.text, value);
+        self
+    }
+
+    /// A string, or `null` if there is none.
+    pub(crate) fn optional(self, key: &str, value: Option<&str>) -> Object {
+        match value {
+            Some(value) => self.string(key, value),
+            None => self.null(key),
+        }
+    }
+
+    /// A number, which `value` must format as one.
+    pub(crate) fn number(mut self, key: &str, value: impl Display) -> Object {
+        self.key(key);
+        let _ = write!(self.text, "{value}");
+        self
+    }
+
+    pub(crate) fn object(mut self, key: &str, value: Object) -> Object {
+        self.key(key);
+        self.text.push_str(&value.finish());
+        self
+    }
+
+    /// An array of strings.
+    pub(crate) fn strings<S: AsRef<str>>(mut self, key: &str, values: &[S]) -> Object {
+        self.key(key);
+        self.text.push('[');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.text.push(',');
+            }
+            push_string(&mut self.text, value.as_ref());
+        }
+        self.text.push(']');
+        self
+    }
+
+    /// An array of objects.
+    pub(crate) fn objects(mut self, key: &str, values: Vec<Object>) -> Object {
+        self.key(key);
+        self.text.push('[');
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                self.text.push(',');
+            }
+            self.text.push_str(&value.finish());
+        }
+        self.text.push(']');
+        self
+    }
+
+    pub(crate) fn null(mut self, key: &str) -> Object {
+        self.key(key);
+        self.text.push_str("null");
+        self
+    }
+
+    pub(crate) fn finish(mut self) -> String {
+        self.text.push('}');
+        self.text
+    }
+}
+
+/// Append `text` as a JSON string. Everything but printable ASCII is escaped,
+/// so that the output stays one ASCII line whatever the input.
+fn push_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ' '..='~' => out.push(c),
+            _ => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    let _ = write!(out, "\\u{unit:04x}");
+                }
+            }
+        }
+    }
+    out.push('"');
+}
+
+/// A parsed JSON value, for tests to read back what was written.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+#[cfg(test)]
+impl Value {
+    /// The member `key` of an object.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(text) => Some(text),
+            _ => None,
+        }
+    }
+}
+
+/// Parse `text` as a single JSON value, none if it is not valid JSON.
+#[cfg(test)]
+pub(crate) fn parse(text: &str) -> Option<Value> {
+    let mut parser = Parser {
+        chars: text.chars().collect(),
+        at: 0,
+    };
+    let value = parser.value()?;
+    parser.whitespace();
+    (parser.at == parser.chars.len()).then_some(value)
+}
+
+#[cfg(test)]
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+#[cfg(test)]
+impl Parser {
+    fn whitespace(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = *self.chars.get(self.at)?;
+        self.at += 1;
+        Some(c)
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        word.chars().all(|c| self.next() == Some(c)).then_some(())
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.whitespace();
+        match *self.chars.get(self.at)? {
+            'n' => self.expect("null").map(|()| Value::Null),
+            't' => self.expect("true").map(|()| Value::Bool(true)),
+            'f' => self.expect("false").map(|()| Value::Bool(false)),
+            '"' => self.string().map(Value::String),
+            '[' => {
+                self.at += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.chars.get(self.at) == Some(&']') {
+                    self.at += 1;
+                    return Some(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        ']' => return Some(Value::Array(items)),
+                        _ => return None,
+                    }
+                }
+            }
+            '{' => {
+                self.at += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.chars.get(self.at) == Some(&'}') {
+                    self.at += 1;
+                    return Some(Value::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.expect(":")?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next()? {
+                        ',' => continue,
+                        '}' => return Some(Value::Object(members)),
+                        _ => return None,
+                    }
+                }
+            }
+            _ => {
+                let start = self.at;
+                while self
+                    .chars
+                    .get(self.at)
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    self.at += 1;
+                }
+                let number: String = self.chars[start..self.at].iter().collect();
+                number.parse().ok().map(Value::Number)
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut units = Vec::new();
+        loop {
+            match self.next()? {
+                '"' => return char::decode_utf16(units).collect::<Result<_, _>>().ok(),
+                '\\' => {
+                    let unit = match self.next()? {
+                        'u' => {
+                            let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                            u16::from_str_radix(&hex, 16).ok()?
+                        }
+                        'n' => u16::from(b'\n'),
+                        'r' => u16::from(b'\r'),
+                        't' => u16::from(b'\t'),
+                        'b' => 0x08,
+                        'f' => 0x0c,
+                        c @ ('"' | '\\' | '/') => c as u16,
+                        _ => return None,
+                    };
+                    units.push(unit);
+                }
+                c if c < ' ' => return None,
+                c => units.extend(c.encode_utf16(&mut [0; 2]).iter()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strings_are_escaped_and_parse_back() {
+        let tricky = "quote \" backslash \\ newline \n tab \t bell \u{7} caf\u{e9} \u{1f980}";
+        let object = Object::new()
+            .string("text", tricky)
+            .optional("none", None)
+            .number("status", 200)
+            .object("inner", Object::new().strings("list", &["a", "b"]))
+            .finish();
+        assert_eq!(
+            object,
+            "{\"text\":\"quote \\\" backslash \\\\ newline \\n tab \\t bell \\u0007 \
+             caf\\u00e9 \\ud83e\\udd80\",\"none\":null,\"status\":200,\
+             \"inner\":{\"list\":[\"a\",\"b\"]}}"
+        );
+        let parsed = parse(&object).expect("valid JSON");
+        assert_eq!(parsed.get("text").and_then(Value::as_str), Some(tricky));
+        assert_eq!(parsed.get("none"), Some(&Value::Null));
+        assert_eq!(parsed.get("status"), Some(&Value::Number(200.0)));
+        assert_eq!(
+            parsed.get("inner").and_then(|inner| inner.get("list")),
+            Some(&Value::Array(vec![
+                Value::String("a".to_string()),
+                Value::String("b".to_string())
+            ]))
+        );
+
+        assert_eq!(Object::new().finish(), "{}");
+        assert_eq!(parse("{\"a\": [1, true]} x"), None);
+    }
+}
@@ -1,8 +1,82 @@
+pub mod access;
+pub mod access_log;
+#[cfg(unix)]
+pub mod activation;
+pub mod admin;
+pub mod auth;
+mod base64;
+pub mod buffer;
+pub mod cache;
+pub mod cgi;
+pub mod chaos;
+mod cidr;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod daemon;
+pub mod deadline;
+pub mod dump;
+mod embedded;
+pub mod error_pages;
+pub mod files;
+pub mod forwarded;
+pub mod header_rules;
+pub mod health;
+pub mod hooks;
+mod glob;
+pub mod http;
+mod httpdate;
+mod json;
+pub mod limit;
+pub mod livereload;
+pub mod log_file;
+pub mod logging;
+pub mod markdown;
+pub mod metrics;
+pub mod mime;
+pub mod net;
+#[cfg(unix)]
+pub mod privileges;
+pub mod proxy;
+pub mod range;
+pub mod ratelimit;
+pub mod redirect;
+pub mod request_id;
+pub mod response;
+pub mod response_cache;
+pub mod router;
+pub mod security;
+pub mod server;
+mod sha1;
+pub mod socket;
+pub mod spool;
+mod spans;
+pub mod sse;
+pub mod status;
+pub mod template;
+pub mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod upgrade;
+pub mod vhost;
+pub mod websocket;
+pub mod well_known;
+
+#[cfg(test)]
+mod test_util;
+
+pub use server::{ListenAddr, Server, ServerHandle};
+
 use std::{
     error::Error,
     fmt,
-    sync::{mpsc, Arc, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc, Arc, Mutex,
+    },
     thread,
+    time::Instant,
 };
 
 /// All errors pertaining to the creation and management of the thread pool. 
@@ -25,11 +99,99 @@ impl Error for ThreadError {}
 #[derive(Debug)]
 pub struct ThreadPool {
     workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    sender: Option<mpsc::Sender<Queued>>,
+    stats: Arc<PoolStats>,
+    /// The sender the handles share, taken once the pool shuts down.
+    shared: Arc<Mutex<Option<mpsc::Sender<Queued>>>>,
+}
+
+/// Executes jobs on a pool without keeping it alive: once the pool is
+/// dropped, jobs are refused.
+///
+/// A job can keep a handle to run more jobs on its own or another pool,
+/// where keeping the pool itself could leave a worker to join itself.
+#[derive(Debug, Clone)]
+pub struct PoolHandle {
+    sender: Arc<Mutex<Option<mpsc::Sender<Queued>>>>,
+    stats: Arc<PoolStats>,
+}
+
+impl PoolHandle {
+    /// Execute closure `f` in one of the pool's worker threads, unless the
+    /// pool shut down.
+    pub fn execute<F>(&self, f: F) -> Result<(), ThreadError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.lock().unwrap_or_else(|e| e.into_inner());
+        let sender = sender.as_ref().ok_or(ThreadError::ThreadSendError)?;
+        send(sender, &self.stats, Box::new(f))
+    }
+
+    /// The counts of the pool's queued, running and finished jobs.
+    pub fn stats(&self) -> &Arc<PoolStats> {
+        &self.stats
+    }
+}
+
+/// Queue `job` with `sender`, counting it in `stats` while it waits.
+fn send(sender: &mpsc::Sender<Queued>, stats: &PoolStats, job: Job) -> Result<(), ThreadError> {
+    stats.queued.fetch_add(1, Ordering::Relaxed);
+    sender.send((job, Instant::now())).map_err(|_| {
+        stats.queued.fetch_sub(1, Ordering::Relaxed);
+        ThreadError::ThreadSendError
+    })
+}
+
+/// What the workers of a pool are doing, counted as they go.
+#[derive(Debug, Default)]
+pub struct PoolStats {
+    queued: AtomicUsize,
+    busy: AtomicUsize,
+    executed: AtomicU64,
+    panicked: AtomicU64,
+    workers: AtomicUsize,
+    closed: AtomicBool,
+}
+
+impl PoolStats {
+    /// The jobs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// The workers running a job.
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// The jobs which ran to the end, panicked ones included.
+    pub fn executed(&self) -> u64 {
+        self.executed.load(Ordering::Relaxed)
+    }
+
+    /// The jobs which panicked.
+    pub fn panicked(&self) -> u64 {
+        self.panicked.load(Ordering::Relaxed)
+    }
+
+    /// The workers which are running, busy or not.
+    pub fn workers(&self) -> usize {
+        self.workers.load(Ordering::Relaxed)
+    }
+
+    /// Whether the pool takes new jobs: it is not shutting down and has
+    /// workers left to run them.
+    pub fn accepting(&self) -> bool {
+        !self.closed.load(Ordering::Relaxed) && self.workers.load(Ordering::Relaxed) > 0
+    }
 }
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// A job with the time it was queued at.
+type Queued = (Job, Instant);
+
 impl ThreadPool {
     /// Create a new ThreadPool.
     ///
@@ -46,40 +208,53 @@ impl ThreadPool {
         let (sender, receiver) = mpsc::channel();
 
         let receiver = Arc::new(Mutex::new(receiver));
+        let stats = Arc::new(PoolStats::default());
 
         let mut workers = Vec::with_capacity(nr);
 
         for id in 0..nr {
-            workers.push(Worker::new(id, Arc::clone(&receiver))?);
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&stats))?);
         }
 
         Ok(ThreadPool {
             workers,
+            shared: Arc::new(Mutex::new(Some(sender.clone()))),
             sender: Some(sender),
+            stats,
         })
     }
 
+    /// The counts of queued, running and finished jobs, which keep changing.
+    pub fn stats(&self) -> Arc<PoolStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// A handle executing jobs on this pool for as long as it runs.
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            sender: Arc::clone(&self.shared),
+            stats: Arc::clone(&self.stats),
+        }
+    }
+
     /// Execute closure `f` in one of the worker threads. 
     pub fn execute<F>(&self, f: F) -> Result<(), ThreadError>
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-        self.sender
-            .as_ref()
-            .expect("Sender should be present.")
-            .send(job)
-            .or(Err(ThreadError::ThreadSendError))?;
-        Ok(())
+        let sender = self.sender.as_ref().expect("Sender should be present.");
+        send(sender, &self.stats, Box::new(f))
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
+        self.stats.closed.store(true, Ordering::Relaxed);
         drop(self.sender.take());
+        drop(self.shared.lock().unwrap_or_else(|e| e.into_inner()).take());
 
         for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+            log::debug!("Shutting down worker {}", worker.id);
             if let Some(thread) = worker.thread.take() {
                 thread
                     .join()
@@ -96,7 +271,12 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Result<Worker, ThreadError> {
+    fn new(
+        id: usize,
+        receiver: Arc<Mutex<mpsc::Receiver<Queued>>>,
+        stats: Arc<PoolStats>,
+    ) -> Result<Worker, ThreadError> {
+        stats.workers.fetch_add(1, Ordering::Relaxed);
         let thread = thread::Builder::new()
             .spawn(move || loop {
                 let message = receiver
@@ -105,12 +285,22 @@ impl Worker {
                     .recv();
 
                 match message {
-                    Ok(job) => {
-                        println!("Worker {id} got a job; executing.");
-                        job();
+                    Ok((job, queued)) => {
+                        let _span = spans::job(id, queued.elapsed());
+                        log::trace!("Worker {id} got a job; executing.");
+                        stats.queued.fetch_sub(1, Ordering::Relaxed);
+                        stats.busy.fetch_add(1, Ordering::Relaxed);
+                        // Keep the worker for the next job when one panics.
+                        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                            log::error!("A job on worker {id} panicked.");
+                            stats.panicked.fetch_add(1, Ordering::Relaxed);
+                        }
+                        stats.executed.fetch_add(1, Ordering::Relaxed);
+                        stats.busy.fetch_sub(1, Ordering::Relaxed);
                     }
                     Err(_) => {
-                        println!("worker {id} disconnected; shutting down.");
+                        log::debug!("worker {id} disconnected; shutting down.");
+                        stats.workers.fetch_sub(1, Ordering::Relaxed);
                         break;
                     }
                 }
@@ -128,8 +318,6 @@ impl Worker {
 mod tests {
     use super::*;
 
-    use std::time::Duration;
-
     #[test]
     fn test_threadpool_build_wrong_args() -> Result<(), Box<dyn std::error::Error>> {
         let pool_neg = ThreadPool::build(-2);
@@ -170,4 +358,49 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_threadpool_counts_jobs() -> Result<(), Box<dyn std::error::Error>> {
+        let pool = ThreadPool::build(1)?;
+        let stats = pool.stats();
+
+        let (started, running) = mpsc::channel();
+        let (finish, finished) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            finished.recv().unwrap();
+        })?;
+        pool.execute(|| panic!("a failing job"))?;
+        running.recv()?;
+        assert_eq!((stats.busy(), stats.queued()), (1, 1));
+        assert!(stats.accepting());
+
+        finish.send(())?;
+        pool.execute(|| ())?;
+        drop(pool);
+        assert_eq!((stats.busy(), stats.queued()), (0, 0));
+        assert_eq!(stats.executed(), 3, "the worker survives the panic");
+        assert_eq!(stats.panicked(), 1);
+        assert!(!stats.accepting());
+        Ok(())
+    }
+
+    #[test]
+    fn test_handles_outlived_by_jobs() -> Result<(), Box<dyn std::error::Error>> {
+        let pool = ThreadPool::build(1)?;
+        let handle = pool.handle();
+        let (ran, done) = mpsc::channel();
+        let again = handle.clone();
+        handle.execute(move || {
+            // A job keeping a handle does not keep the pool from shutting down.
+            again.execute(move || ran.send(()).unwrap()).unwrap();
+        })?;
+        done.recv()?;
+
+        drop(pool);
+        assert_eq!(handle.stats().executed(), 2);
+        assert_eq!(handle.execute(|| ()), Err(ThreadError::ThreadSendError));
+        assert_eq!(handle.stats().queued(), 0);
+        Ok(())
+    }
 }
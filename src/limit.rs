@@ -0,0 +1,159 @@
+//! Limits on the number of connections handled at the same time.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+/// Counts open connections and decides whether new ones are served.
+///
+/// Clones share their counters.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    soft: usize,
+    hard: usize,
+    state: Arc<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    active: Mutex<usize>,
+    drained: Condvar,
+    rejected: AtomicU64,
+}
+
+/// What to do with a newly accepted connection.
+#[derive(Debug)]
+pub enum Admission {
+    /// Serve it normally.
+    Serve(ConnectionGuard),
+    /// Answer it with `503 Service Unavailable` and close it.
+    Reject(ConnectionGuard),
+}
+
+/// Counts a connection as open until it is dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    state: Arc<State>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut active = self.state.active.lock().unwrap_or_else(|e| e.into_inner());
+        *active -= 1;
+        self.state.drained.notify_all();
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits::new()
+    }
+}
+
+impl ConnectionLimits {
+    /// Serve up to 256 connections at once and stop accepting at 512.
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits {
+            soft: 256,
+            hard: 512,
+            state: Arc::default(),
+        }
+    }
+
+    /// Reject connections with 503 while `limit` connections are open.
+    pub fn soft_limit(mut self, limit: usize) -> ConnectionLimits {
+        self.soft = limit;
+        self
+    }
+
+    /// Stop accepting connections while `limit` connections, including the
+    /// ones being rejected, are open.
+    pub fn hard_limit(mut self, limit: usize) -> ConnectionLimits {
+        self.hard = limit;
+        self
+    }
+
+    /// Count a newly accepted connection and decide whether to serve it.
+    pub fn admit(&self) -> Admission {
+        let mut active = self.state.active.lock().unwrap_or_else(|e| e.into_inner());
+        let serve = *active < self.soft;
+        *active += 1;
+        let guard = ConnectionGuard {
+            state: Arc::clone(&self.state),
+        };
+        if serve {
+            Admission::Serve(guard)
+        } else {
+            self.state.rejected.fetch_add(1, Ordering::Relaxed);
+            Admission::Reject(guard)
+        }
+    }
+
+    /// Block until fewer connections than the hard limit are open.
+    pub fn wait_below_hard_limit(&self) {
+        let active = self.state.active.lock().unwrap_or_else(|e| e.into_inner());
+        let _active = self
+            .state
+            .drained
+            .wait_while(active, |active| *active >= self.hard)
+            .unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Block until no connections are open or `timeout` passed, returning
+    /// whether they all closed.
+    pub fn wait_idle(&self, timeout: Duration) -> bool {
+        let active = self.state.active.lock().unwrap_or_else(|e| e.into_inner());
+        let (_active, result) = self
+            .state
+            .drained
+            .wait_timeout_while(active, timeout, |active| *active > 0)
+            .unwrap_or_else(|e| e.into_inner());
+        !result.timed_out()
+    }
+
+    /// The number of connections open right now.
+    pub fn active(&self) -> usize {
+        *self.state.active.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The number of connections rejected since the server started.
+    pub fn rejected(&self) -> u64 {
+        self.state.rejected.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn test_admission() {
+        let limits = ConnectionLimits::new().soft_limit(1).hard_limit(2);
+        let first = limits.admit();
+        assert!(matches!(first, Admission::Serve(_)));
+        let second = limits.admit();
+        assert!(matches!(second, Admission::Reject(_)));
+        assert_eq!((limits.active(), limits.rejected()), (2, 1));
+
+        let waiter = {
+            let limits = limits.clone();
+            thread::spawn(move || limits.wait_below_hard_limit())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished(), "the hard limit is reached");
+        drop(second);
+        waiter.join().unwrap();
+
+        assert!(!limits.wait_idle(Duration::from_millis(10)));
+        drop(first);
+        assert!(limits.wait_idle(Duration::from_millis(10)));
+        assert_eq!(limits.active(), 0);
+        assert!(matches!(limits.admit(), Admission::Serve(_)));
+    }
+}
@@ -0,0 +1,355 @@
+//! Dev mode: reloading the pages open in a browser once a file under the
+//! document root changes.
+//!
+//! The root is polled for changed modification times, which needs nothing
+//! beyond `std` and works on every platform. Served HTML pages get a script
+//! which long-polls [`PATH`] with the version of the files they were served
+//! from, and reloads them once the server answers with a newer one.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, PoisonError, Weak},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+
+use crate::{
+    http::Request,
+    response::{Body, Response},
+};
+
+/// The path the injected script polls for changes.
+pub const PATH: &str = "/__livereload";
+
+/// How often the document root is checked for changes.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long a poll waits for a change before answering `204`.
+pub const MAX_WAIT: Duration = Duration::from_secs(25);
+
+/// The most files checked on each poll, so that a root with a build
+/// directory in it does not keep a core busy.
+const MAX_FILES: usize = 10_000;
+
+/// The largest page a script is injected into.
+const MAX_PAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// The version of the watched files, which the pages poll for changes;
+/// clones share it.
+#[derive(Debug, Clone)]
+pub struct LiveReload {
+    state: Arc<State>,
+    max_wait: Duration,
+}
+
+#[derive(Debug)]
+struct State {
+    /// When a change was last seen, in milliseconds since the epoch.
+    version: Mutex<u64>,
+    changed: Condvar,
+}
+
+impl LiveReload {
+    /// Watch the files under `root`, checking them every `interval` on a
+    /// thread of their own until the last clone is dropped.
+    pub fn watch(root: impl Into<PathBuf>, interval: Duration) -> io::Result<LiveReload> {
+        let reload = LiveReload::new();
+        let root = root.into();
+        let state = Arc::downgrade(&reload.state);
+        thread::Builder::new()
+            .name("livereload".to_string())
+            .spawn(move || poll(&root, interval, &state))?;
+        Ok(reload)
+    }
+
+    /// Versions which only change when [`notify`](LiveReload::notify)
+    /// says so.
+    pub fn new() -> LiveReload {
+        LiveReload {
+            state: Arc::new(State {
+                version: Mutex::new(millis(SystemTime::now())),
+                changed: Condvar::new(),
+            }),
+            max_wait: MAX_WAIT,
+        }
+    }
+
+    /// Answer polls after `wait` at most when nothing changed.
+    pub fn max_wait(mut self, wait: Duration) -> LiveReload {
+        self.max_wait = wait;
+        self
+    }
+
+    /// The version of the files, which grows with every change.
+    pub fn version(&self) -> u64 {
+        *self
+            .state
+            .version
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a change now, waking the polls waiting for one.
+    pub fn notify(&self) {
+        self.state.notify();
+    }
+
+    /// The first version newer than `since`, waiting up to `timeout` for
+    /// one, or none if nothing changed in time.
+    pub fn wait(&self, since: u64, timeout: Duration) -> Option<u64> {
+        let version = self
+            .state
+            .version
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (version, _) = self
+            .state
+            .changed
+            .wait_timeout_while(version, timeout, |version| *version <= since)
+            .unwrap_or_else(PoisonError::into_inner);
+        Some(*version).filter(|version| *version > since)
+    }
+
+    /// Whether `request` polls for changes.
+    pub fn matches(&self, request: &Request) -> bool {
+        request.path() == PATH
+    }
+
+    /// The answer to a poll for versions newer than its `since` parameter:
+    /// `200` with the new version once there is one, `204` if there was
+    /// none within the wait, and `400` without a version to compare to.
+    pub fn respond(&self, request: &Request) -> Response {
+        let since = request
+            .query()
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("since="))
+            .and_then(|since| since.parse().ok());
+        let Some(since) = since else {
+            return Response::builtin_error(400);
+        };
+        let response = match self.wait(since, self.max_wait) {
+            Some(version) => Response::new(200)
+                .with_header("Content-Type", "text/plain")
+                .with_body(Body::Bytes(version.to_string().into_bytes())),
+            None => Response::new(204),
+        };
+        response.with_header("Cache-Control", "no-store")
+    }
+
+    /// `response` as dev mode sends it: never stored, and for HTML pages
+    /// with the reloading script before their `</body>`.
+    ///
+    /// Pages are only rewritten while still in one piece: not compressed,
+    /// not ranges, and of a known length, which the new body carries.
+    pub fn apply(&self, mut response: Response) -> io::Result<Response> {
+        let headers = response.headers_mut();
+        headers.insert("Cache-Control", "no-store");
+        let injectable = response.status() == 200
+            && is_html(&response)
+            && !response.headers().contains("Content-Encoding")
+            && matches!(response.body(), Body::Bytes(_) | Body::File(_))
+            && response.body().len() <= MAX_PAGE_SIZE;
+        if !injectable {
+            return Ok(response);
+        }
+        let mut page = Vec::with_capacity(response.body().len() as usize);
+        response.body().write_to(&mut page)?;
+        let page = inject(&page, &script(self.version()));
+        // The validators describe the file, not the page with the script.
+        let headers = response.headers_mut();
+        headers.remove("ETag");
+        headers.remove("Last-Modified");
+        Ok(response.with_body(Body::Bytes(page)))
+    }
+}
+
+impl State {
+    fn notify(&self) {
+        let mut version = self.version.lock().unwrap_or_else(PoisonError::into_inner);
+        // Two changes within a millisecond still make two versions.
+        *version = millis(SystemTime::now()).max(*version + 1);
+        self.changed.notify_all();
+    }
+}
+
+impl Default for LiveReload {
+    fn default() -> LiveReload {
+        LiveReload::new()
+    }
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|essence| essence.trim().eq_ignore_ascii_case("text/html"))
+}
+
+/// The script reloading a page served at `version` once the files change.
+fn script(version: u64) -> String {
+    format!(
+        "<script>(function(){{var since={version};function poll(){{\
+         fetch(\"{PATH}?since=\"+since,{{cache:\"no-store\"}}).then(function(r){{\
+         if(r.status===200){{location.reload()}}else if(r.status===204){{poll()}}\
+         else{{setTimeout(poll,1000)}}}},function(){{setTimeout(poll,1000)}})}}poll()}})();\
+         </script>"
+    )
+}
+
+/// `page` with `script` before its last `</body>`, or at its end without one.
+fn inject(page: &[u8], script: &str) -> Vec<u8> {
+    let at = page
+        .windows(b"</body>".len())
+        .rposition(|window| window.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(page.len());
+    let mut injected = Vec::with_capacity(page.len() + script.len());
+    injected.extend_from_slice(&page[..at]);
+    injected.extend_from_slice(script.as_bytes());
+    injected.extend_from_slice(&page[at..]);
+    injected
+}
+
+/// Check the files under `root` every `interval`, notifying `state` of each
+/// change, until nothing holds it any more.
+fn poll(root: &Path, interval: Duration, state: &Weak<State>) {
+    let mut last = fingerprint(root);
+    loop {
+        thread::sleep(interval);
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let current = fingerprint(root);
+        if current != last {
+            debug!("Files under {} changed, reloading pages.", root.display());
+            last = current;
+            state.notify();
+        }
+    }
+}
+
+/// The paths, sizes and modification times of the files under `root`,
+/// leaving out hidden ones, which changes whenever one of them does.
+fn fingerprint(root: &Path) -> Vec<(PathBuf, u64, Option<SystemTime>)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if files.len() == MAX_FILES {
+                warn!(
+                    "Watching only {MAX_FILES} files under {} for changes.",
+                    root.display()
+                );
+                files.sort();
+                return files;
+            } else {
+                files.push((entry.path(), metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Method, http::Version, test_util::TempDir};
+
+    fn page(content_type: &str, body: &str) -> Response {
+        Response::new(200)
+            .with_header("Content-Type", content_type)
+            .with_header("ETag", "\"1\"")
+            .with_body(Body::Bytes(body.as_bytes().to_vec()))
+    }
+
+    fn written(response: &Response) -> Result<String, Box<dyn std::error::Error>> {
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+
+    #[test]
+    fn test_inject() -> Result<(), Box<dyn std::error::Error>> {
+        let reload = LiveReload::new();
+        let html = reload.apply(page(
+            "text/html; charset=utf-8",
+            "<html><body><p>hi</p></BODY></html>",
+        ))?;
+        let output = written(&html)?;
+        let (head, body) = output.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(head.contains("Cache-Control: no-store"));
+        assert!(!head.contains("ETag"));
+        assert!(body.starts_with("<html><body><p>hi</p><script>"));
+        assert!(body.contains(&format!("since={}", reload.version())));
+        assert!(body.ends_with("</script></BODY></html>"));
+
+        let fragment = reload.apply(page("text/html", "<p>no body tag</p>"))?;
+        let output = written(&fragment)?;
+        assert!(output.contains("<p>no body tag</p><script>"));
+
+        let text = reload.apply(page("text/plain", "</body>"))?;
+        let output = written(&text)?;
+        assert!(output.contains("Cache-Control: no-store"));
+        assert!(output.ends_with("\r\n\r\n</body>"), "{output}");
+        assert!(output.contains("ETag"), "left alone");
+
+        let missing = reload.apply(Response::builtin_error(404))?;
+        assert!(!written(&missing)?.contains("<script>"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_polls_answer_once_a_watched_file_changes() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let file = dir.write("index.html", "<body>one</body>");
+        let reload = LiveReload::watch(dir.path(), Duration::from_millis(10))?
+            .max_wait(Duration::from_millis(50));
+        let since = reload.version();
+        let poll = Request::new(
+            Method::Get,
+            &format!("{PATH}?since={since}"),
+            Version::Http11,
+        );
+        assert!(reload.matches(&poll));
+        assert_eq!(reload.respond(&poll).status(), 204, "nothing changed yet");
+
+        let waiting = {
+            let reload = reload.clone().max_wait(Duration::from_secs(10));
+            let poll = poll.clone();
+            thread::spawn(move || reload.respond(&poll))
+        };
+        // Longer, so that the change shows even with coarse modification times.
+        fs::write(&file, "<body>two, changed</body>")?;
+        let response = waiting.join().expect("the poll does not panic");
+        assert_eq!(response.status(), 200);
+        assert!(reload.version() > since);
+        let mut body = Vec::new();
+        response.body().write_to(&mut body)?;
+        assert_eq!(String::from_utf8(body)?, reload.version().to_string());
+
+        let stale = Request::new(Method::Get, PATH, Version::Http11);
+        assert_eq!(reload.respond(&stale).status(), 400);
+        Ok(())
+    }
+}
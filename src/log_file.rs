@@ -0,0 +1,228 @@
+//! A log file which rotates itself once it grows too large or a day passes,
+//! keeping a few of the old ones.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::httpdate;
+
+/// When a log file is rotated, and how many rotated files are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    max_size: Option<u64>,
+    daily: bool,
+    keep: usize,
+}
+
+impl Rotation {
+    /// Never rotate.
+    pub fn new() -> Rotation {
+        Rotation {
+            max_size: None,
+            daily: false,
+            keep: 7,
+        }
+    }
+
+    /// Rotate before a line would grow the file beyond `bytes`.
+    pub fn max_size(mut self, bytes: u64) -> Rotation {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Rotate once the first line of a new day, in UTC, is written.
+    pub fn daily(mut self, daily: bool) -> Rotation {
+        self.daily = daily;
+        self
+    }
+
+    /// Keep the `count` newest rotated files, 7 by default, deleting older ones.
+    pub fn keep(mut self, count: usize) -> Rotation {
+        self.keep = count;
+        self
+    }
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::new()
+    }
+}
+
+/// A file lines are appended to, rotated as its [`Rotation`] says by renaming
+/// it with the time as a suffix, like `access.log.20241010T135536Z`.
+#[derive(Debug)]
+pub struct LogFile {
+    path: PathBuf,
+    rotation: Rotation,
+    file: BufWriter<File>,
+    size: u64,
+    /// The day since the epoch the last line was written on.
+    day: u64,
+}
+
+impl LogFile {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>, rotation: Rotation) -> io::Result<LogFile> {
+        let path = path.into();
+        let (file, size) = append(&path)?;
+        Ok(LogFile {
+            path,
+            rotation,
+            file,
+            size,
+            day: day(SystemTime::now()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append `line`, which ends in a line break, rotating first if it is due.
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.write_line_at(line, SystemTime::now())
+    }
+
+    fn write_line_at(&mut self, line: &[u8], now: SystemTime) -> io::Result<()> {
+        let too_large = self
+            .rotation
+            .max_size
+            .is_some_and(|max| self.size + line.len() as u64 > max);
+        let new_day = self.rotation.daily && day(now) != self.day;
+        if self.size > 0 && (too_large || new_day) {
+            self.rotate(now)?;
+        }
+        self.day = day(now);
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Write out the lines buffered so far.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Open the file at the path again, for when another program moved it away.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        (self.file, self.size) = append(&self.path)?;
+        Ok(())
+    }
+
+    /// Move the file aside with the time `now` as a suffix, start a new one
+    /// and delete the rotated files beyond those kept.
+    fn rotate(&mut self, now: SystemTime) -> io::Result<()> {
+        self.file.flush()?;
+        let stamp = httpdate::format_basic(now);
+        let mut rotated = self.sibling(&stamp);
+        for sequence in 1.. {
+            if !rotated.exists() {
+                break;
+            }
+            rotated = self.sibling(&format!("{stamp}-{sequence:03}"));
+        }
+        fs::rename(&self.path, &rotated)?;
+        (self.file, self.size) = append(&self.path)?;
+        self.prune()
+    }
+
+    /// The path of the file rotated with `suffix`.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".");
+        name.push(suffix);
+        self.path.with_file_name(name)
+    }
+
+    /// Every rotated file, oldest first.
+    pub fn rotated(&self) -> io::Result<Vec<PathBuf>> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let prefix = format!(
+            "{}.",
+            self.path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        let mut rotated = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stamped = name
+                .strip_prefix(&prefix)
+                .is_some_and(|suffix| suffix.starts_with(|c: char| c.is_ascii_digit()));
+            if stamped {
+                rotated.push(entry.path());
+            }
+        }
+        // The suffixes sort by time.
+        rotated.sort();
+        Ok(rotated)
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let rotated = self.rotated()?;
+        let excess = rotated.len().saturating_sub(self.rotation.keep);
+        for old in &rotated[..excess] {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+}
+
+/// `path` opened for appending, with its size.
+fn append(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((BufWriter::new(file), size))
+}
+
+fn day(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs() / 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::Duration;
+
+    #[test]
+    fn test_rotates_daily_and_keeps_the_newest() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let rotation = Rotation::new().daily(true).keep(2);
+        let mut log = LogFile::open(dir.path().join("access.log"), rotation)?;
+        let day = |days: u64| UNIX_EPOCH + Duration::from_secs(days * 86400 + 3600);
+        for (days, line) in [(1, "a"), (1, "b"), (2, "c"), (3, "d"), (4, "e")] {
+            log.write_line_at(format!("{line}\n").as_bytes(), day(days))?;
+        }
+        log.flush()?;
+
+        let rotated = log.rotated()?;
+        let names: Vec<_> = rotated
+            .iter()
+            .map(|path| path.file_name().unwrap_or_default().to_string_lossy())
+            .collect();
+        assert_eq!(
+            names,
+            ["access.log.19700104T010000Z", "access.log.19700105T010000Z"]
+        );
+        assert_eq!(fs::read_to_string(&rotated[0])?, "c\n");
+        assert_eq!(fs::read_to_string(&rotated[1])?, "d\n");
+        assert_eq!(fs::read_to_string(log.path())?, "e\n");
+
+        fs::rename(log.path(), dir.path().join("moved.log"))?;
+        log.reopen()?;
+        log.write_line(b"f\n")?;
+        log.flush()?;
+        assert_eq!(fs::read_to_string(log.path())?, "f\n");
+        Ok(())
+    }
+}
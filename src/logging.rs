@@ -0,0 +1,192 @@
+//! A minimal `log` backend writing one line per record to stderr, as text or
+//! as JSON objects.
+
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::{httpdate, json};
+
+/// How log records and lifecycle events are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LogFormat {
+    /// `[LEVEL] message` lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Whether [`init`] chose [`LogFormat::Json`].
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The identifier of the request this thread is answering, if any.
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Tags the records logged on this thread with a request's identifier until
+/// dropped, when the previous one applies again.
+#[derive(Debug)]
+pub struct RequestScope {
+    previous: Option<String>,
+}
+
+/// Tag the records logged on this thread with `id` while the scope lives.
+pub fn request_scope(id: &str) -> RequestScope {
+    let previous = REQUEST_ID.with(|current| current.replace(Some(id.to_string())));
+    RequestScope { previous }
+}
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+/// The identifier of the request being answered on this thread, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.with(|current| current.borrow().clone())
+}
+
+/// Record that `event`, such as `startup`, happened, on stdout: `message` as
+/// is in the text format, or an object naming the event in JSON.
+pub fn event(event: &str, message: &str) {
+    if JSON.load(Ordering::Relaxed) {
+        let record = json::Object::new()
+            .string("timestamp", &httpdate::format_rfc3339(SystemTime::now()))
+            .string("level", "info")
+            .string("event", event)
+            .string("message", message)
+            .finish();
+        println!("{record}");
+    } else {
+        println!("{message}");
+    }
+}
+
+/// `record` as a JSON object, tagged with the request identifier `id`.
+fn format_json(record: &Record, id: Option<&str>, time: SystemTime) -> String {
+    json::Object::new()
+        .string("timestamp", &httpdate::format_rfc3339(time))
+        .string("level", &record.level().as_str().to_ascii_lowercase())
+        .string("message", &record.args().to_string())
+        .optional("request_id", id)
+        .finish()
+}
+
+/// Writes records with their level, and request identifier if any, to stderr.
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let id = current_request_id();
+            if JSON.load(Ordering::Relaxed) {
+                let line = format_json(record, id.as_deref(), SystemTime::now());
+                let _ = writeln!(io::stderr().lock(), "{line}");
+                return;
+            }
+            let mut stderr = io::stderr().lock();
+            let _ = match id {
+                Some(id) => writeln!(stderr, "[{}] [{id}] {}", record.level(), record.args()),
+                None => writeln!(stderr, "[{}] {}", record.level(), record.args()),
+            };
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Log records up to `level` to stderr in `format`, for binaries which have no
+/// other logger.
+pub fn init(level: LevelFilter, format: LogFormat) -> Result<(), SetLoggerError> {
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+    log::set_logger(&LOGGER)?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// Like [`init`], but through a `tracing` subscriber which also writes the
+/// spans each record happened in; `RUST_LOG` directives refine `level`.
+#[cfg(feature = "tracing")]
+pub fn init_tracing(
+    level: LevelFilter,
+    format: LogFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    use std::io::IsTerminal;
+    use tracing_subscriber::{filter::LevelFilter as Directive, EnvFilter};
+
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+    let level = level
+        .as_str()
+        .parse::<Directive>()
+        .unwrap_or(Directive::INFO);
+    let filter = EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+    let result = match format {
+        LogFormat::Text => subscriber.try_init(),
+        LogFormat::Json => subscriber.json().try_init(),
+    };
+    result.map_err(|err| err as Box<dyn std::error::Error>)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_scopes_nest() {
+        assert_eq!(current_request_id(), None);
+        let outer = request_scope("outer");
+        {
+            let _inner = request_scope("inner");
+            assert_eq!(current_request_id().as_deref(), Some("inner"));
+        }
+        assert_eq!(current_request_id().as_deref(), Some("outer"));
+        drop(outer);
+        assert_eq!(current_request_id(), None);
+    }
+
+    #[test]
+    fn test_json_records_parse_back() {
+        let message = "Cannot read \"a\\b\"\nline two";
+        let line = format_json(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .args(format_args!("{message}"))
+                .build(),
+            Some("abc-1"),
+            SystemTime::UNIX_EPOCH,
+        );
+        let parsed = json::parse(&line).expect("valid JSON");
+        let field = |name| parsed.get(name).and_then(json::Value::as_str);
+        assert_eq!(field("timestamp"), Some("1970-01-01T00:00:00.000Z"));
+        assert_eq!(field("level"), Some("warn"));
+        assert_eq!(field("message"), Some(message));
+        assert_eq!(field("request_id"), Some("abc-1"));
+    }
+}
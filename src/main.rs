@@ -1,95 +1,1255 @@
 use std::{
-    fs,
-    io::{prelude::*, BufReader, Read, Write},
-    net::TcpListener,
+    error::Error,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+    time::Duration,
 };
 
-use hello::ThreadPool;
+#[cfg(unix)]
+use hello::daemon;
+use hello::{
+    access_log::AccessLog,
+    config::Config,
+    daemon::PidFile,
+    http::{self, Request},
+    livereload::{self, LiveReload},
+    logging::{self, LogFormat},
+    response::{Body, Response},
+    router::Router,
+    socket::SocketOptions,
+    sse::{self, Event},
+    template::render_template,
+    upgrade::Duplex,
+    websocket::{Message, WebSocket},
+    ListenAddr, Server, ServerHandle,
+};
+use log::{error, info, warn, LevelFilter};
+
+const USAGE: &str = "\
+Usage: hello [OPTIONS]
+
+Settings are read from the configuration file first, then from environment
+variables like HELLO_PORT for --port, and HELLO_LOG for the log level (error,
+warn, info, debug or trace), and finally from the options.
+
+Options:
+  --config PATH    read settings from the TOML file at PATH
+  --strict-config  reject unknown keys in the configuration file instead of warning
+  --i-know-what-im-doing
+                   let [chaos] inject faults into responses outside dev builds
+  --addr ADDR      listen on ADDR, an IP address with an optional port [default: 127.0.0.1];
+                   repeat to listen on several addresses
+  --listen-any     listen on all interfaces, IPv6 and IPv4, like --addr ::
+  --separate-stacks
+                   listen on IPv4 and IPv6 with separate sockets instead of one dual-stack one
+  --reuse-port     bind with SO_REUSEPORT, so a new instance can share the port, on unix
+  --tls-addr ADDR  also answer HTTPS on ADDR, with an optional port [default: 8443];
+                   repeat to listen on several addresses, needs the tls feature
+  --tls-cert PATH  read the PEM certificate chain for HTTPS from PATH
+  --tls-key PATH   read the PEM private key for HTTPS from PATH
+  --redirect-addr ADDR
+                   also listen on ADDR, with an optional port [default: 80], redirecting
+                   every request to HTTPS; repeat to listen on several addresses
+  --canonical-host HOST
+                   redirect requests without a Host header to HOST
+  --security-headers
+                   add X-Content-Type-Options, X-Frame-Options and Referrer-Policy
+                   headers to every response
+  --server-header NAME
+                   name the server as NAME in the Server header, leaving the header
+                   out when empty [default: hello_rust_webserver/VERSION]
+  --trust-request-id
+                   identify requests by the X-Request-Id header the proxy in front sends
+  --unix-socket PATH
+                   also listen on a Unix domain socket at PATH
+  --unix-socket-mode MODE
+                   give the socket file the octal permissions MODE [default: 660]
+  --port PORT      listen on PORT, 0 to let the OS pick one [default: 7878]
+  --threads N      answer connections on N threads [default: one per core]
+  --dir PATH       serve files from PATH [default: .]
+  --index NAME     answer requests of a directory, / included, with its file NAME;
+                   repeat to try several [default: index.html, then hello.html]
+  --dev            reload the HTML pages open in a browser once a file under the
+                   document root changes, and let no response be cached
+  --dump-traffic DIR
+                   copy the raw bytes of every connection into files under DIR
+  --daemon         detach from the terminal and run in the background, on unix
+  --pid-file PATH  write the process id to PATH, refusing to start while another
+                   running server holds it
+  --log-file PATH  append the output of a daemon to PATH [default: /dev/null]
+  --user NAME      switch to the user NAME once the listeners are bound, on unix
+  --group NAME     switch to the group NAME [default: the user's]
+  --log-format FORMAT
+                   log as text or json, one object per line [default: text]
+  --quiet          only log errors
+  -v, --verbose    also log debug messages: connections, routing and cache decisions
+  -vv, --debug-dump
+                   also dump every response head
+  --help           print this help and exit
+  --version        print the version and exit
+";
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let listener = TcpListener::bind("127.0.0.1:7878")?;
-    let pool = ThreadPool::build(-1)?;
+/// The environment variables read as defaults for the flags of the same name.
+const ENV_VARS: [&str; 5] = [
+    "HELLO_ADDR",
+    "HELLO_PORT",
+    "HELLO_THREADS",
+    "HELLO_DIR",
+    "HELLO_LOG",
+];
 
-    for stream in listener.incoming() {
-        let stream = match stream {
-            Ok(stream) => stream,
-            Err(_) => {
-                eprintln!("Got failed connection, ignoring.");
-                continue;
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// Serve with `config`, warning about the unknown keys in its file.
+    Serve {
+        config: Box<Config>,
+        unknown_keys: Vec<String>,
+    },
+    Help,
+    Version,
+}
+
+/// The built-in defaults, overridden by the configuration file, then by the
+/// `HELLO_*` environment variables and finally by the flags in `args`.
+fn from_env_and_args(args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let args: Vec<String> = args.collect();
+    let path = args
+        .iter()
+        .position(|arg| arg == "--config")
+        .map(|at| args.get(at + 1).ok_or("--config needs a value"))
+        .transpose()?;
+    let (mut config, unknown_keys) = match path {
+        Some(path) => load_config(path)?,
+        None => (Config::default(), Vec::new()),
+    };
+    if args.iter().any(|arg| arg == "--strict-config") && !unknown_keys.is_empty() {
+        return Err(format!(
+            "{}: unknown keys {}",
+            path.map_or("", |path| path),
+            unknown_keys.join(", ")
+        ));
+    }
+    apply_env(&mut config)?;
+    let confirmed = args.iter().any(|arg| arg == "--i-know-what-im-doing");
+    match parse_args(config, args.into_iter())? {
+        Command::Serve { mut config, .. } => {
+            config.validate().map_err(|err| err.to_string())?;
+            check_chaos(&config, confirmed, cfg!(debug_assertions))?;
+            // Absolute, so that the logs say which directory is served.
+            let root = &config.static_files.root;
+            config.static_files.root = std::fs::canonicalize(root)
+                .map_err(|err| format!("static.root {}: {err}", root.display()))?;
+            Ok(Command::Serve {
+                config,
+                unknown_keys,
+            })
+        }
+        command => Ok(command),
+    }
+}
+
+/// Refuse to inject faults with a release build, unless `confirmed`.
+fn check_chaos(config: &Config, confirmed: bool, dev_build: bool) -> Result<(), String> {
+    if config.chaos.enabled && !confirmed && !dev_build {
+        return Err(
+            "chaos.enabled makes responses fail on purpose; outside dev builds \
+             it needs --i-know-what-im-doing"
+                .to_string(),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "config")]
+fn load_config(path: &str) -> Result<(Config, Vec<String>), String> {
+    Config::load(path).map_err(|err| err.to_string())
+}
+
+#[cfg(not(feature = "config"))]
+fn load_config(_path: &str) -> Result<(Config, Vec<String>), String> {
+    Err("--config needs the config feature".to_string())
+}
+
+/// Override `config` with the `HELLO_*` environment variables.
+fn apply_env(config: &mut Config) -> Result<(), String> {
+    for name in ENV_VARS {
+        let value = match std::env::var(name) {
+            Ok(value) => value,
+            Err(std::env::VarError::NotPresent) => continue,
+            Err(err) => return Err(format!("{name}: {err}")),
+        };
+        let applied = match name {
+            "HELLO_ADDR" => {
+                parse_addr(&value, config.listener.port).map(|addr| set_addr(config, addr))
             }
+            "HELLO_PORT" => parse_port(&value).map(|port| config.listener.port = port),
+            "HELLO_THREADS" => parse_threads(&value).map(|threads| config.pool.threads = threads),
+            "HELLO_DIR" => parse_dir(&value).map(|dir| config.static_files.root = dir),
+            "HELLO_LOG" => parse_level(&value).map(|level| config.logging.level = level),
+            _ => unreachable!("{name} is not a known variable"),
         };
+        applied.map_err(|err| format!("{name}: {err}"))?;
+    }
+    Ok(())
+}
 
-        let _ = pool.execute(|| {
-            handle_connection(stream);
-        });
+/// Apply the flags in `args` to `config`.
+fn parse_args(
+    mut config: Config,
+    mut args: impl Iterator<Item = String>,
+) -> Result<Command, String> {
+    let mut port = None;
+    let mut addrs = 0;
+    let mut tls_addrs = 0;
+    let mut redirect_addrs = 0;
+    let mut indexes = 0;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        let applied = match arg.as_str() {
+            "--log-format" => {
+                parse_log_format(&value()?).map(|format| config.logging.format = format)
+            }
+            "--quiet" => {
+                config.logging.level = LevelFilter::Error;
+                Ok(())
+            }
+            "-v" | "--verbose" => {
+                config.logging.level = LevelFilter::Debug;
+                Ok(())
+            }
+            "-vv" | "--debug-dump" => {
+                config.logging.level = LevelFilter::Trace;
+                Ok(())
+            }
+            // Read before the other settings, so that they override it.
+            "--config" => value().map(drop),
+            "--strict-config" | "--i-know-what-im-doing" => Ok(()),
+            "--addr" => parse_addr(&value()?, config.listener.port).map(|addr| {
+                addrs += 1;
+                if addrs == 1 {
+                    set_addr(&mut config, addr);
+                    config.listener.extra_addrs.clear();
+                } else {
+                    config.listener.extra_addrs.push(addr);
+                }
+            }),
+            "--listen-any" => {
+                config.listener.addr = IpAddr::from([0u16; 8]);
+                config.listener.extra_addrs.clear();
+                Ok(())
+            }
+            "--separate-stacks" => {
+                config.listener.dual_stack = false;
+                Ok(())
+            }
+            "--reuse-port" => {
+                config.listener.reuse_port = true;
+                Ok(())
+            }
+            "--tls-addr" => parse_addr(&value()?, 8443).map(|addr| {
+                tls_addrs += 1;
+                if tls_addrs == 1 {
+                    config.tls.addrs.clear();
+                }
+                config.tls.addrs.push(addr);
+            }),
+            "--tls-cert" => value().map(|path| config.tls.cert = Some(path.into())),
+            "--tls-key" => value().map(|path| config.tls.key = Some(path.into())),
+            "--redirect-addr" => parse_addr(&value()?, 80).map(|addr| {
+                redirect_addrs += 1;
+                if redirect_addrs == 1 {
+                    config.redirect.addrs.clear();
+                }
+                config.redirect.addrs.push(addr);
+            }),
+            "--canonical-host" => value().map(|host| config.redirect.canonical_host = Some(host)),
+            "--security-headers" => {
+                config.security_headers.enabled = true;
+                Ok(())
+            }
+            "--server-header" => value().map(|name| config.headers.server = name),
+            "--trust-request-id" => {
+                config.proxy.trust_request_id = true;
+                Ok(())
+            }
+            "--unix-socket" => value().map(|path| config.listener.unix_socket = Some(path.into())),
+            "--unix-socket-mode" => {
+                parse_mode(&value()?).map(|mode| config.listener.unix_socket_mode = mode)
+            }
+            "--daemon" => {
+                config.daemon.detach = true;
+                Ok(())
+            }
+            "--pid-file" => value().map(|path| config.daemon.pid_file = Some(path.into())),
+            "--log-file" => value().map(|path| config.daemon.log_file = Some(path.into())),
+            "--user" => value().map(|name| config.daemon.user = Some(name)),
+            "--group" => value().map(|name| config.daemon.group = Some(name)),
+            "--port" => parse_port(&value()?).map(|value| port = Some(value)),
+            "--threads" => parse_threads(&value()?).map(|threads| config.pool.threads = threads),
+            "--dir" => parse_dir(&value()?).map(|dir| config.static_files.root = dir),
+            "--index" => parse_index(&value()?).map(|name| {
+                indexes += 1;
+                if indexes == 1 {
+                    config.static_files.index.clear();
+                }
+                config.static_files.index.push(name);
+            }),
+            "--dev" => {
+                config.debug.dev = true;
+                Ok(())
+            }
+            "--dump-traffic" => value().map(|dir| {
+                config.dump.enabled = true;
+                config.dump.dir = Some(dir.into());
+            }),
+            "--help" => return Ok(Command::Help),
+            "--version" => return Ok(Command::Version),
+            other => return Err(format!("unknown argument {other}")),
+        };
+        applied.map_err(|err| format!("{arg}: {err}"))?;
+    }
+    if let Some(port) = port {
+        config.listener.port = port;
+    }
+    Ok(Command::Serve {
+        config: Box::new(config),
+        unknown_keys: Vec::new(),
+    })
+}
+
+fn set_addr(config: &mut Config, addr: SocketAddr) {
+    config.listener.addr = addr.ip();
+    config.listener.port = addr.port();
+}
+
+/// An IP address with an optional port, falling back to `port`.
+fn parse_addr(value: &str, port: u16) -> Result<SocketAddr, String> {
+    match (value.parse(), value.parse()) {
+        (Ok(addr), _) => Ok(addr),
+        (_, Ok(ip)) => Ok(SocketAddr::new(ip, port)),
+        _ => Err(format!("invalid address {value}")),
+    }
+}
+
+/// Octal permissions like `660`.
+fn parse_mode(value: &str) -> Result<u32, String> {
+    match u32::from_str_radix(value, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(mode),
+        _ => Err(format!("invalid permissions {value}")),
+    }
+}
+
+fn parse_port(value: &str) -> Result<u16, String> {
+    value.parse().map_err(|_| format!("invalid port {value}"))
+}
+
+fn parse_threads(value: &str) -> Result<i32, String> {
+    match value.parse() {
+        Ok(threads) if threads > 0 => Ok(threads),
+        _ => Err(format!("invalid number of threads {value}")),
     }
+}
 
-    println!("Shutting down.");
+fn parse_dir(value: &str) -> Result<PathBuf, String> {
+    let dir = PathBuf::from(value);
+    if !dir.is_dir() {
+        return Err(format!("{value} is not a directory"));
+    }
+    Ok(dir)
+}
+
+/// The name of an index file, which must be in the directory it indexes.
+fn parse_index(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.contains('/') {
+        return Err(format!("{value:?} is not a file name"));
+    }
+    Ok(value.to_string())
+}
+
+/// A log level by name, like `warn` or `debug`.
+fn parse_level(value: &str) -> Result<LevelFilter, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid log level {value}"))
+}
+
+fn parse_log_format(value: &str) -> Result<LogFormat, String> {
+    match value {
+        "text" => Ok(LogFormat::Text),
+        "json" => Ok(LogFormat::Json),
+        _ => Err(format!("invalid log format {value}")),
+    }
+}
+
+/// How long a server stopped with SIGTERM drains, within the grace period
+/// orchestrators usually allow before killing it.
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// The signals the server acts on.
+enum Signal {
+    Interrupt,
+    Terminate,
+    /// A shutdown asked for over HTTP, which drains like `Terminate`.
+    ShutdownRequest,
+    /// Re-read the configuration.
+    Hangup,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let (mut config, unknown_keys) = match from_env_and_args(std::env::args().skip(1)) {
+        Ok(Command::Serve {
+            config,
+            unknown_keys,
+        }) => (config, unknown_keys),
+        Ok(Command::Help) => {
+            print!("{USAGE}");
+            return Ok(());
+        }
+        Ok(Command::Version) => {
+            println!("hello {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Err(err) => {
+            eprint!("{err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    #[cfg(not(feature = "tracing"))]
+    logging::init(config.logging.level, config.logging.format)?;
+    #[cfg(feature = "tracing")]
+    logging::init_tracing(config.logging.level, config.logging.format)?;
+    for key in unknown_keys {
+        warn!("Ignoring unknown configuration key {key}.");
+    }
+    warn_without_index(&config);
+    if config.chaos.enabled {
+        warn!(
+            "Chaos: injecting faults into {}% of the responses.",
+            config.chaos.probability * 100.0
+        );
+    }
+    if let (true, Some(dir)) = (config.dump.enabled, &config.dump.dir) {
+        warn!(
+            "Dumping the raw traffic of connections into {}.",
+            dir.display()
+        );
+    }
+    let server = bind(&config)
+        .map_err(|err| err.to_string())
+        .and_then(|server| server.configure(&config).map_err(|err| err.to_string()));
+    let server = match server {
+        Ok(server) => server.router(
+            Router::new()
+                .websocket("/echo", echo)
+                .get("/events", count)
+                .get("/greet", greet),
+        ),
+        Err(err) => {
+            error!("{err}");
+            process::exit(1);
+        }
+    };
+    // Fork before any threads start, after reporting what can go wrong early.
+    #[cfg(unix)]
+    let detached = match config.daemon.detach {
+        true => Some(daemon::detach(config.daemon.log_file.as_deref())?),
+        false => None,
+    };
+    let Running {
+        server,
+        signals,
+        pid_file,
+    } = match start(server, &config) {
+        Ok(running) => running,
+        Err(err) => {
+            error!("{err}");
+            #[cfg(unix)]
+            if let Some(detached) = detached {
+                detached.fail(&err.to_string());
+            }
+            process::exit(1);
+        }
+    };
+    for addr in server.listen_addrs() {
+        match addr {
+            ListenAddr::Tcp(addr) => {
+                logging::event("startup", &format!("Listening on http://{addr}"))
+            }
+            #[cfg(feature = "tls")]
+            ListenAddr::Tls(addr) => {
+                logging::event("startup", &format!("Listening on https://{addr}"))
+            }
+            #[cfg(unix)]
+            unix => logging::event("startup", &format!("Listening on {unix}")),
+        }
+    }
+    #[cfg(unix)]
+    if let Some(detached) = detached {
+        detached.ready()?;
+    }
+
+    let drained = loop {
+        match signals.recv()? {
+            Signal::Hangup => {
+                if let Err(err) = server.reopen_logs() {
+                    error!("Cannot reopen the access log: {err}");
+                }
+                reload(&server, &mut config);
+            }
+            Signal::Interrupt => {
+                info!("Finishing open connections, press Ctrl-C again to exit immediately.");
+                break server.shutdown();
+            }
+            Signal::Terminate => {
+                info!("Terminated, finishing open connections.");
+                break server.shutdown_within(TERMINATE_TIMEOUT);
+            }
+            Signal::ShutdownRequest => {
+                info!("Shutting down as asked, finishing open connections.");
+                break server.shutdown_within(TERMINATE_TIMEOUT);
+            }
+        }
+    };
+
+    logging::event("shutdown", "Shutting down.");
+    drop(pid_file);
+    if !drained {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+/// Warn when none of the index files are in the document root, so that `/`
+/// would answer 404 without anyone asking why.
+fn warn_without_index(config: &Config) {
+    let files = &config.static_files;
+    let found = files
+        .index
+        .iter()
+        .any(|name| files.root.join(name).is_file());
+    if !found && !files.autoindex && !cfg!(feature = "embedded-assets") {
+        warn!(
+            "None of {} are in {}, so / answers 404.",
+            files.index.join(", "),
+            files.root.display()
+        );
+    }
+}
+
+/// A started server with the signals to act on and its pid file.
+struct Running {
+    server: ServerHandle,
+    signals: mpsc::Receiver<Signal>,
+    pid_file: Option<PidFile>,
+}
+
+/// Write the pid file, open the access log, switch users and start serving on
+/// the background threads.
+fn start(mut server: Server, config: &Config) -> Result<Running, Box<dyn Error>> {
+    let pid_file = match &config.daemon.pid_file {
+        Some(path) => Some(PidFile::create(path, process::id())?),
+        None => None,
+    };
+    // The log file starts a thread, so it is opened after forking, but while
+    // the directories only root may write to are still open to us.
+    let access_log = &config.access_log;
+    if let (true, Some(path)) = (access_log.enabled, &access_log.path) {
+        let file = AccessLog::to_file(config.access_log_format(), path, access_log.rotation())
+            .map_err(|err| format!("Cannot open the access log {}: {err}", path.display()))?;
+        server = server.access_log(file);
+    }
+    // Watching starts a thread too, so it waits for the fork as well.
+    if config.debug.dev {
+        let root = &config.static_files.root;
+        let reload = LiveReload::watch(root, livereload::POLL_INTERVAL)
+            .map_err(|err| format!("Cannot watch {}: {err}", root.display()))?;
+        info!(
+            "Dev mode: reloading pages when files under {} change.",
+            root.display()
+        );
+        server = server.live_reload(reload);
+    }
+    #[cfg(unix)]
+    {
+        use hello::privileges::{self, Libc};
+
+        let daemon = &config.daemon;
+        let switched = privileges::drop_privileges(
+            &mut Libc,
+            daemon.user.as_deref(),
+            daemon.group.as_deref(),
+        )?;
+        if let Some(switched) = switched {
+            info!(
+                "Running as uid {} and gid {}; files are opened with their permissions from now on.",
+                switched.uid, switched.gid
+            );
+        }
+    }
+    // Handle signals before announcing the address, so none arrive unhandled.
+    let (sender, signals) = mpsc::channel();
+    let requests = sender.clone();
+    server = server.on_shutdown_request(move || {
+        let _ = requests.send(Signal::ShutdownRequest);
+    });
+    watch_signals(sender)?;
+    Ok(Running {
+        server: server.spawn()?,
+        signals,
+        pid_file,
+    })
+}
+
+/// Send every message on `socket` back, the example WebSocket at `/echo`.
+fn echo(_request: &Request, mut socket: WebSocket<&mut dyn Duplex>) -> io::Result<()> {
+    while let Some(message) = socket.recv()? {
+        if let Message::Text(_) | Message::Binary(_) = message {
+            socket.send(&message)?;
+        }
+    }
     Ok(())
 }
 
-fn handle_connection<T>(mut stream: T)
-where
-    T: Read + Write,
-{
-    let buf_reader = BufReader::new(&mut stream);
-    let res = buf_reader.lines().next();
-    let request_line = match res {
-        Some(Ok(line)) => Some(line),
-        _ => {
-            eprintln!("Got malformed request.");
-            None
+/// Count up once a second until the client goes away, the example event
+/// stream at `/events`.
+fn count(_request: &Request) -> io::Result<Response> {
+    let (sender, response) = sse::stream(sse::KEEP_ALIVE);
+    thread::spawn(move || {
+        for count in 1u64.. {
+            let event = Event::new(count.to_string()).id(count.to_string());
+            if sender.send(event.name("count")).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
+    Ok(response)
+}
+
+/// The page of the example greeting at `/greet`.
+const GREETING: &str = "\
+<!DOCTYPE html>
+<html lang=\"en\">
+  <head>
+    <meta charset=\"utf-8\">
+    <title>Hello!</title>
+  </head>
+  <body>
+    <h1>Hello{{#if name}}, {{name}}{{/if}}!</h1>
+  </body>
+</html>
+";
+
+/// Greet the `name` in the query string, the example template at `/greet`.
+fn greet(request: &Request) -> io::Result<Response> {
+    let query = http::parse_query(request.query());
+    let name = query
+        .iter()
+        .find(|(key, _)| key == "name")
+        .map_or("", |(_, name)| name.as_str());
+    let page = render_template(GREETING, &[("name", name)]).map_err(io::Error::other)?;
+    Ok(Response::new(200)
+        .with_header("Content-Type", "text/html; charset=utf-8")
+        .with_body(Body::Bytes(page.into_bytes())))
+}
+
+/// Bind every address of `config`, failing on the first which cannot be bound,
+/// unless systemd passed in the listening sockets.
+fn bind(config: &Config) -> io::Result<Server> {
+    let socket = SocketOptions::new()
+        .ipv6_only(!config.listener.dual_stack)
+        .reuse_port(config.listener.reuse_port);
+    #[cfg(unix)]
+    let inherited = hello::activation::inherited()?;
+    #[cfg(not(unix))]
+    let inherited = None;
+    let mut server = match inherited {
+        // The sockets stay systemd's, which hands them to the next instance.
+        Some(listeners) => Server::from_listeners(listeners, socket.reuse_port(false))?,
+        None => {
+            let addrs = config.addrs();
+            let mut server = Server::bind_with(addrs[0], socket)?;
+            for addr in &addrs[1..] {
+                server = server.listen(addr)?;
+            }
+            server
         }
     };
+    server = listen_tls(server, config)?;
+    for addr in &config.redirect.addrs {
+        server = server.listen_redirect(addr)?;
+    }
+    if let (true, Some(addr)) = (config.metrics.enabled, config.metrics.addr) {
+        server = server.listen_metrics(addr)?;
+    }
+    #[cfg(unix)]
+    if let Some(path) = &config.listener.unix_socket {
+        server = server.listen_unix(path, config.listener.unix_socket_mode)?;
+    }
+    Ok(server)
+}
 
-    let (status_line, filename) = if request_line == Some("GET / HTTP/1.1".to_string()) {
-        ("HTTP/1.1 200 OK", "hello.html")
-    } else {
-        ("HTTP/1.1 404 NOT FOUND", "404.html")
+/// Add the HTTPS listeners of `config`, loading the certificate once for all.
+#[cfg(feature = "tls")]
+fn listen_tls(mut server: Server, config: &Config) -> io::Result<Server> {
+    let (Some(cert), Some(key)) = (&config.tls.cert, &config.tls.key) else {
+        return Ok(server);
     };
+    if config.tls.addrs.is_empty() {
+        return Ok(server);
+    }
+    let acceptor = hello::tls::TlsAcceptor::from_pem_files(cert, key)?;
+    for addr in &config.tls.addrs {
+        server = server.listen_tls(addr, acceptor.clone())?;
+    }
+    Ok(server)
+}
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+#[cfg(not(feature = "tls"))]
+fn listen_tls(server: Server, config: &Config) -> io::Result<Server> {
+    if !config.tls.addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "HTTPS needs the tls feature",
+        ));
+    }
+    Ok(server)
+}
 
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    println!("{}", response);
+/// Re-read the configuration and apply what can change while running to
+/// `server`, keeping `config` when the new one is invalid.
+fn reload(server: &ServerHandle, config: &mut Config) {
+    let (mut reloaded, unknown_keys) = match from_env_and_args(std::env::args().skip(1)) {
+        Ok(Command::Serve {
+            config,
+            unknown_keys,
+        }) => (*config, unknown_keys),
+        Ok(_) => return,
+        Err(err) => {
+            error!("Keeping the current configuration, the new one is invalid: {err}");
+            return;
+        }
+    };
+    for key in unknown_keys {
+        warn!("Ignoring unknown configuration key {key}.");
+    }
+    for setting in restart_required(config, &mut reloaded) {
+        warn!("Restart the server to change {setting}.");
+    }
+    if let Err(err) = server.reload(&reloaded) {
+        error!("Keeping the current configuration, the new one is invalid: {err}");
+        return;
+    }
+    log::set_max_level(reloaded.logging.level);
+    *config = reloaded;
+    logging::event("reload", "Reloaded the configuration.");
+}
 
-    stream.write_all(response.as_bytes()).unwrap();
+/// The settings which differ between `running` and `reloaded` but can only
+/// change on a restart, resetting them in `reloaded` to the running values.
+fn restart_required(running: &Config, reloaded: &mut Config) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if reloaded.listener.addr != running.listener.addr {
+        changed.push("listener.addr");
+        reloaded.listener.addr = running.listener.addr;
+    }
+    if reloaded.listener.port != running.listener.port {
+        changed.push("listener.port");
+        reloaded.listener.port = running.listener.port;
+    }
+    if reloaded.listener.extra_addrs != running.listener.extra_addrs {
+        changed.push("listener.extra_addrs");
+        reloaded.listener.extra_addrs = running.listener.extra_addrs.clone();
+    }
+    if reloaded.listener.dual_stack != running.listener.dual_stack {
+        changed.push("listener.dual_stack");
+        reloaded.listener.dual_stack = running.listener.dual_stack;
+    }
+    if reloaded.listener.reuse_port != running.listener.reuse_port {
+        changed.push("listener.reuse_port");
+        reloaded.listener.reuse_port = running.listener.reuse_port;
+    }
+    if reloaded.listener.unix_socket != running.listener.unix_socket
+        || reloaded.listener.unix_socket_mode != running.listener.unix_socket_mode
+    {
+        changed.push("listener.unix_socket");
+        reloaded.listener.unix_socket = running.listener.unix_socket.clone();
+        reloaded.listener.unix_socket_mode = running.listener.unix_socket_mode;
+    }
+    if reloaded.redirect.addrs != running.redirect.addrs {
+        changed.push("redirect.addrs");
+        reloaded.redirect.addrs = running.redirect.addrs.clone();
+    }
+    let (running_tls, reloaded_tls) = (&running.tls, &mut reloaded.tls);
+    if (&reloaded_tls.addrs, &reloaded_tls.cert, &reloaded_tls.key)
+        != (&running_tls.addrs, &running_tls.cert, &running_tls.key)
+    {
+        changed.push("tls");
+        reloaded_tls.addrs = running_tls.addrs.clone();
+        reloaded_tls.cert = running_tls.cert.clone();
+        reloaded_tls.key = running_tls.key.clone();
+    }
+    if reloaded.daemon != running.daemon {
+        changed.push("daemon");
+        reloaded.daemon = running.daemon.clone();
+    }
+    if reloaded.logging.format != running.logging.format {
+        changed.push("logging.format");
+        reloaded.logging.format = running.logging.format;
+    }
+    if reloaded.metrics != running.metrics {
+        changed.push("metrics");
+        reloaded.metrics = running.metrics.clone();
+    }
+    if reloaded.pool.threads != running.pool.threads {
+        changed.push("pool.threads");
+        reloaded.pool.threads = running.pool.threads;
+    }
+    if reloaded.limits.max_connections != running.limits.max_connections {
+        changed.push("limits.max_connections");
+        reloaded.limits.max_connections = running.limits.max_connections;
+    }
+    changed
+}
+
+/// Send signals to `sender`, exiting at once on a second stop signal.
+fn watch_signals(sender: mpsc::Sender<Signal>) -> Result<(), Box<dyn Error>> {
+    let stopping = Arc::new(AtomicBool::new(false));
+    let forward = {
+        let sender = sender.clone();
+        move |signal: Signal, code: i32| {
+            if stopping.swap(true, Ordering::SeqCst) {
+                warn!("Stopped again, exiting immediately.");
+                process::exit(code);
+            }
+            let _ = sender.send(signal);
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        use signal_hook::{
+            consts::{SIGHUP, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals = Signals::new([SIGTERM, SIGHUP])?;
+        let forward = forward.clone();
+        std::thread::spawn(move || {
+            for signal in signals.forever() {
+                match signal {
+                    SIGHUP => {
+                        let _ = sender.send(Signal::Hangup);
+                    }
+                    _ => forward(Signal::Terminate, 128 + SIGTERM),
+                }
+            }
+        });
+    }
+    #[cfg(not(unix))]
+    drop(sender);
+    ctrlc::set_handler(move || forward(Signal::Interrupt, 130))?;
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
-
     use super::*;
 
+    use hello::config::{DaemonConfig, RedirectConfig, TlsConfig};
+    use std::{
+        ffi::OsString,
+        sync::{Mutex, MutexGuard},
+    };
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(Config::default(), args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn serve(args: &[&str]) -> Result<Config, String> {
+        match parse(args)? {
+            Command::Serve { config, .. } => Ok(*config),
+            other => Err(format!("{other:?} instead of serving")),
+        }
+    }
+
     #[test]
-    fn test_handle_connection_with_valid_request() -> Result<(), Box<dyn std::error::Error>> {
-        let mut stream = Cursor::new(b"GET / HTTP/1.1".to_vec());
-        stream.seek(std::io::SeekFrom::Start(0))?;
-        handle_connection(&mut stream);
+    fn test_log_level_flags() {
+        let level = |args: &[&str]| serve(args).map(|config| config.logging.level);
+        assert_eq!(level(&[]), Ok(LevelFilter::Info));
+        assert_eq!(level(&["--quiet"]), Ok(LevelFilter::Error));
+        assert_eq!(level(&["--verbose"]), Ok(LevelFilter::Debug));
+        assert_eq!(level(&["-v"]), Ok(LevelFilter::Debug));
+        assert_eq!(level(&["--debug-dump"]), Ok(LevelFilter::Trace));
+        assert_eq!(level(&["-vv"]), Ok(LevelFilter::Trace));
+        assert!(level(&["--loud"]).is_err());
 
-        let mut output = String::new();
-        stream.seek(std::io::SeekFrom::Start(0))?;
-        stream.read_to_string(&mut output)?;
+        let format = |args: &[&str]| serve(args).map(|config| config.logging.format);
+        assert_eq!(format(&[]), Ok(LogFormat::Text));
+        assert_eq!(format(&["--log-format", "json"]), Ok(LogFormat::Json));
+        assert!(format(&["--log-format", "xml"]).is_err());
+    }
 
-        assert!(output.contains("HTTP/1.1 200 OK"));
-        assert!(output.contains("Content-Length: "));
-        Ok(())
+    #[test]
+    fn test_addr_flags() {
+        let addr = |args: &[&str]| serve(args).map(|config| config.addr());
+        assert_eq!(addr(&[]), Ok(SocketAddr::from(([127, 0, 0, 1], 7878))));
+        assert_eq!(
+            addr(&["--addr", "0.0.0.0:0"]),
+            Ok(SocketAddr::from(([0, 0, 0, 0], 0)))
+        );
+        assert_eq!(
+            addr(&["--port", "8080", "--addr", "::1"]),
+            Ok("[::1]:8080".parse().unwrap())
+        );
+        assert_eq!(
+            addr(&["--addr", "10.0.0.1:80", "--port", "8080"]),
+            Ok(SocketAddr::from(([10, 0, 0, 1], 8080)))
+        );
+        assert!(addr(&["--addr", "localhost"]).is_err());
+        assert!(addr(&["--addr", "127.0.0.1:http"]).is_err());
+        assert!(addr(&["--port", "65536"]).is_err());
+        assert!(addr(&["--addr"]).is_err());
+
+        let any = serve(&["--listen-any", "--separate-stacks", "--port", "80"]);
+        assert_eq!(
+            any.map(|config| config.addrs()),
+            Ok(vec![
+                SocketAddr::from(([0, 0, 0, 0], 80)),
+                "[::]:80".parse().unwrap()
+            ])
+        );
+
+        let addrs = serve(&["--addr", "127.0.0.1:0", "--addr", "::1", "--port", "80"])
+            .map(|config| config.addrs());
+        assert_eq!(
+            addrs,
+            Ok(vec![
+                SocketAddr::from(([127, 0, 0, 1], 80)),
+                "[::1]:0".parse().unwrap()
+            ])
+        );
+    }
+
+    #[test]
+    fn test_threads_and_dir_flags() {
+        let config = serve(&["--threads", "4", "--dir", "src"]).unwrap();
+        assert_eq!(
+            (config.pool.threads, config.static_files.root),
+            (4, PathBuf::from("src"))
+        );
+        assert_eq!(serve(&[]).map(|config| config.pool.threads), Ok(-1));
+        assert!(serve(&["--dir", "src", "--dev"]).unwrap().debug.dev);
+        assert_eq!(
+            serve(&["--index", "home.html", "--index", "site.html"])
+                .unwrap()
+                .static_files
+                .index,
+            ["home.html", "site.html"]
+        );
+        assert!(serve(&["--index", "../hello.html"]).is_err());
+
+        assert!(serve(&["--threads", "0"]).is_err());
+        assert!(serve(&["--threads", "-1"]).is_err());
+        assert!(serve(&["--threads", "many"]).is_err());
+        assert!(serve(&["--dir", "does/not/exist"]).is_err());
+    }
+
+    #[test]
+    fn test_daemon_flags() {
+        let config = serve(&[
+            "--daemon",
+            "--pid-file",
+            "hello.pid",
+            "--log-file",
+            "hello.log",
+            "--user",
+            "www",
+        ]);
+        assert_eq!(
+            config.map(|config| config.daemon),
+            Ok(DaemonConfig {
+                detach: true,
+                pid_file: Some(PathBuf::from("hello.pid")),
+                log_file: Some(PathBuf::from("hello.log")),
+                user: Some("www".to_string()),
+                group: None,
+            })
+        );
+        assert_eq!(serve(&[]).map(|config| config.daemon.detach), Ok(false));
+    }
+
+    #[test]
+    fn test_https_flags() {
+        let config = serve(&[
+            "--tls-addr",
+            "0.0.0.0",
+            "--tls-addr",
+            "[::1]:9443",
+            "--tls-cert",
+            "cert.pem",
+            "--tls-key",
+            "key.pem",
+        ]);
+        assert_eq!(
+            config.map(|config| config.tls),
+            Ok(TlsConfig {
+                addrs: vec![
+                    "0.0.0.0:8443".parse().unwrap(),
+                    "[::1]:9443".parse().unwrap()
+                ],
+                cert: Some(PathBuf::from("cert.pem")),
+                key: Some(PathBuf::from("key.pem")),
+                ..TlsConfig::default()
+            })
+        );
+        assert!(serve(&["--tls-addr", "localhost"]).is_err());
+
+        let config = serve(&[
+            "--redirect-addr",
+            "0.0.0.0",
+            "--canonical-host",
+            "example.com",
+        ]);
+        assert_eq!(
+            config.map(|config| config.redirect),
+            Ok(RedirectConfig {
+                addrs: vec!["0.0.0.0:80".parse().unwrap()],
+                canonical_host: Some("example.com".to_string()),
+                https_port: 443,
+            })
+        );
+    }
+
+    #[test]
+    fn test_help_and_version() {
+        assert_eq!(parse(&["--help"]), Ok(Command::Help));
+        assert_eq!(parse(&["--port", "1", "--version"]), Ok(Command::Version));
+        for flag in [
+            "--config",
+            "--strict-config",
+            "--addr",
+            "--listen-any",
+            "--separate-stacks",
+            "--reuse-port",
+            "--unix-socket",
+            "--tls-addr",
+            "--tls-cert",
+            "--tls-key",
+            "--redirect-addr",
+            "--canonical-host",
+            "--security-headers",
+            "--server-header",
+            "--trust-request-id",
+            "--daemon",
+            "--pid-file",
+            "--log-file",
+            "--user",
+            "--group",
+            "--port",
+            "--threads",
+            "--dir",
+            "--dump-traffic",
+            "--quiet",
+            "--verbose",
+        ] {
+            assert!(USAGE.contains(flag), "{flag} is documented");
+        }
+    }
+
+    /// Serializes the tests which change the environment.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Sets the `HELLO_*` variables for the duration of a test, restoring
+    /// their previous values when dropped.
+    struct ScopedEnv {
+        saved: Vec<(&'static str, Option<OsString>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl ScopedEnv {
+        /// Set the variables in `vars` and unset the other `HELLO_*` ones.
+        fn new(vars: &[(&str, &str)]) -> ScopedEnv {
+            let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let saved = ENV_VARS
+                .iter()
+                .map(|&name| (name, std::env::var_os(name)))
+                .collect();
+            for name in ENV_VARS {
+                match vars.iter().find(|(var, _)| *var == name) {
+                    Some((_, value)) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+            ScopedEnv { saved, _lock: lock }
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for (name, value) in &self.saved {
+                match value {
+                    Some(value) => std::env::set_var(name, value),
+                    None => std::env::remove_var(name),
+                }
+            }
+        }
+    }
+
+    fn layered(args: &[&str]) -> Result<(Config, Vec<String>), String> {
+        match from_env_and_args(args.iter().map(|arg| arg.to_string()))? {
+            Command::Serve {
+                config,
+                unknown_keys,
+            } => Ok((*config, unknown_keys)),
+            other => Err(format!("{other:?} instead of serving")),
+        }
+    }
+
+    #[test]
+    fn test_env_defaults() {
+        let _env = ScopedEnv::new(&[
+            ("HELLO_ADDR", "0.0.0.0"),
+            ("HELLO_PORT", "8080"),
+            ("HELLO_THREADS", "3"),
+            ("HELLO_DIR", "src"),
+            ("HELLO_LOG", "debug"),
+        ]);
+        let (config, _) = layered(&[]).unwrap();
+        assert_eq!(config.logging.level, LevelFilter::Debug);
+        assert_eq!(config.addr(), SocketAddr::from(([0, 0, 0, 0], 8080)));
+        assert_eq!(config.pool.threads, 3);
+        assert_eq!(
+            config.static_files.root,
+            std::fs::canonicalize("src").unwrap()
+        );
+
+        let (config, _) = layered(&["--addr", "127.0.0.1", "--threads", "2", "--quiet"]).unwrap();
+        assert_eq!(config.addr(), SocketAddr::from(([127, 0, 0, 1], 8080)));
+        assert_eq!(
+            (config.pool.threads, config.logging.level),
+            (2, LevelFilter::Error)
+        );
+        let (config, _) = layered(&["--addr", "127.0.0.1:9000"]).unwrap();
+        assert_eq!(config.addr().port(), 9000, "flags take precedence over env");
+    }
+
+    #[test]
+    fn test_env_unset_uses_builtin_defaults() {
+        let _env = ScopedEnv::new(&[]);
+        let mut config = Config::default();
+        config.static_files.root = std::env::current_dir().unwrap();
+        assert_eq!(layered(&[]), Ok((config, Vec::new())));
+    }
+
+    #[test]
+    fn test_invalid_env_names_the_variable() {
+        for (name, value) in [
+            ("HELLO_PORT", "http"),
+            ("HELLO_ADDR", "localhost"),
+            ("HELLO_THREADS", "0"),
+            ("HELLO_DIR", "does/not/exist"),
+            ("HELLO_LOG", "loud"),
+        ] {
+            let _env = ScopedEnv::new(&[(name, value)]);
+            let err = layered(&[]).expect_err("the value is invalid");
+            assert!(err.starts_with(&format!("{name}: ")), "{err}");
+        }
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_config_file_is_overridden_by_env_and_flags() {
+        let _env = ScopedEnv::new(&[("HELLO_PORT", "9000")]);
+        let (config, unknown_keys) = layered(&[
+            "--threads",
+            "2",
+            "--config",
+            "tests/fixtures/server.toml",
+            "--dir",
+            "src",
+        ])
+        .unwrap();
+        assert!(unknown_keys.is_empty());
+        assert_eq!(config.addr(), SocketAddr::from(([0, 0, 0, 0], 9000)));
+        assert_eq!(config.pool.threads, 2);
+        assert_eq!(
+            config.static_files.root,
+            std::fs::canonicalize("src").unwrap()
+        );
+        assert!(config.static_files.autoindex, "the file sets the rest");
+        assert_eq!(config.logging.level, LevelFilter::Debug);
+
+        assert!(
+            layered(&["--config", "tests/fixtures/server.toml"]).is_err(),
+            "the file's document root does not exist"
+        );
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn test_strict_config_rejects_unknown_keys() {
+        let _env = ScopedEnv::new(&[]);
+        let path = std::env::temp_dir().join(format!("hello-strict-{}.toml", process::id()));
+        std::fs::write(&path, "[pool]\nthreds = 2\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let (_, unknown_keys) = layered(&["--config", path]).unwrap();
+        assert_eq!(unknown_keys, ["pool.threds"]);
+        let err = layered(&["--config", path, "--strict-config"]).expect_err("the key is unknown");
+        assert!(err.contains("pool.threds"), "{err}");
+        std::fs::remove_file(path).unwrap();
     }
 
     #[test]
-    fn test_handle_connection_invalid_request() -> Result<(), Box<dyn std::error::Error>> {
-        let mut stream = Cursor::new(b"INVALID".to_vec());
-        stream.seek(std::io::SeekFrom::Start(0))?;
-        handle_connection(&mut stream);
+    fn test_chaos_needs_a_dev_build_or_confirmation() {
+        let mut config = Config::default();
+        assert!(check_chaos(&config, false, false).is_ok(), "off");
+        config.chaos.enabled = true;
+        let err = check_chaos(&config, false, false).expect_err("a release build");
+        assert!(err.contains("--i-know-what-im-doing"), "{err}");
+        assert!(check_chaos(&config, true, false).is_ok());
+        assert!(check_chaos(&config, false, true).is_ok());
+        let _env = ScopedEnv::new(&[]);
+        assert!(layered(&["--i-know-what-im-doing"]).is_ok(), "a known flag");
+    }
 
-        let mut output = String::new();
-        stream.seek(std::io::SeekFrom::Start(0))?;
-        stream.read_to_string(&mut output)?;
+    #[test]
+    fn test_restart_required_keeps_running_values() {
+        let running = Config::default();
+        let mut reloaded = Config::default();
+        reloaded.listener.port = 8080;
+        reloaded.pool.threads = 2;
+        reloaded.limits.max_body_size = 16;
+        assert_eq!(
+            restart_required(&running, &mut reloaded),
+            ["listener.port", "pool.threads"]
+        );
+        assert_eq!((reloaded.listener.port, reloaded.pool.threads), (7878, -1));
+        assert_eq!(reloaded.limits.max_body_size, 16, "the rest is reloaded");
+    }
 
-        assert!(output.contains("HTTP/1.1 404 NOT FOUND"));
-        assert!(output.contains("Content-Length: "));
+    #[test]
+    fn test_greet_escapes_the_name() -> Result<(), Box<dyn Error>> {
+        let page = |target: &str| -> Result<String, Box<dyn Error>> {
+            let request = Request::new(http::Method::Get, target, http::Version::Http11);
+            let response = greet(&request)?;
+            let mut body = Vec::new();
+            response.body().write_to(&mut body)?;
+            Ok(String::from_utf8(body)?)
+        };
+        assert!(page("/greet?name=Ann")?.contains("<h1>Hello, Ann!</h1>"));
+        assert!(page("/greet?name=%3Cscript%3E%22&x=1")?
+            .contains("<h1>Hello, &lt;script&gt;&quot;!</h1>"));
+        assert!(page("/greet")?.contains("<h1>Hello!</h1>"));
         Ok(())
     }
 }
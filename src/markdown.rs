@@ -0,0 +1,973 @@
+//! Markdown files rendered as HTML pages on the fly.
+//!
+//! The renderer covers the common part of CommonMark: ATX and setext
+//! headings, paragraphs, emphasis, code spans, fenced and indented code
+//! blocks, block quotes, thematic breaks, links, images, autolinks and
+//! nested bullet and ordered lists. HTML in the source is escaped unless
+//! [`Markdown::raw_html`] lets it through, and so are links to `javascript:`
+//! and the like.
+
+use std::{
+    path::PathBuf,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::template::{escape_html, Template};
+
+/// The page a rendered document is put in when no shell is configured.
+const SHELL: &str = "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <meta charset=\"utf-8\">\n    <title>{{title}}</title>\n  </head>\n  <body>\n{{{content}}}  </body>\n</html>\n";
+
+/// How often a shell which cannot be rendered is warned about; the built-in
+/// one is used in between.
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How Markdown files are rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markdown {
+    shell: Option<PathBuf>,
+    raw_html: bool,
+}
+
+impl Markdown {
+    /// Render into the built-in page, escaping HTML in the source.
+    pub fn new() -> Markdown {
+        Markdown::default()
+    }
+
+    /// Put rendered documents into the template at `path`, read for every
+    /// page, where `{{title}}` is the text of the first heading and
+    /// `{{{content}}}` the rendered document.
+    pub fn shell(mut self, path: impl Into<PathBuf>) -> Markdown {
+        self.shell = Some(path.into());
+        self
+    }
+
+    /// Pass HTML in the source through as it is, for trusted documents only.
+    pub fn raw_html(mut self, allowed: bool) -> Markdown {
+        self.raw_html = allowed;
+        self
+    }
+
+    /// The HTML page for the Markdown `source`, titled `fallback_title` if
+    /// it has no heading.
+    pub fn page(&self, source: &str, fallback_title: &str) -> String {
+        let content = to_html(source, self.raw_html);
+        let title = title(source).unwrap_or_else(|| fallback_title.to_string());
+        let values = [("title", title.as_str()), ("content", content.as_str())];
+        if let Some(path) = &self.shell {
+            match Template::load(path).and_then(|shell| shell.render(&values)) {
+                Ok(page) => return page,
+                Err(err) => {
+                    static WARNED: Mutex<Option<Instant>> = Mutex::new(None);
+                    let mut warned = WARNED.lock().unwrap_or_else(PoisonError::into_inner);
+                    if !warned.is_some_and(|at| at.elapsed() < WARNING_INTERVAL) {
+                        *warned = Some(Instant::now());
+                        warn!(
+                            "Rendering Markdown into the built-in page, as the shell failed: {err}"
+                        );
+                    }
+                }
+            }
+        }
+        Template::new(SHELL)
+            .render(&values)
+            .expect("the built-in shell has both values")
+    }
+}
+
+/// The Markdown `source` as HTML, with HTML in it escaped unless `raw_html`.
+pub fn to_html(source: &str, raw_html: bool) -> String {
+    let lines: Vec<String> = source
+        .lines()
+        .map(|line| line.replace('\t', "    "))
+        .collect();
+    let mut out = String::with_capacity(source.len() * 3 / 2);
+    Renderer { raw_html }.blocks(&lines, false, &mut out);
+    out
+}
+
+/// The text of the first heading of `source`, without its markup.
+pub fn title(source: &str) -> Option<String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let renderer = Renderer { raw_html: false };
+    let mut in_fence = None;
+    for (i, line) in lines.iter().enumerate() {
+        let (indent, trimmed) = split_indent(line);
+        if let Some(fence) = in_fence {
+            if indent < 4 && closes_fence(trimmed, fence) {
+                in_fence = None;
+            }
+            continue;
+        }
+        if indent >= 4 {
+            continue;
+        }
+        if let Some((fence, _)) = opens_fence(trimmed) {
+            in_fence = Some(fence);
+            continue;
+        }
+        let text = match atx_heading(trimmed) {
+            Some((_, text)) => text,
+            None if !trimmed.is_empty()
+                && lines
+                    .get(i + 1)
+                    .and_then(|next| setext_level(next))
+                    .is_some()
+                && starts_block(trimmed, false).is_none() =>
+            {
+                trimmed
+            }
+            None => continue,
+        };
+        let mut plain = String::new();
+        renderer.inline(text.trim(), true, &mut plain);
+        return Some(plain);
+    }
+    None
+}
+
+/// The number of leading spaces of `line`, and the rest of it.
+fn split_indent(line: &str) -> (usize, &str) {
+    let trimmed = line.trim_start_matches(' ');
+    (line.len() - trimmed.len(), trimmed)
+}
+
+/// The fence `line` opens a code block with, as its character and length,
+/// and the info string after it.
+fn opens_fence(line: &str) -> Option<((char, usize), &str)> {
+    let c = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.chars().take_while(|&d| d == c).count();
+    let info = line[len..].trim();
+    (len >= 3 && !(c == '`' && info.contains('`'))).then_some(((c, len), info))
+}
+
+fn closes_fence(line: &str, (c, len): (char, usize)) -> bool {
+    let run = line.chars().take_while(|&d| d == c).count();
+    run >= len && line[run..].trim().is_empty()
+}
+
+/// The level and text of an ATX heading.
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with(' ')) {
+        return None;
+    }
+    let text = rest.trim();
+    // A closing sequence of #s goes, if it stands apart.
+    let unclosed = text.trim_end_matches('#');
+    let text = match unclosed.is_empty() || unclosed.ends_with(' ') {
+        true => unclosed.trim_end(),
+        false => text,
+    };
+    Some((level, text))
+}
+
+/// The level of the heading `line` underlines a paragraph with, if any.
+fn setext_level(line: &str) -> Option<usize> {
+    let (indent, trimmed) = split_indent(line);
+    let trimmed = trimmed.trim_end();
+    if indent >= 4 || trimmed.is_empty() {
+        return None;
+    }
+    if trimmed.chars().all(|c| c == '=') {
+        Some(1)
+    } else if trimmed.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn is_thematic_break(line: &str) -> bool {
+    let mut marks = line.chars().filter(|c| *c != ' ');
+    let Some(first) = marks.next().filter(|c| matches!(c, '-' | '*' | '_')) else {
+        return false;
+    };
+    let mut count = 1;
+    for c in marks {
+        if c != first {
+            return false;
+        }
+        count += 1;
+    }
+    count >= 3
+}
+
+/// A list item marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+    Bullet(char),
+    /// The delimiter after the number, with the number.
+    Ordered(char, u32),
+}
+
+impl Marker {
+    fn same_list(self, other: Marker) -> bool {
+        match (self, other) {
+            (Marker::Bullet(a), Marker::Bullet(b)) => a == b,
+            (Marker::Ordered(a, _), Marker::Ordered(b, _)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The list item marker `line` starts with, and the offset of its content.
+fn list_marker(line: &str) -> Option<(Marker, usize)> {
+    let (marker, len) = match line.chars().next()? {
+        c @ ('-' | '*' | '+') => (Marker::Bullet(c), 1),
+        _ => {
+            let digits = line.chars().take_while(char::is_ascii_digit).count();
+            let delimiter = line[digits..].chars().next()?;
+            if !(1..=9).contains(&digits) || !matches!(delimiter, '.' | ')') {
+                return None;
+            }
+            let number = line[..digits].parse().ok()?;
+            (Marker::Ordered(delimiter, number), digits + 1)
+        }
+    };
+    let rest = &line[len..];
+    if rest.trim().is_empty() {
+        return Some((marker, len + 1));
+    }
+    let spaces = rest.chars().take_while(|&c| c == ' ').count();
+    match spaces {
+        0 => None,
+        1..=4 => Some((marker, len + spaces)),
+        // The content is an indented code block.
+        _ => Some((marker, len + 1)),
+    }
+}
+
+/// The kind of block a line which is not indented starts, if it is not a
+/// paragraph's; `raw_html` lets HTML blocks start.
+fn starts_block(line: &str, raw_html: bool) -> Option<&'static str> {
+    if atx_heading(line).is_some() {
+        Some("heading")
+    } else if opens_fence(line).is_some() {
+        Some("fence")
+    } else if is_thematic_break(line) {
+        Some("break")
+    } else if line.starts_with('>') {
+        Some("quote")
+    } else if list_marker(line)
+        .is_some_and(|(_, offset)| !line[offset.min(line.len())..].trim().is_empty())
+    {
+        Some("list")
+    } else if raw_html && starts_tag(line) {
+        Some("html")
+    } else {
+        None
+    }
+}
+
+/// Whether `text` starts with something like an HTML tag or comment.
+fn starts_tag(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next() == Some('<')
+        && chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'))
+}
+
+struct Renderer {
+    raw_html: bool,
+}
+
+impl Renderer {
+    /// Render the blocks of `lines`; paragraphs go without `<p>` when `tight`,
+    /// as in the items of tight lists.
+    fn blocks(&self, lines: &[String], tight: bool, out: &mut String) {
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i].as_str();
+            let (indent, trimmed) = split_indent(line);
+            if trimmed.trim().is_empty() {
+                i += 1;
+                continue;
+            }
+            let paragraph = indent < 4
+                && opens_fence(trimmed).is_none()
+                && atx_heading(trimmed).is_none()
+                && !is_thematic_break(trimmed)
+                && !trimmed.starts_with('>')
+                && list_marker(trimmed).is_none()
+                && !(self.raw_html && starts_tag(trimmed));
+            if paragraph {
+                i = self.paragraph(lines, i, tight, out);
+                continue;
+            }
+            start_line(out);
+            if indent >= 4 {
+                i = self.indented_code(lines, i, out);
+            } else if let Some(((c, len), info)) = opens_fence(trimmed) {
+                i = self.fenced_code(lines, i, indent, (c, len), info, out);
+            } else if let Some((level, text)) = atx_heading(trimmed) {
+                out.push_str(&format!("<h{level}>"));
+                self.inline(text, false, out);
+                out.push_str(&format!("</h{level}>\n"));
+                i += 1;
+            } else if is_thematic_break(trimmed) {
+                out.push_str("<hr />\n");
+                i += 1;
+            } else if trimmed.starts_with('>') {
+                i = self.block_quote(lines, i, out);
+            } else if let Some((marker, _)) = list_marker(trimmed) {
+                i = self.list(lines, i, marker, out);
+            } else {
+                while i < lines.len() && !lines[i].trim().is_empty() {
+                    out.push_str(&lines[i]);
+                    out.push('\n');
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn indented_code(&self, lines: &[String], start: usize, out: &mut String) -> usize {
+        let mut end = start;
+        let mut last = start;
+        while end < lines.len() {
+            let (indent, trimmed) = split_indent(&lines[end]);
+            if !trimmed.is_empty() && indent < 4 {
+                break;
+            }
+            if !trimmed.is_empty() {
+                last = end;
+            }
+            end += 1;
+        }
+        out.push_str("<pre><code>");
+        for line in &lines[start..=last] {
+            out.push_str(&escape_html(line.get(4..).unwrap_or("")));
+            out.push('\n');
+        }
+        out.push_str("</code></pre>\n");
+        last + 1
+    }
+
+    fn fenced_code(
+        &self,
+        lines: &[String],
+        start: usize,
+        indent: usize,
+        fence: (char, usize),
+        info: &str,
+        out: &mut String,
+    ) -> usize {
+        match info.split_whitespace().next() {
+            Some(language) => out.push_str(&format!(
+                "<pre><code class=\"language-{}\">",
+                escape_html(language)
+            )),
+            None => out.push_str("<pre><code>"),
+        }
+        let mut i = start + 1;
+        while i < lines.len() {
+            let (line_indent, trimmed) = split_indent(&lines[i]);
+            if line_indent < 4 && closes_fence(trimmed, fence) {
+                i += 1;
+                break;
+            }
+            // As far as the fence was indented.
+            let content = &lines[i][line_indent.min(indent)..];
+            out.push_str(&escape_html(content));
+            out.push('\n');
+            i += 1;
+        }
+        out.push_str("</code></pre>\n");
+        i
+    }
+
+    fn block_quote(&self, lines: &[String], start: usize, out: &mut String) -> usize {
+        let mut inner = Vec::new();
+        let mut i = start;
+        while i < lines.len() {
+            let (indent, trimmed) = split_indent(&lines[i]);
+            match trimmed.strip_prefix('>') {
+                Some(rest) if indent < 4 => {
+                    inner.push(rest.strip_prefix(' ').unwrap_or(rest).to_string())
+                }
+                // Lazy continuation of the paragraph in the quote.
+                _ if !trimmed.is_empty()
+                    && inner
+                        .last()
+                        .is_some_and(|last: &String| !last.trim().is_empty())
+                    && starts_block(trimmed, self.raw_html).is_none() =>
+                {
+                    inner.push(lines[i].clone())
+                }
+                _ => break,
+            }
+            i += 1;
+        }
+        out.push_str("<blockquote>\n");
+        self.blocks(&inner, false, out);
+        out.push_str("</blockquote>\n");
+        i
+    }
+
+    fn list(&self, lines: &[String], start: usize, first: Marker, out: &mut String) -> usize {
+        let mut items: Vec<Vec<String>> = Vec::new();
+        let mut loose = false;
+        let mut i = start;
+        while i < lines.len() {
+            let (indent, trimmed) = split_indent(&lines[i]);
+            let Some((marker, offset)) = list_marker(trimmed).filter(|_| indent < 4) else {
+                break;
+            };
+            if !marker.same_list(first) {
+                break;
+            }
+            let offset = indent + offset;
+            let mut item = vec![lines[i].get(offset..).unwrap_or("").to_string()];
+            i += 1;
+            while i < lines.len() {
+                let line = &lines[i];
+                let (line_indent, trimmed) = split_indent(line);
+                if trimmed.is_empty() {
+                    item.push(String::new());
+                } else if line_indent >= offset {
+                    item.push(line[offset..].to_string());
+                } else if item.last().is_some_and(|last| !last.trim().is_empty())
+                    && starts_block(trimmed, self.raw_html).is_none()
+                {
+                    item.push(trimmed.to_string());
+                } else {
+                    break;
+                }
+                i += 1;
+            }
+            // Blank lines ending the item belong between items.
+            let mut trailing = 0;
+            while item.last().is_some_and(|last| last.trim().is_empty()) {
+                item.pop();
+                trailing += 1;
+            }
+            i -= trailing;
+            let next_is_item = lines.get(i + trailing).is_some_and(|next| {
+                let (indent, trimmed) = split_indent(next);
+                indent < 4 && list_marker(trimmed).is_some_and(|(m, _)| m.same_list(first))
+            });
+            if item.iter().any(|line| line.trim().is_empty()) || (trailing > 0 && next_is_item) {
+                loose = true;
+            }
+            items.push(item);
+            if next_is_item {
+                i += trailing;
+            }
+        }
+
+        let (open, close) = match first {
+            Marker::Bullet(_) => ("<ul>".to_string(), "</ul>"),
+            Marker::Ordered(_, 1) => ("<ol>".to_string(), "</ol>"),
+            Marker::Ordered(_, start) => (format!("<ol start=\"{start}\">"), "</ol>"),
+        };
+        out.push_str(&open);
+        out.push('\n');
+        for item in items {
+            out.push_str("<li>");
+            self.blocks(&item, !loose, out);
+            out.push_str("</li>\n");
+        }
+        out.push_str(close);
+        out.push('\n');
+        i
+    }
+
+    fn paragraph(&self, lines: &[String], start: usize, tight: bool, out: &mut String) -> usize {
+        let mut text: Vec<&str> = vec![lines[start].trim_start()];
+        let mut i = start + 1;
+        while i < lines.len() {
+            let line = lines[i].as_str();
+            let (indent, trimmed) = split_indent(line);
+            if trimmed.is_empty() {
+                break;
+            }
+            if let Some(level) = setext_level(line) {
+                start_line(out);
+                out.push_str(&format!("<h{level}>"));
+                self.inline(text.join("\n").trim(), false, out);
+                out.push_str(&format!("</h{level}>\n"));
+                return i + 1;
+            }
+            if indent < 4 && starts_block(trimmed, self.raw_html).is_some() {
+                break;
+            }
+            text.push(trimmed);
+            i += 1;
+        }
+        let text = text.join("\n");
+        if tight {
+            // Runs on from the item's tag.
+            self.inline(text.trim_end(), false, out);
+        } else {
+            start_line(out);
+            out.push_str("<p>");
+            self.inline(text.trim_end(), false, out);
+            out.push_str("</p>\n");
+        }
+        i
+    }
+
+    /// Render the inline markup of `text`, or only its text if `plain`.
+    fn inline(&self, text: &str, plain: bool, out: &mut String) {
+        let chars: Vec<char> = text.chars().collect();
+        self.inline_chars(&chars, plain, out);
+    }
+
+    fn inline_chars(&self, chars: &[char], plain: bool, out: &mut String) {
+        let text = |out: &mut String, c: char| match plain {
+            true => out.push(c),
+            false => out.push_str(&escape_html(c.encode_utf8(&mut [0; 4]))),
+        };
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\\' if chars.get(i + 1).is_some_and(char::is_ascii_punctuation) => {
+                    text(out, chars[i + 1]);
+                    i += 2;
+                }
+                '\\' if chars.get(i + 1) == Some(&'\n') => {
+                    out.push_str(if plain { " " } else { "<br />\n" });
+                    i += 2;
+                }
+                '\n' => {
+                    let hard = out.ends_with("  ");
+                    let kept = out.trim_end_matches(' ').len();
+                    out.truncate(kept);
+                    out.push_str(match (plain, hard) {
+                        (true, _) => " ",
+                        (false, true) => "<br />\n",
+                        (false, false) => "\n",
+                    });
+                    i += 1;
+                }
+                '`' => {
+                    let run = run_length(chars, i, '`');
+                    match find_code_end(chars, i + run, run) {
+                        Some(end) => {
+                            let code: String = chars[i + run..end]
+                                .iter()
+                                .map(|&c| if c == '\n' { ' ' } else { c })
+                                .collect();
+                            let code = match code.starts_with(' ')
+                                && code.ends_with(' ')
+                                && code.trim() != ""
+                            {
+                                true => &code[1..code.len() - 1],
+                                false => code.as_str(),
+                            };
+                            if plain {
+                                out.push_str(code);
+                            } else {
+                                out.push_str(&format!("<code>{}</code>", escape_html(code)));
+                            }
+                            i = end + run;
+                        }
+                        None => {
+                            out.push_str(&"`".repeat(run));
+                            i += run;
+                        }
+                    }
+                }
+                '*' | '_' => match self.emphasis(chars, i, plain, out) {
+                    Some(next) => i = next,
+                    None => {
+                        out.push(c);
+                        i += 1;
+                    }
+                },
+                '!' if chars.get(i + 1) == Some(&'[') => match parse_link(chars, i + 1) {
+                    Some(link) => {
+                        let mut alt = String::new();
+                        self.inline_chars(&chars[link.label.0..link.label.1], true, &mut alt);
+                        if plain {
+                            out.push_str(&alt);
+                        } else {
+                            out.push_str(&format!(
+                                "<img src=\"{}\" alt=\"{}\"",
+                                escape_html(&self.safe_url(&link.destination)),
+                                escape_html(&alt)
+                            ));
+                            if let Some(title) = &link.title {
+                                out.push_str(&format!(" title=\"{}\"", escape_html(title)));
+                            }
+                            out.push_str(" />");
+                        }
+                        i = link.end;
+                    }
+                    None => {
+                        out.push('!');
+                        i += 1;
+                    }
+                },
+                '[' => match parse_link(chars, i) {
+                    Some(link) => {
+                        let label = &chars[link.label.0..link.label.1];
+                        if plain {
+                            self.inline_chars(label, true, out);
+                        } else {
+                            out.push_str(&format!(
+                                "<a href=\"{}\"",
+                                escape_html(&self.safe_url(&link.destination))
+                            ));
+                            if let Some(title) = &link.title {
+                                out.push_str(&format!(" title=\"{}\"", escape_html(title)));
+                            }
+                            out.push('>');
+                            self.inline_chars(label, false, out);
+                            out.push_str("</a>");
+                        }
+                        i = link.end;
+                    }
+                    None => {
+                        text(out, '[');
+                        i += 1;
+                    }
+                },
+                '<' => {
+                    let close = chars[i + 1..]
+                        .iter()
+                        .position(|&c| c == '>' || c == '<' || c == '\n')
+                        .map(|at| i + 1 + at)
+                        .filter(|&at| chars[at] == '>');
+                    let inside: Option<String> = close.map(|at| chars[i + 1..at].iter().collect());
+                    match (close, inside) {
+                        (Some(at), Some(inside)) if is_autolink(&inside) => {
+                            let href = match inside.contains(':') {
+                                true => inside.clone(),
+                                false => format!("mailto:{inside}"),
+                            };
+                            if plain {
+                                out.push_str(&inside);
+                            } else {
+                                out.push_str(&format!(
+                                    "<a href=\"{}\">{}</a>",
+                                    escape_html(&self.safe_url(&href)),
+                                    escape_html(&inside)
+                                ));
+                            }
+                            i = at + 1;
+                        }
+                        (Some(at), Some(inside))
+                            if self.raw_html && !plain && starts_tag(&format!("<{inside}")) =>
+                        {
+                            out.push('<');
+                            out.push_str(&inside);
+                            out.push('>');
+                            i = at + 1;
+                        }
+                        _ => {
+                            text(out, '<');
+                            i += 1;
+                        }
+                    }
+                }
+                '&' if !plain => {
+                    let entity: String = chars[i..]
+                        .iter()
+                        .take(33)
+                        .take_while(|&&c| c != ';')
+                        .collect();
+                    let name = &entity[1..];
+                    let valid = chars.get(i + entity.chars().count()) == Some(&';')
+                        && match name.strip_prefix('#') {
+                            Some(number) => number.len() <= 8 && is_numeric_reference(number),
+                            None => {
+                                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())
+                            }
+                        };
+                    if valid {
+                        out.push_str(&entity);
+                        out.push(';');
+                        i += entity.chars().count() + 1;
+                    } else {
+                        out.push_str("&amp;");
+                        i += 1;
+                    }
+                }
+                c => {
+                    text(out, c);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    /// Render the emphasis opened by the delimiter run at `start`, returning
+    /// where it ends, or none if it is not closed.
+    fn emphasis(
+        &self,
+        chars: &[char],
+        start: usize,
+        plain: bool,
+        out: &mut String,
+    ) -> Option<usize> {
+        let c = chars[start];
+        let run = run_length(chars, start, c);
+        let width = run.min(2);
+        let after = start + width;
+        let before = start.checked_sub(1).map(|at| chars[at]);
+        // An opener is followed by text, and an underscore not part of a word.
+        if chars
+            .get(start + run)
+            .is_none_or(|next| next.is_whitespace())
+            || (c == '_' && before.is_some_and(char::is_alphanumeric))
+        {
+            return None;
+        }
+        let mut at = after + 1;
+        while at < chars.len() {
+            if chars[at] == '`' {
+                let code = run_length(chars, at, '`');
+                at = find_code_end(chars, at + code, code).map_or(at + code, |end| end + code);
+                continue;
+            }
+            if chars[at] != c {
+                at += 1;
+                continue;
+            }
+            let closing = run_length(chars, at, c);
+            let follows = chars.get(at + closing);
+            let closes = closing >= width
+                && !chars[at - 1].is_whitespace()
+                && !(c == '_' && follows.is_some_and(|next| next.is_alphanumeric()));
+            if closes {
+                // Closing with the last of the run leaves its first to the inside.
+                let end = at + closing - width;
+                let (open, close) = match width {
+                    2 => ("<strong>", "</strong>"),
+                    _ => ("<em>", "</em>"),
+                };
+                if !plain {
+                    out.push_str(open);
+                }
+                self.inline_chars(&chars[after..end], plain, out);
+                if !plain {
+                    out.push_str(close);
+                }
+                return Some(end + width);
+            }
+            at += closing;
+        }
+        None
+    }
+
+    /// `url`, unless it runs script when followed, which raw HTML may do anyway.
+    fn safe_url(&self, url: &str) -> String {
+        let scheme = url.trim_start().to_ascii_lowercase();
+        let unsafe_scheme = ["javascript:", "vbscript:", "data:"]
+            .iter()
+            .any(|prefix| scheme.starts_with(prefix));
+        match unsafe_scheme && !self.raw_html {
+            true => "#".to_string(),
+            false => url.to_string(),
+        }
+    }
+}
+
+/// End the text of a tight item before a block, which starts on a line of
+/// its own.
+fn start_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// The number of `c` in a row from `start` in `chars`.
+fn run_length(chars: &[char], start: usize, c: char) -> usize {
+    chars[start..].iter().take_while(|&&d| d == c).count()
+}
+
+/// Where the run of exactly `run` backticks ending a code span opened before
+/// `from` starts.
+fn find_code_end(chars: &[char], from: usize, run: usize) -> Option<usize> {
+    let mut at = from;
+    while at < chars.len() {
+        if chars[at] == '`' {
+            let closing = run_length(chars, at, '`');
+            if closing == run {
+                return Some(at);
+            }
+            at += closing;
+        } else {
+            at += 1;
+        }
+    }
+    None
+}
+
+fn is_numeric_reference(number: &str) -> bool {
+    match number.strip_prefix(['x', 'X']) {
+        Some(hex) => !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+    }
+}
+
+/// Whether the text between `<` and `>` is a URI with a scheme or an email
+/// address.
+fn is_autolink(inside: &str) -> bool {
+    if inside.contains([' ', '<', '>']) || inside.is_empty() {
+        return false;
+    }
+    match inside.split_once(':') {
+        Some((scheme, _)) => {
+            (2..=32).contains(&scheme.len())
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'))
+        }
+        None => inside
+            .split_once('@')
+            .is_some_and(|(user, domain)| !user.is_empty() && domain.contains('.')),
+    }
+}
+
+/// An inline link or image: the span of its label, its destination and title,
+/// and where it ends.
+struct Link {
+    label: (usize, usize),
+    destination: String,
+    title: Option<String>,
+    end: usize,
+}
+
+/// The link whose label opens with the `[` at `start`.
+fn parse_link(chars: &[char], start: usize) -> Option<Link> {
+    let mut depth = 0;
+    let mut at = start;
+    let label_end = loop {
+        match chars.get(at)? {
+            '\\' => at += 1,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break at;
+                }
+            }
+            _ => {}
+        }
+        at += 1;
+    };
+    if chars.get(label_end + 1) != Some(&'(') {
+        return None;
+    }
+    let mut at = label_end + 2;
+    let skip_spaces = |at: &mut usize| {
+        while chars.get(*at).is_some_and(|c| c.is_whitespace()) {
+            *at += 1;
+        }
+    };
+    skip_spaces(&mut at);
+    let mut destination = String::new();
+    if chars.get(at) == Some(&'<') {
+        at += 1;
+        loop {
+            match chars.get(at)? {
+                '>' => break,
+                '\n' | '<' => return None,
+                '\\' if chars.get(at + 1).is_some_and(char::is_ascii_punctuation) => {
+                    destination.push(chars[at + 1]);
+                    at += 1;
+                }
+                &c => destination.push(c),
+            }
+            at += 1;
+        }
+        at += 1;
+    } else {
+        let mut parens = 0;
+        while let Some(&c) = chars.get(at) {
+            match c {
+                '(' => parens += 1,
+                ')' if parens == 0 => break,
+                ')' => parens -= 1,
+                c if c.is_whitespace() || c.is_control() => break,
+                '\\' if chars.get(at + 1).is_some_and(char::is_ascii_punctuation) => {
+                    at += 1;
+                    destination.push(chars[at]);
+                    at += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            destination.push(c);
+            at += 1;
+        }
+    }
+    skip_spaces(&mut at);
+    let title = match chars.get(at) {
+        Some(&quote @ ('"' | '\'')) => {
+            let close = chars[at + 1..].iter().position(|&c| c == quote)? + at + 1;
+            let title = chars[at + 1..close].iter().collect();
+            at = close + 1;
+            skip_spaces(&mut at);
+            Some(title)
+        }
+        _ => None,
+    };
+    (chars.get(at) == Some(&')')).then_some(Link {
+        label: (start + 1, label_end),
+        destination,
+        title,
+        end: at + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_renders_each_construct() -> Result<(), Box<dyn std::error::Error>> {
+        let source = std::fs::read_to_string("tests/fixtures/markdown.md")?;
+        let expected = std::fs::read_to_string("tests/fixtures/markdown.html")?;
+        assert_eq!(to_html(&source, false), expected);
+        assert_eq!(title(&source).as_deref(), Some("A fixture & more"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_is_escaped() {
+        let source = "<script>alert(1)</script>\n\nHi <b onclick=\"x\">there</b> \
+                      [link](javascript:alert(1)) `<i>`\n";
+        assert_eq!(
+            to_html(source, false),
+            "<p>&lt;script&gt;alert(1)&lt;/script&gt;</p>\n\
+             <p>Hi &lt;b onclick=&quot;x&quot;&gt;there&lt;/b&gt; <a href=\"#\">link</a> \
+             <code>&lt;i&gt;</code></p>\n"
+        );
+        assert_eq!(
+            to_html(source, true),
+            "<script>alert(1)</script>\n\
+             <p>Hi <b onclick=\"x\">there</b> <a href=\"javascript:alert(1)\">link</a> \
+             <code>&lt;i&gt;</code></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_page_in_a_shell() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = crate::test_util::TempDir::new();
+        let shell = dir.write(
+            "shell.html",
+            "<title>{{title}}</title><main>{{{content}}}</main>",
+        );
+        let markdown = Markdown::new().shell(&shell);
+        assert_eq!(
+            markdown.page("Setext <title>\n===\n\ntext\n", "notes"),
+            "<title>Setext &lt;title&gt;</title><main><h1>Setext &lt;title&gt;</h1>\n<p>text</p>\n</main>"
+        );
+        let untitled = Markdown::new().shell(dir.path().join("missing.html"));
+        let page = untitled.page("just text", "notes");
+        assert!(page.contains("<title>notes</title>"), "{page}");
+        assert!(page.contains("<p>just text</p>"), "the built-in shell");
+        Ok(())
+    }
+}
@@ -0,0 +1,721 @@
+//! Counters of what the server did, rendered in the Prometheus text format.
+
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{http::Method, PoolStats};
+
+/// The methods requests are counted by; others are counted as `OTHER`.
+const METHODS: [&str; 10] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "TRACE", "CONNECT", "PATCH", "OTHER",
+];
+
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+/// The upper bounds of the request duration buckets, in seconds.
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The counters updated while serving, each a single atomic.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: [[AtomicU64; STATUS_CLASSES.len()]; METHODS.len()],
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    connections: AtomicU64,
+    in_flight: AtomicU64,
+    durations: Histogram,
+    revalidations: AtomicU64,
+    revalidations_fresh: AtomicU64,
+    /// The requests no route answered.
+    unmatched: RouteMetrics,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    pub fn connection_accepted(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a request as in flight until the returned guard is dropped.
+    pub fn request_started(&self) -> InFlight<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { metrics: self }
+    }
+
+    /// Count a request answered with `status` after `duration`, sending
+    /// `bytes`; `method` is none for requests which could not be parsed.
+    pub fn request_finished(
+        &self,
+        method: Option<&Method>,
+        status: u16,
+        bytes: u64,
+        duration: Duration,
+    ) {
+        let method = method
+            .and_then(|method| METHODS.iter().position(|name| *name == method.as_str()))
+            .unwrap_or(METHODS.len() - 1);
+        let class = usize::from(status / 100).clamp(1, 5) - 1;
+        self.requests[method][class].fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+        self.durations.record(duration);
+    }
+
+    /// Count `bytes` read for a request, its head and body as far as they
+    /// were read.
+    pub fn request_read(&self, bytes: u64) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// The counters of the requests no route answered, which are all
+    /// counted as one route so that scanned paths add no series.
+    pub fn unmatched(&self) -> &RouteMetrics {
+        &self.unmatched
+    }
+
+    /// Count a conditional request, which was `fresh` if the client's copy
+    /// was still good and it got `304 Not Modified`.
+    pub fn revalidated(&self, fresh: bool) {
+        self.revalidations.fetch_add(1, Ordering::Relaxed);
+        if fresh {
+            self.revalidations_fresh.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The current values, with the pool's from `pool` if there is one; the
+    /// values the server keeps elsewhere are left for it to fill in.
+    pub fn snapshot(&self, pool: Option<&PoolStats>) -> Snapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut requests = Vec::new();
+        for (method, classes) in METHODS.iter().zip(&self.requests) {
+            for (class, count) in STATUS_CLASSES.iter().zip(classes) {
+                let count = load(count);
+                if count > 0 {
+                    requests.push((method.to_string(), class.to_string(), count));
+                }
+            }
+        }
+        let (duration_buckets, duration_sum, duration_count) = self.durations.read();
+        Snapshot {
+            requests,
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            connections_accepted: load(&self.connections),
+            jobs_executed: pool.map_or(0, PoolStats::executed),
+            jobs_panicked: pool.map_or(0, PoolStats::panicked),
+            in_flight: load(&self.in_flight),
+            queue_depth: pool.map_or(0, |pool| pool.queued() as u64),
+            busy_workers: pool.map_or(0, |pool| pool.busy() as u64),
+            workers: pool.map_or(0, |pool| pool.workers() as u64),
+            connections_active: 0,
+            revalidations: load(&self.revalidations),
+            revalidations_fresh: load(&self.revalidations_fresh),
+            response_cache_hits: 0,
+            response_cache_misses: 0,
+            started: None,
+            duration_buckets,
+            duration_sum,
+            duration_count,
+            routes: vec![self.unmatched.snapshot(None, UNMATCHED)],
+            pools: Vec::new(),
+        }
+    }
+}
+
+/// The route the requests no route answered are counted under.
+pub const UNMATCHED: &str = "<unmatched>";
+
+/// How many requests took how long.
+#[derive(Debug, Default)]
+struct Histogram {
+    /// The requests which took at most each of [`DURATION_BUCKETS`], not
+    /// cumulative, and those which took longer.
+    buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    micros: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// The cumulative buckets, the seconds taken altogether and the count.
+    fn read(&self) -> (Vec<(f64, u64)>, f64, u64) {
+        let mut cumulative = 0;
+        let buckets = DURATION_BUCKETS
+            .iter()
+            .zip(&self.buckets)
+            .map(|(bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (*bound, cumulative)
+            })
+            .collect();
+        let count = self
+            .buckets
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum();
+        let sum = self.micros.load(Ordering::Relaxed) as f64 / 1e6;
+        (buckets, sum, count)
+    }
+}
+
+/// The counters of the requests one route answered.
+#[derive(Debug, Default)]
+pub struct RouteMetrics {
+    requests: [AtomicU64; STATUS_CLASSES.len()],
+    durations: Histogram,
+}
+
+impl RouteMetrics {
+    pub fn new() -> RouteMetrics {
+        RouteMetrics::default()
+    }
+
+    /// Count a request answered with `status` after `duration`.
+    pub fn record(&self, status: u16, duration: Duration) {
+        let class = usize::from(status / 100).clamp(1, 5) - 1;
+        self.requests[class].fetch_add(1, Ordering::Relaxed);
+        self.durations.record(duration);
+    }
+
+    /// The current values, labelled as `route` of the virtual `host`, if any.
+    pub fn snapshot(&self, host: Option<&str>, route: &str) -> RouteSnapshot {
+        let requests = STATUS_CLASSES
+            .iter()
+            .zip(&self.requests)
+            .map(|(class, count)| (class.to_string(), count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        let (duration_buckets, duration_sum, duration_count) = self.durations.read();
+        RouteSnapshot {
+            host: host.map(str::to_string),
+            route: route.to_string(),
+            requests,
+            duration_buckets,
+            duration_sum,
+            duration_count,
+        }
+    }
+}
+
+/// The values of one route's metrics.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteSnapshot {
+    /// The virtual host whose route it is, none for the server's own.
+    pub host: Option<String>,
+    /// The method and path of the route, like `GET /users`.
+    pub route: String,
+    /// The requests answered by status class.
+    pub requests: Vec<(String, u64)>,
+    pub duration_buckets: Vec<(f64, u64)>,
+    pub duration_sum: f64,
+    pub duration_count: u64,
+}
+
+/// The values of a pool routes were put on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PoolSnapshot {
+    pub name: String,
+    pub queue_depth: u64,
+    pub busy_workers: u64,
+    pub workers: u64,
+}
+
+impl PoolSnapshot {
+    /// The values of the pool called `name` from its `stats`.
+    pub fn new(name: &str, stats: &PoolStats) -> PoolSnapshot {
+        PoolSnapshot {
+            name: name.to_string(),
+            queue_depth: stats.queued() as u64,
+            busy_workers: stats.busy() as u64,
+            workers: stats.workers() as u64,
+        }
+    }
+}
+
+/// Counts a request as in flight while it lives.
+#[derive(Debug)]
+pub struct InFlight<'a> {
+    metrics: &'a Metrics,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The values of the metrics at one point in time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    /// The requests answered by method and status class, such as `2xx`.
+    pub requests: Vec<(String, String, u64)>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub connections_accepted: u64,
+    pub jobs_executed: u64,
+    pub jobs_panicked: u64,
+    pub in_flight: u64,
+    pub queue_depth: u64,
+    pub busy_workers: u64,
+    pub workers: u64,
+    pub connections_active: u64,
+    /// The conditional requests, and those answered `304 Not Modified`.
+    pub revalidations: u64,
+    pub revalidations_fresh: u64,
+    /// The requests of cached routes answered from the response cache, and
+    /// those which ran their handler.
+    pub response_cache_hits: u64,
+    pub response_cache_misses: u64,
+    /// When the server started serving, if it did.
+    pub started: Option<SystemTime>,
+    /// The upper bound of each bucket in seconds, with the requests which
+    /// took at most that long.
+    pub duration_buckets: Vec<(f64, u64)>,
+    /// The seconds all requests took together.
+    pub duration_sum: f64,
+    pub duration_count: u64,
+    /// Each route, followed by [`UNMATCHED`].
+    pub routes: Vec<RouteSnapshot>,
+    /// The pools routes were put on, besides the default one above.
+    pub pools: Vec<PoolSnapshot>,
+}
+
+/// The content type of [`render`]'s output.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// `snapshot` in the Prometheus text exposition format.
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut out = String::new();
+    let name = "hello_requests_total";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Requests answered, by method and status class.\n# TYPE {name} counter"
+    );
+    for (method, status, count) in &snapshot.requests {
+        let _ = writeln!(
+            out,
+            "{name}{{method=\"{}\",status=\"{}\"}} {count}",
+            escape_label(method),
+            escape_label(status)
+        );
+    }
+
+    let simple = [
+        (
+            "hello_response_bytes_total",
+            "counter",
+            "Bytes of responses sent, heads included.",
+            snapshot.bytes_sent,
+        ),
+        (
+            "hello_request_bytes_total",
+            "counter",
+            "Bytes of requests read, heads included.",
+            snapshot.bytes_received,
+        ),
+        (
+            "hello_connections_accepted_total",
+            "counter",
+            "Connections accepted.",
+            snapshot.connections_accepted,
+        ),
+        (
+            "hello_pool_jobs_executed_total",
+            "counter",
+            "Jobs the worker pool ran.",
+            snapshot.jobs_executed,
+        ),
+        (
+            "hello_pool_jobs_panicked_total",
+            "counter",
+            "Jobs of the worker pool which panicked.",
+            snapshot.jobs_panicked,
+        ),
+        (
+            "hello_requests_in_flight",
+            "gauge",
+            "Requests being answered.",
+            snapshot.in_flight,
+        ),
+        (
+            "hello_pool_queue_depth",
+            "gauge",
+            "Jobs waiting for a worker.",
+            snapshot.queue_depth,
+        ),
+        (
+            "hello_pool_busy_workers",
+            "gauge",
+            "Workers running a job.",
+            snapshot.busy_workers,
+        ),
+        (
+            "hello_pool_workers",
+            "gauge",
+            "Workers in the pool.",
+            snapshot.workers,
+        ),
+        (
+            "hello_connections_active",
+            "gauge",
+            "Connections open.",
+            snapshot.connections_active,
+        ),
+        (
+            "hello_cache_revalidations_total",
+            "counter",
+            "Conditional requests.",
+            snapshot.revalidations,
+        ),
+        (
+            "hello_cache_hits_total",
+            "counter",
+            "Conditional requests answered 304 Not Modified.",
+            snapshot.revalidations_fresh,
+        ),
+        (
+            "hello_response_cache_hits_total",
+            "counter",
+            "Requests of cached routes answered from the response cache.",
+            snapshot.response_cache_hits,
+        ),
+        (
+            "hello_response_cache_misses_total",
+            "counter",
+            "Requests of cached routes which ran their handler.",
+            snapshot.response_cache_misses,
+        ),
+    ];
+    for (name, kind, help, value) in simple {
+        let _ = writeln!(
+            out,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+        );
+    }
+
+    let by_pool = [
+        (
+            "hello_route_pool_queue_depth",
+            "Jobs waiting for a worker, by pool routes were put on.",
+            (|pool| pool.queue_depth) as fn(&PoolSnapshot) -> u64,
+        ),
+        (
+            "hello_route_pool_busy_workers",
+            "Workers running a job, by pool routes were put on.",
+            |pool| pool.busy_workers,
+        ),
+        (
+            "hello_route_pool_workers",
+            "Workers in the pool, by pool routes were put on.",
+            |pool| pool.workers,
+        ),
+    ];
+    for (name, help, value) in by_pool {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+        for pool in &snapshot.pools {
+            let _ = writeln!(
+                out,
+                "{name}{{pool=\"{}\"}} {}",
+                escape_label(&pool.name),
+                value(pool)
+            );
+        }
+    }
+
+    if let Some(started) = snapshot.started {
+        let name = "hello_start_time_seconds";
+        let seconds = started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let _ = writeln!(
+            out,
+            "# HELP {name} When the server started, in seconds since the epoch.\n\
+             # TYPE {name} gauge\n{name} {seconds}"
+        );
+    }
+
+    let name = "hello_request_duration_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {name} How long answering requests took.\n# TYPE {name} histogram"
+    );
+    write_histogram(
+        &mut out,
+        name,
+        "",
+        &snapshot.duration_buckets,
+        snapshot.duration_sum,
+        snapshot.duration_count,
+    );
+
+    let name = "hello_route_requests_total";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Requests answered, by route and status class.\n# TYPE {name} counter"
+    );
+    for route in &snapshot.routes {
+        for (status, count) in &route.requests {
+            let _ = writeln!(
+                out,
+                "{name}{{{},status=\"{}\"}} {count}",
+                route_labels(route),
+                escape_label(status)
+            );
+        }
+    }
+    let name = "hello_route_request_duration_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {name} How long answering requests took, by route.\n# TYPE {name} histogram"
+    );
+    for route in &snapshot.routes {
+        write_histogram(
+            &mut out,
+            name,
+            &route_labels(route),
+            &route.duration_buckets,
+            route.duration_sum,
+            route.duration_count,
+        );
+    }
+    out
+}
+
+/// The samples of histogram `name` labelled with `labels`, which are joined
+/// by commas and may be empty.
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    buckets: &[(f64, u64)],
+    sum: f64,
+    count: u64,
+) {
+    let prefix = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{labels},")
+    };
+    for (bound, count) in buckets {
+        let _ = writeln!(out, "{name}_bucket{{{prefix}le=\"{bound}\"}} {count}");
+    }
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    };
+    let _ = writeln!(
+        out,
+        "{name}_bucket{{{prefix}le=\"+Inf\"}} {count}\n{name}_sum{labels} {sum}\n\
+         {name}_count{labels} {count}"
+    );
+}
+
+/// The labels naming `route`, with its host only for virtual hosts.
+fn route_labels(route: &RouteSnapshot) -> String {
+    let route_label = format!("route=\"{}\"", escape_label(&route.route));
+    match &route.host {
+        Some(host) => format!("host=\"{}\",{route_label}", escape_label(host)),
+        None => route_label,
+    }
+}
+
+/// `value` escaped for a label: backslashes, quotes and line breaks.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_exact_format() {
+        let snapshot = Snapshot {
+            requests: vec![
+                ("GET".to_string(), "2xx".to_string(), 3),
+                ("we\"ird\\\n".to_string(), "4xx".to_string(), 1),
+            ],
+            bytes_sent: 1024,
+            bytes_received: 512,
+            connections_accepted: 2,
+            jobs_executed: 2,
+            jobs_panicked: 0,
+            in_flight: 1,
+            queue_depth: 0,
+            busy_workers: 1,
+            workers: 4,
+            connections_active: 2,
+            revalidations: 3,
+            revalidations_fresh: 1,
+            response_cache_hits: 5,
+            response_cache_misses: 2,
+            started: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+            duration_buckets: vec![(0.005, 1), (0.5, 4)],
+            duration_sum: 0.25,
+            duration_count: 4,
+            routes: vec![
+                RouteSnapshot {
+                    host: None,
+                    route: "GET /a".to_string(),
+                    requests: vec![("2xx".to_string(), 2)],
+                    duration_buckets: vec![(0.005, 2)],
+                    duration_sum: 0.002,
+                    duration_count: 2,
+                },
+                RouteSnapshot {
+                    host: Some("b.test".to_string()),
+                    route: UNMATCHED.to_string(),
+                    requests: vec![("4xx".to_string(), 1)],
+                    duration_buckets: vec![(0.005, 0)],
+                    duration_sum: 0.5,
+                    duration_count: 1,
+                },
+            ],
+            pools: vec![PoolSnapshot {
+                name: "slow".to_string(),
+                queue_depth: 3,
+                busy_workers: 2,
+                workers: 2,
+            }],
+        };
+        assert_eq!(
+            render(&snapshot),
+            "# HELP hello_requests_total Requests answered, by method and status class.\n\
+             # TYPE hello_requests_total counter\n\
+             hello_requests_total{method=\"GET\",status=\"2xx\"} 3\n\
+             hello_requests_total{method=\"we\\\"ird\\\\\\n\",status=\"4xx\"} 1\n\
+             # HELP hello_response_bytes_total Bytes of responses sent, heads included.\n\
+             # TYPE hello_response_bytes_total counter\n\
+             hello_response_bytes_total 1024\n\
+             # HELP hello_request_bytes_total Bytes of requests read, heads included.\n\
+             # TYPE hello_request_bytes_total counter\n\
+             hello_request_bytes_total 512\n\
+             # HELP hello_connections_accepted_total Connections accepted.\n\
+             # TYPE hello_connections_accepted_total counter\n\
+             hello_connections_accepted_total 2\n\
+             # HELP hello_pool_jobs_executed_total Jobs the worker pool ran.\n\
+             # TYPE hello_pool_jobs_executed_total counter\n\
+             hello_pool_jobs_executed_total 2\n\
+             # HELP hello_pool_jobs_panicked_total Jobs of the worker pool which panicked.\n\
+             # TYPE hello_pool_jobs_panicked_total counter\n\
+             hello_pool_jobs_panicked_total 0\n\
+             # HELP hello_requests_in_flight Requests being answered.\n\
+             # TYPE hello_requests_in_flight gauge\n\
+             hello_requests_in_flight 1\n\
+             # HELP hello_pool_queue_depth Jobs waiting for a worker.\n\
+             # TYPE hello_pool_queue_depth gauge\n\
+             hello_pool_queue_depth 0\n\
+             # HELP hello_pool_busy_workers Workers running a job.\n\
+             # TYPE hello_pool_busy_workers gauge\n\
+             hello_pool_busy_workers 1\n\
+             # HELP hello_pool_workers Workers in the pool.\n\
+             # TYPE hello_pool_workers gauge\n\
+             hello_pool_workers 4\n\
+             # HELP hello_connections_active Connections open.\n\
+             # TYPE hello_connections_active gauge\n\
+             hello_connections_active 2\n\
+             # HELP hello_cache_revalidations_total Conditional requests.\n\
+             # TYPE hello_cache_revalidations_total counter\n\
+             hello_cache_revalidations_total 3\n\
+             # HELP hello_cache_hits_total Conditional requests answered 304 Not Modified.\n\
+             # TYPE hello_cache_hits_total counter\n\
+             hello_cache_hits_total 1\n\
+             # HELP hello_response_cache_hits_total Requests of cached routes answered from the response cache.\n\
+             # TYPE hello_response_cache_hits_total counter\n\
+             hello_response_cache_hits_total 5\n\
+             # HELP hello_response_cache_misses_total Requests of cached routes which ran their handler.\n\
+             # TYPE hello_response_cache_misses_total counter\n\
+             hello_response_cache_misses_total 2\n\
+             # HELP hello_route_pool_queue_depth Jobs waiting for a worker, by pool routes were put on.\n\
+             # TYPE hello_route_pool_queue_depth gauge\n\
+             hello_route_pool_queue_depth{pool=\"slow\"} 3\n\
+             # HELP hello_route_pool_busy_workers Workers running a job, by pool routes were put on.\n\
+             # TYPE hello_route_pool_busy_workers gauge\n\
+             hello_route_pool_busy_workers{pool=\"slow\"} 2\n\
+             # HELP hello_route_pool_workers Workers in the pool, by pool routes were put on.\n\
+             # TYPE hello_route_pool_workers gauge\n\
+             hello_route_pool_workers{pool=\"slow\"} 2\n\
+             # HELP hello_start_time_seconds When the server started, in seconds since the epoch.\n\
+             # TYPE hello_start_time_seconds gauge\n\
+             hello_start_time_seconds 784111777\n\
+             # HELP hello_request_duration_seconds How long answering requests took.\n\
+             # TYPE hello_request_duration_seconds histogram\n\
+             hello_request_duration_seconds_bucket{le=\"0.005\"} 1\n\
+             hello_request_duration_seconds_bucket{le=\"0.5\"} 4\n\
+             hello_request_duration_seconds_bucket{le=\"+Inf\"} 4\n\
+             hello_request_duration_seconds_sum 0.25\n\
+             hello_request_duration_seconds_count 4\n\
+             # HELP hello_route_requests_total Requests answered, by route and status class.\n\
+             # TYPE hello_route_requests_total counter\n\
+             hello_route_requests_total{route=\"GET /a\",status=\"2xx\"} 2\n\
+             hello_route_requests_total{host=\"b.test\",route=\"<unmatched>\",status=\"4xx\"} 1\n\
+             # HELP hello_route_request_duration_seconds How long answering requests took, by route.\n\
+             # TYPE hello_route_request_duration_seconds histogram\n\
+             hello_route_request_duration_seconds_bucket{route=\"GET /a\",le=\"0.005\"} 2\n\
+             hello_route_request_duration_seconds_bucket{route=\"GET /a\",le=\"+Inf\"} 2\n\
+             hello_route_request_duration_seconds_sum{route=\"GET /a\"} 0.002\n\
+             hello_route_request_duration_seconds_count{route=\"GET /a\"} 2\n\
+             hello_route_request_duration_seconds_bucket{host=\"b.test\",route=\"<unmatched>\",le=\"0.005\"} 0\n\
+             hello_route_request_duration_seconds_bucket{host=\"b.test\",route=\"<unmatched>\",le=\"+Inf\"} 1\n\
+             hello_route_request_duration_seconds_sum{host=\"b.test\",route=\"<unmatched>\"} 0.5\n\
+             hello_route_request_duration_seconds_count{host=\"b.test\",route=\"<unmatched>\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_counters_fill_the_snapshot() {
+        let metrics = Metrics::new();
+        metrics.connection_accepted();
+        let in_flight = metrics.request_started();
+        metrics.request_finished(Some(&Method::Get), 200, 100, Duration::from_millis(1));
+        metrics.request_finished(Some(&Method::Get), 404, 50, Duration::from_millis(30));
+        metrics.request_finished(None, 400, 10, Duration::from_secs(20));
+        metrics.request_read(30);
+        metrics.revalidated(true);
+        metrics.revalidated(false);
+        let snapshot = metrics.snapshot(None);
+        assert_eq!(
+            snapshot.requests,
+            [
+                ("GET".to_string(), "2xx".to_string(), 1),
+                ("GET".to_string(), "4xx".to_string(), 1),
+                ("OTHER".to_string(), "4xx".to_string(), 1),
+            ]
+        );
+        assert_eq!(snapshot.bytes_sent, 160);
+        assert_eq!(snapshot.bytes_received, 30);
+        assert_eq!(snapshot.connections_accepted, 1);
+        assert_eq!(snapshot.in_flight, 1);
+        assert_eq!(
+            (snapshot.revalidations, snapshot.revalidations_fresh),
+            (2, 1)
+        );
+        assert_eq!(snapshot.duration_buckets[0], (0.005, 1));
+        assert_eq!(snapshot.duration_buckets[3], (0.05, 2));
+        assert_eq!(snapshot.duration_buckets.last(), Some(&(10.0, 2)));
+        assert_eq!(snapshot.duration_count, 3);
+        assert!((snapshot.duration_sum - 20.031).abs() < 1e-9);
+        drop(in_flight);
+        assert_eq!(metrics.snapshot(None).in_flight, 0);
+    }
+}
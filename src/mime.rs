@@ -0,0 +1,132 @@
+//! Content types guessed from file extensions.
+
+use std::path::Path;
+
+/// Which content types are labelled with a `charset` parameter, and with which charset.
+#[derive(Debug, Clone)]
+pub struct CharsetConfig {
+    charset: String,
+    types: Vec<String>,
+}
+
+impl Default for CharsetConfig {
+    fn default() -> CharsetConfig {
+        CharsetConfig::new()
+    }
+}
+
+impl CharsetConfig {
+    /// Label text, JSON, JavaScript and SVG as UTF-8.
+    pub fn new() -> CharsetConfig {
+        CharsetConfig {
+            charset: "utf-8".to_string(),
+            types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+
+    /// Label with `charset` instead of UTF-8, for legacy content.
+    pub fn charset(mut self, charset: &str) -> CharsetConfig {
+        self.charset = charset.to_string();
+        self
+    }
+
+    /// Label only these content types, where `text/*` stands for every subtype.
+    pub fn types(mut self, types: &[&str]) -> CharsetConfig {
+        self.types = types.iter().map(|t| t.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// `content_type` with the charset parameter appended, unless it already
+    /// has one or is not a listed type.
+    pub fn apply(&self, content_type: &str) -> String {
+        let mut parts = content_type.split(';');
+        let essence = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let has_charset = parts.any(|param| {
+            param
+                .split_once('=')
+                .is_some_and(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        });
+        let listed = self.types.iter().any(|t| match t.strip_suffix("/*") {
+            Some(top) => essence.split_once('/').is_some_and(|(t, _)| t == top),
+            None => *t == essence,
+        });
+        if has_charset || !listed {
+            return content_type.to_string();
+        }
+        format!("{content_type}; charset={}", self.charset)
+    }
+}
+
+/// The content type for the file at `path`, by its extension.
+pub fn from_path(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "mp3" => "audio/mpeg",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(from_path(Path::new("hello.html")), "text/html");
+        assert_eq!(from_path(Path::new("static/APP.JS")), "text/javascript");
+        assert_eq!(from_path(Path::new("Makefile")), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_charset() {
+        let charsets = CharsetConfig::new();
+        assert_eq!(charsets.apply("text/html"), "text/html; charset=utf-8");
+        assert_eq!(
+            charsets.apply("application/json"),
+            "application/json; charset=utf-8"
+        );
+        assert_eq!(charsets.apply("image/png"), "image/png");
+        assert_eq!(
+            charsets.apply("text/plain; Charset=iso-8859-1"),
+            "text/plain; Charset=iso-8859-1"
+        );
+
+        let legacy = CharsetConfig::new()
+            .charset("windows-1252")
+            .types(&["text/html"]);
+        assert_eq!(legacy.apply("text/html"), "text/html; charset=windows-1252");
+        assert_eq!(legacy.apply("text/css"), "text/css");
+    }
+}
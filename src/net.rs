@@ -0,0 +1,340 @@
+//! The listeners and streams the server core is generic over, implemented by
+//! real sockets and by in-memory test streams.
+
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    time::Duration,
+};
+
+use crate::socket::SocketOptions;
+
+/// Streams whose blocking reads and writes can be given a deadline.
+///
+/// In-memory streams never block, so their implementations do nothing.
+pub trait Timeouts {
+    /// Fail reads which wait longer than `timeout`, or never when `None`.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    /// Fail writes which wait longer than `timeout`, or never when `None`.
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Timeouts for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Timeouts for std::os::unix::net::UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        std::os::unix::net::UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+/// A stream which a client's requests arrive on and their responses leave by.
+pub trait Connection: Read + Write + Timeouts + Send + 'static {
+    /// Apply `options` to the stream once accepted; only TCP has any to apply.
+    fn configure(&self, _options: &SocketOptions) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Stop sending, while still reading what the peer sends.
+    fn shutdown_write(&mut self) -> io::Result<()>;
+}
+
+/// The addresses at either end of a connection, where it has them: those
+/// over Unix sockets and in memory have none.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl ConnectionInfo {
+    /// A connection from `peer_addr` which came in on `local_addr`.
+    pub fn new(peer_addr: Option<SocketAddr>, local_addr: Option<SocketAddr>) -> ConnectionInfo {
+        ConnectionInfo {
+            peer_addr,
+            local_addr,
+        }
+    }
+
+    /// The address of the other end, the client or a proxy in front.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The address of the server's end.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+/// A source of connections, each with what is known about its ends.
+pub trait Listener: Send + Sync {
+    type Conn: Connection;
+
+    /// Wait up to `timeout` for the next connection, none if nobody connected
+    /// in time, so that the caller can check whether to stop in between.
+    fn accept(&self, timeout: Duration) -> io::Result<Option<(Self::Conn, ConnectionInfo)>>;
+
+    /// Stop taking connections while still held, so that those for a port
+    /// shared with `SO_REUSEPORT` go to the other sockets bound to it.
+    /// Unsupported by default.
+    fn stop_listening(&self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Connection for TcpStream {
+    fn configure(&self, options: &SocketOptions) -> io::Result<()> {
+        options.configure(self)
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+/// Expects a non-blocking listener, like the server's; a blocking one waits
+/// for a connection regardless of the timeout.
+impl Listener for TcpListener {
+    type Conn = TcpStream;
+
+    fn accept(&self, timeout: Duration) -> io::Result<Option<(TcpStream, ConnectionInfo)>> {
+        let accepted = accept_within(self, timeout, || TcpListener::accept(self))?;
+        accepted
+            .map(|(stream, peer)| {
+                // Some platforms pass the listener's non-blocking mode on.
+                stream.set_nonblocking(false)?;
+                let local = stream.local_addr().ok();
+                Ok((stream, ConnectionInfo::new(Some(peer), local)))
+            })
+            .transpose()
+    }
+
+    /// Shuts the socket down, which on Linux takes it out of the port's
+    /// group and resets the connections still waiting to be accepted.
+    fn stop_listening(&self) -> io::Result<()> {
+        socket2::SockRef::from(self).shutdown(Shutdown::Read)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Write)
+    }
+}
+
+/// Unix peers have no IP address to check or rate limit. Expects a
+/// non-blocking listener like the TCP one.
+#[cfg(unix)]
+impl Listener for std::os::unix::net::UnixListener {
+    type Conn = std::os::unix::net::UnixStream;
+
+    fn accept(&self, timeout: Duration) -> io::Result<Option<(Self::Conn, ConnectionInfo)>> {
+        let accepted = accept_within(self, timeout, || {
+            std::os::unix::net::UnixListener::accept(self)
+        })?;
+        accepted
+            .map(|(stream, _)| {
+                stream.set_nonblocking(false)?;
+                Ok((stream, ConnectionInfo::default()))
+            })
+            .transpose()
+    }
+}
+
+/// Take a connection from a non-blocking listener with `accept`, waiting up
+/// to `timeout` for one to arrive when none is waiting.
+fn accept_within<L, T>(
+    listener: &L,
+    timeout: Duration,
+    accept: impl Fn() -> io::Result<T>,
+) -> io::Result<Option<T>>
+where
+    L: Readable,
+{
+    match accept() {
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+        accepted => return accepted.map(Some),
+    }
+    if !listener.wait_readable(timeout)? {
+        return Ok(None);
+    }
+    match accept() {
+        // Another process took the connection first.
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        accepted => accepted.map(Some),
+    }
+}
+
+/// Listeners which can wait for a connection without taking it.
+trait Readable {
+    /// Wait up to `timeout` for a connection, returning whether one may be waiting.
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool>;
+}
+
+#[cfg(unix)]
+impl<L: std::os::fd::AsRawFd> Readable for L {
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+        // SAFETY: `fd` is a single valid pollfd for the duration of the call.
+        match unsafe { libc::poll(&mut fd, 1, millis) } {
+            -1 => match io::Error::last_os_error() {
+                err if err.kind() == io::ErrorKind::Interrupted => Ok(false),
+                err => Err(err),
+            },
+            ready => Ok(ready > 0),
+        }
+    }
+}
+
+/// Without poll, the listener is tried again every few milliseconds.
+#[cfg(not(unix))]
+impl Readable for TcpListener {
+    fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        std::thread::sleep(timeout.min(Duration::from_millis(10)));
+        Ok(true)
+    }
+}
+
+impl<T> Timeouts for Cursor<T> {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: Timeouts + ?Sized> Timeouts for &mut T {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        (**self).set_write_timeout(timeout)
+    }
+}
+
+/// A stream which counts the bytes read from it and those its inner writer
+/// accepted.
+///
+/// Wrapped around the socket, beneath any buffering, it counts what crossed
+/// the connection once, however the layers above read and write.
+#[derive(Debug)]
+pub struct Counted<T> {
+    inner: T,
+    read: u64,
+    written: u64,
+}
+
+impl<T> Counted<T> {
+    pub fn new(inner: T) -> Counted<T> {
+        Counted {
+            inner,
+            read: 0,
+            written: 0,
+        }
+    }
+
+    /// The number of bytes read so far, even if a later read failed.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// The number of bytes written so far, even if a later write failed.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+impl<T: Timeouts> Timeouts for Counted<T> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Whether `err` means that the peer closed or reset the connection.
+pub fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Whether `err` is what a read or write returns once its timeout expired.
+pub fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counted_keeps_partial_count() {
+        let mut buf = [0; 4];
+        let mut writer = Counted::new(&mut buf[..]);
+        assert!(writer.write_all(b"hello").is_err());
+        assert_eq!(writer.written(), 4);
+
+        let mut reader = Counted::new(&b"hello"[..]);
+        let mut head = [0; 2];
+        reader.read_exact(&mut head).unwrap();
+        assert_eq!(reader.bytes_read(), 2);
+        assert!(reader.read_exact(&mut [0; 4]).is_err());
+        assert_eq!(reader.bytes_read(), 5);
+    }
+}
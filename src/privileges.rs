@@ -0,0 +1,278 @@
+//! Giving up root once the listeners are bound, by switching to another user
+//! and group.
+
+use std::{ffi::CString, io};
+
+/// A user's ids, as found in the user database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct User {
+    pub uid: u32,
+    /// The user's primary group.
+    pub gid: u32,
+}
+
+/// The system calls switching users needs, so that tests can stand in for them.
+pub trait System {
+    /// The user named `name`, none if there is no such user.
+    fn user(&self, name: &str) -> io::Result<Option<User>>;
+    /// The id of the group named `name`, none if there is no such group.
+    fn group(&self, name: &str) -> io::Result<Option<u32>>;
+    /// The real user id of the process.
+    fn uid(&self) -> u32;
+    fn set_groups(&mut self, groups: &[u32]) -> io::Result<()>;
+    fn set_gid(&mut self, gid: u32) -> io::Result<()>;
+    fn set_uid(&mut self, uid: u32) -> io::Result<()>;
+}
+
+/// The ids the process runs with after switching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Switched {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Switch to `user` and `group`, by name, the group defaulting to the user's
+/// own. The supplementary groups and the group are set before the user, which
+/// takes the right to change them away. Fails if any step fails or the
+/// process would still run as root; does nothing without either name.
+pub fn drop_privileges(
+    system: &mut impl System,
+    user: Option<&str>,
+    group: Option<&str>,
+) -> io::Result<Option<Switched>> {
+    if user.is_none() && group.is_none() {
+        return Ok(None);
+    }
+    let user = user.map(|name| resolve_user(system, name)).transpose()?;
+    let gid = match group {
+        Some(name) => resolve_group(system, name)?,
+        None => user.map_or(0, |user| user.gid),
+    };
+    let failed = |step: &str, err: io::Error| io::Error::new(err.kind(), format!("{step}: {err}"));
+    system
+        .set_groups(&[gid])
+        .map_err(|err| failed("could not set the supplementary groups", err))?;
+    system
+        .set_gid(gid)
+        .map_err(|err| failed(&format!("could not switch to group {gid}"), err))?;
+    if let Some(user) = user {
+        system
+            .set_uid(user.uid)
+            .map_err(|err| failed(&format!("could not switch to user {}", user.uid), err))?;
+    }
+    let uid = system.uid();
+    if uid == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "still running as root, name a user to switch to",
+        ));
+    }
+    Ok(Some(Switched { uid, gid }))
+}
+
+/// The user named `name`, an error if there is none.
+pub fn resolve_user(system: &impl System, name: &str) -> io::Result<User> {
+    system
+        .user(name)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("there is no user {name}")))
+}
+
+/// The id of the group named `name`, an error if there is none.
+pub fn resolve_group(system: &impl System, name: &str) -> io::Result<u32> {
+    system
+        .group(name)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("there is no group {name}")))
+}
+
+/// The real system calls.
+#[derive(Debug, Default)]
+pub struct Libc;
+
+/// The size of the buffer the user and group database entries are read into.
+const ENTRY_BUFFER_SIZE: usize = 16 * 1024;
+
+impl System for Libc {
+    fn user(&self, name: &str) -> io::Result<Option<User>> {
+        let name = c_name(name)?;
+        let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+        // SAFETY: all-zero is a valid `passwd`, which getpwnam_r only fills in.
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: the pointers are valid for the duration of the call and the
+        // buffer length is its actual size.
+        let code = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        match (code, found.is_null()) {
+            (0, true) => Ok(None),
+            (0, false) => Ok(Some(User {
+                uid: entry.pw_uid,
+                gid: entry.pw_gid,
+            })),
+            (code, _) => Err(io::Error::from_raw_os_error(code)),
+        }
+    }
+
+    fn group(&self, name: &str) -> io::Result<Option<u32>> {
+        let name = c_name(name)?;
+        let mut buffer = vec![0; ENTRY_BUFFER_SIZE];
+        // SAFETY: all-zero is a valid `group`, which getgrnam_r only fills in.
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut found = std::ptr::null_mut();
+        // SAFETY: as for getpwnam_r.
+        let code = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            )
+        };
+        match (code, found.is_null()) {
+            (0, true) => Ok(None),
+            (0, false) => Ok(Some(entry.gr_gid)),
+            (code, _) => Err(io::Error::from_raw_os_error(code)),
+        }
+    }
+
+    fn uid(&self) -> u32 {
+        // SAFETY: getuid cannot fail.
+        unsafe { libc::getuid() }
+    }
+
+    fn set_groups(&mut self, groups: &[u32]) -> io::Result<()> {
+        // SAFETY: the pointer and length describe `groups`.
+        check(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })
+    }
+
+    fn set_gid(&mut self, gid: u32) -> io::Result<()> {
+        // SAFETY: setgid only changes the process credentials.
+        check(unsafe { libc::setgid(gid) })
+    }
+
+    fn set_uid(&mut self, uid: u32) -> io::Result<()> {
+        // SAFETY: setuid only changes the process credentials.
+        check(unsafe { libc::setuid(uid) })
+    }
+}
+
+fn c_name(name: &str) -> io::Result<CString> {
+    CString::new(name).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid name {name:?}"),
+        )
+    })
+}
+
+fn check(code: libc::c_int) -> io::Result<()> {
+    match code {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the calls made, failing the one named in `fail`.
+    #[derive(Debug, Default)]
+    struct Fake {
+        uid: u32,
+        fail: Option<&'static str>,
+        calls: Vec<String>,
+    }
+
+    impl Fake {
+        fn call(&mut self, name: &'static str, arg: String) -> io::Result<()> {
+            if self.fail == Some(name) {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            self.calls.push(format!("{name}({arg})"));
+            Ok(())
+        }
+    }
+
+    impl System for Fake {
+        fn user(&self, name: &str) -> io::Result<Option<User>> {
+            Ok((name == "www").then_some(User { uid: 33, gid: 34 }))
+        }
+
+        fn group(&self, name: &str) -> io::Result<Option<u32>> {
+            Ok((name == "web").then_some(80))
+        }
+
+        fn uid(&self) -> u32 {
+            self.uid
+        }
+
+        fn set_groups(&mut self, groups: &[u32]) -> io::Result<()> {
+            self.call("setgroups", format!("{groups:?}"))
+        }
+
+        fn set_gid(&mut self, gid: u32) -> io::Result<()> {
+            self.call("setgid", gid.to_string())
+        }
+
+        fn set_uid(&mut self, uid: u32) -> io::Result<()> {
+            self.call("setuid", uid.to_string())?;
+            self.uid = uid;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_switches_groups_before_the_user() -> Result<(), Box<dyn std::error::Error>> {
+        let mut system = Fake::default();
+        assert_eq!(drop_privileges(&mut system, None, None)?, None);
+        assert!(system.calls.is_empty());
+
+        let switched = drop_privileges(&mut system, Some("www"), None)?;
+        assert_eq!(switched, Some(Switched { uid: 33, gid: 34 }));
+        assert_eq!(
+            system.calls,
+            ["setgroups([34])", "setgid(34)", "setuid(33)"]
+        );
+
+        let mut system = Fake::default();
+        let switched = drop_privileges(&mut system, Some("www"), Some("web"))?;
+        assert_eq!(switched, Some(Switched { uid: 33, gid: 80 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_refuses_to_continue_as_root() {
+        let mut system = Fake {
+            fail: Some("setgid"),
+            ..Fake::default()
+        };
+        let err = drop_privileges(&mut system, Some("www"), None).expect_err("setgid fails");
+        assert!(err.to_string().contains("group 34"), "{err}");
+        assert_eq!(system.calls, ["setgroups([34])"], "the user is kept");
+
+        let mut system = Fake::default();
+        let err = drop_privileges(&mut system, None, Some("web")).expect_err("still root");
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let mut system = Fake::default();
+        let err = drop_privileges(&mut system, Some("nobody"), None).expect_err("no such user");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(system.calls.is_empty());
+    }
+
+    #[test]
+    fn test_resolves_names_from_the_user_database() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(resolve_user(&Libc, "root")?, User { uid: 0, gid: 0 });
+        assert!(resolve_user(&Libc, "no-such-user-here").is_err());
+        assert!(resolve_user(&Libc, "nul\0byte").is_err());
+        Ok(())
+    }
+}
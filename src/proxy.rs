@@ -0,0 +1,535 @@
+//! Forwarding the requests under a path prefix to upstream servers, and
+//! relaying their responses.
+
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use log::warn;
+
+use crate::{
+    forwarded::{self, ForwardedHeaders},
+    http::{self, BodyReader, Headers, Method, ParseError, Request},
+    net,
+    response::{Body, Response, StreamBody},
+    spans,
+};
+
+/// The headers which only concern one connection, so they are not forwarded
+/// in either direction.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// The largest response head accepted from the upstream, in bytes.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// The size of the chunks a chunked request body is forwarded in.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Whether an upstream gets requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpstreamState {
+    Up,
+    /// Out of rotation after failing to connect too often, until the cooldown
+    /// ends and a request probes it again.
+    Down,
+}
+
+/// The counters of one upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpstreamStats {
+    pub addr: SocketAddr,
+    /// The requests sent its way, connected or not.
+    pub requests: u64,
+    /// The requests which could not be forwarded to it.
+    pub failures: u64,
+    pub state: UpstreamState,
+}
+
+/// An upstream server with its health, shared by the clones of a [`Proxy`].
+#[derive(Debug)]
+struct Upstream {
+    addr: SocketAddr,
+    requests: AtomicU64,
+    failures: AtomicU64,
+    /// The connect failures since the last connection which succeeded.
+    consecutive_failures: AtomicU32,
+    /// When the upstream may be probed again, while it is down.
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Upstream {
+    fn new(addr: SocketAddr) -> Upstream {
+        Upstream {
+            addr,
+            requests: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            consecutive_failures: AtomicU32::new(0),
+            down_until: Mutex::new(None),
+        }
+    }
+
+    fn down_until(&self) -> Option<Instant> {
+        *self
+            .down_until
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_available(&self, now: Instant) -> bool {
+        self.down_until().is_none_or(|until| now >= until)
+    }
+}
+
+/// Forwards the requests under a prefix to upstream HTTP servers in turn, a
+/// new connection for each request.
+///
+/// Clones share the rotation and the health of the upstreams.
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    prefix: String,
+    upstreams: Vec<Arc<Upstream>>,
+    next: Arc<AtomicUsize>,
+    strip_prefix: bool,
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_retries: usize,
+    failure_threshold: u32,
+    cooldown: Duration,
+    forwarded_headers: ForwardedHeaders,
+}
+
+impl Proxy {
+    /// Forward the requests for `prefix` and the paths below it to `upstream`,
+    /// keeping the prefix.
+    pub fn new(prefix: &str, upstream: SocketAddr) -> Proxy {
+        Proxy {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstreams: vec![Arc::new(Upstream::new(upstream))],
+            next: Arc::new(AtomicUsize::new(0)),
+            strip_prefix: false,
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            max_retries: 2,
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(10),
+            forwarded_headers: ForwardedHeaders::default(),
+        }
+    }
+
+    /// Take turns with `upstream` as well.
+    pub fn with_upstream(mut self, upstream: SocketAddr) -> Proxy {
+        self.upstreams.push(Arc::new(Upstream::new(upstream)));
+        self
+    }
+
+    /// Try up to `retries` more upstreams when connecting fails, 2 by default.
+    pub fn max_retries(mut self, retries: usize) -> Proxy {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Take an upstream out of rotation for `cooldown` after `failures`
+    /// connect failures in a row, 3 and 10 seconds by default.
+    pub fn passive_health(mut self, failures: u32, cooldown: Duration) -> Proxy {
+        self.failure_threshold = failures.max(1);
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Remove the prefix from the paths sent upstream, so that the prefix
+    /// itself becomes `/`.
+    pub fn strip_prefix(mut self, strip: bool) -> Proxy {
+        self.strip_prefix = strip;
+        self
+    }
+
+    /// Answer 504 when connecting takes longer than `timeout`, 5 seconds by default.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Answer 504 when the upstream takes longer than `timeout` to accept or
+    /// send each part of a message, 30 seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Tell upstreams about the client in `headers`, the `X-Forwarded-*` ones
+    /// by default.
+    pub fn forwarded_headers(mut self, headers: ForwardedHeaders) -> Proxy {
+        self.forwarded_headers = headers;
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// The counters of each upstream, in the order they were added.
+    pub fn stats(&self) -> Vec<UpstreamStats> {
+        self.upstreams
+            .iter()
+            .map(|upstream| UpstreamStats {
+                addr: upstream.addr,
+                requests: upstream.requests.load(Ordering::Relaxed),
+                failures: upstream.failures.load(Ordering::Relaxed),
+                state: match upstream.down_until() {
+                    None => UpstreamState::Up,
+                    Some(_) => UpstreamState::Down,
+                },
+            })
+            .collect()
+    }
+
+    /// Whether the path of `request` is the prefix or below it.
+    pub fn matches(&self, request: &Request) -> bool {
+        http::origin_form(request.path())
+            .strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+
+    /// Forward `request` from `peer`, over TLS if `secure`, reading its body
+    /// from `body`, to the next upstream in turn, and relay the response.
+    ///
+    /// Both bodies are streamed: the request's as it is read, the response's
+    /// as the server writes it to the client. Failing to reach any upstream or
+    /// to get the response head is answered with 502, or 504 on a timeout.
+    pub fn forward<R: BufRead>(
+        &self,
+        request: &Request,
+        peer: Option<IpAddr>,
+        secure: bool,
+        body: &mut BodyReader<R>,
+    ) -> Response {
+        if !body.is_valid() {
+            return Response::builtin_error(400);
+        }
+        let mut added = forwarded::headers(request.headers(), peer, secure, self.forwarded_headers);
+        if !request.id().is_empty() {
+            added.push(("X-Request-Id", request.id().to_string()));
+        }
+        let result = self.connect().and_then(|(upstream, stream)| {
+            match self.exchange(stream, request, &added, body) {
+                Ok(response) => {
+                    spans::debug_event!(
+                        "Forwarded {} {} to {}, which answered {}",
+                        request.method(),
+                        request.target(),
+                        upstream.addr,
+                        response.status()
+                    );
+                    Ok(response)
+                }
+                Err(err) => {
+                    upstream.failures.fetch_add(1, Ordering::Relaxed);
+                    Err(io::Error::new(
+                        err.kind(),
+                        format!("{}: {err}", upstream.addr),
+                    ))
+                }
+            }
+        });
+        match result {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(
+                    "Could not forward {} {}: {err}",
+                    request.method(),
+                    request.target()
+                );
+                let status = if net::is_timeout(&err) { 504 } else { 502 };
+                Response::builtin_error(status).with_header("Connection", "close")
+            }
+        }
+    }
+
+    /// Connect to the next upstream in rotation which accepts, trying up to
+    /// `max_retries` others after the first.
+    ///
+    /// Nothing was sent yet, so moving on to another upstream is safe for
+    /// every request.
+    fn connect(&self) -> io::Result<(&Upstream, TcpStream)> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut last_err = None;
+        let available = (0..self.upstreams.len())
+            .map(|offset| &*self.upstreams[(start + offset) % self.upstreams.len()])
+            .filter(|upstream| upstream.is_available(now));
+        for upstream in available.take(self.max_retries.saturating_add(1)) {
+            upstream.requests.fetch_add(1, Ordering::Relaxed);
+            match TcpStream::connect_timeout(&upstream.addr, self.connect_timeout) {
+                Ok(stream) => {
+                    upstream.consecutive_failures.store(0, Ordering::Relaxed);
+                    *upstream
+                        .down_until
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner) = None;
+                    return Ok((upstream, stream));
+                }
+                Err(err) => {
+                    self.connect_failed(upstream);
+                    last_err = Some(io::Error::new(
+                        err.kind(),
+                        format!("{}: {err}", upstream.addr),
+                    ));
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "every upstream is down")
+        }))
+    }
+
+    /// Count a connect failure of `upstream`, taking it out of rotation once
+    /// there were too many in a row.
+    fn connect_failed(&self, upstream: &Upstream) {
+        upstream.failures.fetch_add(1, Ordering::Relaxed);
+        let failures = upstream
+            .consecutive_failures
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if failures >= self.failure_threshold {
+            let mut down_until = upstream
+                .down_until
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if down_until.is_none() {
+                warn!(
+                    "Taking upstream {} out of rotation for {:?} after {failures} failures.",
+                    upstream.addr, self.cooldown
+                );
+            }
+            *down_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+
+    /// Send `request` over `upstream`, with the `added` headers replacing
+    /// those of the same names, and read the response head.
+    fn exchange<R: BufRead>(
+        &self,
+        upstream: TcpStream,
+        request: &Request,
+        added: &[(&str, String)],
+        body: &mut BodyReader<R>,
+    ) -> io::Result<Response> {
+        upstream.set_read_timeout(Some(self.timeout))?;
+        upstream.set_write_timeout(Some(self.timeout))?;
+
+        let mut writer = BufWriter::new(&upstream);
+        write!(
+            writer,
+            "{} {} HTTP/1.1\r\n",
+            request.method(),
+            self.upstream_target(request)
+        )?;
+        for (name, value) in end_to_end(request.headers()) {
+            if !added
+                .iter()
+                .any(|(added, _)| added.eq_ignore_ascii_case(name))
+            {
+                write!(writer, "{name}: {value}\r\n")?;
+            }
+        }
+        for (name, value) in added {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        match body.remaining() {
+            Some(0) => {}
+            Some(length) => write!(writer, "Content-Length: {length}\r\n")?,
+            None => writer.write_all(b"Transfer-Encoding: chunked\r\n")?,
+        }
+        writer.write_all(b"Connection: close\r\n\r\n")?;
+        if body.is_chunked() {
+            write_chunked(body, &mut writer)?;
+        } else {
+            io::copy(body, &mut writer)?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let mut reader = BufReader::new(upstream);
+        let (status, headers) = loop {
+            let head = http::read_response_head(&mut reader, MAX_HEAD_SIZE)
+                .map_err(upstream_error)?
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the upstream closed without a response",
+                    )
+                })?;
+            // Interim responses are for the upstream's client, which is the proxy.
+            if !matches!(head.0, 100..=199) {
+                break head;
+            }
+        };
+
+        let mut response = Response::new(status);
+        for (name, value) in end_to_end(&headers) {
+            response.headers_mut().append(name, value);
+        }
+        if request.method() != &Method::Head && !matches!(status, 204 | 304) {
+            let body = BodyReader::response(reader, &headers);
+            let length = body.remaining();
+            response = response.with_body(Body::Stream(StreamBody::new(body, length)));
+        }
+        Ok(response)
+    }
+
+    /// The target sent upstream: the path the prefix matched, encoded again
+    /// and without the prefix if it is stripped, and the query.
+    fn upstream_target(&self, request: &Request) -> String {
+        let mut path = http::origin_form(request.path());
+        if self.strip_prefix {
+            path = path.get(self.prefix.len()..).unwrap_or("");
+        }
+        let mut target = match path.starts_with('/') {
+            true => http::encode_path(path),
+            false => format!("/{}", http::encode_path(path)),
+        };
+        if !request.query().is_empty() {
+            target.push('?');
+            target.push_str(request.query());
+        }
+        target
+    }
+}
+
+/// The fields of `headers` which are forwarded: all but the hop-by-hop ones,
+/// those `Connection` names, and the length, which is set for the new message.
+fn end_to_end(headers: &Headers) -> impl Iterator<Item = (&str, &str)> {
+    let listed: Vec<&str> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    headers.iter().filter(move |(name, _)| {
+        !HOP_BY_HOP
+            .iter()
+            .chain(&listed)
+            .chain(&["Content-Length"])
+            .any(|hop| hop.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Copy `body` to `writer` in chunked transfer coding, a chunk per read.
+fn write_chunked<R: Read, W: Write>(body: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            return writer.write_all(b"0\r\n\r\n");
+        }
+        write!(writer, "{read:x}\r\n")?;
+        writer.write_all(&buffer[..read])?;
+        writer.write_all(b"\r\n")?;
+    }
+}
+
+/// An error for a response head the upstream could not send or got wrong.
+fn upstream_error(err: ParseError) -> io::Error {
+    match err {
+        ParseError::Io(err) => err,
+        err => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid response head: {err}"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Version;
+
+    fn get(target: &str) -> Request {
+        Request::new(Method::Get, target, Version::Http11)
+    }
+
+    #[test]
+    fn test_prefix_matching_and_stripping() {
+        let upstream = SocketAddr::from(([127, 0, 0, 1], 9000));
+        let proxy = Proxy::new("/api/", upstream);
+        for target in [
+            "/api",
+            "/api/",
+            "/api/users?page=2",
+            "/api?x=1",
+            "http://a.test/api/v1",
+        ] {
+            assert!(proxy.matches(&get(target)), "{target}");
+        }
+        for target in ["/", "/apis", "/v1/api", "*"] {
+            assert!(!proxy.matches(&get(target)), "{target}");
+        }
+
+        let kept = |target| proxy.upstream_target(&get(target));
+        assert_eq!(kept("/api/users?page=2"), "/api/users?page=2");
+        assert_eq!(kept("http://a.test/api/v1"), "/api/v1");
+
+        let proxy = proxy.strip_prefix(true);
+        let stripped = |target| proxy.upstream_target(&get(target));
+        assert_eq!(stripped("/api/users?page=2"), "/users?page=2");
+        assert_eq!(stripped("/api"), "/");
+        assert_eq!(stripped("/api?x=1"), "/?x=1");
+
+        let mut climbing = get("/docs/../api/a%20b%2Fc?q=%20");
+        assert!(climbing.normalize(true));
+        assert!(proxy.matches(&climbing));
+        assert_eq!(proxy.upstream_target(&climbing), "/a%20b/c?q=%20");
+    }
+
+    #[test]
+    fn test_down_upstreams_are_probed_after_the_cooldown() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let proxy = Proxy::new("/", closed).passive_health(1, Duration::from_millis(50));
+        let forward = || {
+            let mut body = BodyReader::request(io::Cursor::new(Vec::new()), &Headers::new());
+            proxy.forward(&get("/"), None, false, &mut body).status()
+        };
+        assert_eq!(forward(), 502);
+        assert_eq!(proxy.stats()[0].state, UpstreamState::Down);
+        assert_eq!(forward(), 502);
+        assert_eq!(
+            proxy.stats()[0].requests,
+            1,
+            "nothing is sent its way while down"
+        );
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(forward(), 502);
+        assert_eq!(proxy.stats()[0].requests, 2, "it is probed again");
+        Ok(())
+    }
+
+    #[test]
+    fn test_hop_by_hop_headers_are_dropped() {
+        let request = get("/")
+            .with_header("Connection", "keep-alive, X-Secret")
+            .with_header("Keep-Alive", "timeout=5")
+            .with_header("X-Secret", "1")
+            .with_header("te", "trailers")
+            .with_header("Content-Length", "3")
+            .with_header("Accept", "*/*");
+        let forwarded: Vec<_> = end_to_end(request.headers()).collect();
+        assert_eq!(forwarded, [("Accept", "*/*")]);
+    }
+}
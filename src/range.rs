@@ -0,0 +1,187 @@
+//! Parsing of `Range` request headers.
+
+/// An inclusive range of byte offsets within a representation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// The number of bytes covered by the range.
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// How a request's `Range` header applies to a representation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// Serve the full representation, either because no valid header was sent
+    /// or because the ranges cannot be served partially.
+    Full,
+    /// Serve only these ranges, in the order they were requested.
+    Partial(Vec<ByteRange>),
+    /// None of the ranges overlap the representation.
+    Unsatisfiable,
+}
+
+/// Resolve the `Range` header `header` against a representation of `total` bytes.
+///
+/// Syntactically invalid headers are ignored, as are requests for more than
+/// `max_ranges` ranges or for ranges which overlap each other.
+pub fn parse_range(header: &str, total: u64, max_ranges: usize) -> RangeRequest {
+    let specs = match header.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => return RangeRequest::Full,
+    };
+
+    let mut ranges = Vec::new();
+    let mut count = 0;
+    for spec in specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+    {
+        count += 1;
+        if count > max_ranges {
+            return RangeRequest::Full;
+        }
+        match parse_spec(spec, total) {
+            Some(Some(range)) => ranges.push(range),
+            Some(None) => {}
+            None => return RangeRequest::Full,
+        }
+    }
+    if count == 0 {
+        return RangeRequest::Full;
+    }
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    let mut sorted = ranges.clone();
+    sorted.sort_by_key(|range| range.start);
+    if sorted.windows(2).any(|pair| pair[1].start <= pair[0].end) {
+        return RangeRequest::Full;
+    }
+    RangeRequest::Partial(ranges)
+}
+
+/// Parse a single range spec: `None` when it is invalid, `Some(None)` when it lies
+/// outside the representation.
+fn parse_spec(spec: &str, total: u64) -> Option<Option<ByteRange>> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (parse_offset(start)?, parse_offset(end)?) {
+        (Some(start), end) => (start, end),
+        (None, Some(suffix)) => {
+            if suffix == 0 || total == 0 {
+                return Some(None);
+            }
+            (total.saturating_sub(suffix), None)
+        }
+        (None, None) => return None,
+    };
+
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= total {
+        return Some(None);
+    }
+    let end = end.map_or(total - 1, |end| end.min(total - 1));
+    Some(Some(ByteRange { start, end }))
+}
+
+/// Parse one side of a range spec: `Some(None)` when it is empty, `None` when invalid.
+fn parse_offset(offset: &str) -> Option<Option<u64>> {
+    let offset = offset.trim();
+    if offset.is_empty() {
+        return Some(None);
+    }
+    if !offset.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    offset.parse().ok().map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(start: u64, end: u64) -> RangeRequest {
+        RangeRequest::Partial(vec![ByteRange { start, end }])
+    }
+
+    #[test]
+    fn test_parse_range_forms() {
+        assert_eq!(parse_range("bytes=10-19", 100, 16), partial(10, 19));
+        assert_eq!(parse_range("bytes=90-", 100, 16), partial(90, 99));
+        assert_eq!(parse_range("bytes=-5", 100, 16), partial(95, 99));
+        assert_eq!(parse_range("bytes=-500", 100, 16), partial(0, 99));
+        assert_eq!(parse_range("bytes=50-500", 100, 16), partial(50, 99));
+        assert_eq!(parse_range("bytes=0-9, 200-", 100, 16), partial(0, 9));
+    }
+
+    #[test]
+    fn test_parse_multiple_ranges() {
+        assert_eq!(
+            parse_range("bytes=50-59,0-9", 100, 16),
+            RangeRequest::Partial(vec![
+                ByteRange { start: 50, end: 59 },
+                ByteRange { start: 0, end: 9 }
+            ])
+        );
+        assert_eq!(
+            parse_range("bytes=0-9,5-20", 100, 16),
+            RangeRequest::Full,
+            "overlapping ranges are collapsed"
+        );
+        assert_eq!(
+            parse_range("bytes=0-0,2-2,4-4", 100, 2),
+            RangeRequest::Full,
+            "too many ranges are collapsed"
+        );
+    }
+
+    #[test]
+    fn test_parse_range_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=100-", 100, 16),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=200-300", 100, 16),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=-0", 100, 16),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=0-", 0, 16), RangeRequest::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=100-,300-", 100, 16),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn test_parse_range_invalid_is_ignored() {
+        for header in [
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=1",
+            "bytes=+1-2",
+            "bytes=",
+            "bytes=0-1,x",
+        ] {
+            assert_eq!(
+                parse_range(header, 100, 16),
+                RangeRequest::Full,
+                "{header} should be ignored"
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Token bucket rate limiting of requests by client address.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Limits each client address to a steady rate of requests with short bursts.
+///
+/// Clones share their buckets, so one limiter can serve all workers.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    allowed: Vec<IpAddr>,
+    table: Arc<Mutex<Table>>,
+}
+
+#[derive(Debug)]
+struct Table {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_cleanup: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    /// Allow `rate` requests per second from each address, and bursts of up to
+    /// `burst` requests. Loopback addresses are never limited.
+    ///
+    /// # Panics
+    ///
+    /// When `rate` is not a positive, finite number, which no wait could be
+    /// computed from.
+    pub fn new(rate: f64, burst: u32) -> RateLimiter {
+        assert!(
+            rate.is_finite() && rate > 0.0,
+            "a rate limit needs a positive rate, not {rate}"
+        );
+        RateLimiter {
+            rate,
+            burst: f64::from(burst.max(1)),
+            allowed: vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+            table: Arc::new(Mutex::new(Table {
+                buckets: HashMap::new(),
+                last_cleanup: Instant::now(),
+            })),
+        }
+    }
+
+    /// Never limit `addresses`, such as health checkers, instead of loopback.
+    pub fn allow(mut self, addresses: &[IpAddr]) -> RateLimiter {
+        self.allowed = addresses.to_vec();
+        self
+    }
+
+    /// Take a token for a request from `ip` at `now`, or return how long the
+    /// client should wait before retrying.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.allowed.contains(&ip) {
+            return Ok(());
+        }
+        let mut table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        self.clean_up(&mut table, now);
+
+        let bucket = table.buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated = now;
+        self.wait(bucket.tokens)?;
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Whether a request from `ip` at `now` would get a token, without
+    /// taking it, such as before work only failures are charged for.
+    pub fn peek(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        if self.allowed.contains(&ip) {
+            return Ok(());
+        }
+        let table = self.table.lock().unwrap_or_else(|e| e.into_inner());
+        match table.buckets.get(&ip) {
+            Some(bucket) => self.wait(self.refilled(bucket, now)),
+            None => Ok(()),
+        }
+    }
+
+    /// The number of addresses with a bucket, for the status page.
+    pub fn tracked(&self) -> usize {
+        self.table
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .buckets
+            .len()
+    }
+
+    /// How long a bucket holding `tokens` takes to hold one, if it holds none.
+    fn wait(&self, tokens: f64) -> Result<(), Duration> {
+        if tokens >= 1.0 {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - tokens) / self.rate))
+        }
+    }
+
+    /// The tokens in `bucket` at `now`.
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+
+    /// Forget the buckets which have filled up again, at most once per refill period.
+    fn clean_up(&self, table: &mut Table, now: Instant) {
+        let period = Duration::from_secs_f64(self.burst / self.rate);
+        if now.saturating_duration_since(table.last_cleanup) < period {
+            return;
+        }
+        table.last_cleanup = now;
+        table
+            .buckets
+            .retain(|_, bucket| self.refilled(bucket, now) < self.burst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills() {
+        let limiter = RateLimiter::new(2.0, 3);
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check(client, start).is_ok());
+        }
+        let wait = limiter.check(client, start).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        assert!(
+            limiter.check(other, start).is_ok(),
+            "buckets are per address"
+        );
+
+        assert_eq!(limiter.peek(client, start), Err(wait));
+        assert!(limiter.peek(client, start + wait).is_ok());
+        assert!(limiter.check(client, start + wait).is_ok());
+        assert!(limiter.check(client, start + wait).is_err());
+    }
+
+    #[test]
+    fn test_rate_must_be_positive() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let built = std::panic::catch_unwind(|| RateLimiter::new(rate, 1));
+            assert!(built.is_err(), "{rate}");
+        }
+        assert!(std::panic::catch_unwind(|| RateLimiter::new(0.001, 0)).is_ok());
+    }
+
+    #[test]
+    fn test_allowlist_and_cleanup() {
+        let limiter = RateLimiter::new(1.0, 1);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter
+                .check(IpAddr::V4(Ipv4Addr::LOCALHOST), start)
+                .is_ok());
+        }
+        assert_eq!(limiter.tracked(), 0);
+
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(limiter.check(client, start).is_ok());
+        assert_eq!(limiter.tracked(), 1);
+
+        let other: IpAddr = "2001:db8::2".parse().unwrap();
+        assert!(limiter.check(other, start + Duration::from_secs(5)).is_ok());
+        assert_eq!(limiter.tracked(), 1, "the idle bucket is forgotten");
+    }
+}
@@ -0,0 +1,143 @@
+//! Sending requests which arrive over plain HTTP to the same URL over HTTPS.
+
+use crate::{http::Request, response::Response};
+
+/// Where requests are redirected to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpsRedirect {
+    canonical_host: Option<String>,
+    port: Option<u16>,
+}
+
+impl HttpsRedirect {
+    /// Redirect to the host each request names, on the default HTTPS port.
+    pub fn new() -> HttpsRedirect {
+        HttpsRedirect::default()
+    }
+
+    /// Redirect requests without a `Host` header to `host`.
+    pub fn canonical_host(mut self, host: impl Into<String>) -> HttpsRedirect {
+        self.canonical_host = Some(host.into());
+        self
+    }
+
+    /// Redirect to HTTPS on `port` instead of 443.
+    pub fn port(mut self, port: u16) -> HttpsRedirect {
+        self.port = Some(port).filter(|&port| port != 443);
+        self
+    }
+
+    /// The HTTPS URL for `request`, with its path and query, none if it names
+    /// no host to send the client to.
+    ///
+    /// The port of the host the client asked for belongs to plain HTTP, so it
+    /// is replaced by the HTTPS one.
+    pub fn location(&self, request: &Request) -> Option<String> {
+        let target = request.target();
+        let (authority, path) = match target.strip_prefix("http://") {
+            Some(absolute) => match absolute.find('/') {
+                Some(at) => (Some(&absolute[..at]), &absolute[at..]),
+                None => (Some(absolute), "/"),
+            },
+            None if target.starts_with('/') => (request.header("Host"), target),
+            None => return None,
+        };
+        let host = match authority.filter(|authority| !authority.is_empty()) {
+            Some(authority) => host_of(authority)?,
+            None => self.canonical_host.as_deref()?,
+        };
+        let port = self
+            .port
+            .map_or_else(String::new, |port| format!(":{port}"));
+        Some(format!("https://{host}{port}{path}"))
+    }
+
+    /// The permanent redirect for `request`, or 400 when it could not be
+    /// parsed or names no host.
+    pub fn respond(&self, request: Option<&Request>) -> Response {
+        match request.and_then(|request| self.location(request)) {
+            Some(location) => Response::new(301).with_header("Location", location),
+            None => Response::builtin_error(400),
+        }
+    }
+}
+
+/// The host of `authority`, without its port, none if it is not a host name
+/// or IP address.
+pub(crate) fn host_of(authority: &str) -> Option<&str> {
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => &authority[..bracketed.find(']')? + 2],
+        None => authority.split(':').next()?,
+    };
+    let rest = &authority[host.len()..];
+    let valid_port = rest.is_empty()
+        || rest
+            .strip_prefix(':')
+            .is_some_and(|port| port.bytes().all(|byte| byte.is_ascii_digit()));
+    let valid_host = !host.is_empty()
+        && host
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-.[]:".contains(&byte));
+    (valid_port && valid_host).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+
+    fn get(target: &str) -> Request {
+        Request::new(Method::Get, target, Version::Http11)
+    }
+
+    #[test]
+    fn test_host_is_taken_from_the_request() {
+        let redirect = HttpsRedirect::new().canonical_host("example.com");
+        let request = get("/").with_header("Host", "www.example.com:80");
+        assert_eq!(
+            redirect.location(&request).as_deref(),
+            Some("https://www.example.com/")
+        );
+        let request = get("/").with_header("Host", "[::1]:8080");
+        let redirect = redirect.port(8443);
+        assert_eq!(
+            redirect.location(&request).as_deref(),
+            Some("https://[::1]:8443/")
+        );
+        let absolute = get("http://other.example/page").with_header("Host", "ignored");
+        assert_eq!(
+            redirect.location(&absolute).as_deref(),
+            Some("https://other.example:8443/page")
+        );
+        for host in ["evil.example/path", "a@b", "example.com:http"] {
+            let request = get("/").with_header("Host", host);
+            assert_eq!(redirect.location(&request), None, "{host}");
+        }
+    }
+
+    #[test]
+    fn test_path_and_query_are_kept() {
+        let request = get("/search/page.html?q=rust&page=2").with_header("Host", "example.com");
+        let response = HttpsRedirect::new().respond(Some(&request));
+        assert_eq!(response.status(), 301);
+        assert_eq!(
+            response.headers().get("Location"),
+            Some("https://example.com/search/page.html?q=rust&page=2")
+        );
+    }
+
+    #[test]
+    fn test_missing_host_falls_back_to_the_canonical_one() {
+        let request = Request::new(Method::Get, "/about?lang=en", Version::Http10);
+        assert_eq!(HttpsRedirect::new().respond(Some(&request)).status(), 400);
+
+        let redirect = HttpsRedirect::new().canonical_host("example.com");
+        let response = redirect.respond(Some(&request));
+        assert_eq!(
+            response.headers().get("Location"),
+            Some("https://example.com/about?lang=en")
+        );
+        assert_eq!(redirect.respond(None).status(), 400, "unparsable");
+        assert_eq!(redirect.respond(Some(&get("*"))).status(), 400);
+    }
+}
@@ -0,0 +1,65 @@
+//! Identifiers telling requests apart, so that a failed request reported by a
+//! user can be found in the logs.
+
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The longest identifier accepted from a proxy.
+const MAX_LEN: usize = 128;
+
+/// A new identifier: the time in milliseconds, a counter and a random suffix,
+/// each in hex.
+///
+/// The counter keeps identifiers made in the same millisecond apart, the
+/// suffix those of separate processes.
+pub fn generate() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis());
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(count);
+    hasher.write_u128(millis);
+    format!("{millis:x}-{count:x}-{:08x}", hasher.finish() as u32)
+}
+
+/// Whether `id`, sent by a client or proxy, can be taken over: up to 128
+/// visible ASCII characters, so that it cannot break up log lines.
+pub fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, thread};
+
+    #[test]
+    fn test_generated_ids_are_unique() {
+        let threads: Vec<_> = (0..8)
+            .map(|_| thread::spawn(|| (0..500).map(|_| generate()).collect::<Vec<_>>()))
+            .collect();
+        let mut ids = HashSet::new();
+        for thread in threads {
+            for id in thread.join().expect("the thread finishes") {
+                assert!(is_valid(&id), "{id}");
+                assert!(ids.insert(id), "the ids are unique");
+            }
+        }
+        assert_eq!(ids.len(), 4000);
+    }
+
+    #[test]
+    fn test_incoming_ids_are_checked() {
+        assert!(is_valid("req-7f3a_9b"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("two words"));
+        assert!(!is_valid("line\nbreak"));
+        assert!(!is_valid(&"x".repeat(129)));
+    }
+}
@@ -0,0 +1,910 @@
+//! Responses and their serialization onto a stream.
+
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+};
+
+use crate::{
+    http::{self, Headers},
+    net::Counted,
+    range::ByteRange,
+};
+
+/// The reason phrase sent alongside `status`.
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "NOT FOUND",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        421 => "Misdirected Request",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "",
+    }
+}
+
+/// The payload of a response.
+#[derive(Debug)]
+pub enum Body {
+    Empty,
+    Bytes(Vec<u8>),
+    File(FileBody),
+    Multipart(MultipartBody),
+    Stream(StreamBody),
+    Chunked(ChunkedBody),
+}
+
+impl Body {
+    /// The number of bytes the body will write, zero for a stream of unknown length.
+    pub fn len(&self) -> u64 {
+        match self {
+            Body::Empty => 0,
+            Body::Bytes(bytes) => bytes.len() as u64,
+            Body::File(file) => file.len,
+            Body::Multipart(multipart) => multipart.len(),
+            Body::Stream(stream) => stream.len.unwrap_or(0),
+            Body::Chunked(_) => 0,
+        }
+    }
+
+    /// Whether the length is known before the body is written.
+    pub fn has_known_length(&self) -> bool {
+        !matches!(
+            self,
+            Body::Stream(StreamBody { len: None, .. }) | Body::Chunked(_)
+        )
+    }
+
+    /// Whether the body goes out in chunks, which delimit it without a length.
+    pub fn is_chunked(&self) -> bool {
+        matches!(self, Body::Chunked(ChunkedBody { framed: true, .. }))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the body bytes to `writer`.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Body::Empty => Ok(()),
+            Body::Bytes(bytes) => writer.write_all(bytes),
+            Body::File(file) => file.source.copy_section(file.offset, file.len, writer),
+            Body::Multipart(multipart) => multipart.write_to(writer),
+            Body::Stream(stream) => stream.write_to(writer),
+            Body::Chunked(chunked) => chunked.write_to(writer),
+        }
+    }
+}
+
+/// The bytes a file-like body is read from.
+#[derive(Debug)]
+pub enum Source {
+    File(File),
+    Static(&'static [u8]),
+}
+
+impl Source {
+    /// The total number of bytes available.
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            Source::File(file) => Ok(file.metadata()?.len()),
+            Source::Static(bytes) => Ok(bytes.len() as u64),
+        }
+    }
+
+    /// Copy `len` bytes starting at `offset` into `writer`.
+    fn copy_section<W: Write>(&self, offset: u64, len: u64, writer: &mut W) -> io::Result<()> {
+        let copied = match self {
+            Source::File(file) => {
+                let mut file = file;
+                file.seek(SeekFrom::Start(offset))?;
+                io::copy(&mut file.take(len), writer)?
+            }
+            Source::Static(bytes) => {
+                let start = bytes.len().min(offset as usize);
+                let end = bytes.len().min(start.saturating_add(len as usize));
+                writer.write_all(&bytes[start..end])?;
+                (end - start) as u64
+            }
+        };
+        if copied < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "source ended before the announced length",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl From<File> for Source {
+    fn from(file: File) -> Source {
+        Source::File(file)
+    }
+}
+
+impl From<&'static [u8]> for Source {
+    fn from(bytes: &'static [u8]) -> Source {
+        Source::Static(bytes)
+    }
+}
+
+/// A section of a file or static buffer, streamed to the client when the
+/// response is written.
+#[derive(Debug)]
+pub struct FileBody {
+    source: Source,
+    offset: u64,
+    len: u64,
+}
+
+impl FileBody {
+    /// Stream `len` bytes of `source` starting at `offset`.
+    pub fn new(source: impl Into<Source>, offset: u64, len: u64) -> FileBody {
+        FileBody {
+            source: source.into(),
+            offset,
+            len,
+        }
+    }
+}
+
+/// Several sections of a file or static buffer, framed as `multipart/byteranges`.
+#[derive(Debug)]
+pub struct MultipartBody {
+    source: Source,
+    parts: Vec<(String, ByteRange)>,
+    closing: String,
+}
+
+impl MultipartBody {
+    /// Frame `ranges` of `source`, which is `total` bytes of `content_type`, with
+    /// `boundary` delimiters.
+    pub fn new(
+        source: impl Into<Source>,
+        ranges: &[ByteRange],
+        total: u64,
+        content_type: &str,
+        boundary: &str,
+    ) -> MultipartBody {
+        let parts = ranges
+            .iter()
+            .map(|range| {
+                let head = format!(
+                    "--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{total}\r\n\r\n",
+                    range.start, range.end
+                );
+                (head, *range)
+            })
+            .collect();
+        MultipartBody {
+            source: source.into(),
+            parts,
+            closing: format!("--{boundary}--\r\n"),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|(head, range)| head.len() as u64 + range.length() + 2)
+            .sum();
+        parts + self.closing.len() as u64
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (head, range) in &self.parts {
+            writer.write_all(head.as_bytes())?;
+            self.source
+                .copy_section(range.start, range.length(), writer)?;
+            writer.write_all(b"\r\n")?;
+        }
+        writer.write_all(self.closing.as_bytes())
+    }
+}
+
+/// Bytes read from elsewhere while the response is written, like the
+/// response of an upstream server, without holding all of them in memory.
+pub struct StreamBody {
+    reader: Mutex<Box<dyn Read + Send>>,
+    len: Option<u64>,
+    /// Flush after every read instead of filling the write buffer.
+    live: bool,
+    /// Set to end a live stream early.
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl StreamBody {
+    /// Stream what `reader` reads: `len` bytes, or up to its end without a
+    /// length, in which case the connection closes after the response.
+    pub fn new(reader: impl Read + Send + 'static, len: Option<u64>) -> StreamBody {
+        StreamBody {
+            reader: Mutex::new(Box::new(reader)),
+            len,
+            live: false,
+            stop: None,
+        }
+    }
+
+    /// Stream what `reader` reads up to its end, sending each read on at
+    /// once, for bodies like event streams which trickle in as they happen.
+    pub fn live(reader: impl Read + Send + 'static) -> StreamBody {
+        StreamBody {
+            live: true,
+            ..StreamBody::new(reader, None)
+        }
+    }
+
+    /// End a live stream once `stop` is set, at its next read.
+    pub fn stop_when(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.live {
+            return self.write_through(&mut **reader, writer);
+        }
+        let Some(len) = self.len else {
+            return io::copy(&mut *reader, writer).map(drop);
+        };
+        if io::copy(&mut (&mut *reader).take(len), writer)? < len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended before the announced length",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Copy `reader` to `writer`, flushing before every read so that what was
+    /// read, the head included, goes out before waiting for more.
+    fn write_through<W: Write>(&self, reader: &mut dyn Read, writer: &mut W) -> io::Result<()> {
+        let mut buffer = [0; 8 * 1024];
+        loop {
+            writer.flush()?;
+            if let Some(stop) = &self.stop {
+                if stop.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+            }
+            match reader.read(&mut buffer) {
+                Ok(0) => return Ok(()),
+                Ok(read) => writer.write_all(&buffer[..read])?,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for StreamBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StreamBody")
+            .field("len", &self.len)
+            .field("live", &self.live)
+            .finish()
+    }
+}
+
+/// A closure writing a body.
+type WriteBody = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()> + Send>;
+
+/// A closure giving the value of a trailer field.
+type TrailerValue = Box<dyn FnOnce() -> String + Send>;
+
+/// Fields which may not be sent after the body, because they frame, route,
+/// authenticate or otherwise control the message.
+const DISALLOWED_TRAILERS: [&str; 24] = [
+    "Age",
+    "Authorization",
+    "Cache-Control",
+    "Connection",
+    "Content-Encoding",
+    "Content-Length",
+    "Content-Range",
+    "Content-Type",
+    "Cookie",
+    "Date",
+    "Expect",
+    "Expires",
+    "Host",
+    "Location",
+    "Max-Forwards",
+    "Pragma",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Range",
+    "Retry-After",
+    "Set-Cookie",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+];
+
+/// A field name which cannot be declared as a trailer.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DisallowedTrailer(pub String);
+
+impl fmt::Display for DisallowedTrailer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} cannot be sent as a trailer", self.0)
+    }
+}
+
+impl Error for DisallowedTrailer {}
+
+/// What produces the bytes of a [`ChunkedBody`].
+enum Producer {
+    Chunks(Box<dyn Iterator<Item = Vec<u8>> + Send>),
+    Writer(WriteBody),
+}
+
+/// Bytes a handler produces while the response is written, without knowing
+/// how many there will be, sent with `Transfer-Encoding: chunked`.
+///
+/// Each chunk is flushed as soon as it is produced. The body can be written
+/// once only.
+pub struct ChunkedBody {
+    producer: Mutex<Option<Producer>>,
+    /// The trailer fields announced up front, with their values to be taken
+    /// once the last chunk went out.
+    trailers: Vec<(String, Mutex<Option<TrailerValue>>)>,
+    /// Whether to frame the chunks, rather than end the body by closing the
+    /// connection for clients which do not know chunks.
+    framed: bool,
+}
+
+impl ChunkedBody {
+    /// Send every item of `chunks` as one chunk, skipping empty ones.
+    pub fn new<I>(chunks: I) -> ChunkedBody
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        ChunkedBody::from_producer(Producer::Chunks(Box::new(chunks.into_iter())))
+    }
+
+    /// Let `write` write the body, sending what every write call gets as one
+    /// chunk; an error from it cuts the response off.
+    pub fn writer<F>(write: F) -> ChunkedBody
+    where
+        F: FnOnce(&mut dyn Write) -> io::Result<()> + Send + 'static,
+    {
+        ChunkedBody::from_producer(Producer::Writer(Box::new(write)))
+    }
+
+    fn from_producer(producer: Producer) -> ChunkedBody {
+        ChunkedBody {
+            producer: Mutex::new(Some(producer)),
+            trailers: Vec::new(),
+            framed: true,
+        }
+    }
+
+    /// Announce a `name` field in the `Trailer` header, and send it after the
+    /// last chunk with the value `value` returns once every chunk went out.
+    ///
+    /// Fields which frame or control the message cannot be trailers.
+    pub fn trailer<F>(mut self, name: &str, value: F) -> Result<ChunkedBody, DisallowedTrailer>
+    where
+        F: FnOnce() -> String + Send + 'static,
+    {
+        let is_token = http::is_field_name(name);
+        let disallowed = DISALLOWED_TRAILERS
+            .iter()
+            .any(|disallowed| disallowed.eq_ignore_ascii_case(name));
+        if !is_token || disallowed {
+            return Err(DisallowedTrailer(name.to_string()));
+        }
+        self.trailers
+            .push((name.to_string(), Mutex::new(Some(Box::new(value)))));
+        Ok(self)
+    }
+
+    /// The value of the `Trailer` header, if the body has trailer fields
+    /// and sends them.
+    fn trailer_header(&self) -> Option<String> {
+        let names: Vec<&str> = self
+            .trailers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        (self.framed && !names.is_empty()).then(|| names.join(", "))
+    }
+
+    /// Pass the bytes through `encode` on their way to the chunks, keeping
+    /// the trailer fields; `encode` is given the chunks and what writes the
+    /// bytes.
+    pub(crate) fn through<F>(self, encode: F) -> ChunkedBody
+    where
+        F: FnOnce(&mut dyn Write, WriteBody) -> io::Result<()> + Send + 'static,
+    {
+        let producer = self
+            .producer
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        let write: WriteBody = Box::new(move |writer| match producer {
+            Some(Producer::Chunks(iter)) => {
+                for chunk in iter {
+                    writer.write_all(&chunk)?;
+                }
+                Ok(())
+            }
+            Some(Producer::Writer(write)) => write(writer),
+            None => Err(io::Error::other("the chunked body was written already")),
+        });
+        ChunkedBody {
+            producer: Mutex::new(Some(Producer::Writer(Box::new(move |chunks| {
+                encode(chunks, write)
+            })))),
+            ..self
+        }
+    }
+
+    /// Send the bytes as they are and close the connection after them, for
+    /// HTTP/1.0 clients.
+    pub fn unframed(&mut self) {
+        self.framed = false;
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let producer = self
+            .producer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+            .ok_or_else(|| io::Error::other("the chunked body was written already"))?;
+        let mut chunks = Chunks {
+            inner: writer,
+            framed: self.framed,
+        };
+        match producer {
+            Producer::Chunks(iter) => {
+                for chunk in iter {
+                    chunks.write_all(&chunk)?;
+                }
+            }
+            Producer::Writer(write) => write(&mut chunks)?,
+        }
+        if !self.framed {
+            return Ok(());
+        }
+        let writer = chunks.inner;
+        writer.write_all(b"0\r\n")?;
+        for (name, value) in &self.trailers {
+            let value = value.lock().unwrap_or_else(PoisonError::into_inner).take();
+            if let Some(value) = value {
+                let value = value().replace(['\r', '\n'], " ");
+                write!(writer, "{name}: {value}\r\n")?;
+            }
+        }
+        writer.write_all(b"\r\n")
+    }
+}
+
+impl fmt::Debug for ChunkedBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkedBody")
+            .field(
+                "trailers",
+                &self
+                    .trailers
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("framed", &self.framed)
+            .finish()
+    }
+}
+
+/// Frames every write as a chunk and flushes it.
+struct Chunks<'a, W> {
+    inner: &'a mut W,
+    framed: bool,
+}
+
+impl<W: Write> Write for Chunks<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.framed {
+            write!(self.inner, "{:x}\r\n", buf.len())?;
+            self.inner.write_all(buf)?;
+            self.inner.write_all(b"\r\n")?;
+        } else {
+            self.inner.write_all(buf)?;
+        }
+        self.inner.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A response with its status, headers and body.
+#[derive(Debug)]
+pub struct Response {
+    status: u16,
+    headers: Headers,
+    body: Body,
+    /// Whether the body is the page of [`Response::builtin_error`].
+    builtin: bool,
+}
+
+impl Response {
+    /// Create an empty response with the given status code.
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+            builtin: false,
+        }
+    }
+
+    /// A minimal HTML page for `status`, for when the configured page is
+    /// missing or could not be read.
+    pub fn builtin_error(status: u16) -> Response {
+        let title = format!("{status} {}", reason_phrase(status));
+        let page = format!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n  <head>\n    <title>{title}</title>\n  </head>\n  <body>\n    <h1>{title}</h1>\n  </body>\n</html>\n"
+        );
+        let mut response = Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(Body::Bytes(page.into_bytes()));
+        response.builtin = true;
+        response
+    }
+
+    /// Whether the body is still the page [`Response::builtin_error`] made,
+    /// which configured error pages replace.
+    pub fn is_builtin_error(&self) -> bool {
+        self.builtin
+    }
+
+    /// Add a header field.
+    pub fn with_header(mut self, name: &str, value: impl Into<String>) -> Response {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Replace the body.
+    pub fn with_body(mut self, body: Body) -> Response {
+        self.body = body;
+        self.builtin = false;
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    /// The `Content-Length` announced for this response, if the status allows
+    /// one and the length is known.
+    pub fn content_length(&self) -> Option<u64> {
+        match self.status {
+            100..=199 | 204 | 304 => None,
+            _ if !self.body.has_known_length() => None,
+            _ => Some(self.body.len()),
+        }
+    }
+
+    /// Whether the body only ends when the connection closes, for lack of a length.
+    pub fn is_close_delimited(&self) -> bool {
+        !matches!(self.status, 100..=199 | 204 | 304)
+            && !self.body.has_known_length()
+            && !self.body.is_chunked()
+    }
+
+    /// Write the status line, headers and body to `writer`.
+    ///
+    /// The head and small bodies are buffered, so they reach `writer` in as
+    /// few writes as possible, without building the response in memory first.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_buffered(writer, &mut Vec::with_capacity(8 * 1024))
+    }
+
+    /// Write the response like [`write_to`](Response::write_to), buffering in
+    /// `buffer` up to its capacity so that the buffer can be reused.
+    pub fn write_buffered<W: Write>(&self, writer: &mut W, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.clear();
+        let mut writer = Buffered {
+            inner: writer,
+            buffer,
+        };
+        self.write_head(&mut writer)?;
+        self.body.write_to(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Write only the head like [`write_buffered`](Response::write_buffered),
+    /// to answer `HEAD`: the body's length is announced but it is not sent.
+    pub fn write_head_buffered<W: Write>(
+        &self,
+        writer: &mut W,
+        buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        buffer.clear();
+        let mut writer = Buffered {
+            inner: writer,
+            buffer,
+        };
+        self.write_head(&mut writer)?;
+        writer.flush()
+    }
+
+    /// The size of the head as written, up to and including the blank line.
+    pub fn head_len(&self) -> u64 {
+        let mut counted = Counted::new(io::sink());
+        // Writing to a sink cannot fail.
+        let _ = self.write_head(&mut counted);
+        counted.written()
+    }
+
+    /// Write the status line and headers up to and including the blank line.
+    fn write_head<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\n",
+            self.status,
+            reason_phrase(self.status)
+        )?;
+        if let Some(length) = self.content_length() {
+            write!(writer, "Content-Length: {length}\r\n")?;
+        } else if self.body.is_chunked() && !matches!(self.status, 100..=199 | 204 | 304) {
+            writer.write_all(b"Transfer-Encoding: chunked\r\n")?;
+            let trailers = match &self.body {
+                Body::Chunked(chunked) => chunked.trailer_header(),
+                _ => None,
+            };
+            if let Some(names) = trailers {
+                write!(writer, "Trailer: {names}\r\n")?;
+            }
+        }
+        for (name, value) in self.headers.iter() {
+            write!(writer, "{name}: {value}\r\n")?;
+        }
+        writer.write_all(b"\r\n")
+    }
+}
+
+/// Collects writes in a borrowed buffer, writing it out whenever it is full.
+///
+/// Unlike `BufWriter` it never writes on drop, so nothing is retried after an error.
+struct Buffered<'a, W> {
+    inner: &'a mut W,
+    buffer: &'a mut Vec<u8>,
+}
+
+impl<W: Write> Buffered<'_, W> {
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.inner.write_all(self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Buffered<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() + buf.len() > self.buffer.capacity() {
+            self.write_buffer()?;
+        }
+        if buf.len() >= self.buffer.capacity() {
+            self.inner.write_all(buf)?;
+        } else {
+            self.buffer.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.inner.flush()
+    }
+}
+
+/// Formats the response head, status line and headers up to and including the blank line.
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut head = Vec::new();
+        self.write_head(&mut head).map_err(|_| fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&head))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{self, BodyReader};
+
+    #[test]
+    fn test_write_bytes_response() -> Result<(), Box<dyn std::error::Error>> {
+        let response = Response::new(200)
+            .with_header("Accept-Ranges", "bytes")
+            .with_body(Body::Bytes(b"hello".to_vec()));
+
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+
+        assert_eq!(
+            output,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nAccept-Ranges: bytes\r\n\r\nhello"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_static_source_section() -> Result<(), Box<dyn std::error::Error>> {
+        let body = Body::File(FileBody::new(&b"hello world"[..], 6, 5));
+        let mut output = Vec::new();
+        body.write_to(&mut output)?;
+        assert_eq!(output, b"world");
+
+        let short = Body::File(FileBody::new(&b"hello"[..], 3, 5));
+        assert!(short.write_to(&mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stream_body() -> Result<(), Box<dyn std::error::Error>> {
+        let stream = |len| Body::Stream(StreamBody::new(&b"streamed"[..], len));
+        let response = Response::new(200).with_body(stream(Some(6)));
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        assert_eq!(
+            output,
+            b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nstream"
+        );
+        assert!(!response.is_close_delimited());
+
+        let response = Response::new(200).with_body(stream(None));
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        assert_eq!(output, b"HTTP/1.1 200 OK\r\n\r\nstreamed");
+        assert!(response.is_close_delimited());
+
+        assert!(stream(Some(9)).write_to(&mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_chunked_body() -> Result<(), Box<dyn std::error::Error>> {
+        let chunks = || vec![b"id,name\n".to_vec(), Vec::new(), b"1,one\n".repeat(3)];
+        let response = Response::new(200).with_body(Body::Chunked(ChunkedBody::new(chunks())));
+        assert_eq!(response.content_length(), None);
+        assert!(!response.is_close_delimited());
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             8\r\nid,name\n\r\n12\r\n1,one\n1,one\n1,one\n\r\n0\r\n\r\n"
+        );
+        assert!(
+            response.write_to(&mut Vec::new()).is_err(),
+            "the chunks are gone"
+        );
+
+        let body = ChunkedBody::writer(|writer| {
+            writer.write_all(b"a")?;
+            writer.write_all(b"bc")
+        });
+        let mut response = Response::new(200).with_body(Body::Chunked(body));
+        let Body::Chunked(body) = response.body_mut() else {
+            unreachable!("the body is chunked");
+        };
+        body.unframed();
+        assert!(response.is_close_delimited());
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        assert_eq!(String::from_utf8(output)?, "HTTP/1.1 200 OK\r\n\r\nabc");
+        Ok(())
+    }
+
+    #[test]
+    fn test_trailers() -> Result<(), Box<dyn std::error::Error>> {
+        let digest = Arc::new(Mutex::new(0u32));
+        let summed = Arc::clone(&digest);
+        let body = ChunkedBody::writer(move |writer| {
+            for chunk in [&b"Wiki"[..], b"pedia"] {
+                let mut digest = summed.lock().unwrap();
+                *digest = chunk
+                    .iter()
+                    .fold(*digest, |sum, &byte| sum + u32::from(byte));
+                writer.write_all(chunk)?;
+            }
+            Ok(())
+        })
+        .trailer("X-Checksum", move || {
+            format!("{:x}", *digest.lock().unwrap())
+        })?
+        .trailer("X-Status", || "done".to_string())?;
+        let response = Response::new(200).with_body(Body::Chunked(body));
+        let mut output = Vec::new();
+        response.write_to(&mut output)?;
+        assert_eq!(
+            String::from_utf8(output.clone())?,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: X-Checksum, X-Status\r\n\r\n\
+             4\r\nWiki\r\n5\r\npedia\r\n0\r\nX-Checksum: 397\r\nX-Status: done\r\n\r\n"
+        );
+
+        let (_, headers) = http::read_response_head(&mut &output[..], 1024)?.expect("a head");
+        let body_start = output
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap()
+            + 4;
+        let mut body = BodyReader::request(&output[body_start..], &headers);
+        let mut read = String::new();
+        body.read_to_string(&mut read)?;
+        assert_eq!(read, "Wikipedia");
+        assert_eq!(body.trailers().get("x-checksum"), Some("397"));
+        assert_eq!(body.trailers().len(), 2);
+
+        for name in ["Content-Length", "host", "Transfer-Encoding", "X Bad", ""] {
+            assert_eq!(
+                ChunkedBody::new(Vec::new())
+                    .trailer(name, String::new)
+                    .map(drop),
+                Err(DisallowedTrailer(name.to_string()))
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_modified_has_no_length() {
+        let response = Response::new(304);
+        assert_eq!(response.content_length(), None);
+        assert!(!response.to_string().contains("Content-Length"));
+    }
+}
@@ -0,0 +1,409 @@
+//! Responses of handlers kept in memory and answered again while fresh, for
+//! routes which opt in with [`Router::cached`](crate::router::Router::cached).
+//!
+//! Responses are kept by method, host, target and negotiated coding, and only
+//! whole ones: a body of known bytes, no `Set-Cookie`, and no `no-store` or
+//! `private` in `Cache-Control`. When an entry goes stale, the first request
+//! to notice recomputes it while the others keep getting the stale copy; when
+//! there is none yet, they wait for the first. The least recently used
+//! entries are evicted to stay within the entry and byte limits.
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compression::Encoding,
+    http::{Headers, Request},
+    response::{Body, Response},
+};
+
+/// The statuses which are kept, those RFC 9111 lets caches store without
+/// explicit freshness.
+const CACHEABLE: [u16; 7] = [200, 203, 204, 300, 301, 404, 410];
+
+/// The responses kept for cached routes, shared by all workers.
+#[derive(Debug)]
+pub struct ResponseCache {
+    max_entries: usize,
+    max_bytes: u64,
+    state: Mutex<State>,
+    /// Notified whenever an entry stops being computed.
+    computed: Condvar,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    entries: HashMap<String, Entry>,
+    /// The size of the responses kept, in bytes.
+    bytes: u64,
+    /// Counts up with every use, for finding the least recently used entry.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// None until the first response is computed.
+    stored: Option<Stored>,
+    /// Whether a request is computing a new response.
+    computing: bool,
+    used: u64,
+}
+
+/// A response as kept, with the time it goes stale.
+#[derive(Debug)]
+struct Stored {
+    status: u16,
+    headers: Headers,
+    body: Vec<u8>,
+    expires: Instant,
+}
+
+impl Stored {
+    fn size(&self, key: &str) -> u64 {
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum();
+        (key.len() + headers + self.body.len()) as u64
+    }
+
+    fn response(&self) -> Response {
+        let mut response = Response::new(self.status);
+        *response.headers_mut() = self.headers.clone();
+        match self.body.is_empty() {
+            true => response,
+            false => response.with_body(Body::Bytes(self.body.clone())),
+        }
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> ResponseCache {
+        ResponseCache::new()
+    }
+}
+
+impl ResponseCache {
+    /// Keep up to 1024 responses of up to 64 MiB in all.
+    pub fn new() -> ResponseCache {
+        ResponseCache {
+            max_entries: 1024,
+            max_bytes: 64 * 1024 * 1024,
+            state: Mutex::new(State::default()),
+            computed: Condvar::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Keep at most `entries` responses.
+    pub fn max_entries(mut self, entries: usize) -> ResponseCache {
+        self.max_entries = entries;
+        self
+    }
+
+    /// Keep at most `bytes` of responses, counting their bodies, headers and
+    /// keys; larger responses are not kept at all.
+    pub fn max_bytes(mut self, bytes: u64) -> ResponseCache {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// The kept response to `request` with the coding `encoding`, or else the
+    /// one `compute` returns, which is kept for `ttl` if it may be.
+    pub fn fetch(
+        &self,
+        request: &Request,
+        encoding: Option<Encoding>,
+        ttl: Duration,
+        compute: impl FnOnce() -> io::Result<Response>,
+    ) -> io::Result<Response> {
+        let key = format!(
+            "{} {} {} {}",
+            request.method(),
+            request.header("Host").unwrap_or("").to_ascii_lowercase(),
+            request.target(),
+            encoding.map_or("identity", |encoding| encoding.token())
+        );
+        let mut state = self.lock();
+        loop {
+            state.clock += 1;
+            let clock = state.clock;
+            let Some(entry) = state.entries.get_mut(&key) else {
+                let entry = Entry {
+                    stored: None,
+                    computing: true,
+                    used: clock,
+                };
+                state.entries.insert(key.clone(), entry);
+                break;
+            };
+            entry.used = clock;
+            match &entry.stored {
+                Some(stored) if entry.computing || stored.expires > Instant::now() => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(stored.response());
+                }
+                Some(_) => {
+                    entry.computing = true;
+                    break;
+                }
+                // Entries without a response are removed once not computed.
+                None => {
+                    state = self
+                        .computed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+            }
+        }
+        drop(state);
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let computing = Computing { cache: self, key };
+        let response = compute()?;
+        if let Some(stored) = keepable(&response, ttl) {
+            computing.store(stored);
+        }
+        Ok(response)
+    }
+
+    /// The requests answered with a kept response, stale ones included.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// The requests which computed their response.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A copy of `response` to keep for `ttl`, if it may be kept.
+fn keepable(response: &Response, ttl: Duration) -> Option<Stored> {
+    let headers = response.headers();
+    let uncacheable = headers.contains("Set-Cookie")
+        || headers.has_token("Cache-Control", "no-store")
+        || headers.has_token("Cache-Control", "private");
+    if uncacheable || !CACHEABLE.contains(&response.status()) {
+        return None;
+    }
+    let body = match response.body() {
+        Body::Empty => Vec::new(),
+        Body::Bytes(bytes) => bytes.clone(),
+        _ => return None,
+    };
+    Some(Stored {
+        status: response.status(),
+        headers: headers.clone(),
+        body,
+        expires: Instant::now() + ttl,
+    })
+}
+
+/// A response being computed for `key`, which lets the requests waiting for
+/// it go on once it is stored or given up on.
+struct Computing<'a> {
+    cache: &'a ResponseCache,
+    key: String,
+}
+
+impl Computing<'_> {
+    /// Keep `stored`, evicting the least recently used entries to make room.
+    fn store(self, stored: Stored) {
+        let cache = self.cache;
+        let size = stored.size(&self.key);
+        if size > cache.max_bytes || cache.max_entries == 0 {
+            return;
+        }
+        let mut state = cache.lock();
+        let state = &mut *state;
+        let Some(entry) = state.entries.get_mut(&self.key) else {
+            return;
+        };
+        let previous = entry.stored.replace(stored);
+        entry.computing = false;
+        state.bytes += size;
+        if let Some(previous) = previous {
+            state.bytes -= previous.size(&self.key);
+        }
+        while state.entries.len() > cache.max_entries || state.bytes > cache.max_bytes {
+            let oldest = state
+                .entries
+                .iter()
+                .filter(|(key, entry)| **key != self.key && !entry.computing)
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone());
+            let Some(oldest) = oldest else { break };
+            if let Some(evicted) = state.entries.remove(&oldest).and_then(|entry| entry.stored) {
+                state.bytes -= evicted.size(&oldest);
+            }
+        }
+    }
+}
+
+impl Drop for Computing<'_> {
+    /// Also reached when the response was not kept, or `compute` failed or
+    /// panicked: a stale copy stays in place, an empty entry goes.
+    fn drop(&mut self) {
+        let mut state = self.cache.lock();
+        if let Some(entry) = state.entries.get_mut(&self.key) {
+            entry.computing = false;
+            if entry.stored.is_none() {
+                state.entries.remove(&self.key);
+            }
+        }
+        drop(state);
+        self.cache.computed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+    };
+
+    fn request(target: &str) -> Request {
+        Request::new(Method::Get, target, Version::Http11)
+    }
+
+    fn page(text: &str) -> io::Result<Response> {
+        Ok(Response::new(200).with_body(Body::Bytes(text.as_bytes().to_vec())))
+    }
+
+    fn text(response: &Response) -> String {
+        let mut body = Vec::new();
+        response.body().write_to(&mut body).unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_fresh_until_the_ttl_passed() -> Result<(), Box<dyn std::error::Error>> {
+        let cache = ResponseCache::new();
+        let ttl = Duration::from_millis(200);
+        let fetch = |target, body| cache.fetch(&request(target), None, ttl, || page(body));
+        assert_eq!(text(&fetch("/a?x=1", "first")?), "first");
+        assert_eq!(text(&fetch("/a?x=1", "second")?), "first");
+        assert_eq!(text(&fetch("/a?x=2", "other")?), "other", "by query too");
+        let gzip = cache.fetch(&request("/a?x=1"), Some(Encoding::Gzip), ttl, || page("gz"))?;
+        assert_eq!(text(&gzip), "gz", "and by coding");
+        assert_eq!((cache.hits(), cache.misses()), (1, 3));
+
+        thread::sleep(ttl);
+        assert_eq!(text(&fetch("/a?x=1", "third")?), "third");
+        assert_eq!(text(&fetch("/a?x=1", "fourth")?), "third");
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_keepable_responses() -> Result<(), Box<dyn std::error::Error>> {
+        let cache = ResponseCache::new();
+        let ttl = Duration::from_secs(60);
+        let with_header = |name, value| move || Ok(Response::new(200).with_header(name, value));
+        for (name, value) in [
+            ("Set-Cookie", "session=1"),
+            ("Cache-Control", "no-store"),
+            ("Cache-Control", "max-age=60, private"),
+        ] {
+            cache.fetch(&request("/a"), None, ttl, with_header(name, value))?;
+            let again = cache.fetch(&request("/a"), None, ttl, || page("again"))?;
+            assert_eq!(text(&again), "again", "{name}: {value} is not kept");
+            cache.fetch(&request("/b"), None, ttl, || Ok(Response::new(500)))?;
+        }
+        let computed = cache.fetch(&request("/b"), None, ttl, || page("computed"))?;
+        assert_eq!(text(&computed), "computed", "nor is a 500");
+        Ok(())
+    }
+
+    #[test]
+    fn test_least_recently_used_are_evicted() -> Result<(), Box<dyn std::error::Error>> {
+        let cache = ResponseCache::new().max_entries(2);
+        let ttl = Duration::from_secs(60);
+        let fetch = |target, body| cache.fetch(&request(target), None, ttl, || page(body));
+        fetch("/a", "a")?;
+        fetch("/b", "b")?;
+        fetch("/a", "not again")?;
+        fetch("/c", "c")?;
+        assert_eq!(text(&fetch("/a", "not again")?), "a");
+        assert_eq!(text(&fetch("/b", "b again")?), "b again", "/b was evicted");
+
+        let small = ResponseCache::new().max_bytes(64);
+        small.fetch(&request("/big"), None, ttl, || page(&"x".repeat(64)))?;
+        let big = small.fetch(&request("/big"), None, ttl, || page("small"))?;
+        assert_eq!(text(&big), "small", "too large to keep");
+        Ok(())
+    }
+
+    #[test]
+    fn test_one_request_recomputes() -> Result<(), Box<dyn std::error::Error>> {
+        let cache = Arc::new(ResponseCache::new());
+        let (ttl, long) = (Duration::from_millis(20), Duration::from_secs(60));
+        cache.fetch(&request("/slow"), None, ttl, || page("stale"))?;
+        thread::sleep(ttl);
+
+        let (started, computing) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let recomputing = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                cache.fetch(&request("/slow"), None, long, || {
+                    started.send(()).expect("the test waits");
+                    released.recv().expect("the test releases");
+                    page("fresh")
+                })
+            })
+        };
+        computing.recv()?;
+        for _ in 0..4 {
+            let stale = cache.fetch(&request("/slow"), None, ttl, || page("stampede"))?;
+            assert_eq!(text(&stale), "stale");
+        }
+        release.send(())?;
+        let fresh = recomputing.join().expect("the fetch does not panic")?;
+        assert_eq!(text(&fresh), "fresh");
+        let kept = cache.fetch(&request("/slow"), None, Duration::ZERO, || page("late"))?;
+        assert_eq!(text(&kept), "fresh");
+        assert_eq!((cache.hits(), cache.misses()), (5, 2));
+
+        // Without any copy yet, the others wait for the first.
+        let (started, computing) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let first = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                cache.fetch(&request("/new"), None, long, || {
+                    started.send(()).expect("the test waits");
+                    released.recv().expect("the test releases");
+                    page("first")
+                })
+            })
+        };
+        computing.recv()?;
+        let waiting = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.fetch(&request("/new"), None, long, || page("second")))
+        };
+        release.send(())?;
+        assert_eq!(text(&first.join().expect("no panic")?), "first");
+        assert_eq!(text(&waiting.join().expect("no panic")?), "first");
+        Ok(())
+    }
+}
@@ -0,0 +1,286 @@
+//! Dispatch of requests to handlers registered by method and path.
+
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    http::{Method, Request},
+    metrics::RouteMetrics,
+    response::Response,
+    upgrade::{self, Duplex, Protocol},
+    websocket::{WebSocket, WebSocketRoute},
+};
+
+/// A function answering the requests of a route, shared with the thread it
+/// runs on when it has a deadline.
+pub type Handler = Arc<dyn Fn(&Request) -> io::Result<Response> + Send + Sync>;
+
+/// A function answering the requests of a route as their bodies arrive,
+/// reading them from the connection.
+pub type StreamingHandler =
+    Arc<dyn Fn(&Request, &mut dyn Read) -> io::Result<Response> + Send + Sync>;
+
+/// The protocol a request upgrades to, with the `101` accepting it or the
+/// response refusing it.
+pub type Upgrade<'a> = (&'a dyn Protocol, Result<Response, Response>);
+
+/// Handlers by the method and path of the requests they answer.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// The protocols requests may upgrade to, by their tokens.
+    upgrades: Vec<(String, Box<dyn Protocol>)>,
+}
+
+/// A handler with the method and path it answers, counting its requests.
+pub struct Route {
+    method: Method,
+    path: String,
+    handler: Handler,
+    /// The handler reading the body from the connection, for streaming routes.
+    streaming: Option<StreamingHandler>,
+    metrics: RouteMetrics,
+    /// How long its responses are kept, if they are.
+    ttl: Option<Duration>,
+    /// The named pool its requests are answered on, if not the default one.
+    pool: Option<String>,
+}
+
+impl Route {
+    pub fn method(&self) -> &Method {
+        &self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The handler, which for streaming routes reads the body the request
+    /// was given.
+    pub fn handler(&self) -> &Handler {
+        &self.handler
+    }
+
+    /// The handler reading the body from the connection, if the route streams.
+    pub fn streaming(&self) -> Option<&StreamingHandler> {
+        self.streaming.as_ref()
+    }
+
+    pub fn metrics(&self) -> &RouteMetrics {
+        &self.metrics
+    }
+
+    /// How long the responses to `GET` and `HEAD` requests are kept, if
+    /// they are.
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl
+    }
+
+    /// The name of the pool its requests are answered on, if not the
+    /// server's default one.
+    pub fn pool(&self) -> Option<&str> {
+        self.pool.as_deref()
+    }
+}
+
+impl Router {
+    /// Create a router without any routes.
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            upgrades: Vec::new(),
+        }
+    }
+
+    /// Answer `method` requests for `path` with `handler`.
+    ///
+    /// Routes are tried in the order they were added and the query string is
+    /// ignored when matching.
+    pub fn route<F>(mut self, method: Method, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> io::Result<Response> + Send + Sync + 'static,
+    {
+        self.routes.push(Route {
+            method,
+            path: path.to_string(),
+            handler: Arc::new(handler),
+            streaming: None,
+            metrics: RouteMetrics::new(),
+            ttl: None,
+            pool: None,
+        });
+        self
+    }
+
+    /// Answer `method` requests for `path` with `handler`, which reads the
+    /// body as it arrives instead of once the server read all of it.
+    ///
+    /// The body stops at its end and fails past the size limit; what the
+    /// handler leaves unread is skipped before the next request. Streaming
+    /// handlers have no deadline, as the body can only be read on the
+    /// connection's thread.
+    pub fn streaming<F>(mut self, method: Method, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, &mut dyn Read) -> io::Result<Response> + Send + Sync + 'static,
+    {
+        let streaming: StreamingHandler = Arc::new(handler);
+        let buffered = Arc::clone(&streaming);
+        self.routes.push(Route {
+            method,
+            path: path.to_string(),
+            handler: Arc::new(move |request| buffered(request, &mut request.body_reader()?)),
+            streaming: Some(streaming),
+            metrics: RouteMetrics::new(),
+            ttl: None,
+            pool: None,
+        });
+        self
+    }
+
+    /// Keep the responses of the route added last for `ttl`, so that its
+    /// handler only runs again once they went stale. See
+    /// [`ResponseCache`](crate::response_cache::ResponseCache) for which
+    /// responses are kept.
+    pub fn cached(mut self, ttl: Duration) -> Router {
+        if let Some(route) = self.routes.last_mut() {
+            route.ttl = Some(ttl);
+        }
+        self
+    }
+
+    /// Answer the requests of the route added last on the pool the server
+    /// was given as `name`, so that they wait for its workers instead of
+    /// keeping the other routes waiting. See [`Server::pool`](crate::Server::pool).
+    pub fn on_pool(mut self, name: &str) -> Router {
+        if let Some(route) = self.routes.last_mut() {
+            route.pool = Some(name.to_string());
+        }
+        self
+    }
+
+    /// Answer `GET` requests for `path` with `handler`.
+    pub fn get<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request) -> io::Result<Response> + Send + Sync + 'static,
+    {
+        self.route(Method::Get, path, handler)
+    }
+
+    /// Hand the connections of requests asking to upgrade to `token` to
+    /// `protocol`, once it accepted them. Upgrades are tried before the
+    /// routes, those registered first first.
+    pub fn upgrade(mut self, token: &str, protocol: impl Protocol + 'static) -> Router {
+        self.upgrades.push((token.to_string(), Box::new(protocol)));
+        self
+    }
+
+    /// Upgrade requests for `path` to WebSockets, handing them to `handler`
+    /// once the handshake was answered.
+    pub fn websocket<F>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(&Request, WebSocket<&mut dyn Duplex>) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.upgrade("websocket", WebSocketRoute::new(path, Box::new(handler)))
+    }
+
+    /// The protocol `request` asks to switch to, with the answer accepting
+    /// or refusing the switch; none if no protocol here takes any of its tokens.
+    pub fn find_upgrade(&self, request: &Request) -> Option<Upgrade<'_>> {
+        upgrade::offered(request).find_map(|token| {
+            self.upgrades
+                .iter()
+                .filter(|(registered, _)| registered.eq_ignore_ascii_case(token))
+                .find_map(|(registered, protocol)| {
+                    let answer = protocol.accept(request)?;
+                    let answer = answer.map(|accepted| upgrade::switching(accepted, registered));
+                    Some((protocol.as_ref(), answer))
+                })
+        })
+    }
+
+    /// The handler for `request`, if any route matches it.
+    pub fn find(&self, request: &Request) -> Option<&Handler> {
+        self.find_route(request).map(Route::handler)
+    }
+
+    /// The route matching `request`, if any.
+    pub fn find_route(&self, request: &Request) -> Option<&Route> {
+        self.routes
+            .iter()
+            .find(|route| &route.method == request.method() && route.path == request.path())
+    }
+
+    /// Every route, in the order they are tried.
+    pub fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.routes.iter()
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.routes
+                    .iter()
+                    .map(|route| format!("{} {}", route.method, route.path))
+                    .chain(
+                        self.upgrades
+                            .iter()
+                            .map(|(token, _)| format!("Upgrade {token}")),
+                    ),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{http::Version, spool::RequestBody};
+
+    #[test]
+    fn test_find_by_method_and_path() -> Result<(), Box<dyn std::error::Error>> {
+        let router =
+            Router::new()
+                .get("/a", |_| Ok(Response::new(200)))
+                .route(Method::Post, "/a", |_| Ok(Response::new(204)));
+
+        let get = Request::new(Method::Get, "/a?x=1", Version::Http11);
+        let handler = router.find(&get).expect("GET /a is routed");
+        assert_eq!(handler(&get)?.status(), 200);
+
+        let post = Request::new(Method::Post, "/a", Version::Http11);
+        assert_eq!(
+            router
+                .find(&post)
+                .map(|h| h(&post))
+                .transpose()?
+                .map(|r| r.status()),
+            Some(204)
+        );
+
+        let other = Request::new(Method::Get, "/b", Version::Http11);
+        assert!(router.find(&other).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_streaming_route_reads_a_given_body() -> Result<(), Box<dyn std::error::Error>> {
+        let router = Router::new().streaming(Method::Put, "/len", |_, body| {
+            let len = io::copy(body, &mut io::sink())?;
+            Ok(Response::new(200).with_header("X-Length", len.to_string()))
+        });
+        let request = Request::new(Method::Put, "/len", Version::Http11)
+            .with_body(RequestBody::Memory(Arc::from(&b"abc"[..])));
+        let route = router.find_route(&request).ok_or("PUT /len is routed")?;
+        assert!(route.streaming().is_some());
+        let response = route.handler()(&request)?;
+        assert_eq!(response.headers().get("X-Length"), Some("3"));
+        Ok(())
+    }
+}
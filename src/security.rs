@@ -0,0 +1,181 @@
+//! Headers telling browsers how to protect the site's users.
+
+use std::time::Duration;
+
+use crate::http::Headers;
+
+/// A `Strict-Transport-Security` policy, which tells browsers to reach the
+/// site over HTTPS only, for `max_age` after they last saw it.
+///
+/// It only means something over TLS, so the server never sends it on plain
+/// HTTP responses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    max_age: Duration,
+    include_subdomains: bool,
+    preload: bool,
+}
+
+impl Hsts {
+    /// A policy lasting `max_age`, for this host only.
+    pub fn new(max_age: Duration) -> Hsts {
+        Hsts {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    /// Apply the policy to the subdomains of the host as well.
+    pub fn include_subdomains(mut self, include: bool) -> Hsts {
+        self.include_subdomains = include;
+        self
+    }
+
+    /// Consent to the host being built into browsers' preload lists.
+    pub fn preload(mut self, preload: bool) -> Hsts {
+        self.preload = preload;
+        self
+    }
+
+    /// The value of the `Strict-Transport-Security` header.
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// Headers restricting what browsers do with the responses, added to every
+/// response which lacks them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl SecurityHeaders {
+    /// `X-Content-Type-Options: nosniff`, `X-Frame-Options: DENY` and
+    /// `Referrer-Policy: strict-origin-when-cross-origin`, with no
+    /// `Content-Security-Policy`.
+    pub fn new() -> SecurityHeaders {
+        SecurityHeaders {
+            fields: vec![
+                ("X-Content-Type-Options", "nosniff".to_string()),
+                ("X-Frame-Options", "DENY".to_string()),
+                (
+                    "Referrer-Policy",
+                    "strict-origin-when-cross-origin".to_string(),
+                ),
+            ],
+        }
+    }
+
+    /// Whether content types are sniffed, `nosniff` by default.
+    pub fn content_type_options(self, value: Option<&str>) -> SecurityHeaders {
+        self.set("X-Content-Type-Options", value)
+    }
+
+    /// Who may frame the pages, like `DENY`, the default, or `SAMEORIGIN`.
+    pub fn frame_options(self, value: Option<&str>) -> SecurityHeaders {
+        self.set("X-Frame-Options", value)
+    }
+
+    /// How much of the URL is passed on as the referrer.
+    pub fn referrer_policy(self, value: Option<&str>) -> SecurityHeaders {
+        self.set("Referrer-Policy", value)
+    }
+
+    /// Where the pages may load content from, none by default.
+    pub fn content_security_policy(self, value: Option<&str>) -> SecurityHeaders {
+        self.set("Content-Security-Policy", value)
+    }
+
+    /// Send `name` with `value`, or not at all without one.
+    fn set(mut self, name: &'static str, value: Option<&str>) -> SecurityHeaders {
+        self.fields.retain(|(field, _)| *field != name);
+        if let Some(value) = value {
+            self.fields.push((name, value.to_string()));
+        }
+        self
+    }
+
+    /// Add the headers missing from `headers`.
+    pub fn apply(&self, headers: &mut Headers) {
+        for (name, value) in &self.fields {
+            if !headers.contains(name) {
+                headers.insert(name, value.as_str());
+            }
+        }
+    }
+}
+
+/// The headers isolating pages from other origins, which browsers require
+/// before they allow `SharedArrayBuffer` and so WebAssembly threads: the
+/// page gets a browsing context group of its own, embeds nothing which did
+/// not opt in, and is embedded by nothing from elsewhere.
+///
+/// Isolation only engages when the page and every subresource it loads
+/// carry them.
+pub const CROSS_ORIGIN_ISOLATION: [(&str, &str); 3] = [
+    ("Cross-Origin-Opener-Policy", "same-origin"),
+    ("Cross-Origin-Embedder-Policy", "require-corp"),
+    ("Cross-Origin-Resource-Policy", "same-origin"),
+];
+
+/// Add the [`CROSS_ORIGIN_ISOLATION`] headers missing from `headers`.
+pub fn isolate(headers: &mut Headers) {
+    for (name, value) in CROSS_ORIGIN_ISOLATION {
+        if !headers.contains(name) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> SecurityHeaders {
+        SecurityHeaders::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsts_header_value() {
+        let year = Duration::from_secs(365 * 24 * 60 * 60);
+        assert_eq!(Hsts::new(year).header_value(), "max-age=31536000");
+        assert_eq!(
+            Hsts::new(year)
+                .include_subdomains(true)
+                .preload(true)
+                .header_value(),
+            "max-age=31536000; includeSubDomains; preload"
+        );
+    }
+
+    #[test]
+    fn test_security_headers_keep_the_handlers() {
+        let headers = SecurityHeaders::new()
+            .frame_options(Some("SAMEORIGIN"))
+            .referrer_policy(None)
+            .content_security_policy(Some("default-src 'self'"));
+        let mut set = Headers::new();
+        set.insert("X-Content-Type-Options", "handler");
+        headers.apply(&mut set);
+        let set: Vec<_> = set.iter().collect();
+        assert_eq!(
+            set,
+            [
+                ("X-Content-Type-Options", "handler"),
+                ("X-Frame-Options", "SAMEORIGIN"),
+                ("Content-Security-Policy", "default-src 'self'"),
+            ]
+        );
+    }
+}
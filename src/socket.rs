@@ -0,0 +1,225 @@
+//! Options applied to listening sockets and the connections they accept.
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+/// How listeners are bound and accepted streams configured.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    reuse_address: bool,
+    reuse_port: bool,
+    backlog: i32,
+    nodelay: bool,
+    ipv6_only: bool,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions::new()
+    }
+}
+
+impl SocketOptions {
+    /// Reuse addresses, queue up to 1024 connections, disable Nagle's
+    /// algorithm and accept IPv4 clients on IPv6 listeners.
+    pub fn new() -> SocketOptions {
+        SocketOptions {
+            reuse_address: true,
+            reuse_port: false,
+            backlog: 1024,
+            nodelay: true,
+            ipv6_only: false,
+        }
+    }
+
+    /// Set `SO_REUSEADDR` before binding, so that a restarted server can bind
+    /// while connections of the old one linger. Ignored outside unix, where the
+    /// option lets other processes steal the port.
+    pub fn reuse_address(mut self, reuse: bool) -> SocketOptions {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Set `SO_REUSEPORT` before binding, so that a new server can bind the
+    /// port while the old one still listens, and the kernel spreads
+    /// connections over both until the old one stops. Only on unix; off by
+    /// default, as any process of the same user could then share the port.
+    pub fn reuse_port(mut self, reuse: bool) -> SocketOptions {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Whether listeners are bound with `SO_REUSEPORT`.
+    pub fn reuses_port(&self) -> bool {
+        cfg!(unix) && self.reuse_port
+    }
+
+    /// Queue up to `backlog` connections which have not been accepted yet.
+    pub fn backlog(mut self, backlog: i32) -> SocketOptions {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Set `TCP_NODELAY` on accepted streams, so small responses are sent at once.
+    pub fn nodelay(mut self, nodelay: bool) -> SocketOptions {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Set `IPV6_V6ONLY` on IPv6 listeners, so that one bound to `::` only
+    /// accepts IPv6 clients and IPv4 can be bound separately on the same port.
+    /// Off by default, making `::` dual-stack where the platform supports it.
+    pub fn ipv6_only(mut self, only: bool) -> SocketOptions {
+        self.ipv6_only = only;
+        self
+    }
+
+    /// A listener bound to `addr` with these options.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if cfg!(unix) && self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        #[cfg(unix)]
+        if self.reuse_port {
+            socket.set_reuse_port(true)?;
+        }
+        if addr.is_ipv6() {
+            socket.set_only_v6(self.ipv6_only)?;
+        }
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog)?;
+        Ok(socket.into())
+    }
+
+    /// A Unix domain socket listener at `path` whose file gets permissions
+    /// `mode`, replacing a stale socket file nobody listens on.
+    #[cfg(unix)]
+    pub fn bind_unix(
+        &self,
+        path: &std::path::Path,
+        mode: u32,
+    ) -> io::Result<std::os::unix::net::UnixListener> {
+        use std::{
+            fs,
+            os::unix::{
+                fs::{FileTypeExt, PermissionsExt},
+                net::{UnixListener, UnixStream},
+            },
+        };
+
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "another server listens on the socket",
+                ));
+            }
+            fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        Ok(listener)
+    }
+
+    /// Apply the per-connection options to an accepted `stream`.
+    pub fn configure(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rebind_after_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let options = SocketOptions::new();
+        let listener = options.bind("127.0.0.1:0".parse()?)?;
+        let addr = listener.local_addr()?;
+
+        let client = TcpStream::connect(addr)?;
+        let (server, _) = listener.accept()?;
+        options.configure(&server)?;
+        assert!(server.nodelay()?);
+
+        drop(server);
+        drop(client);
+        drop(listener);
+        options.bind(addr)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_nodelay_can_be_disabled() -> Result<(), Box<dyn std::error::Error>> {
+        let options = SocketOptions::new().nodelay(false).backlog(1);
+        let listener = options.bind("127.0.0.1:0".parse()?)?;
+        let _client = TcpStream::connect(listener.local_addr()?)?;
+        let (server, _) = listener.accept()?;
+        options.configure(&server)?;
+        assert!(!server.nodelay()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dual_stack_and_separate_stacks() -> Result<(), Box<dyn std::error::Error>> {
+        let Ok(listener) = SocketOptions::new().bind("[::]:0".parse()?) else {
+            return Ok(()); // No IPv6 here.
+        };
+        let port = listener.local_addr()?.port();
+        let _client = TcpStream::connect(("127.0.0.1", port))?;
+        let (_, peer) = listener.accept()?;
+        assert!(peer.is_ipv6(), "IPv4 clients arrive as mapped addresses");
+        drop(listener);
+
+        let v4 = SocketOptions::new().bind("0.0.0.0:0".parse()?)?;
+        let port = v4.local_addr()?.port();
+        let v6 = SocketOptions::new()
+            .ipv6_only(true)
+            .bind(SocketAddr::from(([0u16; 8], port)))?;
+        assert_eq!(v6.local_addr()?.port(), port);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reuse_port_shares_the_port() -> Result<(), Box<dyn std::error::Error>> {
+        let options = SocketOptions::new().reuse_port(true);
+        let old = options.bind("127.0.0.1:0".parse()?)?;
+        let addr = old.local_addr()?;
+        let new = options.bind(addr)?;
+        assert_eq!(new.local_addr()?, addr);
+        let err = SocketOptions::new()
+            .bind(addr)
+            .expect_err("only with the option");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_bind_unix_replaces_stale_sockets() -> Result<(), Box<dyn std::error::Error>> {
+        use std::os::unix::{fs::PermissionsExt, net::UnixListener};
+
+        let path = std::env::temp_dir().join(format!("hello-bind-{}.sock", std::process::id()));
+        drop(UnixListener::bind(&path)?);
+        assert!(path.exists(), "the stale socket file is left behind");
+
+        let options = SocketOptions::new();
+        let listener = options.bind_unix(&path, 0o600)?;
+        let mode = std::fs::metadata(&path)?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let err = options
+            .bind_unix(&path, 0o600)
+            .expect_err("the socket is in use");
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        drop(listener);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
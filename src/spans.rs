@@ -0,0 +1,82 @@
+//! Spans around connections, requests and pool jobs, with the `tracing`
+//! feature; without it they are empty and cost nothing.
+//!
+//! Records logged through `log` inside a span, which a `tracing` subscriber
+//! converts into events, belong to it as well.
+
+use std::time::Duration;
+
+/// A span entered until dropped.
+#[derive(Debug)]
+#[must_use = "the span is left when dropped"]
+pub(crate) struct Entered {
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl Entered {
+    /// Record how a request span ended, once its response was written.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn finish(&self, status: u16, duration: Duration) {
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("status", status);
+            self.span
+                .record("duration_ms", duration.as_secs_f64() * 1000.0);
+        }
+    }
+}
+
+/// Enter the span of the connection `id` from `peer`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn connection(peer: &str, id: u64) -> Entered {
+    Entered {
+        #[cfg(feature = "tracing")]
+        span: tracing::info_span!("connection", peer, id).entered(),
+    }
+}
+
+/// Enter the span of the request `id`, inside its connection's span; its
+/// status and duration are recorded by [`Entered::finish`].
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn request(id: &str, method: &str, path: &str) -> Entered {
+    Entered {
+        #[cfg(feature = "tracing")]
+        span: tracing::info_span!(
+            "request",
+            id,
+            method,
+            path,
+            status = tracing::field::Empty,
+            duration_ms = tracing::field::Empty,
+        )
+        .entered(),
+    }
+}
+
+/// Enter the span of a job run by `worker` after waiting `queue_wait`.
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn job(worker: usize, queue_wait: Duration) -> Entered {
+    Entered {
+        #[cfg(feature = "tracing")]
+        span: tracing::info_span!(
+            "job",
+            worker,
+            queue_wait_us = u64::try_from(queue_wait.as_micros()).unwrap_or(u64::MAX),
+        )
+        .entered(),
+    }
+}
+
+/// Log a debug message as a `tracing` event in the current spans with the
+/// `tracing` feature, and through `log` without it.
+macro_rules! debug_event {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)+);
+        #[cfg(not(feature = "tracing"))]
+        log::debug!($($arg)+);
+    }};
+}
+
+pub(crate) use debug_event;
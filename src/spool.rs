@@ -0,0 +1,232 @@
+//! Request bodies read before their handler runs, kept in memory while they
+//! are small and spooled to a temporary file once they pass a threshold, so
+//! that a large upload under the size limit does not have to fit in memory.
+//!
+//! A spool file is removed once the last clone of its body is dropped, which
+//! happens when a handler panics as well.
+
+use std::{
+    env, fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use log::warn;
+
+/// Numbers the spool files of this process.
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// Where bodies go while they are read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spool {
+    threshold: u64,
+    dir: PathBuf,
+}
+
+impl Spool {
+    /// Keep bodies of up to 1 MiB in memory, and spool larger ones into the
+    /// system's temporary directory.
+    pub fn new() -> Spool {
+        Spool {
+            threshold: 1024 * 1024,
+            dir: env::temp_dir(),
+        }
+    }
+
+    /// Spool bodies larger than `bytes`.
+    pub fn threshold(mut self, bytes: u64) -> Spool {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Create spool files in `dir`.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> Spool {
+        self.dir = dir.into();
+        self
+    }
+
+    /// Read `body` to its end, none if it is longer than `max_size`, which is
+    /// found out as it is read, before storing more than that.
+    pub fn read(&self, body: impl Read, max_size: u64) -> io::Result<Option<RequestBody>> {
+        let mut body = body.take(max_size.saturating_add(1));
+        let mut buffered = Vec::new();
+        (&mut body)
+            .take(self.threshold.saturating_add(1))
+            .read_to_end(&mut buffered)?;
+        let len = buffered.len() as u64;
+        if len > max_size {
+            return Ok(None);
+        }
+        if len <= self.threshold {
+            return Ok(Some(match buffered.is_empty() {
+                true => RequestBody::Empty,
+                false => RequestBody::Memory(buffered.into()),
+            }));
+        }
+        let (mut spooled, mut file) = SpoolFile::create(&self.dir)?;
+        file.write_all(&buffered)?;
+        let len = len + io::copy(&mut body, &mut file)?;
+        if len > max_size {
+            return Ok(None);
+        }
+        file.flush()?;
+        spooled.len = len;
+        Ok(Some(RequestBody::Spooled(Arc::new(spooled))))
+    }
+}
+
+impl Default for Spool {
+    fn default() -> Spool {
+        Spool::new()
+    }
+}
+
+/// The body of a request, which clones cheaply.
+#[derive(Debug, Clone, Default)]
+pub enum RequestBody {
+    #[default]
+    Empty,
+    Memory(Arc<[u8]>),
+    Spooled(Arc<SpoolFile>),
+}
+
+impl RequestBody {
+    /// The length in bytes.
+    pub fn len(&self) -> u64 {
+        match self {
+            RequestBody::Empty => 0,
+            RequestBody::Memory(bytes) => bytes.len() as u64,
+            RequestBody::Spooled(file) => file.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The bytes, if the body is kept in memory.
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            RequestBody::Empty => Some(&[]),
+            RequestBody::Memory(bytes) => Some(bytes),
+            RequestBody::Spooled(_) => None,
+        }
+    }
+
+    /// The file the body was spooled to, if it was.
+    pub fn spool_path(&self) -> Option<&Path> {
+        match self {
+            RequestBody::Spooled(file) => Some(&file.path),
+            _ => None,
+        }
+    }
+
+    /// Read the body from its start, wherever it is kept.
+    pub fn reader(&self) -> io::Result<BodyContents<'_>> {
+        Ok(match self {
+            RequestBody::Spooled(file) => BodyContents::File(File::open(&file.path)?),
+            body => BodyContents::Memory(body.bytes().unwrap_or_default()),
+        })
+    }
+}
+
+/// A temporary file holding a body, removed when dropped.
+pub struct SpoolFile {
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpoolFile {
+    /// A new, empty spool file in `dir`, and the file to write it with.
+    fn create(dir: &Path) -> io::Result<(SpoolFile, File)> {
+        loop {
+            let number = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!("hello-body-{}-{number}", process::id()));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((SpoolFile { path, len: 0 }, file)),
+                // Left over by a process which had the same id.
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for SpoolFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpoolFile")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for SpoolFile {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            warn!("Failed to remove spool file {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// A reader of a [`RequestBody`].
+#[derive(Debug)]
+pub enum BodyContents<'a> {
+    Memory(&'a [u8]),
+    File(File),
+}
+
+impl Read for BodyContents<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            BodyContents::Memory(bytes) => bytes.read(buf),
+            BodyContents::File(file) => file.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_spools_past_the_threshold() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let spool = Spool::new().threshold(4).dir(dir.path());
+
+        let small = spool.read(&b"abcd"[..], 16)?.ok_or("under the limit")?;
+        assert_eq!(small.bytes(), Some(&b"abcd"[..]));
+        assert!(spool.read(&b""[..], 16)?.ok_or("empty")?.is_empty());
+
+        let large = spool.read(&b"abcdefgh"[..], 16)?.ok_or("under the limit")?;
+        let path = large.spool_path().ok_or("spooled")?.to_path_buf();
+        assert_eq!((large.len(), large.bytes()), (8, None));
+        let mut read = String::new();
+        large.reader()?.read_to_string(&mut read)?;
+        assert_eq!(read, "abcdefgh");
+        let clone = large.clone();
+        drop(large);
+        assert!(path.exists(), "a clone keeps the file");
+        drop(clone);
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_while_reading() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        let spool = Spool::new().threshold(4).dir(dir.path());
+        assert!(spool.read(&b"abc"[..], 2)?.is_none());
+        assert!(spool.read(&b"abcdefgh"[..], 6)?.is_none());
+        assert!(spool.read(&b"abcdefgh"[..], 8)?.is_some());
+        assert_eq!(fs::read_dir(dir.path())?.count(), 0, "nothing is left over");
+        Ok(())
+    }
+}
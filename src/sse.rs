@@ -0,0 +1,193 @@
+//! Server-Sent Events: `text/event-stream` responses which stay open while
+//! a handler, or a job it started, pushes events down them.
+
+use std::{
+    io::{self, Read},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    time::Duration,
+};
+
+use crate::response::{Body, Response, StreamBody};
+
+/// How long a stream may go without events before a comment keeps it open.
+pub const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// One event with its optional type and id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    name: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: String,
+}
+
+impl Event {
+    /// A `message` event carrying `data`, which may span several lines.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            name: None,
+            id: None,
+            retry: None,
+            data: data.into(),
+        }
+    }
+
+    /// Set the type, which clients listen for instead of `message`.
+    pub fn name(mut self, name: impl Into<String>) -> Event {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the id, which reconnecting clients send back as `Last-Event-ID`.
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Ask clients to wait `retry` before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The event as sent, ending with the blank line which dispatches it.
+    ///
+    /// Every line of the data gets a `data:` field of its own; line breaks in
+    /// the type and id, which cannot hold them, are left out.
+    pub fn encode(&self) -> String {
+        let mut encoded = String::new();
+        if let Some(name) = &self.name {
+            encoded.push_str(&format!("event: {}\n", single_line(name)));
+        }
+        if let Some(id) = &self.id {
+            encoded.push_str(&format!("id: {}\n", single_line(id).replace('\0', "")));
+        }
+        if let Some(retry) = self.retry {
+            encoded.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|line| line.split(['\r', '\n']))
+        {
+            encoded.push_str(&format!("data: {line}\n"));
+        }
+        encoded.push('\n');
+        encoded
+    }
+}
+
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+/// Pushes events down a stream, from any thread.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: Sender<Event>,
+}
+
+impl EventSender {
+    /// Send `event`, failing once its client disconnected or the server
+    /// ended the stream, so that the producer can stop.
+    pub fn send(&self, event: Event) -> io::Result<()> {
+        self.sender
+            .send(event)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the event stream ended"))
+    }
+}
+
+/// An event stream response, open until every sender is dropped, the client
+/// disconnects or the server drains, and the sender to push its events with.
+///
+/// A comment goes out whenever no event did for `keep_alive`, which also
+/// notices a client that went away while nothing happened.
+pub fn stream(keep_alive: Duration) -> (EventSender, Response) {
+    let (sender, receiver) = mpsc::channel();
+    let events = Events {
+        receiver,
+        keep_alive,
+        pending: Vec::new(),
+        offset: 0,
+    };
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/event-stream")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(Body::Stream(StreamBody::live(events)));
+    (EventSender { sender }, response)
+}
+
+/// The encoded events of a stream as they arrive.
+struct Events {
+    receiver: Receiver<Event>,
+    keep_alive: Duration,
+    /// The encoded event being read, from `offset` on.
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for Events {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() {
+            self.pending = match self.receiver.recv_timeout(self.keep_alive) {
+                Ok(event) => event.encode().into_bytes(),
+                Err(RecvTimeoutError::Timeout) => b":\n\n".to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.offset = 0;
+        }
+        let len = buf.len().min(self.pending.len() - self.offset);
+        buf[..len].copy_from_slice(&self.pending[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(Event::new("hello").encode(), "data: hello\n\n");
+        assert_eq!(
+            Event::new("one\ntwo\r\nthree\rfour")
+                .name("update")
+                .id("7")
+                .retry(Duration::from_secs(3))
+                .encode(),
+            "event: update\nid: 7\nretry: 3000\ndata: one\ndata: two\ndata: three\ndata: four\n\n"
+        );
+        assert_eq!(Event::new("").encode(), "data: \n\n");
+        assert_eq!(
+            Event::new("x").name("a\nb").id("1\r\n").encode(),
+            "event: ab\nid: 1\ndata: x\n\n"
+        );
+    }
+
+    #[test]
+    fn test_stream() -> Result<(), Box<dyn std::error::Error>> {
+        let (sender, response) = stream(Duration::from_millis(10));
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/event-stream")
+        );
+        assert!(response.is_close_delimited());
+        sender.send(Event::new("first"))?;
+        let producer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            sender.send(Event::new("second"))
+        });
+        let mut output = Vec::new();
+        response.body().write_to(&mut output)?;
+        producer.join().expect("the producer does not panic")?;
+        let output = String::from_utf8(output)?;
+        assert!(output.starts_with("data: first\n\n:\n\n"), "{output}");
+        assert!(output.ends_with("\n\ndata: second\n\n"), "{output}");
+
+        let (sender, response) = stream(KEEP_ALIVE);
+        drop(response);
+        assert!(sender.send(Event::new("lost")).is_err());
+        Ok(())
+    }
+}
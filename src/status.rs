@@ -0,0 +1,236 @@
+//! A page of JSON about what the server is doing, for people to look at.
+
+use std::time::SystemTime;
+
+use crate::{
+    access::{Access, AccessList},
+    http::{Method, Request},
+    httpdate,
+    json::Object,
+    metrics::{PoolSnapshot, RouteSnapshot, Snapshot},
+};
+
+/// Where the status page is answered, and to whom.
+#[derive(Debug, Clone)]
+pub struct StatusPage {
+    path: String,
+    allowed: AccessList,
+}
+
+impl StatusPage {
+    /// Answer `GET` requests for `path`, from clients on the loopback
+    /// addresses only.
+    pub fn new(path: &str) -> StatusPage {
+        let allowed = AccessList::new()
+            .allow("127.0.0.0/8")
+            .and_then(|list| list.allow("::1"))
+            .expect("the loopback ranges are valid");
+        StatusPage {
+            path: path.to_string(),
+            allowed,
+        }
+    }
+
+    /// Answer the clients `allowed` lets connect instead, every client if it
+    /// allows no range in particular.
+    pub fn allow(mut self, allowed: AccessList) -> StatusPage {
+        self.allowed = allowed;
+        self
+    }
+
+    /// Whether `request` asks for the page and may see it. Requests without
+    /// a known client came over a Unix socket, so they are local.
+    pub fn matches(&self, request: &Request) -> bool {
+        request.method() == &Method::Get
+            && request.path() == self.path
+            && request
+                .client_ip()
+                .is_none_or(|ip| matches!(self.allowed.check(ip), Access::Allowed(_)))
+    }
+}
+
+/// The status page at `now` of a server listening on `addrs`.
+pub fn render(snapshot: &Snapshot, addrs: &[String], now: SystemTime) -> String {
+    let requests = by_class(
+        snapshot
+            .requests
+            .iter()
+            .map(|(_, class, count)| (class.as_str(), *count)),
+    );
+    let routes = snapshot.routes.iter().map(route).collect();
+
+    let cache = Object::new()
+        .number("revalidations", snapshot.revalidations)
+        .number("hits", snapshot.revalidations_fresh);
+    let cache = match snapshot.revalidations {
+        0 => cache.null("hit_ratio"),
+        all => cache.number(
+            "hit_ratio",
+            snapshot.revalidations_fresh as f64 / all as f64,
+        ),
+    };
+
+    let status = Object::new().string("version", env!("CARGO_PKG_VERSION"));
+    let status = match snapshot.started {
+        Some(started) => status
+            .string("started", &httpdate::format_rfc3339(started))
+            .number(
+                "uptime_seconds",
+                now.duration_since(started)
+                    .map_or(0, |uptime| uptime.as_secs()),
+            ),
+        None => status.null("started").null("uptime_seconds"),
+    };
+    status
+        .strings("addresses", addrs)
+        .object(
+            "pool",
+            Object::new()
+                .number("workers", snapshot.workers)
+                .number("busy", snapshot.busy_workers)
+                .number("queue_depth", snapshot.queue_depth),
+        )
+        .objects("route_pools", snapshot.pools.iter().map(pool).collect())
+        .object("requests", requests)
+        .objects("routes", routes)
+        .object(
+            "connections",
+            Object::new()
+                .number("active", snapshot.connections_active)
+                .number("accepted", snapshot.connections_accepted),
+        )
+        .number("bytes_sent", snapshot.bytes_sent)
+        .number("bytes_received", snapshot.bytes_received)
+        .object("cache", cache)
+        .finish()
+}
+
+/// The values of a pool routes were put on.
+fn pool(pool: &PoolSnapshot) -> Object {
+    Object::new()
+        .string("name", &pool.name)
+        .number("workers", pool.workers)
+        .number("busy", pool.busy_workers)
+        .number("queue_depth", pool.queue_depth)
+}
+
+/// The `counts` of requests by status class, added up, with their total.
+fn by_class<'a>(counts: impl Iterator<Item = (&'a str, u64)> + Clone) -> Object {
+    let answered = |class: &str| -> u64 {
+        counts
+            .clone()
+            .filter(|(counted, _)| *counted == class)
+            .map(|(_, count)| count)
+            .sum()
+    };
+    ["1xx", "2xx", "3xx", "4xx", "5xx"]
+        .into_iter()
+        .fold(Object::new(), |object, class| {
+            object.number(class, answered(class))
+        })
+        .number("total", counts.clone().map(|(_, count)| count).sum::<u64>())
+}
+
+fn route(route: &RouteSnapshot) -> Object {
+    let requests = by_class(
+        route
+            .requests
+            .iter()
+            .map(|(class, count)| (class.as_str(), *count)),
+    );
+    let mean = match route.duration_count {
+        0 => None,
+        count => Some(route.duration_sum / count as f64),
+    };
+    let duration = Object::new().number("sum", route.duration_sum);
+    let duration = match mean {
+        Some(mean) => duration.number("mean", mean),
+        None => duration.null("mean"),
+    };
+    Object::new()
+        .string("route", &route.route)
+        .optional("host", route.host.as_deref())
+        .object("requests", requests)
+        .object("duration_seconds", duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Version, json};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_render_and_access() {
+        let started = UNIX_EPOCH + Duration::from_secs(784111777);
+        let snapshot = Snapshot {
+            requests: vec![
+                ("GET".to_string(), "2xx".to_string(), 3),
+                ("POST".to_string(), "2xx".to_string(), 1),
+                ("OTHER".to_string(), "4xx".to_string(), 2),
+            ],
+            revalidations: 4,
+            revalidations_fresh: 1,
+            started: Some(started),
+            routes: vec![RouteSnapshot {
+                host: None,
+                route: "GET /a".to_string(),
+                requests: vec![("2xx".to_string(), 3), ("5xx".to_string(), 1)],
+                duration_sum: 2.0,
+                duration_count: 4,
+                ..RouteSnapshot::default()
+            }],
+            ..Snapshot::default()
+        };
+        let page = render(
+            &snapshot,
+            &["127.0.0.1:7878".to_string()],
+            started + Duration::from_secs(90),
+        );
+        let status = json::parse(&page).expect("valid JSON");
+        let get = |path: &[&str]| {
+            path.iter()
+                .try_fold(&status, |value, key| value.get(key))
+                .cloned()
+        };
+        assert_eq!(
+            get(&["started"]),
+            Some(json::Value::String("1994-11-06T08:49:37.000Z".to_string()))
+        );
+        assert_eq!(get(&["uptime_seconds"]), Some(json::Value::Number(90.0)));
+        assert_eq!(get(&["requests", "2xx"]), Some(json::Value::Number(4.0)));
+        assert_eq!(get(&["requests", "total"]), Some(json::Value::Number(6.0)));
+        assert_eq!(
+            get(&["cache", "hit_ratio"]),
+            Some(json::Value::Number(0.25))
+        );
+        let Some(json::Value::Array(routes)) = get(&["routes"]) else {
+            panic!("the routes are a list: {page}");
+        };
+        assert_eq!(
+            routes[0].get("route").and_then(json::Value::as_str),
+            Some("GET /a")
+        );
+        assert_eq!(routes[0].get("host"), Some(&json::Value::Null));
+        let requests = routes[0].get("requests");
+        assert_eq!(
+            requests.and_then(|requests| requests.get("total")),
+            Some(&json::Value::Number(4.0))
+        );
+        let duration = routes[0].get("duration_seconds");
+        assert_eq!(
+            duration.and_then(|duration| duration.get("mean")),
+            Some(&json::Value::Number(0.5))
+        );
+
+        let page = StatusPage::new("/status");
+        let request = |client: &str| {
+            Request::new(Method::Get, "/status", Version::Http11)
+                .with_client_ip(client.parse().ok())
+        };
+        assert!(page.matches(&request("127.0.0.1")));
+        assert!(page.matches(&request("::1")));
+        assert!(!page.matches(&request("192.0.2.1")));
+        assert!(page.matches(&Request::new(Method::Get, "/status", Version::Http11)));
+    }
+}
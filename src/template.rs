@@ -0,0 +1,360 @@
+//! Pages made from templates with `{{name}}` placeholders, for handlers which
+//! need a little more than a static file.
+//!
+//! `{{name}}` is replaced by the HTML-escaped value of `name`, `{{{name}}}` by
+//! the value as it is, and `{{#if name}}...{{/if}}` keeps what it encloses
+//! only if `name` has a value which is not empty. Names are made of letters,
+//! digits, `_`, `-` and `.`.
+
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// What a placeholder without a value is replaced with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Missing {
+    /// Nothing: rendering fails instead.
+    #[default]
+    Error,
+    /// The placeholder itself, as written.
+    Keep,
+    /// The empty string.
+    Blank,
+}
+
+/// All errors which can occur while loading or rendering a template.
+#[derive(Debug)]
+pub enum TemplateError {
+    /// The template file could not be read.
+    Io(PathBuf, io::Error),
+    /// The template is malformed on `line`, counting from 1.
+    Syntax { line: usize, message: String },
+    /// No value was given for the placeholder `name` on `line`.
+    Missing { line: usize, name: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Io(path, err) => write!(f, "could not read {}: {err}", path.display()),
+            TemplateError::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            TemplateError::Missing { line, name } => {
+                write!(f, "line {line}: no value for {{{{{name}}}}}")
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+/// A template and what to do with its placeholders which have no value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    text: String,
+    missing: Missing,
+}
+
+impl Template {
+    /// A template made of `text`, failing to render placeholders without values.
+    pub fn new(text: impl Into<String>) -> Template {
+        Template {
+            text: text.into(),
+            missing: Missing::default(),
+        }
+    }
+
+    /// The template in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Template, TemplateError> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .map(Template::new)
+            .map_err(|err| TemplateError::Io(path.to_path_buf(), err))
+    }
+
+    /// Replace placeholders without values as `missing` says.
+    pub fn missing(mut self, missing: Missing) -> Template {
+        self.missing = missing;
+        self
+    }
+
+    /// The template with its placeholders replaced by `values`, the first
+    /// of which for a name applies.
+    pub fn render(&self, values: &[(&str, &str)]) -> Result<String, TemplateError> {
+        let nodes = parse(&self.text)?;
+        let mut rendered = String::with_capacity(self.text.len());
+        self.render_nodes(&nodes, values, &mut rendered)?;
+        Ok(rendered)
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node<'_>],
+        values: &[(&str, &str)],
+        rendered: &mut String,
+    ) -> Result<(), TemplateError> {
+        let lookup = |name: &str| values.iter().find(|(key, _)| *key == name).map(|(_, v)| *v);
+        for node in nodes {
+            match node {
+                Node::Text(text) => rendered.push_str(text),
+                Node::Value {
+                    name,
+                    raw,
+                    source,
+                    line,
+                } => match (lookup(name), self.missing) {
+                    (Some(value), _) if *raw => rendered.push_str(value),
+                    (Some(value), _) => rendered.push_str(&escape_html(value)),
+                    (None, Missing::Keep) => rendered.push_str(source),
+                    (None, Missing::Blank) => {}
+                    (None, Missing::Error) => {
+                        return Err(TemplateError::Missing {
+                            line: *line,
+                            name: name.to_string(),
+                        })
+                    }
+                },
+                Node::If { name, body } => {
+                    if lookup(name).is_some_and(|value| !value.is_empty()) {
+                        self.render_nodes(body, values, rendered)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `template` with its placeholders replaced by `values`, escaped for HTML
+/// unless written with three braces; placeholders without values fail.
+pub fn render_template(template: &str, values: &[(&str, &str)]) -> Result<String, TemplateError> {
+    Template::new(template).render(values)
+}
+
+/// `text` with the characters which are special in HTML escaped, so that it
+/// shows as written in text and in quoted attribute values.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A piece of a parsed template.
+#[derive(Debug)]
+enum Node<'a> {
+    Text(&'a str),
+    /// A placeholder, with the text it was written as.
+    Value {
+        name: &'a str,
+        raw: bool,
+        source: &'a str,
+        line: usize,
+    },
+    If {
+        name: &'a str,
+        body: Vec<Node<'a>>,
+    },
+}
+
+/// The pieces of `text`, with the `if` blocks holding theirs.
+fn parse(text: &str) -> Result<Vec<Node<'_>>, TemplateError> {
+    // The blocks still open, with the name, line and pieces so far of each.
+    let mut open: Vec<(&str, usize, Vec<Node<'_>>)> = Vec::new();
+    let mut nodes = Vec::new();
+    let mut at = 0;
+    while let Some(start) = text[at..].find("{{").map(|start| at + start) {
+        let line = text[..start].matches('\n').count() + 1;
+        let syntax = |message: &str| TemplateError::Syntax {
+            line,
+            message: message.to_string(),
+        };
+        if start > at {
+            nodes.push(Node::Text(&text[at..start]));
+        }
+        let raw = text[start..].starts_with("{{{");
+        let (open_len, close) = if raw { (3, "}}}") } else { (2, "}}") };
+        let inner_start = start + open_len;
+        let end = text[inner_start..]
+            .find(close)
+            .map(|end| inner_start + end)
+            .ok_or_else(|| syntax("unclosed placeholder"))?;
+        let inner = text[inner_start..end].trim();
+        at = end + close.len();
+        let source = &text[start..at];
+
+        if let Some(name) = inner.strip_prefix("#if ") {
+            let name = name.trim();
+            if raw || !is_name(name) {
+                return Err(syntax(&format!("invalid block {source}")));
+            }
+            open.push((name, line, std::mem::take(&mut nodes)));
+        } else if inner == "/if" && !raw {
+            let (name, _, outer) = open
+                .pop()
+                .ok_or_else(|| syntax("{{/if}} without {{#if}}"))?;
+            let body = std::mem::replace(&mut nodes, outer);
+            nodes.push(Node::If { name, body });
+        } else if is_name(inner) {
+            nodes.push(Node::Value {
+                name: inner,
+                raw,
+                source,
+                line,
+            });
+        } else {
+            return Err(syntax(&format!("invalid placeholder {source}")));
+        }
+    }
+    if let Some((name, line, _)) = open.pop() {
+        return Err(TemplateError::Syntax {
+            line,
+            message: format!("{{{{#if {name}}}}} without {{{{/if}}}}"),
+        });
+    }
+    if at < text.len() {
+        nodes.push(Node::Text(&text[at..]));
+    }
+    Ok(nodes)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escaping() -> Result<(), Box<dyn std::error::Error>> {
+        let attack = r#"<script>alert("x" + 'y')</script> & more"#;
+        let rendered = render_template(
+            r#"<p title="{{v}}">{{v}}</p><p title='{{ v }}'>{{{v}}}</p>"#,
+            &[("v", attack)],
+        )?;
+        let escaped = "&lt;script&gt;alert(&quot;x&quot; + &#39;y&#39;)&lt;/script&gt; &amp; more";
+        assert_eq!(
+            rendered,
+            format!(r#"<p title="{escaped}">{escaped}</p><p title='{escaped}'>{attack}</p>"#)
+        );
+        assert_eq!(
+            render_template("{{v}}", &[("v", "&amp; already")])?,
+            "&amp;amp; already",
+            "escaped again, as the value is text"
+        );
+        assert_eq!(
+            render_template("{{v}}", &[("v", "{{w}}"), ("w", "<")])?,
+            "{{w}}",
+            "values are not templates"
+        );
+        assert_eq!(
+            render_template("{{a}}{{b}}", &[("a", "1"), ("b", "2"), ("a", "3")])?,
+            "12",
+            "the first value applies"
+        );
+        assert_eq!(escape_html("plain text, ünïcode"), "plain text, ünïcode");
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_values() -> Result<(), Box<dyn std::error::Error>> {
+        let template = Template::new("Hi {{name}}!\n{{{raw}}}.");
+        match template.render(&[("name", "you")]) {
+            Err(TemplateError::Missing { line, name }) => {
+                assert_eq!((line, name.as_str()), (2, "raw"))
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(template.render(&[]).is_err());
+        assert_eq!(
+            template
+                .clone()
+                .missing(Missing::Keep)
+                .render(&[("raw", "<b>")])?,
+            "Hi {{name}}!\n<b>."
+        );
+        assert_eq!(template.missing(Missing::Blank).render(&[])?, "Hi !\n.");
+        assert_eq!(
+            render_template("{{#if name}}Hi {{name}}{{/if}}", &[])?,
+            "",
+            "a block asks whether there is a value"
+        );
+        let error = render_template("{{x}}", &[]).unwrap_err();
+        assert_eq!(error.to_string(), "line 1: no value for {{x}}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_if_blocks() -> Result<(), Box<dyn std::error::Error>> {
+        let template = "<h1>Hello{{#if name}}, {{name}}{{#if admin}} (admin){{/if}}{{/if}}!</h1>";
+        assert_eq!(
+            render_template(template, &[("name", "Ann")])?,
+            "<h1>Hello, Ann!</h1>"
+        );
+        assert_eq!(
+            render_template(template, &[("name", "<Ann>"), ("admin", "yes")])?,
+            "<h1>Hello, &lt;Ann&gt; (admin)!</h1>"
+        );
+        assert_eq!(
+            render_template(template, &[("name", "")])?,
+            "<h1>Hello!</h1>"
+        );
+        assert_eq!(
+            render_template(template, &[("admin", "yes")])?,
+            "<h1>Hello!</h1>",
+            "nested blocks go with theirs"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for (template, message) in [
+            ("open {{name", "line 1: unclosed placeholder"),
+            ("\n{{{raw}}", "line 2: unclosed placeholder"),
+            ("{{}}", "line 1: invalid placeholder {{}}"),
+            ("{{a b}}", "line 1: invalid placeholder {{a b}}"),
+            ("{{<script>}}", "line 1: invalid placeholder {{<script>}}"),
+            ("{{{#if a}}}x{{/if}}", "line 1: invalid block {{{#if a}}}"),
+            ("{{#if}}x{{/if}}", "line 1: invalid placeholder {{#if}}"),
+            ("x{{/if}}", "line 1: {{/if}} without {{#if}}"),
+            ("a\n{{#if a}}\nb", "line 2: {{#if a}} without {{/if}}"),
+        ] {
+            match render_template(template, &[("a", "1"), ("raw", "1")]) {
+                Err(err @ TemplateError::Syntax { .. }) => {
+                    assert_eq!(err.to_string(), message, "{template}")
+                }
+                other => panic!("{template}: {other:?}"),
+            }
+        }
+        assert_eq!(
+            render_template("}} and { braces {", &[]).ok().as_deref(),
+            Some("}} and { braces {"),
+            "only placeholders are special"
+        );
+    }
+
+    #[test]
+    fn test_load() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = crate::test_util::TempDir::new();
+        let path = dir.write("page.html", "<p>{{greeting}}</p>");
+        let template = Template::load(&path)?;
+        assert_eq!(template.render(&[("greeting", "hi")])?, "<p>hi</p>");
+        let missing = Template::load(dir.path().join("missing.html"));
+        assert!(matches!(missing, Err(TemplateError::Io(..))));
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Read, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Mutex, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::net::{Connection, ConnectionInfo, Listener, Timeouts};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh directory under the system temp dir, removed again on drop.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("hello-test-{}-{id}", process::id()));
+        fs::create_dir_all(&path).expect("the temp dir should be writable");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `contents` to `name` inside the directory, creating parents as needed.
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).expect("the temp dir should be writable");
+        }
+        fs::write(&path, contents).expect("the temp dir should be writable");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// What a scripted client does when the server reads from it.
+#[derive(Debug)]
+enum Step {
+    Send(Vec<u8>),
+    /// Send nothing until the read timeout expires.
+    Stall,
+    /// Send nothing for this long, or until the read timeout expires first.
+    Pause(Duration),
+    /// Fail the read with this error at once.
+    Fail(io::ErrorKind),
+}
+
+/// An in-memory connection which plays back a client's script and records
+/// what the server writes, standing in for a socket including its timeouts.
+#[derive(Debug)]
+pub struct Scripted {
+    steps: VecDeque<Step>,
+    /// Stall instead of closing once the script is done.
+    hang: bool,
+    /// The bytes writes accept before they stall.
+    write_capacity: usize,
+    /// Fail writes beyond the capacity with this error instead of stalling.
+    write_error: Option<io::ErrorKind>,
+    read_timeout: Mutex<Option<Duration>>,
+    write_timeout: Mutex<Option<Duration>>,
+    output: Arc<Mutex<Vec<u8>>>,
+    failed_writes: Arc<AtomicUsize>,
+    /// Disconnected once the connection is dropped.
+    _open: mpsc::Sender<()>,
+    closed: Option<mpsc::Receiver<()>>,
+}
+
+impl Scripted {
+    /// A client which sends nothing and closes at once.
+    pub fn new() -> Scripted {
+        let (open, closed) = mpsc::channel();
+        Scripted {
+            steps: VecDeque::new(),
+            hang: false,
+            write_capacity: usize::MAX,
+            write_error: None,
+            read_timeout: Mutex::new(None),
+            write_timeout: Mutex::new(None),
+            output: Arc::new(Mutex::new(Vec::new())),
+            failed_writes: Arc::new(AtomicUsize::new(0)),
+            _open: open,
+            closed: Some(closed),
+        }
+    }
+
+    /// Send `bytes` next.
+    pub fn send(mut self, bytes: impl AsRef<[u8]>) -> Scripted {
+        self.steps.push_back(Step::Send(bytes.as_ref().to_vec()));
+        self
+    }
+
+    /// Send nothing for one read timeout.
+    pub fn stall(mut self) -> Scripted {
+        self.steps.push_back(Step::Stall);
+        self
+    }
+
+    /// Send nothing for `delay`, like a client taking its time: reads wait
+    /// that long for what follows, or fail once their timeout expires first.
+    pub fn pause(mut self, delay: Duration) -> Scripted {
+        self.steps.push_back(Step::Pause(delay));
+        self
+    }
+
+    /// Fail the next read with `kind`, like a socket which broke.
+    pub fn fail(mut self, kind: io::ErrorKind) -> Scripted {
+        self.steps.push_back(Step::Fail(kind));
+        self
+    }
+
+    /// Keep the connection open without sending anything once the script is done.
+    pub fn hang(mut self) -> Scripted {
+        self.hang = true;
+        self
+    }
+
+    /// Stop reading the response after `bytes`, so that later writes stall.
+    pub fn write_capacity(mut self, bytes: usize) -> Scripted {
+        self.write_capacity = bytes;
+        self
+    }
+
+    /// Fail writes with `kind` once the write capacity is used up, like a
+    /// socket whose peer went away, instead of stalling them.
+    pub fn fail_writes(mut self, kind: io::ErrorKind) -> Scripted {
+        self.write_error = Some(kind);
+        self
+    }
+
+    /// A handle to what the server writes, which outlives the connection.
+    pub fn output(&mut self) -> Output {
+        Output {
+            written: Arc::clone(&self.output),
+            failed_writes: Arc::clone(&self.failed_writes),
+            closed: self.closed.take().expect("the output is taken once"),
+        }
+    }
+
+    /// Wait for the timeout set with `timeout` like a blocked socket, and fail.
+    fn stalled(timeout: &Mutex<Option<Duration>>) -> io::Error {
+        let timeout = *timeout.lock().unwrap_or_else(PoisonError::into_inner);
+        thread::sleep(timeout.unwrap_or(Duration::from_secs(60)));
+        io::ErrorKind::WouldBlock.into()
+    }
+}
+
+impl Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.steps.front_mut() {
+            Some(Step::Send(bytes)) => {
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                bytes.drain(..len);
+                if bytes.is_empty() {
+                    self.steps.pop_front();
+                }
+                Ok(len)
+            }
+            Some(Step::Stall) => {
+                self.steps.pop_front();
+                Err(Scripted::stalled(&self.read_timeout))
+            }
+            Some(Step::Pause(delay)) => {
+                let timeout = *self
+                    .read_timeout
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                match timeout.filter(|timeout| timeout <= delay) {
+                    Some(timeout) => {
+                        thread::sleep(timeout);
+                        *delay -= timeout;
+                        Err(io::ErrorKind::WouldBlock.into())
+                    }
+                    None => {
+                        thread::sleep(*delay);
+                        self.steps.pop_front();
+                        self.read(buf)
+                    }
+                }
+            }
+            Some(&mut Step::Fail(kind)) => {
+                self.steps.pop_front();
+                Err(kind.into())
+            }
+            None if self.hang => Err(Scripted::stalled(&self.read_timeout)),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.write_capacity == 0 {
+            if let Some(kind) = self.write_error {
+                self.failed_writes.fetch_add(1, Ordering::Relaxed);
+                return Err(kind.into());
+            }
+            return Err(Scripted::stalled(&self.write_timeout));
+        }
+        let len = buf.len().min(self.write_capacity);
+        self.write_capacity -= len;
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+        output.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Timeouts for Scripted {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self
+            .read_timeout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = timeout;
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self
+            .write_timeout
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = timeout;
+        Ok(())
+    }
+}
+
+impl Connection for Scripted {
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What the server wrote to a [`Scripted`] connection.
+#[derive(Debug)]
+pub struct Output {
+    written: Arc<Mutex<Vec<u8>>>,
+    failed_writes: Arc<AtomicUsize>,
+    closed: mpsc::Receiver<()>,
+}
+
+impl Output {
+    /// Everything written so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.written
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The writes which failed because of [`Scripted::fail_writes`].
+    pub fn failed_writes(&self) -> usize {
+        self.failed_writes.load(Ordering::Relaxed)
+    }
+
+    /// Everything written once the server dropped the connection, or an
+    /// error if it is still open after `timeout`.
+    pub fn when_closed(&self, timeout: Duration) -> io::Result<String> {
+        match self.closed.recv_timeout(timeout) {
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                let written = self.written.lock().unwrap_or_else(PoisonError::into_inner);
+                Ok(String::from_utf8_lossy(&written).into_owned())
+            }
+            _ => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
+
+/// A writer whose clones all append to the same bytes.
+#[derive(Debug, Clone, Default)]
+pub struct SharedBuffer {
+    bytes: Arc<Mutex<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    pub fn new() -> SharedBuffer {
+        SharedBuffer::default()
+    }
+
+    /// Everything written so far.
+    pub fn contents(&self) -> String {
+        let bytes = self.bytes.lock().unwrap_or_else(PoisonError::into_inner);
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = self.bytes.lock().unwrap_or_else(PoisonError::into_inner);
+        bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// An in-memory listener handing out the connections queued with
+/// [`connect`](MemoryListener::connect).
+#[derive(Debug)]
+pub struct MemoryListener {
+    sender: Mutex<mpsc::Sender<(Scripted, ConnectionInfo)>>,
+    incoming: Mutex<mpsc::Receiver<(Scripted, ConnectionInfo)>>,
+}
+
+impl MemoryListener {
+    pub fn new() -> MemoryListener {
+        let (sender, incoming) = mpsc::channel();
+        MemoryListener {
+            sender: Mutex::new(sender),
+            incoming: Mutex::new(incoming),
+        }
+    }
+
+    /// Queue `connection` from `peer`, returning what the server writes to it.
+    pub fn connect(&self, mut connection: Scripted, peer: Option<SocketAddr>) -> Output {
+        let output = connection.output();
+        let sender = self.sender.lock().unwrap_or_else(PoisonError::into_inner);
+        sender
+            .send((connection, ConnectionInfo::new(peer, None)))
+            .expect("the listener holds the receiver");
+        output
+    }
+}
+
+impl Listener for MemoryListener {
+    type Conn = Scripted;
+
+    fn accept(&self, timeout: Duration) -> io::Result<Option<(Scripted, ConnectionInfo)>> {
+        let incoming = self.incoming.lock().unwrap_or_else(PoisonError::into_inner);
+        match incoming.recv_timeout(timeout) {
+            Ok(accepted) => Ok(Some(accepted)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+}
@@ -0,0 +1,674 @@
+//! A client for tests, sending requests to a server listening on a socket or
+//! answering connections in memory, and parsing the responses it gets back;
+//! and load made of many such clients, to benchmark a server with.
+
+use std::{
+    cell::Cell,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::{
+    http::{self, BodyReader, Headers, Method},
+    net::{ConnectionInfo, Timeouts},
+    server::{self, Server, ServerConfig},
+    upgrade::Duplex,
+};
+
+/// How long the client waits for a response before giving up, so that a
+/// server which never answers fails the test instead of hanging it.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The largest response head the client reads.
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// A request to send, with a `Content-Length` added when it has a body.
+#[derive(Debug, Clone)]
+pub struct TestClient {
+    method: Method,
+    target: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl TestClient {
+    /// A `method` request for `target`, without headers or a body.
+    pub fn new(method: Method, target: &str) -> TestClient {
+        TestClient {
+            method,
+            target: target.to_string(),
+            headers: Headers::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A `GET` request for `target`.
+    pub fn get(target: &str) -> TestClient {
+        TestClient::new(Method::Get, target)
+    }
+
+    /// A `HEAD` request for `target`, whose responses are read without a body.
+    pub fn head(target: &str) -> TestClient {
+        TestClient::new(Method::Head, target)
+    }
+
+    /// A `POST` request for `target`.
+    pub fn post(target: &str) -> TestClient {
+        TestClient::new(Method::Post, target)
+    }
+
+    /// Add a header field, keeping any others of that name.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> TestClient {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Send `body` with the request.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> TestClient {
+        self.body = body.into();
+        self
+    }
+
+    /// Send the request to `target` and read its response.
+    pub fn send_to(self, target: impl Target) -> io::Result<ParsedResponse> {
+        target.exchange(&self)
+    }
+
+    /// The request as sent.
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = format!("{} {} HTTP/1.1\r\n", self.method, self.target);
+        for (name, value) in self.headers.iter() {
+            encoded.push_str(&format!("{name}: {value}\r\n"));
+        }
+        let framed =
+            self.headers.contains("Content-Length") || self.headers.contains("Transfer-Encoding");
+        if !self.body.is_empty() && !framed {
+            encoded.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        encoded.push_str("\r\n");
+        let mut encoded = encoded.into_bytes();
+        encoded.extend_from_slice(&self.body);
+        encoded
+    }
+}
+
+/// Where a request can be sent.
+pub trait Target {
+    /// Send `request` and read its response.
+    fn exchange(self, request: &TestClient) -> io::Result<ParsedResponse>;
+}
+
+/// A connection of its own to the server listening on the address.
+impl Target for SocketAddr {
+    fn exchange(self, request: &TestClient) -> io::Result<ParsedResponse> {
+        Session::connect(self)?.send(request)
+    }
+}
+
+/// A connection of its own to the in-memory server.
+impl Target for &MemoryServer {
+    fn exchange(self, request: &TestClient) -> io::Result<ParsedResponse> {
+        self.connect()?.send(request)
+    }
+}
+
+/// The session's connection, kept alive for the requests after.
+impl Target for &mut Session {
+    fn exchange(self, request: &TestClient) -> io::Result<ParsedResponse> {
+        self.send(request)
+    }
+}
+
+/// One connection, which requests are sent on one after another.
+pub struct Session {
+    reader: BufReader<Box<dyn Duplex + Send>>,
+}
+
+impl Session {
+    /// Connect to the server listening on `addr`.
+    pub fn connect(addr: SocketAddr) -> io::Result<Session> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        Ok(Session::new(Box::new(stream)))
+    }
+
+    fn new(stream: Box<dyn Duplex + Send>) -> Session {
+        Session {
+            reader: BufReader::new(stream),
+        }
+    }
+
+    /// Send `request` and read its response, leaving the connection at the
+    /// next one.
+    pub fn send(&mut self, request: &TestClient) -> io::Result<ParsedResponse> {
+        let stream = self.reader.get_mut();
+        stream.write_all(&request.encode())?;
+        stream.flush()?;
+        read_response(&mut self.reader, request.method == Method::Head)
+    }
+}
+
+/// Answers connections made in memory like a server answers those of its
+/// listeners, each on a thread of its own.
+#[derive(Debug)]
+pub struct MemoryServer {
+    config: Arc<ServerConfig>,
+}
+
+impl MemoryServer {
+    /// Answer with the routes and settings of `server`, which usually comes
+    /// from [`Server::in_memory`]; its listeners go unused.
+    pub fn new(server: Server) -> MemoryServer {
+        MemoryServer {
+            config: Arc::new(server.into_config()),
+        }
+    }
+
+    /// Open a connection to the server, which has no addresses at its ends.
+    pub fn connect(&self) -> io::Result<Session> {
+        self.connect_from(ConnectionInfo::default())
+    }
+
+    /// Open a connection to the server which seems to be between the
+    /// addresses `info` names, for handlers which look at them.
+    pub fn connect_from(&self, info: ConnectionInfo) -> io::Result<Session> {
+        let (client, connection) = pipe();
+        let config = Arc::clone(&self.config);
+        thread::Builder::new()
+            .name("memory connection".to_string())
+            .spawn(move || {
+                if let Err(err) = server::serve_in_memory(connection, info, &config) {
+                    debug!("An in-memory connection failed: {err}");
+                }
+            })?;
+        client.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+        Ok(Session::new(Box::new(client)))
+    }
+}
+
+/// Read a response from `reader`, skipping interim `1xx` ones except a `101`,
+/// and its body unless it has none: when `head_only` because it answers a
+/// `HEAD` request, or by its status.
+pub fn read_response<R: BufRead>(reader: &mut R, head_only: bool) -> io::Result<ParsedResponse> {
+    let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+    loop {
+        let (status, headers) = http::read_response_head(reader, MAX_HEAD_SIZE)
+            .map_err(invalid)?
+            .ok_or(io::ErrorKind::UnexpectedEof)?;
+        if (100..200).contains(&status) && status != 101 {
+            continue;
+        }
+        let mut response = ParsedResponse {
+            status,
+            headers,
+            body: Vec::new(),
+            trailers: Headers::new(),
+        };
+        if head_only || matches!(status, 101 | 204 | 304) {
+            return Ok(response);
+        }
+        let mut body = BodyReader::response(&mut *reader, &response.headers);
+        if !body.is_valid() {
+            return Err(invalid(http::ParseError::MalformedHeader));
+        }
+        body.read_to_end(&mut response.body)?;
+        response.trailers = body.trailers().clone();
+        return Ok(response);
+    }
+}
+
+/// A response as the client read it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedResponse {
+    status: u16,
+    headers: Headers,
+    body: Vec<u8>,
+    trailers: Headers,
+}
+
+impl ParsedResponse {
+    /// The status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The header fields, looked up case-insensitively.
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    /// The value of the first header field called `name`.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body, without any chunked transfer coding.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The body as text, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// The trailer fields after a chunked body.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+}
+
+/// Load to put on a server: clients on connections of their own, all at
+/// once, each sending the same request over and over.
+#[derive(Debug, Clone)]
+pub struct Load {
+    connections: usize,
+    requests: usize,
+    keep_alive: bool,
+    request: TestClient,
+}
+
+impl Load {
+    /// `connections` clients sending `requests` requests each for `/`, on
+    /// connections kept alive.
+    pub fn new(connections: usize, requests: usize) -> Load {
+        Load {
+            connections,
+            requests,
+            keep_alive: true,
+            request: TestClient::get("/"),
+        }
+    }
+
+    /// Send every request on the same connection, or each on a new one.
+    pub fn keep_alive(mut self, keep_alive: bool) -> Load {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// Send `request` instead of a `GET /`.
+    pub fn request(mut self, request: TestClient) -> Load {
+        self.request = request;
+        self
+    }
+
+    /// Put the load on the server listening on `addr`, returning once every
+    /// client is done.
+    pub fn run(&self, addr: SocketAddr) -> LoadReport {
+        let started = Instant::now();
+        let clients: Vec<_> = (0..self.connections)
+            .map(|_| {
+                let load = self.clone();
+                thread::spawn(move || load.client(addr))
+            })
+            .collect();
+        let mut report = LoadReport::default();
+        for client in clients {
+            match client.join() {
+                Ok(samples) => {
+                    report.latencies.extend(samples.latencies);
+                    report.connects.extend(samples.connects);
+                    report.errors += samples.errors;
+                }
+                Err(_) => report.errors += self.requests,
+            }
+        }
+        report.elapsed = started.elapsed();
+        report.latencies.sort();
+        report.connects.sort();
+        report
+    }
+
+    /// Send the requests of one client, reconnecting after each one unless
+    /// the connection is kept alive, or after it failed.
+    fn client(&self, addr: SocketAddr) -> LoadReport {
+        let mut samples = LoadReport::default();
+        let request = match self.keep_alive {
+            true => self.request.clone(),
+            false => self.request.clone().header("Connection", "close"),
+        };
+        let mut session = None;
+        for _ in 0..self.requests {
+            let connection = match &mut session {
+                Some(session) => session,
+                None => {
+                    let connecting = Instant::now();
+                    match Session::connect(addr) {
+                        Ok(connected) => {
+                            samples.connects.push(connecting.elapsed());
+                            session.insert(connected)
+                        }
+                        Err(_) => {
+                            samples.errors += 1;
+                            continue;
+                        }
+                    }
+                }
+            };
+            let sending = Instant::now();
+            match connection.send(&request) {
+                Ok(response) => {
+                    samples.latencies.push(sending.elapsed());
+                    if response.status() >= 500 {
+                        samples.errors += 1;
+                    }
+                    if !self.keep_alive || response.headers().has_token("Connection", "close") {
+                        session = None;
+                    }
+                }
+                Err(_) => {
+                    samples.errors += 1;
+                    session = None;
+                }
+            }
+        }
+        samples
+    }
+}
+
+/// What a [`Load`] measured, with monotonic clocks; its summary is what it
+/// displays as.
+#[derive(Debug, Clone, Default)]
+pub struct LoadReport {
+    elapsed: Duration,
+    /// The time from sending each answered request to reading all of its
+    /// response, connecting left out.
+    latencies: Vec<Duration>,
+    /// The time each connection took to open.
+    connects: Vec<Duration>,
+    errors: usize,
+}
+
+impl LoadReport {
+    /// The requests which got a response.
+    pub fn answered(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// The requests which failed, or were answered with a server error.
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// How long the whole load took.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The responses per second.
+    pub fn throughput(&self) -> f64 {
+        self.answered() as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// The latency which `percentile` percent of the requests stayed within.
+    pub fn latency(&self, percentile: f64) -> Duration {
+        nearest_rank(&self.latencies, percentile)
+    }
+
+    /// The time to connect which `percentile` percent of the connections
+    /// stayed within.
+    pub fn connect_time(&self, percentile: f64) -> Duration {
+        nearest_rank(&self.connects, percentile)
+    }
+}
+
+/// The sample at `percentile` of the sorted `samples`, zero without any.
+fn nearest_rank(samples: &[Duration], percentile: f64) -> Duration {
+    let rank = (percentile / 100.0 * samples.len() as f64).ceil() as usize;
+    let index = rank.clamp(1, samples.len().max(1)) - 1;
+    samples.get(index).copied().unwrap_or_default()
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} requests, {} errors in {:.2?}: {:.0} requests/s",
+            self.answered(),
+            self.errors,
+            self.elapsed,
+            self.throughput()
+        )?;
+        writeln!(
+            f,
+            "latency  p50 {:.2?}  p90 {:.2?}  p99 {:.2?}  max {:.2?}",
+            self.latency(50.0),
+            self.latency(90.0),
+            self.latency(99.0),
+            self.latency(100.0)
+        )?;
+        write!(
+            f,
+            "connect  p50 {:.2?}  p99 {:.2?}  max {:.2?}  over {} connections",
+            self.connect_time(50.0),
+            self.connect_time(99.0),
+            self.connect_time(100.0),
+            self.connects.len()
+        )
+    }
+}
+
+/// Two connected ends of an in-memory connection.
+fn pipe() -> (Pipe, Pipe) {
+    let (to_server, from_client) = mpsc::channel();
+    let (to_client, from_server) = mpsc::channel();
+    (
+        Pipe::new(from_server, to_server),
+        Pipe::new(from_client, to_client),
+    )
+}
+
+/// One end of an in-memory connection, which reads what the other end
+/// wrote until it is dropped or shuts down writing.
+struct Pipe {
+    incoming: Receiver<Vec<u8>>,
+    outgoing: Option<Sender<Vec<u8>>>,
+    /// The bytes received and not read yet, from `offset` on.
+    pending: Vec<u8>,
+    offset: usize,
+    read_timeout: Cell<Option<Duration>>,
+}
+
+impl Pipe {
+    fn new(incoming: Receiver<Vec<u8>>, outgoing: Sender<Vec<u8>>) -> Pipe {
+        Pipe {
+            incoming,
+            outgoing: Some(outgoing),
+            pending: Vec::new(),
+            offset: 0,
+            read_timeout: Cell::new(None),
+        }
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.pending.len() {
+            let received = match self.read_timeout.get() {
+                Some(timeout) => self.incoming.recv_timeout(timeout),
+                None => self
+                    .incoming
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            self.pending = match received {
+                Ok(bytes) => bytes,
+                Err(RecvTimeoutError::Timeout) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.offset = 0;
+        }
+        let len = buf.len().min(self.pending.len() - self.offset);
+        buf[..len].copy_from_slice(&self.pending[self.offset..self.offset + len]);
+        self.offset += len;
+        Ok(len)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let outgoing = self.outgoing.as_ref().ok_or(io::ErrorKind::BrokenPipe)?;
+        outgoing
+            .send(buf.to_vec())
+            .map_err(|_| io::ErrorKind::BrokenPipe)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes never block, as the other end takes everything written.
+impl Timeouts for Pipe {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.read_timeout.set(timeout);
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::{
+        http::Request,
+        response::{Body, Response},
+        router::Router,
+    };
+
+    #[test]
+    fn test_read_response() -> Result<(), Box<dyn std::error::Error>> {
+        let mut input = Cursor::new(
+            b"HTTP/1.1 204 No Content\r\nX-One: 1\r\n\r\n\
+              HTTP/1.1 100 Continue\r\n\r\n\
+              HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTrailer: Digest\r\n\r\n\
+              4\r\nWiki\r\n5\r\npedia\r\n0\r\nDigest: 397\r\n\r\n\
+              HTTP/1.1 304 Not Modified\r\nContent-Length: 12\r\nETag: \"v1\"\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n\
+              HTTP/1.1 200 OK\r\n\r\nuntil the end"
+                .to_vec(),
+        );
+        let empty = read_response(&mut input, false)?;
+        assert_eq!((empty.status(), empty.body()), (204, &b""[..]));
+        assert_eq!(empty.header("x-one"), Some("1"));
+
+        let chunked = read_response(&mut input, false)?;
+        assert_eq!(chunked.status(), 200, "the interim response is skipped");
+        assert_eq!(chunked.text(), "Wikipedia");
+        assert_eq!(chunked.trailers().get("digest"), Some("397"));
+
+        let unmodified = read_response(&mut input, false)?;
+        assert_eq!(unmodified.status(), 304);
+        assert!(unmodified.body().is_empty(), "its length is the entity's");
+
+        let head = read_response(&mut input, true)?;
+        assert_eq!((head.status(), head.body()), (200, &b""[..]));
+
+        let close_delimited = read_response(&mut input, false)?;
+        assert_eq!(close_delimited.text(), "until the end");
+        let ended = read_response(&mut input, false).unwrap_err();
+        assert_eq!(ended.kind(), io::ErrorKind::UnexpectedEof);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_report() {
+        let millis = Duration::from_millis;
+        let report = LoadReport {
+            elapsed: Duration::from_secs(2),
+            latencies: (1..=10).map(millis).collect(),
+            connects: vec![millis(3)],
+            errors: 1,
+        };
+        assert_eq!(report.throughput(), 5.0);
+        assert_eq!(report.latency(50.0), millis(5));
+        assert_eq!(report.latency(99.0), millis(10));
+        assert_eq!(report.latency(0.0), millis(1));
+        assert_eq!(report.connect_time(99.0), millis(3));
+        assert_eq!(LoadReport::default().latency(50.0), Duration::ZERO);
+        let summary = report.to_string();
+        assert!(
+            summary.starts_with("10 requests, 1 errors in 2.00s: 5 requests/s\n"),
+            "{summary}"
+        );
+    }
+
+    #[test]
+    fn test_sessions_keep_connections_alive() -> Result<(), Box<dyn std::error::Error>> {
+        let echo = |request: &Request| {
+            let word = request.headers().get("X-Word").unwrap_or("");
+            Ok(Response::new(200).with_body(Body::Bytes(word.as_bytes().to_vec())))
+        };
+        let server = MemoryServer::new(
+            Server::in_memory().router(
+                Router::new()
+                    .route(Method::Post, "/echo", echo)
+                    .route(Method::Head, "/echo", echo)
+                    .get("/gone", |_| {
+                        Ok(Response::new(200).with_header("Connection", "close"))
+                    }),
+            ),
+        );
+        let mut session = server.connect()?;
+        let echoed = TestClient::post("/echo")
+            .header("X-Word", "one")
+            .body("a body the server skips")
+            .send_to(&mut session)?;
+        assert_eq!((echoed.status(), echoed.text()), (200, "one".to_string()));
+        let echoed = TestClient::post("/echo")
+            .header("X-Word", "two")
+            .send_to(&mut session)?;
+        assert_eq!(echoed.text(), "two", "on the same connection");
+        let head = TestClient::head("/echo")
+            .header("X-Word", "three")
+            .send_to(&mut session)?;
+        assert_eq!(head.header("content-length"), Some("5"));
+        assert!(head.body().is_empty());
+
+        TestClient::get("/gone").send_to(&mut session)?;
+        let closed = TestClient::get("/hello.html").send_to(&mut session);
+        assert!(closed.is_err(), "the server closed the connection");
+
+        let missing = TestClient::get("/missing").send_to(&server)?;
+        assert_eq!(missing.status(), 404);
+        Ok(())
+    }
+
+    #[test]
+    fn test_connections_from_given_addresses() -> Result<(), Box<dyn std::error::Error>> {
+        let server = MemoryServer::new(Server::in_memory().router(Router::new().get(
+            "/whoami",
+            |request| {
+                let local = request.client_ip().is_some_and(|ip| ip.is_loopback());
+                let body = format!("{local} {:?}", request.local_addr());
+                Ok(Response::new(200).with_body(Body::Bytes(body.into_bytes())))
+            },
+        )));
+        let whoami = |info| -> io::Result<String> {
+            let mut session = server.connect_from(info)?;
+            Ok(TestClient::get("/whoami").send_to(&mut session)?.text())
+        };
+        let local = "127.0.0.1:8080".parse().ok();
+        let loopback = ConnectionInfo::new("127.0.0.1:4000".parse().ok(), local);
+        assert_eq!(whoami(loopback)?, "true Some(127.0.0.1:8080)");
+        let remote = ConnectionInfo::new("192.0.2.1:4000".parse().ok(), local);
+        assert_eq!(whoami(remote)?, "false Some(127.0.0.1:8080)");
+        assert_eq!(whoami(ConnectionInfo::default())?, "false None");
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! HTTPS: TLS with rustls around accepted TCP connections, with the `tls`
+//! feature.
+
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+
+use crate::{
+    httpdate,
+    net::{Connection, ConnectionInfo, Listener, Timeouts},
+    socket::SocketOptions,
+};
+
+/// The certificate and key connections are secured with.
+#[derive(Debug, Clone)]
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsAcceptor {
+    /// Secure connections as set up by `config`.
+    pub fn new(config: Arc<ServerConfig>) -> TlsAcceptor {
+        TlsAcceptor { config }
+    }
+
+    /// Present the PEM certificate chain at `cert`, leaf first, proving it
+    /// with the PEM private key at `key`.
+    ///
+    /// Fails naming the file when either cannot be read, when the leaf is
+    /// expired or not yet valid, or when the key does not belong to it.
+    pub fn from_pem_files(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let unreadable =
+            |path: &Path, err| invalid(format!("could not read {}: {err}", path.display()));
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| unreadable(cert, err))?;
+        let Some(leaf) = chain.first() else {
+            return Err(invalid(format!("{} holds no certificates", cert.display())));
+        };
+        check_validity(leaf, SystemTime::now())
+            .map_err(|err| invalid(format!("{}: {err}", cert.display())))?;
+        let private_key = PrivateKeyDer::from_pem_file(key).map_err(|err| unreadable(key, err))?;
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(chain, private_key)
+            .map_err(|err| {
+                invalid(format!(
+                    "{} does not fit {}: {err}",
+                    key.display(),
+                    cert.display()
+                ))
+            })?;
+        Ok(TlsAcceptor::new(Arc::new(config)))
+    }
+}
+
+/// Whether `cert` is valid at `now`, describing when it is not.
+fn check_validity(cert: &CertificateDer, now: SystemTime) -> Result<(), String> {
+    let (_, parsed) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|err| format!("the certificate cannot be parsed: {err}"))?;
+    let validity = parsed.validity();
+    let at = |time: &x509_parser::time::ASN1Time| {
+        let seconds = u64::try_from(time.timestamp()).unwrap_or(0);
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    };
+    let (not_before, not_after) = (at(&validity.not_before), at(&validity.not_after));
+    if now < not_before {
+        return Err(format!(
+            "the certificate is not valid until {}",
+            httpdate::format(not_before)
+        ));
+    }
+    if now > not_after {
+        return Err(format!(
+            "the certificate expired on {}",
+            httpdate::format(not_after)
+        ));
+    }
+    Ok(())
+}
+
+/// A TCP listener whose connections speak TLS.
+#[derive(Debug)]
+pub struct TlsListener {
+    inner: TcpListener,
+    acceptor: TlsAcceptor,
+}
+
+impl TlsListener {
+    /// Secure the connections of `inner`, a non-blocking listener like the
+    /// server's, with `acceptor`.
+    pub fn new(inner: TcpListener, acceptor: TlsAcceptor) -> TlsListener {
+        TlsListener { inner, acceptor }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+/// Only takes the TCP connection; the handshake happens on the worker with
+/// the first read or write, bounded by the stream's timeouts.
+impl Listener for TlsListener {
+    type Conn = TlsStream;
+
+    fn accept(&self, timeout: Duration) -> io::Result<Option<(TlsStream, ConnectionInfo)>> {
+        let Some((sock, info)) = Listener::accept(&self.inner, timeout)? else {
+            return Ok(None);
+        };
+        let conn =
+            ServerConnection::new(Arc::clone(&self.acceptor.config)).map_err(io::Error::other)?;
+        Ok(Some((
+            TlsStream {
+                inner: StreamOwned::new(conn, sock),
+            },
+            info,
+        )))
+    }
+
+    fn stop_listening(&self) -> io::Result<()> {
+        self.inner.stop_listening()
+    }
+}
+
+/// A TLS connection to a client.
+#[derive(Debug)]
+pub struct TlsStream {
+    inner: StreamOwned<ServerConnection, TcpStream>,
+}
+
+impl TlsStream {
+    /// Finish the handshake if it is still going, naming it in the error.
+    fn handshake(&mut self) -> io::Result<()> {
+        while self.inner.conn.is_handshaking() {
+            self.inner
+                .conn
+                .complete_io(&mut self.inner.sock)
+                .map_err(|err| {
+                    io::Error::new(err.kind(), format!("TLS handshake failed: {err}"))
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.handshake()?;
+        self.inner.read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.handshake()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Timeouts for TlsStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.sock.set_read_timeout(timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.inner.sock.set_write_timeout(timeout)
+    }
+}
+
+impl Connection for TlsStream {
+    fn configure(&self, options: &SocketOptions) -> io::Result<()> {
+        self.inner.sock.configure(options)
+    }
+
+    /// Tell the client that nothing follows before closing the TCP side.
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.inner.conn.send_close_notify();
+        self.inner.flush()?;
+        self.inner.sock.shutdown_write()
+    }
+}
+
+/// Clients reading to the end of the stream expect the close notification,
+/// without which the response could have been cut short.
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        if !self.inner.conn.is_handshaking() {
+            self.inner.conn.send_close_notify();
+            let _ = self.inner.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validity_is_checked() -> Result<(), Box<dyn std::error::Error>> {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()])?;
+        params.not_before = rcgen::date_time_ymd(2020, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2021, 1, 1);
+        let key = rcgen::KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        let der = cert.der();
+
+        let valid = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        assert_eq!(check_validity(der, valid), Ok(()));
+        let err = check_validity(der, SystemTime::now()).expect_err("it expired");
+        assert_eq!(
+            err,
+            "the certificate expired on Fri, 01 Jan 2021 00:00:00 GMT"
+        );
+        let early = SystemTime::UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+        assert!(check_validity(der, early).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+//! Switching a connection from HTTP/1.1 to another protocol, which a
+//! request asks for with `Connection: Upgrade` and an `Upgrade` token.
+
+use std::io::{self, Read, Write};
+
+use crate::{
+    http::{Request, Version},
+    response::Response,
+};
+
+/// A connection handed to the protocol it was switched to.
+pub trait Duplex: Read + Write {}
+
+impl<T: Read + Write + ?Sized> Duplex for T {}
+
+/// The connection a protocol took over, once the `101` went out.
+pub struct Upgraded<'a> {
+    /// The stream, starting with whatever the client sent after the request
+    /// head, even if the server already read it.
+    pub stream: &'a mut dyn Duplex,
+    /// The largest message the protocol should take, `limits.max_body_size`.
+    pub max_message_size: u64,
+}
+
+/// A protocol a connection can switch to, registered by its `Upgrade` token.
+pub trait Protocol: Send + Sync {
+    /// The `101` switching `request` over, another response refusing it, or
+    /// none to answer it as if it asked for no upgrade.
+    ///
+    /// The server adds the `Upgrade` and `Connection` headers to a `101`
+    /// which has none.
+    fn accept(&self, _request: &Request) -> Option<Result<Response, Response>> {
+        Some(Ok(Response::new(101)))
+    }
+
+    /// Speak the protocol over `connection` until either side is done.
+    fn run(&self, request: &Request, connection: Upgraded<'_>) -> io::Result<()>;
+}
+
+/// Any function taking over the connection is a protocol accepting every
+/// request for its token.
+impl<F> Protocol for F
+where
+    F: Fn(&Request, Upgraded<'_>) -> io::Result<()> + Send + Sync,
+{
+    fn run(&self, request: &Request, connection: Upgraded<'_>) -> io::Result<()> {
+        self(request, connection)
+    }
+}
+
+/// The tokens `request` asks to upgrade to, in its order of preference;
+/// none unless it is HTTP/1.1 and names `Upgrade` in its `Connection` header.
+///
+/// `h2c` is left out: the server does not speak HTTP/2, so such requests are
+/// answered over HTTP/1.1 as if they had not asked.
+pub fn offered(request: &Request) -> impl Iterator<Item = &str> {
+    let headers = request.headers();
+    let asks = request.version() == Version::Http11 && headers.has_token("Connection", "upgrade");
+    headers
+        .get_all("Upgrade")
+        .filter(move |_| asks)
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|token| !token.is_empty() && !token.eq_ignore_ascii_case("h2c"))
+}
+
+/// `response` switching to `token`, with the headers saying so if it is a
+/// `101` without them.
+pub fn switching(mut response: Response, token: &str) -> Response {
+    if response.status() == 101 && !response.headers().contains("Upgrade") {
+        let headers = response.headers_mut();
+        headers.insert("Upgrade", token);
+        headers.insert("Connection", "Upgrade");
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+
+    #[test]
+    fn test_offered() {
+        let request = |version, connection| {
+            Request::new(Method::Get, "/", version)
+                .with_header("Connection", connection)
+                .with_header("Upgrade", "h2c, echo/1 , websocket")
+                .with_header("Upgrade", "")
+        };
+        assert_eq!(
+            offered(&request(Version::Http11, "keep-alive, Upgrade")).collect::<Vec<_>>(),
+            ["echo/1", "websocket"]
+        );
+        assert_eq!(offered(&request(Version::Http11, "close")).count(), 0);
+        assert_eq!(offered(&request(Version::Http10, "Upgrade")).count(), 0);
+
+        let switched = switching(Response::new(101), "echo/1");
+        assert_eq!(switched.headers().get("Upgrade"), Some("echo/1"));
+        assert_eq!(switched.headers().get("Connection"), Some("Upgrade"));
+        let refused = switching(Response::new(400), "echo/1");
+        assert!(!refused.headers().contains("Upgrade"));
+    }
+}
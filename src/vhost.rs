@@ -0,0 +1,221 @@
+//! Serving several sites from one server, chosen by the host each request names.
+
+use std::{path::PathBuf, sync::Arc};
+
+use crate::{files::StaticFiles, http::Request, redirect, router::Router};
+
+/// A site with its own files and routes, answering the requests for its names.
+#[derive(Debug, Clone)]
+pub struct VirtualHost {
+    names: Vec<String>,
+    static_files: StaticFiles,
+    router: Arc<Router>,
+}
+
+impl VirtualHost {
+    /// Serve the requests for `name` from the current directory, without routes.
+    ///
+    /// A name starting with `*.` matches every subdomain of the rest, but not
+    /// the rest itself. Names are matched regardless of case.
+    pub fn new(name: &str) -> VirtualHost {
+        VirtualHost {
+            names: vec![name.to_ascii_lowercase()],
+            static_files: StaticFiles::new(),
+            router: Arc::new(Router::new()),
+        }
+    }
+
+    /// Serve the requests for `name` as well.
+    pub fn alias(mut self, name: &str) -> VirtualHost {
+        self.names.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Serve static files as `files` says.
+    pub fn static_files(mut self, files: StaticFiles) -> VirtualHost {
+        self.static_files = files;
+        self
+    }
+
+    /// Serve static files from `root`.
+    pub fn document_root(mut self, root: impl Into<PathBuf>) -> VirtualHost {
+        self.static_files = std::mem::take(&mut self.static_files).root(root);
+        self
+    }
+
+    /// Answer requests matching a route of `router` with its handler, before
+    /// looking for static files.
+    pub fn router(mut self, router: Router) -> VirtualHost {
+        self.router = Arc::new(router);
+        self
+    }
+
+    /// The first name of the host, which the logs show.
+    pub fn name(&self) -> &str {
+        &self.names[0]
+    }
+
+    pub fn files(&self) -> &StaticFiles {
+        &self.static_files
+    }
+
+    pub fn routes(&self) -> &Router {
+        &self.router
+    }
+
+    /// Whether one of the exact names is `host`, which must be lowercase.
+    fn is_named(&self, host: &str) -> bool {
+        self.names.iter().any(|name| name == host)
+    }
+
+    /// Whether one of the wildcard names covers `host`, which must be lowercase.
+    fn covers(&self, host: &str) -> bool {
+        self.names.iter().any(|name| {
+            name.strip_prefix('*')
+                .filter(|domain| domain.starts_with('.'))
+                .and_then(|domain| host.strip_suffix(domain))
+                .is_some_and(|subdomain| !subdomain.is_empty())
+        })
+    }
+}
+
+/// What to answer the requests whose host no virtual host serves.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum UnknownHost {
+    /// Serve them as without virtual hosts.
+    #[default]
+    Default,
+    /// `421 Misdirected Request`.
+    Misdirected,
+    /// The not found page.
+    NotFound,
+}
+
+/// Where a request goes, by the host it names.
+#[derive(Debug, Clone, Copy)]
+pub enum Selection<'a> {
+    Host(&'a VirtualHost),
+    /// The files and routes of the server itself.
+    Default,
+    /// A response with this status instead.
+    Reject(u16),
+}
+
+/// The virtual hosts of a server.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualHosts {
+    hosts: Vec<VirtualHost>,
+    unknown: UnknownHost,
+}
+
+impl VirtualHosts {
+    /// No virtual hosts, so that every request is served by default.
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// Serve `host` as well.
+    pub fn host(mut self, host: VirtualHost) -> VirtualHosts {
+        self.hosts.push(host);
+        self
+    }
+
+    /// Answer requests for hosts none of the virtual hosts serve as `unknown` says.
+    pub fn unknown_host(mut self, unknown: UnknownHost) -> VirtualHosts {
+        self.unknown = unknown;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Every virtual host, in the order they were added.
+    pub fn hosts(&self) -> impl Iterator<Item = &VirtualHost> {
+        self.hosts.iter()
+    }
+
+    /// Where `request` goes: exact names are tried before wildcards, each in
+    /// the order the hosts were added.
+    pub fn select(&self, request: &Request) -> Selection<'_> {
+        if self.hosts.is_empty() {
+            return Selection::Default;
+        }
+        let host = host(request);
+        let found = host.as_deref().and_then(|host| {
+            self.hosts
+                .iter()
+                .find(|vhost| vhost.is_named(host))
+                .or_else(|| self.hosts.iter().find(|vhost| vhost.covers(host)))
+        });
+        match (found, self.unknown) {
+            (Some(vhost), _) => Selection::Host(vhost),
+            (None, UnknownHost::Default) => Selection::Default,
+            (None, UnknownHost::Misdirected) => Selection::Reject(421),
+            (None, UnknownHost::NotFound) => Selection::Reject(404),
+        }
+    }
+}
+
+/// The lowercase host `request` is for, without port or trailing dot: the one
+/// of an absolute target, or else the `Host` header.
+pub fn host(request: &Request) -> Option<String> {
+    let target = request.target();
+    let absolute = target
+        .strip_prefix("http://")
+        .or_else(|| target.strip_prefix("https://"));
+    let authority = match absolute {
+        Some(rest) => rest.split(['/', '?']).next().unwrap_or(rest),
+        None => request.header("Host")?,
+    };
+    let host = redirect::host_of(authority)?;
+    Some(host.strip_suffix('.').unwrap_or(host).to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, Version};
+
+    fn for_host(host: &str) -> Request {
+        Request::new(Method::Get, "/", Version::Http11).with_header("Host", host)
+    }
+
+    fn selected(hosts: &VirtualHosts, request: &Request) -> Result<&'static str, u16> {
+        match hosts.select(request) {
+            Selection::Host(vhost) if vhost.name() == "a.test" => Ok("a"),
+            Selection::Host(_) => Ok("b"),
+            Selection::Default => Ok("default"),
+            Selection::Reject(status) => Err(status),
+        }
+    }
+
+    #[test]
+    fn test_hosts_are_matched_by_name_then_wildcard() {
+        let hosts = VirtualHosts::new()
+            .host(VirtualHost::new("a.test").alias("*.b.test"))
+            .host(VirtualHost::new("B.test").alias("www.b.test"));
+        assert_eq!(selected(&hosts, &for_host("A.Test:8080")), Ok("a"));
+        assert_eq!(selected(&hosts, &for_host("a.test.")), Ok("a"));
+        assert_eq!(selected(&hosts, &for_host("b.test")), Ok("b"));
+        assert_eq!(selected(&hosts, &for_host("www.b.test")), Ok("b"));
+        assert_eq!(selected(&hosts, &for_host("cdn.b.test")), Ok("a"));
+        assert_eq!(selected(&hosts, &for_host("c.test")), Ok("default"));
+
+        let absolute = Request::new(Method::Get, "http://b.test/x", Version::Http11)
+            .with_header("Host", "a.test");
+        assert_eq!(selected(&hosts, &absolute), Ok("b"));
+
+        let hosts = hosts.unknown_host(UnknownHost::Misdirected);
+        assert_eq!(selected(&hosts, &for_host("c.test")), Err(421));
+        let headless = Request::new(Method::Get, "/", Version::Http11);
+        assert_eq!(selected(&hosts, &headless), Err(421));
+        let hosts = hosts.unknown_host(UnknownHost::NotFound);
+        assert_eq!(selected(&hosts, &for_host("b.test.evil")), Err(404));
+    }
+}
@@ -0,0 +1,647 @@
+//! The server side of WebSockets (RFC 6455): the handshake upgrading a
+//! request, and the frames sent over the connection afterwards.
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    str,
+};
+
+use crate::{
+    base64,
+    http::{Method, Request, Version},
+    response::Response,
+    sha1,
+    upgrade::{Duplex, Protocol, Upgraded},
+};
+
+/// The GUID appended to the client's key to prove the server understood it.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A function talking to a client over the WebSocket its request opened.
+pub type WebSocketHandler =
+    Box<dyn Fn(&Request, WebSocket<&mut dyn Duplex>) -> io::Result<()> + Send + Sync>;
+
+/// The WebSocket at a path, as the protocol of the `websocket` token:
+/// upgrades for other paths are left to the other routes.
+pub(crate) struct WebSocketRoute {
+    path: String,
+    handler: WebSocketHandler,
+}
+
+impl WebSocketRoute {
+    pub(crate) fn new(path: &str, handler: WebSocketHandler) -> WebSocketRoute {
+        WebSocketRoute {
+            path: path.to_string(),
+            handler,
+        }
+    }
+}
+
+impl Protocol for WebSocketRoute {
+    fn accept(&self, request: &Request) -> Option<Result<Response, Response>> {
+        let path = request.path();
+        (path == self.path).then(|| handshake(request))
+    }
+
+    fn run(&self, request: &Request, connection: Upgraded<'_>) -> io::Result<()> {
+        let socket =
+            WebSocket::new(connection.stream).max_message_size(connection.max_message_size);
+        (self.handler)(request, socket)
+    }
+}
+
+/// The `Sec-WebSocket-Accept` answering `key`.
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::digest(format!("{key}{GUID}").as_bytes()))
+}
+
+/// The `101 Switching Protocols` accepting the upgrade `request` asks for,
+/// or what it gets instead: `400` when it is not a valid handshake, `426`
+/// asking for version 13 when it wants another.
+pub fn handshake(request: &Request) -> Result<Response, Response> {
+    let headers = request.headers();
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .map(str::trim)
+        .filter(|key| base64::decode(key).is_some_and(|nonce| nonce.len() == 16));
+    let version = request.header("Sec-WebSocket-Version").map(str::trim);
+    let (Some(key), Some(version)) = (key, version) else {
+        return Err(Response::builtin_error(400));
+    };
+    if request.method() != &Method::Get
+        || request.version() != Version::Http11
+        || !headers.has_token("Upgrade", "websocket")
+        || !headers.has_token("Connection", "upgrade")
+    {
+        return Err(Response::builtin_error(400));
+    }
+    if version != "13" {
+        return Err(Response::builtin_error(426).with_header("Sec-WebSocket-Version", "13"));
+    }
+    Ok(Response::new(101)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key)))
+}
+
+/// What a frame carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    /// The next fragment of a text or binary message.
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Opcode> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    /// Whether frames with the opcode control the connection rather than
+    /// carry a message.
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// Why frames from a client could not be read.
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// The client broke the protocol.
+    Protocol(&'static str),
+    /// A message was larger than allowed.
+    TooLarge,
+    /// A text message was not UTF-8.
+    InvalidUtf8,
+}
+
+impl FrameError {
+    /// The status code of the close frame telling the client why.
+    pub fn close_code(&self) -> u16 {
+        match self {
+            FrameError::Io(_) | FrameError::Protocol(_) => 1002,
+            FrameError::InvalidUtf8 => 1007,
+            FrameError::TooLarge => 1009,
+        }
+    }
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(err) => write!(f, "{err}"),
+            FrameError::Protocol(err) => f.write_str(err),
+            FrameError::TooLarge => f.write_str("the message is too large"),
+            FrameError::InvalidUtf8 => f.write_str("the text is not UTF-8"),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(err: io::Error) -> FrameError {
+        FrameError::Io(err)
+    }
+}
+
+/// One frame, unmasked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Whether it is the last fragment of its message.
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A frame with all of a message or control payload.
+    pub fn new(opcode: Opcode, payload: Vec<u8>) -> Frame {
+        Frame {
+            fin: true,
+            opcode,
+            payload,
+        }
+    }
+
+    /// Read a frame from a client, which masks them all, if its payload is at
+    /// most `max_payload` bytes.
+    pub fn read(reader: &mut impl Read, max_payload: u64) -> Result<Frame, FrameError> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head)?;
+        if head[0] & 0x70 != 0 {
+            return Err(FrameError::Protocol("reserved bits are set"));
+        }
+        let fin = head[0] & 0x80 != 0;
+        let opcode =
+            Opcode::from_bits(head[0] & 0x0F).ok_or(FrameError::Protocol("unknown opcode"))?;
+        if head[1] & 0x80 == 0 {
+            return Err(FrameError::Protocol("client frames must be masked"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                u64::from(u16::from_be_bytes(len))
+            }
+            127 => {
+                let mut len = [0; 8];
+                reader.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => u64::from(len),
+        };
+        if opcode.is_control() && (!fin || len > 125) {
+            return Err(FrameError::Protocol(
+                "control frames must be short and whole",
+            ));
+        }
+        if len > max_payload {
+            return Err(FrameError::TooLarge);
+        }
+        let mut mask = [0; 4];
+        reader.read_exact(&mut mask)?;
+        let mut payload = vec![0; len as usize];
+        reader.read_exact(&mut payload)?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok(Frame {
+            fin,
+            opcode,
+            payload,
+        })
+    }
+
+    /// The bytes of the frame, masked with `mask` as clients send them, or
+    /// unmasked as servers do without one.
+    pub fn encode(&self, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.payload.len() + 14);
+        bytes.push(u8::from(self.fin) << 7 | self.opcode.bits());
+        let masked = if mask.is_some() { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => bytes.push(masked | len as u8),
+            len @ 126..=0xFFFF => {
+                bytes.push(masked | 126);
+                bytes.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                bytes.push(masked | 127);
+                bytes.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(mask) => {
+                bytes.extend_from_slice(&mask);
+                let masked = self.payload.iter().enumerate();
+                bytes.extend(masked.map(|(i, byte)| byte ^ mask[i % 4]));
+            }
+            None => bytes.extend_from_slice(&self.payload),
+        }
+        bytes
+    }
+}
+
+/// A message, or a control frame, sent over a WebSocket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The end of the conversation, with a status code and reason if given.
+    Close(Option<(u16, String)>),
+}
+
+/// The server's end of a WebSocket.
+#[derive(Debug)]
+pub struct WebSocket<S> {
+    stream: S,
+    max_message_size: u64,
+    /// The fragments of a message received so far, with its opcode.
+    partial: Option<(Opcode, Vec<u8>)>,
+    /// Whether a close frame was sent, after which only one is received.
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// Talk over `stream`, after the handshake, taking messages of up to
+    /// 1 MiB.
+    pub fn new(stream: S) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            max_message_size: 1024 * 1024,
+            partial: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    /// Take messages of up to `bytes` instead, closing the connection with
+    /// 1009 on larger ones.
+    pub fn max_message_size(mut self, bytes: u64) -> WebSocket<S> {
+        self.max_message_size = bytes;
+        self
+    }
+
+    /// The next message from the client, once all its fragments arrived;
+    /// none once the connection was closed.
+    ///
+    /// Pings are answered with pongs and a close with a close before they
+    /// are returned. When the client breaks the protocol, the connection is
+    /// closed with the code saying why and an `InvalidData` error returned.
+    pub fn recv(&mut self) -> io::Result<Option<Message>> {
+        if self.close_received {
+            return Ok(None);
+        }
+        loop {
+            let received = self.partial.as_ref().map_or(0, |(_, data)| data.len());
+            let allowed = self.max_message_size.saturating_sub(received as u64);
+            let frame = match self.next_message(allowed) {
+                Ok(frame) => frame,
+                Err(FrameError::Io(err)) => return Err(err),
+                Err(err) => {
+                    let _ = self.close(err.close_code(), "");
+                    self.close_received = true;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+                }
+            };
+            if let Some(message) = frame {
+                return Ok(Some(message));
+            }
+        }
+    }
+
+    /// Read one frame, returning the message it completes, if any.
+    fn next_message(&mut self, allowed: u64) -> Result<Option<Message>, FrameError> {
+        let control_allowed = allowed.max(125);
+        let frame = Frame::read(&mut self.stream, control_allowed)?;
+        if !frame.opcode.is_control() && frame.payload.len() as u64 > allowed {
+            return Err(FrameError::TooLarge);
+        }
+        let (opcode, data) = match (frame.opcode, self.partial.take()) {
+            (Opcode::Ping, partial) => {
+                self.partial = partial;
+                self.write(Frame::new(Opcode::Pong, frame.payload.clone()))?;
+                return Ok(Some(Message::Ping(frame.payload)));
+            }
+            (Opcode::Pong, partial) => {
+                self.partial = partial;
+                return Ok(Some(Message::Pong(frame.payload)));
+            }
+            (Opcode::Close, _) => return self.closed_by_client(&frame.payload).map(Some),
+            (Opcode::Continuation, None) => {
+                return Err(FrameError::Protocol("no message to continue"))
+            }
+            (Opcode::Continuation, Some((opcode, mut data))) => {
+                data.extend_from_slice(&frame.payload);
+                (opcode, data)
+            }
+            (_, Some(_)) => return Err(FrameError::Protocol("the last message is unfinished")),
+            (opcode, None) => (opcode, frame.payload),
+        };
+        if !frame.fin {
+            self.partial = Some((opcode, data));
+            return Ok(None);
+        }
+        match opcode {
+            Opcode::Text => match String::from_utf8(data) {
+                Ok(text) => Ok(Some(Message::Text(text))),
+                Err(_) => Err(FrameError::InvalidUtf8),
+            },
+            _ => Ok(Some(Message::Binary(data))),
+        }
+    }
+
+    /// Answer the close frame with `payload`, returning what it said.
+    fn closed_by_client(&mut self, payload: &[u8]) -> Result<Message, FrameError> {
+        let status = match payload {
+            [] => None,
+            [_] => return Err(FrameError::Protocol("the close code is cut short")),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                let reason = str::from_utf8(reason).map_err(|_| FrameError::InvalidUtf8)?;
+                Some((code, reason.to_string()))
+            }
+        };
+        self.close_received = true;
+        if !self.close_sent {
+            let code = status.as_ref().map_or(1000, |(code, _)| *code);
+            self.close(code, "")?;
+        }
+        Ok(Message::Close(status))
+    }
+
+    /// Send `message`.
+    pub fn send(&mut self, message: &Message) -> io::Result<()> {
+        if self.close_sent {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "the WebSocket is closed",
+            ));
+        }
+        let frame = match message {
+            Message::Text(text) => Frame::new(Opcode::Text, text.as_bytes().to_vec()),
+            Message::Binary(data) => Frame::new(Opcode::Binary, data.clone()),
+            Message::Ping(data) => Frame::new(Opcode::Ping, data.clone()),
+            Message::Pong(data) => Frame::new(Opcode::Pong, data.clone()),
+            Message::Close(None) => Frame::new(Opcode::Close, Vec::new()),
+            Message::Close(Some((code, reason))) => return self.close(*code, reason),
+        };
+        self.close_sent = frame.opcode == Opcode::Close;
+        self.write(frame)
+    }
+
+    /// Close the connection with `code`, like 1000 for a normal closure,
+    /// and `reason`.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        let mut payload = code.to_be_bytes().to_vec();
+        payload.extend_from_slice(reason.as_bytes());
+        self.close_sent = true;
+        self.write(Frame::new(Opcode::Close, payload))
+    }
+
+    fn write(&mut self, frame: Frame) -> io::Result<()> {
+        self.stream.write_all(&frame.encode(None))?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const MASK: Option<[u8; 4]> = Some([0x37, 0xfa, 0x21, 0x3d]);
+
+    /// A connection reading what a client sent and keeping what the server
+    /// writes apart.
+    #[derive(Debug)]
+    struct Pipe {
+        sent: Cursor<Vec<u8>>,
+        written: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.sent.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A socket reading `sent` from the client.
+    fn raw_socket(sent: Vec<u8>) -> WebSocket<Pipe> {
+        WebSocket::new(Pipe {
+            sent: Cursor::new(sent),
+            written: Vec::new(),
+        })
+    }
+
+    /// A socket reading the `frames` the client sent.
+    fn socket(frames: &[Frame]) -> WebSocket<Pipe> {
+        raw_socket(frames.iter().flat_map(|frame| frame.encode(MASK)).collect())
+    }
+
+    /// The frames the server wrote to `socket`.
+    fn written(socket: WebSocket<Pipe>) -> Vec<u8> {
+        socket.stream.written
+    }
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455, section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let request = |headers: &[(&str, &str)]| {
+            headers.iter().fold(
+                Request::new(Method::Get, "/echo", Version::Http11),
+                |request, (name, value)| request.with_header(name, value),
+            )
+        };
+        let complete = [
+            ("Upgrade", "websocket"),
+            ("Connection", "keep-alive, Upgrade"),
+            ("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ=="),
+            ("Sec-WebSocket-Version", "13"),
+        ];
+        let response = handshake(&request(&complete)).expect("a valid handshake");
+        assert_eq!(response.status(), 101);
+        assert_eq!(
+            response.headers().get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        for missing in 0..complete.len() {
+            let mut headers = complete.to_vec();
+            headers.remove(missing);
+            let refused = handshake(&request(&headers)).expect_err("a header is missing");
+            assert_eq!(refused.status(), 400, "{headers:?}");
+        }
+        let mut headers = complete.to_vec();
+        headers[2] = ("Sec-WebSocket-Key", "c2hvcnQ=");
+        assert_eq!(
+            handshake(&request(&headers)).map_err(|r| r.status()).err(),
+            Some(400)
+        );
+        headers = complete.to_vec();
+        headers[3] = ("Sec-WebSocket-Version", "8");
+        let refused = handshake(&request(&headers)).expect_err("version 8 is not spoken");
+        assert_eq!(refused.status(), 426);
+        assert_eq!(refused.headers().get("Sec-WebSocket-Version"), Some("13"));
+    }
+
+    #[test]
+    fn test_masked_text() -> Result<(), Box<dyn std::error::Error>> {
+        // "Hello" masked, from RFC 6455, section 5.7.
+        let sent = [
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        let mut socket = raw_socket(sent.to_vec());
+        assert_eq!(socket.recv()?, Some(Message::Text("Hello".to_string())));
+        assert_eq!(
+            Frame::new(Opcode::Text, b"Hello".to_vec()).encode(MASK),
+            sent
+        );
+
+        socket.send(&Message::Text("Hello".to_string()))?;
+        assert_eq!(written(socket), [0x81, 0x05, b'H', b'e', b'l', b'l', b'o']);
+
+        let mut unmasked = raw_socket(vec![0x81, 0x01, b'a']);
+        let err = unmasked.recv().expect_err("client frames are masked");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            written(unmasked),
+            [0x88, 0x02, 0x03, 0xea],
+            "closed with 1002"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fragmented_message_around_a_ping() -> Result<(), Box<dyn std::error::Error>> {
+        let mut socket = socket(&[
+            Frame {
+                fin: false,
+                opcode: Opcode::Text,
+                payload: b"Hel".to_vec(),
+            },
+            Frame::new(Opcode::Ping, b"beat".to_vec()),
+            Frame {
+                fin: false,
+                opcode: Opcode::Continuation,
+                payload: b"l".to_vec(),
+            },
+            Frame::new(Opcode::Continuation, "o, wörld".as_bytes().to_vec()),
+            Frame::new(Opcode::Close, vec![0x03, 0xe8]),
+        ]);
+        assert_eq!(socket.recv()?, Some(Message::Ping(b"beat".to_vec())));
+        assert_eq!(
+            socket.recv()?,
+            Some(Message::Text("Hello, wörld".to_string()))
+        );
+        assert_eq!(
+            socket.recv()?,
+            Some(Message::Close(Some((1000, String::new()))))
+        );
+        assert_eq!(socket.recv()?, None);
+        let mut answers = b"\x8a\x04beat".to_vec();
+        answers.extend_from_slice(&[0x88, 0x02, 0x03, 0xe8]);
+        assert_eq!(written(socket), answers, "a pong, then the close echoed");
+        Ok(())
+    }
+
+    #[test]
+    fn test_violations_close_the_connection() {
+        let close_code = |frames: &[Frame], max_message_size: u64| {
+            let mut socket = socket(frames).max_message_size(max_message_size);
+            while let Ok(Some(_)) = socket.recv() {}
+            let written = written(socket);
+            assert_eq!(written[0], 0x88, "a close frame");
+            u16::from_be_bytes([written[2], written[3]])
+        };
+        let fragment = |opcode, payload: &[u8]| Frame {
+            fin: false,
+            opcode,
+            payload: payload.to_vec(),
+        };
+        assert_eq!(
+            close_code(&[Frame::new(Opcode::Binary, vec![0; 11])], 10),
+            1009
+        );
+        assert_eq!(
+            close_code(
+                &[
+                    fragment(Opcode::Binary, &[0; 6]),
+                    Frame::new(Opcode::Continuation, vec![0; 6])
+                ],
+                10
+            ),
+            1009,
+            "the fragments add up"
+        );
+        assert_eq!(
+            close_code(&[Frame::new(Opcode::Text, vec![0xff])], 10),
+            1007
+        );
+        assert_eq!(
+            close_code(&[Frame::new(Opcode::Continuation, vec![1])], 10),
+            1002
+        );
+        assert_eq!(
+            close_code(
+                &[
+                    fragment(Opcode::Text, b"a"),
+                    Frame::new(Opcode::Text, b"b".to_vec())
+                ],
+                10
+            ),
+            1002
+        );
+        assert_eq!(
+            close_code(&[fragment(Opcode::Ping, b"")], 10),
+            1002,
+            "control frames are whole"
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! The documents under `/.well-known/`, like ACME challenge tokens or
+//! `security.txt`, answered ahead of the routes and the document root.
+//!
+//! They come from a directory of their own, which need not be under the
+//! document root, and from documents given inline. Hidden files are refused
+//! beneath the prefix as they are elsewhere, but the prefix itself is not.
+
+use std::{io, path::PathBuf};
+
+use crate::{
+    files::{AssetSource, StaticFiles},
+    http::{Method, Request},
+    response::{Body, Response},
+};
+
+/// The path prefix answered.
+pub const PREFIX: &str = "/.well-known/";
+
+/// What the paths under [`PREFIX`] answer; everything else there is `404`.
+#[derive(Debug, Clone, Default)]
+pub struct WellKnown {
+    files: Option<StaticFiles>,
+    documents: Vec<Document>,
+}
+
+#[derive(Debug, Clone)]
+struct Document {
+    name: String,
+    content_type: String,
+    content: Vec<u8>,
+}
+
+impl WellKnown {
+    /// Answer every path under the prefix with `404`.
+    pub fn new() -> WellKnown {
+        WellKnown::default()
+    }
+
+    /// Serve the files in `dir`, so that `dir/acme-challenge/token` is at
+    /// `/.well-known/acme-challenge/token`.
+    pub fn dir(mut self, dir: impl Into<PathBuf>) -> WellKnown {
+        let files = StaticFiles::new()
+            .root(dir)
+            .source(AssetSource::Disk)
+            .index(&[]);
+        self.files = Some(files);
+        self
+    }
+
+    /// Answer `/.well-known/<name>` with `content`, ahead of the directory.
+    pub fn document(
+        mut self,
+        name: &str,
+        content_type: &str,
+        content: impl Into<Vec<u8>>,
+    ) -> WellKnown {
+        self.documents.push(Document {
+            name: name.trim_start_matches('/').to_string(),
+            content_type: content_type.to_string(),
+            content: content.into(),
+        });
+        self
+    }
+
+    /// Whether `path` is under the prefix, and so answered here.
+    pub fn covers(path: &str) -> bool {
+        path.starts_with(PREFIX) || path == PREFIX.trim_end_matches('/')
+    }
+
+    /// The response to `request`, for a path under the prefix.
+    pub fn respond(&self, request: &Request) -> io::Result<Response> {
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return Ok(Response::builtin_error(405).with_header("Allow", "GET, HEAD"));
+        }
+        let name = request.path().strip_prefix(PREFIX).unwrap_or("");
+        if let Some(document) = self.documents.iter().find(|document| document.name == name) {
+            return Ok(Response::new(200)
+                .with_header("Content-Type", document.content_type.as_str())
+                .with_body(Body::Bytes(document.content.clone())));
+        }
+        let Some(files) = &self.files else {
+            return Ok(Response::builtin_error(404));
+        };
+        let Some(asset) = files.lookup(&format!("/{name}")) else {
+            return Ok(Response::builtin_error(404));
+        };
+        files
+            .serve_asset(request, &asset)
+            .or_else(|err| match err.kind() {
+                io::ErrorKind::NotFound => Ok(Response::builtin_error(404)),
+                io::ErrorKind::PermissionDenied => Ok(Response::builtin_error(403)),
+                _ => Err(err),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{http::Version, test_util::TempDir};
+
+    fn get(path: &str) -> Request {
+        Request::new(Method::Get, path, Version::Http11)
+    }
+
+    #[test]
+    fn test_documents_and_files() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = TempDir::new();
+        dir.write("acme-challenge/token", "token.key");
+        dir.write(".hidden", "hidden");
+        let well_known = WellKnown::new().dir(dir.path()).document(
+            "security.txt",
+            "text/plain",
+            "Contact: mailto:a@b.test\n",
+        );
+
+        let response = well_known.respond(&get("/.well-known/security.txt"))?;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("Content-Type"), Some("text/plain"));
+        let response = well_known.respond(&get("/.well-known/acme-challenge/token"))?;
+        assert_eq!(response.status(), 200);
+        for path in [
+            "/.well-known/.hidden",
+            "/.well-known/../secret",
+            "/.well-known/acme-challenge",
+            "/.well-known/",
+        ] {
+            assert_eq!(well_known.respond(&get(path))?.status(), 404, "{path}");
+        }
+        let post = Request::new(Method::Post, "/.well-known/security.txt", Version::Http11);
+        assert_eq!(well_known.respond(&post)?.status(), 405);
+
+        assert!(WellKnown::covers("/.well-known"));
+        assert!(WellKnown::covers("/.well-known/a"));
+        assert!(!WellKnown::covers("/.well-knownx"));
+        Ok(())
+    }
+}
@@ -0,0 +1,54 @@
+//! Taking over a listening socket passed in like systemd does.
+#![cfg(unix)]
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+use socket2::SockRef;
+
+#[test]
+fn test_serves_an_inherited_listener() -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    SockRef::from(&listener).set_cloexec(false)?;
+
+    // The shell keeps its process id across `exec`, so it can name the server
+    // in LISTEN_PID, and moves the listener to the first passed descriptor.
+    let script = format!(
+        "export LISTEN_PID=$$ LISTEN_FDS=1; exec \"$0\" --quiet --addr 127.0.0.1:1 3<&{}",
+        listener.as_raw_fd()
+    );
+    let mut child = Command::new("sh")
+        .args(["-c", &script, env!("CARGO_BIN_EXE_hello")])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    drop(listener);
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+
+    let served = (|| -> io::Result<String> {
+        let mut client = TcpStream::connect(addr)?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        client.write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut output = String::new();
+        client.read_to_string(&mut output)?;
+        Ok(output)
+    })();
+    let signalled = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    io::copy(&mut stdout, &mut io::sink())?;
+    let status = child.wait()?;
+
+    assert_eq!(listening, format!("Listening on http://{addr}\n"));
+    assert!(served?.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(signalled.success() && status.success());
+    Ok(())
+}
@@ -0,0 +1,82 @@
+//! Shutting the binary down over HTTP with an admin bearer token.
+#![cfg(all(unix, feature = "config"))]
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader},
+    net::{SocketAddr, TcpStream},
+    process::{Command, Stdio},
+};
+
+use hello::testing::TestClient;
+
+// Written to the temp dir, where relative paths would be taken from.
+const CONFIG: &str = concat!(
+    "[listener]\nport = 0\n\n[admin]\ntokens = \"",
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/tokens\"\nendpoints = true\n"
+);
+
+/// Ask the server on `addr` to shut down, checking that only a `POST` with
+/// the token does it.
+fn shut_down(addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
+    let token = "Bearer 9d8e7f6a5b4c3d2e";
+    let get = TestClient::get("/admin/shutdown")
+        .header("Authorization", token)
+        .send_to(addr)?;
+    assert_eq!(get.status(), 405);
+    let anonymous = TestClient::post("/admin/shutdown")
+        .header("Content-Length", "0")
+        .send_to(addr)?;
+    assert_eq!(anonymous.status(), 401);
+
+    let accepted = TestClient::post("/admin/shutdown")
+        .header("Authorization", token)
+        .header("Content-Length", "0")
+        .send_to(addr)?;
+    assert_eq!(accepted.status(), 202);
+    assert_eq!(accepted.header("connection"), Some("close"));
+    assert_eq!(
+        accepted.text(),
+        "{\"action\":\"shutdown\",\"status\":\"draining\",\"connections\":0}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_shutdown_endpoint_drains_the_server() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("hello-admin-{}.toml", std::process::id()));
+    fs::write(&path, CONFIG)?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--config"])
+        .arg(&path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address")
+        .parse::<SocketAddr>()?;
+
+    let shut = shut_down(addr);
+    if shut.is_err() {
+        child.kill()?;
+    }
+    io::copy(&mut stdout, &mut io::sink())?;
+    let status = child.wait()?;
+    fs::remove_file(&path)?;
+    shut?;
+    assert!(
+        status.success(),
+        "the server exits cleanly, not with {status}"
+    );
+    assert!(
+        TcpStream::connect(addr).is_err(),
+        "the server stopped listening"
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+//! Allocations made while writing a response, counted by a global allocator.
+//!
+//! Before responses were written through a buffer, the head was formatted into
+//! a `String` first and a response with a `Bytes` body needed an allocation the
+//! size of its head. Now writing should allocate at most the write buffer.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use hello::response::{Body, Response};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+#[test]
+fn test_writing_allocates_only_the_buffer() -> Result<(), Box<dyn std::error::Error>> {
+    let body = vec![b'x'; 256 * 1024];
+    let response = Response::new(200)
+        .with_header("Content-Type", "text/plain")
+        .with_header("Cache-Control", "no-cache")
+        .with_body(Body::Bytes(body));
+
+    let (allocations, allocated) = (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED.load(Ordering::Relaxed),
+    );
+    response.write_to(&mut io::sink())?;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - allocated;
+
+    assert!(
+        allocations <= 1,
+        "{allocations} allocations for one response"
+    );
+    assert!(
+        allocated <= 8 * 1024,
+        "{allocated} bytes allocated for one response"
+    );
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! Running the shell scripts in `tests/fixtures/cgi-bin` as CGI scripts.
+#![cfg(unix)]
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+use hello::{cgi::Cgi, Server, ServerHandle};
+
+fn serve(cgi: Cgi) -> Result<ServerHandle, Box<dyn std::error::Error>> {
+    Ok(Server::bind("127.0.0.1:0")?.pool_size(2).cgi(cgi).spawn()?)
+}
+
+/// Send `request` to `addr` and read until the server closes the connection.
+fn exchange(addr: SocketAddr, request: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let mut client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(request)?;
+    let mut output = Vec::new();
+    client.read_to_end(&mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+#[test]
+fn test_scripts_get_the_query_and_body() -> Result<(), Box<dyn std::error::Error>> {
+    let server = serve(Cgi::new("/cgi-bin", "tests/fixtures/cgi-bin"))?;
+
+    let output = exchange(
+        server.local_addr(),
+        b"GET /cgi-bin/echo/extra?name=a%20b&x=1 HTTP/1.1\r\nUser-Agent: tests\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+    let (head, body) = output.split_once("\r\n\r\n").expect("a complete head");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Type: text/plain\r\n"), "{head}");
+    assert!(head.contains("\r\nX-Method: GET\r\n"), "{head}");
+    assert_eq!(body, "path=/extra\nquery=name=a%20b&x=1\nagent=tests\n");
+
+    let output = exchange(
+        server.local_addr(),
+        b"POST /cgi-bin/echo HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+          Content-Length: 9\r\nConnection: close\r\n\r\nname=post",
+    )?;
+    let (head, body) = output.split_once("\r\n\r\n").expect("a complete head");
+    assert!(head.contains("\r\nX-Method: POST\r\n"), "{head}");
+    assert_eq!(
+        body,
+        "path=\nquery=\nagent=\ntype=application/x-www-form-urlencoded\nbody=name=post"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_large_bodies_and_output() -> Result<(), Box<dyn std::error::Error>> {
+    let server = serve(Cgi::new("/cgi-bin", "tests/fixtures/cgi-bin"))?;
+    let body = "b".repeat(256 * 1024);
+    let request = format!(
+        "POST /cgi-bin/large HTTP/1.1\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    let output = exchange(server.local_addr(), request.as_bytes())?;
+    let (head, output) = output.split_once("\r\n\r\n").expect("a complete head");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert_eq!(output.len(), 256 * 1024 + "\nread=262144\n".len());
+    assert!(
+        output.ends_with("a\nread=262144\n"),
+        "the whole body was read"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_failing_missing_and_slow_scripts() -> Result<(), Box<dyn std::error::Error>> {
+    let cgi = Cgi::new("/cgi-bin", "tests/fixtures/cgi-bin").timeout(Duration::from_millis(300));
+    let server = serve(cgi)?;
+    let get = |path: &str| {
+        let request = format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n");
+        exchange(server.local_addr(), request.as_bytes())
+    };
+
+    let output = get("/cgi-bin/fail")?;
+    assert!(output.starts_with("HTTP/1.1 418 "), "{output}");
+    assert!(output.ends_with("\r\n\r\npartial"), "the body just ends");
+
+    assert!(get("/cgi-bin/notes.txt")?.starts_with("HTTP/1.1 404 "));
+    assert!(get("/cgi-bin/missing")?.starts_with("HTTP/1.1 404 "));
+    assert!(get("/cgi-bin/")?.starts_with("HTTP/1.1 404 "));
+
+    let started = Instant::now();
+    assert!(get("/cgi-bin/slow")?.starts_with("HTTP/1.1 504 Gateway Timeout\r\n"));
+    assert!(
+        started.elapsed() < Duration::from_secs(4),
+        "the script is killed"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_concurrent_scripts_are_capped() -> Result<(), Box<dyn std::error::Error>> {
+    let cgi = Cgi::new("/cgi-bin", "tests/fixtures/cgi-bin")
+        .timeout(Duration::from_millis(500))
+        .max_processes(1);
+    let server = serve(cgi)?;
+    let mut slow = TcpStream::connect(server.local_addr())?;
+    slow.write_all(b"GET /cgi-bin/slow HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+    std::thread::sleep(Duration::from_millis(100));
+
+    let output = exchange(
+        server.local_addr(),
+        b"GET /cgi-bin/echo HTTP/1.1\r\nConnection: close\r\n\r\n",
+    )?;
+    assert!(output.starts_with("HTTP/1.1 503 "), "{output}");
+    let mut output = String::new();
+    slow.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 504 "), "{output}");
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Running the binary as a daemon with a pid file.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    path::Path,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Poll `done` until it holds, for up to ten seconds.
+fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+    let started = Instant::now();
+    while !done() {
+        if started.elapsed() > Duration::from_secs(10) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    true
+}
+
+fn hello(args: &[&str], pid_file: &Path, log_file: &Path) -> io::Result<std::process::Output> {
+    Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(args)
+        .arg("--pid-file")
+        .arg(pid_file)
+        .arg("--log-file")
+        .arg(log_file)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+}
+
+/// The response to a request of `/hello.html` from the server whose address
+/// the log file announces.
+fn request(log_file: &Path) -> io::Result<String> {
+    let log = fs::read_to_string(log_file)?;
+    let addr = log
+        .lines()
+        .find_map(|line| line.strip_prefix("Listening on http://"))
+        .ok_or_else(|| io::Error::other(format!("no address in {log:?}")))?;
+    let mut client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(5)))?;
+    client.write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+    let mut output = String::new();
+    client.read_to_string(&mut output)?;
+    Ok(output)
+}
+
+#[test]
+fn test_daemon_writes_and_removes_its_pid_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("hello-daemon-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let (pid_file, log_file) = (dir.join("hello.pid"), dir.join("hello.log"));
+    let args = ["--daemon", "--addr", "127.0.0.1:0"];
+
+    let launched = hello(&args, &pid_file, &log_file)?;
+    assert!(launched.status.success(), "{launched:?}");
+    let pid = fs::read_to_string(&pid_file)?.trim().to_string();
+
+    let served = request(&log_file);
+    let second = hello(&args, &pid_file, &log_file);
+    let signalled = Command::new("kill").args(["-TERM", &pid]).status()?;
+    let removed = wait_until(|| !pid_file.exists());
+
+    assert!(served?.starts_with("HTTP/1.1 200 OK\r\n"));
+    let second = second?;
+    assert!(!second.status.success(), "a second server refuses to start");
+    let stderr = String::from_utf8_lossy(&second.stderr);
+    assert!(
+        stderr.contains(&format!("running process {pid}")),
+        "{stderr}"
+    );
+    assert!(signalled.success());
+    assert!(removed, "the pid file is removed on shutdown");
+
+    // Failures before detaching reach the terminal.
+    let taken = TcpListener::bind("127.0.0.1:0")?;
+    let addr = taken.local_addr()?.to_string();
+    let failed = hello(&["--daemon", "--addr", &addr], &pid_file, &log_file)?;
+    assert_eq!(failed.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&failed.stderr).contains(&addr));
+    assert!(!pid_file.exists());
+
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
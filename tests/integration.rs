@@ -0,0 +1,238 @@
+//! Servers spawned on port 0, driven over real sockets through the accept
+//! loop and the pool.
+
+use std::{
+    fs,
+    io::Write,
+    net::TcpStream,
+    sync::{mpsc, Mutex},
+    thread,
+};
+
+use hello::{
+    response::{Body, Response},
+    router::Router,
+    socket::SocketOptions,
+    testing::{Session, TestClient},
+    Server, ServerHandle,
+};
+
+/// A server with `workers` workers answering `/work` with a fixed body.
+fn spawn(workers: i32, router: Router) -> Result<ServerHandle, Box<dyn std::error::Error>> {
+    let router = router.get("/work", |_| {
+        Ok(Response::new(200).with_body(Body::Bytes(b"done".to_vec())))
+    });
+    Ok(Server::bind("127.0.0.1:0")?
+        .pool_size(workers)
+        .router(router)
+        .spawn()?)
+}
+
+fn accepted(server: &ServerHandle) -> u64 {
+    server.accepted().iter().map(|(_, accepted)| accepted).sum()
+}
+
+#[test]
+fn test_more_clients_than_workers() -> Result<(), Box<dyn std::error::Error>> {
+    let server = spawn(2, Router::new())?;
+    let addr = server.local_addr();
+    let clients: Vec<_> = (0..8)
+        .map(|_| thread::spawn(move || TestClient::get("/work").send_to(addr)))
+        .collect();
+    for client in clients {
+        let response = client.join().expect("the client does not panic")?;
+        assert_eq!((response.status(), response.text()), (200, "done".into()));
+    }
+    assert_eq!(accepted(&server), 8);
+    assert!(server.shutdown());
+    Ok(())
+}
+
+#[test]
+fn test_keep_alive_reuses_the_connection() -> Result<(), Box<dyn std::error::Error>> {
+    let server = spawn(1, Router::new())?;
+    let mut session = Session::connect(server.local_addr())?;
+    for _ in 0..3 {
+        let response = TestClient::get("/work").send_to(&mut session)?;
+        assert_eq!(response.header("connection"), Some("keep-alive"));
+        assert_eq!(response.text(), "done");
+    }
+    let file = TestClient::get("/hello.html").send_to(&mut session)?;
+    assert_eq!(file.body(), fs::read("hello.html")?);
+    assert_eq!(accepted(&server), 1, "all on one connection");
+    drop(session);
+    assert!(server.shutdown());
+    Ok(())
+}
+
+#[test]
+fn test_a_client_leaving_mid_request() -> Result<(), Box<dyn std::error::Error>> {
+    // With a single worker, the next client is only answered once the
+    // worker let go of the one that left.
+    let server = spawn(1, Router::new())?;
+    let addr = server.local_addr();
+    let mut leaving = TcpStream::connect(addr)?;
+    leaving.write_all(b"GET /work HTTP/1.1\r\nHost: loc")?;
+    drop(leaving);
+
+    let response = TestClient::get("/work").send_to(addr)?;
+    assert_eq!((response.status(), response.text()), (200, "done".into()));
+    assert!(server.shutdown());
+    Ok(())
+}
+
+#[test]
+fn test_shutdown_finishes_requests_in_flight() -> Result<(), Box<dyn std::error::Error>> {
+    let (started, running) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let (started, released) = (Mutex::new(started), Mutex::new(released));
+    let router = Router::new().get("/slow", move |_| {
+        started.lock().unwrap().send(()).expect("the test waits");
+        released.lock().unwrap().recv().expect("the test releases");
+        Ok(Response::new(200).with_body(Body::Bytes(b"slow".to_vec())))
+    });
+    let server = spawn(2, router)?;
+    let addr = server.local_addr();
+    let slow = thread::spawn(move || TestClient::get("/slow").send_to(addr));
+    running.recv()?;
+
+    let shutdown = thread::spawn(move || server.shutdown());
+    // New clients are turned away once the server is draining.
+    while TestClient::get("/work").send_to(addr)?.status() != 503 {}
+
+    release.send(())?;
+    let response = slow.join().expect("the client does not panic")?;
+    assert_eq!((response.status(), response.text()), (200, "slow".into()));
+    assert_eq!(response.header("connection"), Some("close"));
+    assert!(
+        shutdown.join().expect("the shutdown does not panic"),
+        "the request finished before the drain ended"
+    );
+    assert!(TcpStream::connect(addr).is_err(), "the listener is closed");
+    Ok(())
+}
+
+#[test]
+fn test_handlers_see_both_ends_of_the_connection() -> Result<(), Box<dyn std::error::Error>> {
+    let router = Router::new().get("/ends", |request| {
+        let ends = format!(
+            "{:?} {:?} {:?}",
+            request.peer_addr().map(|peer| peer.ip()),
+            request.client_ip(),
+            request.local_addr()
+        );
+        Ok(Response::new(200).with_body(Body::Bytes(ends.into_bytes())))
+    });
+    let server = spawn(1, router)?;
+    let addr = server.local_addr();
+    let ends = TestClient::get("/ends").send_to(addr)?.text();
+    assert_eq!(
+        ends,
+        format!("Some(127.0.0.1) Some(127.0.0.1) Some({addr})")
+    );
+    assert!(server.shutdown());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_a_new_instance_takes_over_the_port() -> Result<(), Box<dyn std::error::Error>> {
+    let (started, running) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+    let (started, released) = (Mutex::new(started), Mutex::new(released));
+    let instance = |name: &'static str,
+                    addr,
+                    router: Router|
+     -> Result<ServerHandle, Box<dyn std::error::Error>> {
+        let router = router.get("/name", move |_| {
+            Ok(Response::new(200).with_body(Body::Bytes(name.as_bytes().to_vec())))
+        });
+        let socket = SocketOptions::new().reuse_port(true);
+        Ok(Server::bind_with(addr, socket)?
+            .pool_size(2)
+            .router(router)
+            .spawn()?)
+    };
+    let router = Router::new().get("/slow", move |_| {
+        started.lock().unwrap().send(()).expect("the test waits");
+        released.lock().unwrap().recv().expect("the test releases");
+        Ok(Response::new(200).with_body(Body::Bytes(b"slow".to_vec())))
+    });
+    let old = instance("old", "127.0.0.1:0".parse()?, router)?;
+    let addr = old.local_addr();
+    let slow = thread::spawn(move || TestClient::get("/slow").send_to(addr));
+    running.recv()?;
+    let new = instance("new", addr, Router::new())?;
+    assert_eq!(new.local_addr(), addr);
+
+    // Until the old one notices it drains, it may still get connections.
+    let shutdown = thread::spawn(move || old.shutdown());
+    let mut in_a_row = 0;
+    while in_a_row < 8 {
+        match TestClient::get("/name").send_to(addr) {
+            Ok(response) if response.text() == "new" => in_a_row += 1,
+            Ok(response) => {
+                assert_eq!(response.status(), 503);
+                in_a_row = 0;
+            }
+            // Reset while waiting in the old one's queue.
+            Err(_) => in_a_row = 0,
+        }
+    }
+
+    release.send(())?;
+    let response = slow.join().expect("the client does not panic")?;
+    assert_eq!((response.status(), response.text()), (200, "slow".into()));
+    assert!(shutdown.join().expect("the shutdown does not panic"));
+    assert_eq!(TestClient::get("/name").send_to(addr)?.text(), "new");
+    assert!(new.shutdown());
+    Ok(())
+}
+
+/// A fresh directory under the system temp dir, removed again on drop.
+struct TempDir(std::path::PathBuf);
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_serves_a_temporary_document_root() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TempDir(std::env::temp_dir().join(format!("hello-root-{}", std::process::id())));
+    fs::create_dir_all(root.0.join("docs"))?;
+    fs::write(root.0.join("docs/notes.txt"), "kept in a tempdir")?;
+    fs::write(root.0.join("home.html"), "<p>home</p>")?;
+    let server = Server::bind("127.0.0.1:0")?
+        .pool_size(1)
+        .document_root(&root.0)
+        .index(&["home.html"])
+        .spawn()?;
+    let addr = server.local_addr();
+
+    let notes = TestClient::get("/docs/notes.txt").send_to(addr)?;
+    assert_eq!(notes.status(), 200);
+    assert_eq!(notes.text(), "kept in a tempdir");
+    assert!(notes
+        .header("content-type")
+        .is_some_and(|kind| kind.starts_with("text/plain")));
+    let index = TestClient::get("/").send_to(addr)?;
+    assert_eq!((index.status(), index.text()), (200, "<p>home</p>".into()));
+    let docs = TestClient::get("/docs/").send_to(addr)?;
+    assert_eq!(docs.status(), 404, "no index file there");
+    let outside = TestClient::get("/hello.html").send_to(addr)?;
+    // Built with embedded assets, those stand in for the files missing on disk.
+    let expected = if cfg!(feature = "embedded-assets") {
+        200
+    } else {
+        404
+    };
+    assert_eq!(
+        outside.status(),
+        expected,
+        "the crate's own files are not served from disk"
+    );
+    assert!(server.shutdown());
+    Ok(())
+}
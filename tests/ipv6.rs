@@ -0,0 +1,43 @@
+//! Serving the binary over IPv6 loopback.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+#[test]
+fn test_serves_over_ipv6_loopback() -> Result<(), Box<dyn std::error::Error>> {
+    if TcpListener::bind("[::1]:0").is_err() {
+        return Ok(()); // No IPv6 here.
+    }
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--addr", "[::1]:0"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address")
+        .to_string();
+
+    let served = (|| -> std::io::Result<String> {
+        let mut client = TcpStream::connect(&addr)?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        client.write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut output = String::new();
+        client.read_to_string(&mut output)?;
+        Ok(output)
+    })();
+    child.kill()?;
+    child.wait()?;
+
+    assert!(addr.starts_with("[::1]:"), "{addr} has brackets");
+    assert!(served?.starts_with("HTTP/1.1 200 OK\r\n"));
+    Ok(())
+}
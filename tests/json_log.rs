@@ -0,0 +1,81 @@
+//! Running the binary with `--log-format json`.
+#![cfg(all(unix, feature = "config"))]
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+fn signal(pid: u32, signal: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn test_events_and_access_lines_are_json() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("hello-json-log-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("server.toml");
+    fs::write(&path, "[listener]\nport = 0\n\n[access_log]\nenabled = true\n")?;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--log-format", "json", "--config"])
+        .arg(&path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut next_line = || -> Result<String, std::io::Error> {
+        let mut line = String::new();
+        stdout.read_line(&mut line)?;
+        Ok(line)
+    };
+
+    let startup = next_line()?;
+    assert!(startup.starts_with("{\"timestamp\":\""), "{startup}");
+    assert!(startup.contains(",\"event\":\"startup\","), "{startup}");
+    let addr = startup
+        .split("\"message\":\"Listening on http://")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("the server names its address")
+        .to_string();
+
+    let mut client = TcpStream::connect(&addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(
+        b"GET /hello.html HTTP/1.1\r\nHost: a.test\r\nUser-Agent: agent \"quoted\"\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+    let mut output = String::new();
+    client.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let access = next_line()?;
+    for field in [
+        "\"method\":\"GET\"",
+        "\"path\":\"/hello.html\"",
+        "\"query\":null",
+        "\"status\":200",
+        "\"user_agent\":\"agent \\\"quoted\\\"\"",
+        "\"host\":\"a.test\"",
+    ] {
+        assert!(access.contains(field), "{field} in {access}");
+    }
+
+    signal(child.id(), "-HUP")?;
+    let reload = next_line()?;
+    assert!(reload.contains(",\"event\":\"reload\","), "{reload}");
+    signal(child.id(), "-INT")?;
+    let shutdown = next_line()?;
+    assert!(shutdown.contains(",\"event\":\"shutdown\","), "{shutdown}");
+    assert!(child.wait()?.success());
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1,28 @@
+//! A short benchmark of an in-process server, run on demand:
+//!
+//! ```sh
+//! cargo test --release --test load -- --ignored --nocapture
+//! ```
+
+use hello::{
+    testing::{Load, TestClient},
+    Server,
+};
+
+#[test]
+#[ignore = "a benchmark, run with --ignored"]
+fn bench_keep_alive_and_new_connections() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::bind("127.0.0.1:0")?.pool_size(4).spawn()?;
+    let addr = server.local_addr();
+    for keep_alive in [true, false] {
+        let report = Load::new(8, 500)
+            .keep_alive(keep_alive)
+            .request(TestClient::get("/hello.html"))
+            .run(addr);
+        println!("keep-alive {keep_alive}:\n{report}");
+        assert_eq!(report.errors(), 0);
+        assert_eq!(report.answered(), 8 * 500);
+    }
+    assert!(server.shutdown());
+    Ok(())
+}
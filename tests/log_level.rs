@@ -0,0 +1,64 @@
+//! What the binary logs at different levels, in the format of the built-in
+//! logger which the `tracing` feature replaces.
+#![cfg(all(unix, not(feature = "tracing")))]
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// The stderr of a server run with `flag` which answers one request.
+fn stderr_with(flag: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args([flag, "--addr", "127.0.0.1:0"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address at every level")
+        .to_string();
+
+    let mut client = TcpStream::connect(&addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+    let mut output = String::new();
+    client.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let status = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    let mut stderr = String::new();
+    child.stderr.take().expect("stderr is piped").read_to_string(&mut stderr)?;
+    child.wait()?;
+    Ok(stderr)
+}
+
+#[test]
+fn test_quiet_and_verbose() -> Result<(), Box<dyn std::error::Error>> {
+    let quiet = stderr_with("--quiet")?;
+    assert_eq!(quiet, "", "only errors are logged");
+
+    let verbose = stderr_with("-v")?;
+    for message in [
+        "[DEBUG] Accepted a connection from 127.0.0.1:",
+        "] Serving /hello.html from ",
+        "[INFO] [",
+        " 127.0.0.1 GET /hello.html 200 ",
+        "] Closed the connection to 127.0.0.1:",
+        "[INFO] Finishing open connections",
+    ] {
+        assert!(verbose.contains(message), "{message:?} in {verbose}");
+    }
+    assert!(!verbose.contains("[TRACE]"), "{verbose}");
+    Ok(())
+}
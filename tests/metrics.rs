@@ -0,0 +1,101 @@
+//! Scraping the metrics of a running server.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use hello::Server;
+
+/// Send `request` to `addr` and read until the server closes the connection.
+fn exchange(addr: SocketAddr, request: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(request.as_bytes())?;
+    let mut output = Vec::new();
+    client.read_to_end(&mut output)?;
+    Ok(String::from_utf8(output)?)
+}
+
+fn get(addr: SocketAddr, path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    exchange(
+        addr,
+        &format!("GET {path} HTTP/1.1\r\nConnection: close\r\n\r\n"),
+    )
+}
+
+/// The value of the sample `series` in the metrics page `page`.
+fn sample(page: &str, series: &str) -> Option<f64> {
+    page.lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .and_then(|value| value.parse().ok())
+}
+
+#[test]
+fn test_counters_move_with_requests() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::bind("127.0.0.1:0")?
+        .pool_size(2)
+        .metrics("/metrics")
+        .spawn()?;
+    let addr = server.local_addr();
+    assert!(get(addr, "/hello.html")?.starts_with("HTTP/1.1 200 "));
+    assert!(get(addr, "/hello.html")?.starts_with("HTTP/1.1 200 "));
+    assert!(get(addr, "/missing")?.starts_with("HTTP/1.1 404 "));
+    exchange(
+        addr,
+        "POST /hello.html HTTP/1.1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let output = get(addr, "/metrics")?;
+    let (head, page) = output.split_once("\r\n\r\n").expect("a complete head");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n"));
+    let requests = |labels| sample(page, &format!("hello_requests_total{{{labels}}}"));
+    assert_eq!(requests("method=\"GET\",status=\"2xx\""), Some(2.0));
+    assert_eq!(requests("method=\"GET\",status=\"4xx\""), Some(1.0));
+    assert_eq!(requests("method=\"POST\",status=\"4xx\""), Some(1.0));
+    assert!(sample(page, "hello_response_bytes_total") > Some(0.0));
+    assert_eq!(sample(page, "hello_connections_accepted_total"), Some(5.0));
+    assert_eq!(
+        sample(page, "hello_requests_in_flight"),
+        Some(1.0),
+        "the scrape itself"
+    );
+    // The worker of the previous connection may not have finished its job yet.
+    assert!(sample(page, "hello_pool_busy_workers") >= Some(1.0));
+    assert!(sample(page, "hello_pool_jobs_executed_total") >= Some(3.0));
+    assert_eq!(
+        sample(page, "hello_request_duration_seconds_bucket{le=\"+Inf\"}"),
+        Some(4.0)
+    );
+    assert_eq!(
+        sample(page, "hello_request_duration_seconds_count"),
+        Some(4.0)
+    );
+    assert!(page.contains("# TYPE hello_request_duration_seconds histogram\n"));
+
+    let snapshot = server.metrics();
+    assert_eq!(
+        snapshot.duration_count, 5,
+        "the scrape is counted once answered"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_metrics_listener_is_isolated() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::bind("127.0.0.1:0")?
+        .pool_size(2)
+        .metrics("/stats")
+        .listen_metrics("127.0.0.1:0")?
+        .spawn()?;
+    let (public, private) = (server.local_addrs()[0], server.local_addrs()[1]);
+
+    assert!(get(public, "/stats")?.starts_with("HTTP/1.1 404 "));
+    assert!(get(private, "/hello.html")?.starts_with("HTTP/1.1 404 "));
+    let page = get(private, "/stats")?;
+    assert!(page.starts_with("HTTP/1.1 200 OK\r\n"), "{page}");
+    assert!(page.contains("\nhello_requests_total{method=\"GET\",status=\"4xx\"} 2\n"));
+    Ok(())
+}
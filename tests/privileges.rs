@@ -0,0 +1,37 @@
+//! Switching to an unprivileged user after binding; needs root.
+#![cfg(unix)]
+
+use std::{
+    io::{self, BufRead, BufReader},
+    process::{Command, Stdio},
+};
+
+#[test]
+#[ignore = "needs to run as root"]
+fn test_switches_to_nobody() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--addr", "127.0.0.1:0", "--user", "nobody"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+
+    let uid = Command::new("ps")
+        .args(["-o", "uid=", "-p", &child.id().to_string()])
+        .output();
+    let signalled = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    io::copy(&mut stdout, &mut io::sink())?;
+    let output = child.wait_with_output()?;
+
+    assert!(listening.starts_with("Listening on http://"), "{listening}");
+    let uid = String::from_utf8(uid?.stdout)?;
+    assert_ne!(uid.trim(), "0", "the server no longer runs as root");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Running as uid"));
+    assert!(signalled.success() && output.status.success());
+    Ok(())
+}
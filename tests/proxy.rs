@@ -0,0 +1,284 @@
+//! Forwarding requests to upstream servers running in the same process.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use hello::{
+    forwarded::ForwardedHeaders,
+    proxy::{Proxy, UpstreamState},
+    response::{Body, Response},
+    router::Router,
+    Server, ServerHandle,
+};
+
+/// Start a server forwarding `/api` to `upstream`, without the prefix.
+fn proxy_to(
+    upstream: SocketAddr,
+    timeout: Duration,
+) -> Result<ServerHandle, Box<dyn std::error::Error>> {
+    let proxy = Proxy::new("/api", upstream)
+        .strip_prefix(true)
+        .timeout(timeout);
+    Ok(Server::bind("127.0.0.1:0")?
+        .pool_size(2)
+        .proxy(proxy)
+        .spawn()?)
+}
+
+/// Send `request` to `addr` and read until the server closes the connection.
+fn exchange(addr: SocketAddr, request: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(request)?;
+    let mut output = Vec::new();
+    client.read_to_end(&mut output)?;
+    Ok(output)
+}
+
+/// The head of `response` as text and the bytes after it.
+fn split(response: &[u8]) -> (String, &[u8]) {
+    let end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .expect("a complete head")
+        + 4;
+    (
+        String::from_utf8_lossy(&response[..end]).into_owned(),
+        &response[end..],
+    )
+}
+
+#[test]
+fn test_relays_status_headers_and_binary_body() -> Result<(), Box<dyn std::error::Error>> {
+    let binary: Vec<u8> = (0..=255).rev().collect();
+    let router = {
+        let binary = binary.clone();
+        Router::new().get("/bin", move |request| {
+            Ok(Response::new(200)
+                .with_header("Content-Type", "application/octet-stream")
+                .with_header("X-Upstream", request.target())
+                .with_header(
+                    "X-Saw-Keep-Alive",
+                    request.header("Keep-Alive").unwrap_or("no"),
+                )
+                .with_body(Body::Bytes(binary.clone())))
+        })
+    };
+    let upstream = Server::bind("127.0.0.1:0")?.router(router).spawn()?;
+    let proxy = proxy_to(upstream.local_addr(), Duration::from_secs(10))?;
+
+    let output = exchange(
+        proxy.local_addr(),
+        b"GET /api/bin?x=1 HTTP/1.1\r\nHost: a\r\nKeep-Alive: timeout=5\r\n\r\n\
+          GET /api/bin HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )?;
+    let (head, rest) = split(&output);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains("\r\nContent-Length: 256\r\n"), "{head}");
+    assert!(head.contains("\r\nX-Upstream: /bin?x=1\r\n"), "{head}");
+    assert!(head.contains("\r\nX-Saw-Keep-Alive: no\r\n"), "{head}");
+    assert!(head.contains("\r\nConnection: keep-alive\r\n"), "{head}");
+    assert_eq!(&rest[..256], &binary[..]);
+
+    let (head, body) = split(&rest[256..]);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert_eq!(body, &binary[..]);
+
+    let output = exchange(
+        proxy.local_addr(),
+        b"GET /api/missing HTTP/1.1\r\nConnection: close\r\n\r\n",
+    )?;
+    assert!(output.starts_with(b"HTTP/1.1 404 "));
+    Ok(())
+}
+
+#[test]
+fn test_streams_request_bodies_upstream() -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let upstream = listener.local_addr()?;
+    let received = thread::spawn(move || -> std::io::Result<Vec<u8>> {
+        let (mut stream, _) = listener.accept()?;
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        while !received.ends_with(b"0\r\n\r\n") {
+            let read = stream.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            received.extend_from_slice(&buffer[..read]);
+        }
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        stream.write_all(
+            b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\nX-Id: 7\r\n\r\n\
+              5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )?;
+        Ok(received)
+    });
+    let proxy = proxy_to(upstream, Duration::from_secs(10))?;
+
+    let output = exchange(
+        proxy.local_addr(),
+        b"POST /api/submit HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+          3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+    )?;
+    let (head, body) = split(&output);
+    assert!(head.starts_with("HTTP/1.1 201 "), "{head}");
+    assert!(head.contains("\r\nX-Id: 7\r\n"), "{head}");
+    assert!(head.contains("\r\nConnection: close\r\n"), "{head}");
+    assert!(!head.contains("Content-Length"), "{head}");
+    assert!(!head.contains("Transfer-Encoding"), "{head}");
+    assert_eq!(body, b"hello world");
+
+    let received = String::from_utf8(received.join().expect("the upstream thread")?)?;
+    let (head, body) = received.split_once("\r\n\r\n").expect("a complete head");
+    assert!(head.starts_with("POST /submit HTTP/1.1\r\n"), "{head}");
+    assert!(head.contains("\r\nHost: a\r\n"), "{head}");
+    assert!(
+        head.contains("\r\nTransfer-Encoding: chunked\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("\r\nConnection: close"), "{head}");
+    assert_eq!(body, "3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n", "a chunk per read");
+    Ok(())
+}
+
+#[test]
+fn test_forwarded_headers_name_the_client() -> Result<(), Box<dyn std::error::Error>> {
+    let router = Router::new().get("/echo", |request| {
+        let seen: Vec<String> = [
+            "X-Forwarded-For",
+            "X-Forwarded-Proto",
+            "X-Forwarded-Host",
+            "Forwarded",
+        ]
+        .iter()
+        .map(|name| {
+            format!(
+                "{name}={}",
+                request
+                    .headers()
+                    .get_all(name)
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            )
+        })
+        .collect();
+        Ok(Response::new(200).with_body(Body::Bytes(seen.join("\n").into_bytes())))
+    });
+    let upstream = Server::bind("127.0.0.1:0")?.router(router).spawn()?;
+    let proxy = proxy_to(upstream.local_addr(), Duration::from_secs(10))?;
+    let echo = |request: &[u8]| -> Result<String, Box<dyn std::error::Error>> {
+        let output = exchange(proxy.local_addr(), request)?;
+        Ok(String::from_utf8_lossy(split(&output).1).into_owned())
+    };
+
+    assert_eq!(
+        echo(b"GET /api/echo HTTP/1.1\r\nHost: a.test\r\nConnection: close\r\n\r\n")?,
+        "X-Forwarded-For=127.0.0.1\n\
+         X-Forwarded-Proto=http\n\
+         X-Forwarded-Host=a.test\n\
+         Forwarded="
+    );
+    assert_eq!(
+        echo(
+            b"GET /api/echo HTTP/1.1\r\nHost: a.test\r\nX-Forwarded-For: 192.0.2.1\r\n\
+              X-Forwarded-Proto: https\r\nConnection: close\r\n\r\n"
+        )?,
+        "X-Forwarded-For=192.0.2.1, 127.0.0.1\n\
+         X-Forwarded-Proto=http\n\
+         X-Forwarded-Host=a.test\n\
+         Forwarded=",
+        "the chain is appended to and the rest replaced"
+    );
+
+    let proxy = Proxy::new("/api", upstream.local_addr())
+        .strip_prefix(true)
+        .forwarded_headers(ForwardedHeaders::Forwarded);
+    let server = Server::bind("127.0.0.1:0")?.proxy(proxy).spawn()?;
+    let output = exchange(
+        server.local_addr(),
+        b"GET /api/echo HTTP/1.1\r\nHost: a.test\r\nForwarded: for=192.0.2.1\r\n\
+          Connection: close\r\n\r\n",
+    )?;
+    assert_eq!(
+        String::from_utf8_lossy(split(&output).1),
+        "X-Forwarded-For=\n\
+         X-Forwarded-Proto=\n\
+         X-Forwarded-Host=\n\
+         Forwarded=for=192.0.2.1, for=127.0.0.1;proto=http;host=\"a.test\""
+    );
+    Ok(())
+}
+
+#[test]
+fn test_unreachable_upstreams_are_gateway_errors() -> Result<(), Box<dyn std::error::Error>> {
+    let closed = TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let proxy = proxy_to(closed, Duration::from_secs(10))?;
+    let output = exchange(proxy.local_addr(), b"GET /api/ HTTP/1.1\r\n\r\n")?;
+    assert!(output.starts_with(b"HTTP/1.1 502 Bad Gateway\r\n"));
+
+    // Connections complete in the backlog, but nothing ever answers them.
+    let silent = TcpListener::bind("127.0.0.1:0")?;
+    let proxy = proxy_to(silent.local_addr()?, Duration::from_millis(200))?;
+    let output = exchange(proxy.local_addr(), b"GET /api/ HTTP/1.1\r\n\r\n")?;
+    assert!(output.starts_with(b"HTTP/1.1 504 Gateway Timeout\r\n"));
+    Ok(())
+}
+
+#[test]
+fn test_round_robin_fails_over_to_the_survivor() -> Result<(), Box<dyn std::error::Error>> {
+    let tagged = |name: &'static str| -> Result<ServerHandle, Box<dyn std::error::Error>> {
+        let router = Router::new().get("/who", move |_| {
+            Ok(Response::new(200).with_body(Body::Bytes(name.as_bytes().to_vec())))
+        });
+        Ok(Server::bind("127.0.0.1:0")?.router(router).spawn()?)
+    };
+    let (a, b) = (tagged("a")?, tagged("b")?);
+    let proxy = Proxy::new("/api", a.local_addr())
+        .with_upstream(b.local_addr())
+        .strip_prefix(true)
+        .passive_health(2, Duration::from_secs(60));
+    let server = Server::bind("127.0.0.1:0")?
+        .pool_size(2)
+        .proxy(proxy)
+        .spawn()?;
+    let who = || -> Result<String, Box<dyn std::error::Error>> {
+        let output = exchange(
+            server.local_addr(),
+            b"GET /api/who HTTP/1.1\r\nConnection: close\r\n\r\n",
+        )?;
+        let (head, body) = split(&output);
+        Ok(format!(
+            "{} {}",
+            &head[9..12],
+            String::from_utf8_lossy(body)
+        ))
+    };
+
+    let answers = (0..4).map(|_| who()).collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(answers, ["200 a", "200 b", "200 a", "200 b"]);
+
+    assert!(b.shutdown());
+    for _ in 0..4 {
+        assert_eq!(who()?, "200 a", "b's turns are retried on a");
+    }
+    let stats = &server.upstreams()[0];
+    assert_eq!(stats.0, "/api");
+    let (to_a, to_b) = (&stats.1[0], &stats.1[1]);
+    assert_eq!(
+        (to_a.requests, to_a.failures, to_a.state),
+        (6, 0, UpstreamState::Up)
+    );
+    assert_eq!(
+        (to_b.requests, to_b.failures, to_b.state),
+        (4, 2, UpstreamState::Down)
+    );
+
+    assert!(a.shutdown());
+    assert!(who()?.starts_with("502 "));
+    Ok(())
+}
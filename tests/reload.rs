@@ -0,0 +1,174 @@
+//! Reloading the configuration of the binary with SIGHUP.
+#![cfg(all(unix, feature = "config"))]
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A configuration on any free port accepting request bodies up to
+/// `max_body_size`, with enough workers to answer other clients next to a
+/// kept-alive connection.
+fn config(max_body_size: u64) -> String {
+    format!("[listener]\nport = 0\n\n[pool]\nthreads = 4\n\n[limits]\nmax_body_size = {max_body_size}\n")
+}
+
+/// Send a request with a five byte body on `client` and return the status line
+/// of the response.
+fn post(client: &mut BufReader<TcpStream>) -> Result<String, Box<dyn std::error::Error>> {
+    client
+        .get_mut()
+        .write_all(b"POST /form HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello")?;
+    let mut status = String::new();
+    client.read_line(&mut status)?;
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        client.read_line(&mut line)?;
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            length = value.trim().parse()?;
+        }
+        if line == "\r\n" {
+            break;
+        }
+    }
+    io::copy(&mut client.take(length), &mut io::sink())?;
+    Ok(status.trim_end().to_string())
+}
+
+fn connect(addr: &str) -> io::Result<BufReader<TcpStream>> {
+    let client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(5)))?;
+    Ok(BufReader::new(client))
+}
+
+fn signal(pid: u32, signal: &str) -> io::Result<()> {
+    let status = Command::new("kill")
+        .args([signal, &pid.to_string()])
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+/// Edit the configuration at `path` of the server with `pid` on `addr`, and
+/// check that a reload applies it to a connection which stays open.
+fn reload_while_connected(
+    path: &Path,
+    pid: u32,
+    addr: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut kept_alive = connect(addr)?;
+    assert_eq!(post(&mut kept_alive)?, "HTTP/1.1 404 NOT FOUND");
+
+    fs::write(path, config(4))?;
+    signal(pid, "-HUP")?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while post(&mut connect(addr)?)? != "HTTP/1.1 413 Content Too Large" {
+        assert!(Instant::now() < deadline, "the reload takes effect");
+        thread::sleep(Duration::from_millis(50));
+    }
+    assert_eq!(
+        post(&mut kept_alive)?,
+        "HTTP/1.1 413 Content Too Large",
+        "the open connection picks up the new limit"
+    );
+
+    // 413 closes the connection, as its body is left unread.
+    let mut kept_alive = connect(addr)?;
+    fs::write(path, "[limits]\nmax_requests = 0\n")?;
+    signal(pid, "-HUP")?;
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(
+        post(&mut kept_alive)?,
+        "HTTP/1.1 413 Content Too Large",
+        "an invalid file keeps the old configuration"
+    );
+    Ok(())
+}
+
+/// The `Cache-Control` header of `/hello.html` on `addr`, if it has one.
+fn cache_control(addr: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let mut client = connect(addr)?;
+    client
+        .get_mut()
+        .write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+    let mut output = String::new();
+    client.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 200 OK\r\n"), "{output}");
+    Ok(output
+        .lines()
+        .find_map(|line| line.strip_prefix("Cache-Control: "))
+        .map(str::to_string))
+}
+
+/// Edit the cache policy in the configuration at `path` of the server with
+/// `pid` on `addr`, and check that a reload swaps it.
+fn reload_cache_policy(
+    path: &Path,
+    pid: u32,
+    addr: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    assert_eq!(cache_control(addr)?, None);
+
+    fs::write(
+        path,
+        format!("{}\n[cache]\n\"*.html\" = \"no-cache\"\n", config(1024)),
+    )?;
+    signal(pid, "-HUP")?;
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while cache_control(addr)?.as_deref() != Some("no-cache") {
+        assert!(Instant::now() < deadline, "the reload takes effect");
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sighup_reloads_without_dropping_connections() -> Result<(), Box<dyn std::error::Error>> {
+    with_server("limits", reload_while_connected)
+}
+
+#[test]
+fn test_sighup_swaps_the_cache_policy() -> Result<(), Box<dyn std::error::Error>> {
+    with_server("cache", reload_cache_policy)
+}
+
+/// Checks a reload of the configuration at a path, of the server with a pid
+/// listening on an address.
+type Reload = fn(&Path, u32, &str) -> Result<(), Box<dyn std::error::Error>>;
+
+/// Run `reload` against the binary serving on a free port, with its
+/// configuration in a directory of its own named after `name`, then stop it.
+fn with_server(name: &str, reload: Reload) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::env::temp_dir().join(format!("hello-reload-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("server.toml");
+    fs::write(&path, config(1024))?;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--config"])
+        .arg(&path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address")
+        .to_string();
+
+    let reloaded = reload(&path, child.id(), &addr);
+    signal(child.id(), "-INT")?;
+    io::copy(&mut stdout, &mut io::sink())?;
+    child.wait()?;
+    fs::remove_dir_all(&dir)?;
+    reloaded
+}
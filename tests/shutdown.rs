@@ -0,0 +1,66 @@
+//! Stopping the binary with a signal while a request is in flight.
+#![cfg(unix)]
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// Send `signal` to a server while a request is in flight, and check that the
+/// request is answered, later clients are turned away and the server exits cleanly.
+fn stop_mid_request(signal: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--addr", "127.0.0.1:0"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // Keep reading the output, so that the server can print to it until it exits.
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address")
+        .to_string();
+
+    let mut client = TcpStream::connect(&addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    client.write_all(b"GET /hello.html HTTP/1.1\r\n")?;
+    thread::sleep(Duration::from_millis(100));
+
+    let signalled = Command::new("kill")
+        .args([signal, &child.id().to_string()])
+        .status()?;
+    assert!(signalled.success());
+    thread::sleep(Duration::from_millis(200));
+
+    let mut late = TcpStream::connect(&addr)?;
+    late.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut output = String::new();
+    late.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+    client.write_all(b"\r\n")?;
+    let mut output = String::new();
+    client.read_to_string(&mut output)?;
+    assert!(output.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(output.contains("\r\nConnection: close\r\n"));
+
+    io::copy(&mut stdout, &mut io::sink())?;
+    let status = child.wait()?;
+    assert!(
+        status.success(),
+        "the server exits cleanly, not with {status}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_signals_finish_requests_in_flight() -> Result<(), Box<dyn std::error::Error>> {
+    stop_mid_request("-INT")?;
+    stop_mid_request("-TERM")
+}
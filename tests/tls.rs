@@ -0,0 +1,168 @@
+//! Serving the binary over HTTPS next to plain HTTP, with a self-signed
+//! certificate made for the test.
+#![cfg(feature = "tls")]
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use rcgen::{CertificateParams, KeyPair};
+use rustls::{pki_types::CertificateDer, ClientConfig, ClientConnection, RootCertStore};
+
+/// A directory for the certificate files, removed again when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> io::Result<TempDir> {
+        let path = std::env::temp_dir().join(format!("hello-{name}-{}", std::process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write a certificate for localhost made by `params` and its key to `dir`,
+/// returning the DER certificate and the paths of both files.
+fn write_cert(
+    dir: &Path,
+    params: CertificateParams,
+) -> Result<(CertificateDer<'static>, PathBuf, PathBuf), Box<dyn std::error::Error>> {
+    let key = KeyPair::generate()?;
+    let cert = params.self_signed(&key)?;
+    let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+    fs::write(&cert_path, cert.pem())?;
+    fs::write(&key_path, key.serialize_pem())?;
+    Ok((cert.der().clone(), cert_path, key_path))
+}
+
+fn localhost() -> Result<CertificateParams, rcgen::Error> {
+    CertificateParams::new(vec!["localhost".to_string()])
+}
+
+fn get(stream: &mut impl ReadWrite) -> io::Result<String> {
+    stream
+        .write_all(b"GET /hello.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+    let mut output = String::new();
+    stream.read_to_string(&mut output)?;
+    Ok(output)
+}
+
+trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+#[test]
+fn test_serves_https_next_to_http() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new("tls")?;
+    let (cert, cert_path, key_path) = write_cert(&dir.0, localhost()?)?;
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args([
+            "--quiet",
+            "--addr",
+            "127.0.0.1:0",
+            "--tls-addr",
+            "127.0.0.1:0",
+        ])
+        .arg("--tls-cert")
+        .arg(&cert_path)
+        .arg("--tls-key")
+        .arg(&key_path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+    let served = (|| -> Result<(String, String, String), Box<dyn std::error::Error>> {
+        let (mut http, mut https) = (String::new(), String::new());
+        stdout.read_line(&mut http)?;
+        stdout.read_line(&mut https)?;
+        let http = http
+            .trim()
+            .strip_prefix("Listening on http://")
+            .ok_or(http.clone())?;
+        let https = https
+            .trim()
+            .strip_prefix("Listening on https://")
+            .ok_or(https.clone())?;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert)?;
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let conn = ClientConnection::new(Arc::new(client), "localhost".try_into()?)?;
+        let tcp = TcpStream::connect(https)?;
+        tcp.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let secure = get(&mut rustls::StreamOwned::new(conn, tcp))?;
+
+        // Plain HTTP on the TLS port fails its handshake without taking the
+        // server down.
+        let mut plain = TcpStream::connect(https)?;
+        plain.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let refused = get(&mut plain).unwrap_or_default();
+
+        let mut plain = TcpStream::connect(http)?;
+        plain.set_read_timeout(Some(Duration::from_secs(5)))?;
+        Ok((secure, refused, get(&mut plain)?))
+    })();
+    child.kill()?;
+    child.wait()?;
+
+    let (secure, refused, plain) = served?;
+    assert!(secure.starts_with("HTTP/1.1 200 OK\r\n"), "{secure}");
+    assert!(!refused.contains("200 OK"), "{refused}");
+    assert!(plain.starts_with("HTTP/1.1 200 OK\r\n"), "{plain}");
+    Ok(())
+}
+
+/// Run the binary with the certificate and key at `cert` and `key`, returning
+/// its exit code and what it printed to stderr.
+fn start_with(cert: &Path, key: &Path) -> io::Result<(Option<i32>, String)> {
+    let output = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--addr", "127.0.0.1:0", "--tls-addr", "127.0.0.1:0"])
+        .arg("--tls-cert")
+        .arg(cert)
+        .arg("--tls-key")
+        .arg(key)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdin(Stdio::null())
+        .output()?;
+    Ok((
+        output.status.code(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    ))
+}
+
+#[test]
+fn test_bad_key_material_stops_startup() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TempDir::new("tls-bad")?;
+    let mut expired = localhost()?;
+    expired.not_before = rcgen::date_time_ymd(2020, 1, 1);
+    expired.not_after = rcgen::date_time_ymd(2021, 1, 1);
+    let (_, cert, key) = write_cert(&dir.0, expired)?;
+    let (code, stderr) = start_with(&cert, &key)?;
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("the certificate expired on"), "{stderr}");
+
+    let (_, cert, _) = write_cert(&dir.0, localhost()?)?;
+    let other_key = dir.0.join("other.pem");
+    fs::write(&other_key, KeyPair::generate()?.serialize_pem())?;
+    let (code, stderr) = start_with(&cert, &other_key)?;
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("does not fit"), "{stderr}");
+
+    let (code, stderr) = start_with(&dir.0.join("missing.pem"), &other_key)?;
+    assert_eq!(code, Some(1));
+    assert!(stderr.contains("could not read"), "{stderr}");
+    Ok(())
+}
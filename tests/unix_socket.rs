@@ -0,0 +1,47 @@
+//! Serving the binary over a Unix domain socket.
+#![cfg(unix)]
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+#[test]
+fn test_serves_over_unix_socket() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::env::temp_dir().join(format!("hello-{}.sock", std::process::id()));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--addr", "127.0.0.1:0", "--unix-socket"])
+        .arg(&path)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    stdout.read_line(&mut listening)?;
+    assert!(
+        listening.contains(&format!("Listening on unix:{}\n", path.display())),
+        "{listening}"
+    );
+
+    let served = (|| -> io::Result<String> {
+        let mut client = UnixStream::connect(&path)?;
+        client.set_read_timeout(Some(Duration::from_secs(5)))?;
+        client.write_all(b"GET /hello.html HTTP/1.1\r\nConnection: close\r\n\r\n")?;
+        let mut output = String::new();
+        client.read_to_string(&mut output)?;
+        Ok(output)
+    })();
+    let signalled = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()?;
+    io::copy(&mut stdout, &mut io::sink())?;
+    let status = child.wait()?;
+
+    assert!(served?.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(signalled.success() && status.success());
+    assert!(!path.exists(), "the socket file is removed on shutdown");
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Echoing over a WebSocket to the binary's `/echo` example.
+#![cfg(unix)]
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use hello::websocket::{Frame, Opcode};
+
+const MASK: Option<[u8; 4]> = Some([1, 2, 3, 4]);
+
+/// The opcode bits and payload of the next frame the server sent, which it
+/// leaves unmasked.
+fn read_frame(reader: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head)?;
+    assert_eq!(head[1] & 0x80, 0, "server frames are not masked");
+    let mut payload = vec![0; usize::from(head[1] & 0x7f)];
+    reader.read_exact(&mut payload)?;
+    Ok((head[0], payload))
+}
+
+/// Open a WebSocket to `/echo` on `addr` and check that it echoes a text
+/// message sent in two fragments, then closes cleanly.
+fn echo(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let client = TcpStream::connect(addr)?;
+    client.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut client = client;
+    client.write_all(
+        b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+          Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+          Sec-WebSocket-Version: 13\r\n\r\n",
+    )?;
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head)? == 0 {
+            break;
+        }
+    }
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+    assert!(
+        head.contains("\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"),
+        "{head}"
+    );
+
+    let first = Frame {
+        fin: false,
+        opcode: Opcode::Text,
+        payload: b"hello, ".to_vec(),
+    };
+    let last = Frame::new(Opcode::Continuation, b"socket".to_vec());
+    client.write_all(&first.encode(MASK))?;
+    client.write_all(&last.encode(MASK))?;
+    assert_eq!(read_frame(&mut reader)?, (0x81, b"hello, socket".to_vec()));
+
+    client.write_all(&Frame::new(Opcode::Ping, b"?".to_vec()).encode(MASK))?;
+    assert_eq!(read_frame(&mut reader)?, (0x8a, b"?".to_vec()));
+
+    let close = Frame::new(Opcode::Close, 1000u16.to_be_bytes().to_vec());
+    client.write_all(&close.encode(MASK))?;
+    assert_eq!(read_frame(&mut reader)?, (0x88, vec![0x03, 0xe8]));
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest)?;
+    assert!(rest.is_empty(), "the server closes the connection");
+    Ok(())
+}
+
+fn stop(mut child: Child) -> io::Result<()> {
+    child.kill()?;
+    child.wait()?;
+    Ok(())
+}
+
+#[test]
+fn test_echo_over_a_socket() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+        .args(["--quiet", "--addr", "127.0.0.1:0"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut listening = String::new();
+    stdout.read_line(&mut listening)?;
+    let addr = listening
+        .trim()
+        .strip_prefix("Listening on http://")
+        .expect("the server prints its address")
+        .to_string();
+
+    let echoed = echo(&addr);
+    stop(child)?;
+    echoed
+}